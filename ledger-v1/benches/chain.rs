@@ -0,0 +1,49 @@
+// Micro-benchmarks of the hot paths: `cargo bench --bench chain`, with criterion
+// keeping the results of the last run under target/criterion to compare against.
+// Everything runs in memory, so storage costs are left to the `durability` bench
+// and the `bench` subcommand.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ledger_v1::{Block, Blockchain};
+
+fn blocks(c: &mut Criterion) {
+    c.bench_function("create block (256 B)", |b| {
+        b.iter(|| Block::new_with_timestamp(black_box(vec![7u8; 256]), "0".repeat(64), 1));
+    });
+
+    let mut group = c.benchmark_group("hash block");
+    for size in [256, 64 << 10, 1 << 20] {
+        let block = Block::new_with_timestamp(vec![7u8; size], "0".repeat(64), 1);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &block, |b, block| b.iter(|| block.calculate_hash()));
+    }
+    group.finish();
+
+    let header = Block::new_with_timestamp(vec![7u8; 1 << 20], "0".repeat(64), 1).header();
+    c.bench_function("hash header", |b| b.iter(|| black_box(&header).calculate_hash()));
+}
+
+fn chains(c: &mut Criterion) {
+    let chain = Blockchain::in_memory().expect("open an in-memory chain");
+    let mut i = 0u64;
+    c.bench_function("add block (in memory)", |b| {
+        b.iter(|| {
+            chain.add_block(format!("block {}", i)).expect("add a block");
+            i += 1;
+        });
+    });
+
+    let chain = Blockchain::in_memory().expect("open an in-memory chain");
+    for i in 0..1000 {
+        chain.add_block(format!("block {}", i)).expect("add a block");
+    }
+    let mut group = c.benchmark_group("validate chain");
+    group.sample_size(10);
+    group.bench_function("1000 blocks", |b| b.iter(|| assert!(chain.is_chain_valid().expect("validate"))));
+    group.finish();
+}
+
+criterion_group!(benches, blocks, chains);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+// Bulk load under each durability policy: `cargo bench --bench durability`.
+// Each iteration takes BLOCKS blocks one `add_block` at a time into a fresh sled
+// database; opening and removing it is left out of the time.
+
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ledger_v1::{Blockchain, Config, Durability};
+
+const BLOCKS: u64 = 500;
+
+fn durability(c: &mut Criterion) {
+    let policies = [
+        ("every block", Durability::EveryBlock),
+        ("every 100", Durability::EveryN(100)),
+        ("every 250 ms", Durability::IntervalMs(250)),
+        ("manual", Durability::Manual),
+    ];
+    let mut group = c.benchmark_group("bulk load");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(BLOCKS));
+    for (name, durability) in policies {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &durability, |b, durability| {
+            b.iter_custom(|iterations| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iterations {
+                    elapsed += load(*durability);
+                }
+                elapsed
+            });
+        });
+    }
+    group.finish();
+}
+
+fn load(durability: Durability) -> Duration {
+    let path = std::env::temp_dir().join(format!("ledger-bench-durability-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let chain = Blockchain::open_with_config(path.to_str().unwrap(), None, Config { durability, ..Config::default() })
+        .expect("open the benchmark database");
+
+    let started = Instant::now();
+    for i in 0..BLOCKS {
+        chain.add_block(format!("block {}", i)).expect("add a block");
+    }
+    chain.flush().expect("flush");
+    let elapsed = started.elapsed();
+    drop(chain);
+    let _ = std::fs::remove_dir_all(&path);
+    elapsed
+}
+
+criterion_group!(benches, durability);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+/*
+ * C interface to the ledger, exported by the cdylib (libledger_v1.so, .dylib or
+ * .dll) when it is built with the ffi feature:
+ *
+ *     cargo build --release --features ffi
+ *
+ * The rules:
+ *
+ * - Every function returns one of the LEDGER_* codes below. On failure
+ *   ledger_last_error() describes it until the same thread's next failure.
+ * - Arguments are only borrowed for the call. Strings are NUL-terminated UTF-8.
+ * - A chain from ledger_open() is the caller's, to be passed to ledger_close()
+ *   exactly once. Until then it may be used from several threads at the same time.
+ * - A string a function hands back through an out pointer is the caller's, to be
+ *   freed with ledger_string_free() and nothing else.
+ * - A panic never crosses into the caller: it is returned as LEDGER_ERR_PANIC,
+ *   after which the chain should be closed.
+ */
+#ifndef LEDGER_H
+#define LEDGER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define LEDGER_OK 0
+/* A required pointer was NULL. */
+#define LEDGER_ERR_NULL 1
+/* A string argument is not UTF-8. */
+#define LEDGER_ERR_UTF8 2
+#define LEDGER_ERR_NOT_FOUND 3
+/* Another process has the database open for writing. */
+#define LEDGER_ERR_IN_USE 4
+/* Anything else, e.g. an unreadable config or a block the chain refuses. */
+#define LEDGER_ERR_FAILED 5
+#define LEDGER_ERR_PANIC 6
+/* A block with the same hash is stored already. */
+#define LEDGER_ERR_DUPLICATE 7
+
+/* An open chain. */
+typedef struct LedgerChain LedgerChain;
+
+/* The message for the last failure on this thread, or NULL. Owned by the library
+ * and valid until the thread's next failure. */
+const char *ledger_last_error(void);
+
+/* Opens the database at path, creating it if need be. config_path (a node config)
+ * and genesis_path (used only when creating) may be NULL for the defaults. On
+ * success *out is the chain; on failure it is NULL. */
+int32_t ledger_open(const char *path, const char *config_path, const char *genesis_path, LedgerChain **out);
+
+/* Flushes and closes chain, which must not be used again whatever this returns.
+ * NULL is ignored. */
+int32_t ledger_close(LedgerChain *chain);
+
+/* Appends a block holding the len bytes at data (NULL when len is 0). If hash_out
+ * isn't NULL it receives the new block's hash. */
+int32_t ledger_add_block(const LedgerChain *chain, const uint8_t *data, size_t len, char **hash_out);
+
+/* The block with hash hash as JSON, in *json_out. LEDGER_ERR_NOT_FOUND if there is
+ * none. */
+int32_t ledger_get_block(const LedgerChain *chain, const char *hash, char **json_out);
+
+/* Checks the integrity of the chain, as `ledger validate` does: *valid_out is false
+ * if it fails. The error code only says whether the check could run. */
+int32_t ledger_validate(const LedgerChain *chain, bool *valid_out);
+
+int32_t ledger_height(const LedgerChain *chain, uint64_t *height_out);
+
+/* Frees a string the library handed out. NULL is ignored. */
+void ledger_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,518 @@
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use ed25519_dalek::Signature;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::authority;
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::signer::{self, Signer};
+use crate::store::{BlockStore, TreeId};
+
+// Anchoring: the tip hash is published somewhere the database's operators cannot
+// rewrite, so an audit can later show that a historical tip existed by then and is
+// still the chain's block at its height. Each anchor covers the statement
+// "ledger-anchor:<network id>:<height>:<hash>:<anchored ms>", and goes to
+// `Config::anchor_target`:
+//
+// - a log: a file of JSON lines, one attestation each, signed with the operator's
+//   ed25519 anchor key. The file (or copies of it) is what gets audited, with
+//   `Blockchain::verify_anchor_log`.
+// - an RFC 3161 timestamp authority, over plain HTTP: the request carries the
+//   statement's SHA-256, and the token the authority signs back is kept with the
+//   anchor. Checking a token here covers its status, imprint and nonce; its CMS
+//   signature and the authority's certificate are left to `openssl ts -verify`,
+//   given the token (`ledger anchor token`) and the digest.
+//
+// Every anchor is also recorded in the anchors tree, which `Blockchain::verify_anchors`
+// checks.
+
+// Read when the config names no key file.
+pub const KEY_ENV: &str = "LEDGER_ANCHOR_KEY";
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// Where anchors are published.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AnchorTarget {
+    // Appended to, one line per anchor. Several chains may share one.
+    Log { path: PathBuf },
+    // An http:// URL taking application/timestamp-query posts.
+    Timestamp { url: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Anchor {
+    pub height: u64,
+    pub hash: String,
+    pub anchored_ms: u64,
+    pub proof: AnchorProof,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AnchorProof {
+    // The log attestation: `public_key`'s ed25519 signature over the statement, hex.
+    Signed { public_key: String, signature: String },
+    // The authority's DER TimeStampToken.
+    Timestamp {
+        #[serde(with = "crate::payload")]
+        token: Vec<u8>,
+    },
+}
+
+impl Anchor {
+    pub fn statement(&self, network_id: &str) -> String {
+        statement(network_id, self.height, &self.hash, self.anchored_ms)
+    }
+
+    // The SHA-256 of the statement, hex: what a timestamp token is over.
+    pub fn digest(&self, network_id: &str) -> String {
+        hex::encode(Sha256::digest(self.statement(network_id).as_bytes()))
+    }
+}
+
+fn statement(network_id: &str, height: u64, hash: &str, anchored_ms: u64) -> String {
+    format!("ledger-anchor:{}:{}:{}:{}", network_id, height, hash, anchored_ms)
+}
+
+// An anchor, checked.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnchorCheck {
+    pub anchor: Anchor,
+    // The key that signed a log attestation, or the time in a timestamp token as the
+    // authority wrote it (e.g. "20240101120000Z").
+    pub attested: String,
+    // Why it does not hold up, if it doesn't.
+    pub problem: Option<String>,
+}
+
+impl AnchorCheck {
+    pub fn holds(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+// One line of a log target.
+#[derive(Serialize, Deserialize)]
+struct Attestation {
+    network_id: String,
+    height: u64,
+    hash: String,
+    anchored_ms: u64,
+    public_key: String,
+    signature: String,
+}
+
+// This node's anchor key: `Config::anchor_signer`, or else the key (32 secret bytes,
+// hex) from `Config::anchor_key_file` or the LEDGER_ANCHOR_KEY variable. Also checks
+// that periodic anchoring has somewhere to publish.
+pub(crate) fn signer_from_config(config: &Config) -> Result<Option<Arc<dyn Signer>>, Box<dyn Error>> {
+    let signer = signer::from_config(config.anchor_signer.as_ref(), config.anchor_key_file.as_deref(), KEY_ENV, "anchor")?;
+    if let Some(AnchorTarget::Timestamp { url }) = &config.anchor_target {
+        Endpoint::parse(url)?;
+    }
+    if config.anchor_interval > 0 {
+        match &config.anchor_target {
+            None => return Err("anchor_interval needs an anchor_target".into()),
+            Some(AnchorTarget::Log { .. }) if signer.is_none() => {
+                return Err(format!("Anchoring to a log needs an anchor key; set anchor_key_file or {}", KEY_ENV).into());
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(signer)
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Publishes the current tip to `Config::anchor_target` and records the anchor.
+    pub fn anchor(&self) -> Result<Anchor, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        self.publish_anchor()
+    }
+
+    fn publish_anchor(&self) -> Result<Anchor, Box<dyn Error>> {
+        let target = self.config.anchor_target.as_ref().ok_or("No anchor_target is configured")?;
+        let network_id = self.network_id()?;
+        let height = self.height()?;
+        let hash = self.current_hash();
+        let anchored_ms = self.clock.now_ms();
+        let statement = statement(&network_id, height, &hash, anchored_ms);
+        let proof = match target {
+            AnchorTarget::Log { path } => {
+                let signer = self
+                    .anchor_signer
+                    .as_ref()
+                    .ok_or_else(|| format!("Log attestations are signed with the anchor key; set anchor_key_file or {}", KEY_ENV))?;
+                let public_key = signer.public_key();
+                let signature = hex::encode(signer.sign(statement.as_bytes())?.to_bytes());
+                let attestation = Attestation {
+                    network_id,
+                    height,
+                    hash: hash.clone(),
+                    anchored_ms,
+                    public_key: public_key.clone(),
+                    signature: signature.clone(),
+                };
+                append_line(path, &serde_json::to_string(&attestation)?)?;
+                AnchorProof::Signed { public_key, signature }
+            }
+            AnchorTarget::Timestamp { url } => AnchorProof::Timestamp { token: request_timestamp(url, &statement)? },
+        };
+        let anchor = Anchor { height, hash, anchored_ms, proof };
+        self.store().insert(TreeId::Anchors, &anchor_key(height, &anchor.hash), rmp_serde::to_vec(&anchor)?)?;
+        self.store().flush()?;
+        info!(height, hash = %anchor.hash, "tip anchored");
+        Ok(anchor)
+    }
+
+    // Called whenever the tip moves. The block is in by then, so a target that cannot
+    // be reached only costs this anchor; the next interval tries again.
+    pub(crate) fn maybe_anchor(&self) -> Result<(), Box<dyn Error>> {
+        let interval = self.config.anchor_interval;
+        if interval > 0 && self.height()? % interval == 0 && let Err(e) = self.publish_anchor() {
+            warn!(error = %e, "cannot anchor the tip");
+        }
+        Ok(())
+    }
+
+    // The recorded anchors, by height.
+    pub fn anchors(&self) -> Result<Vec<Anchor>, Box<dyn Error>> {
+        let mut anchors = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::Anchors, &[]) {
+            let (_, bytes) = entry?;
+            anchors.push(rmp_serde::from_slice(&bytes)?);
+        }
+        Ok(anchors)
+    }
+
+    // Checks every recorded anchor: its proof, and that its block is still canonical.
+    pub fn verify_anchors(&self) -> Result<Vec<AnchorCheck>, Box<dyn Error>> {
+        let network_id = self.network_id()?;
+        self.anchors()?.into_iter().map(|anchor| self.check_anchor(&network_id, anchor)).collect()
+    }
+
+    // Likewise for the attestations in a log target, wherever the file now is. Lines
+    // for other chains are skipped.
+    pub fn verify_anchor_log(&self, path: impl AsRef<Path>) -> Result<Vec<AnchorCheck>, Box<dyn Error>> {
+        let path = path.as_ref();
+        let network_id = self.network_id()?;
+        let file = std::fs::File::open(path).map_err(|e| format!("Cannot read anchor log {}: {}", path.display(), e))?;
+        let mut checks = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let attestation: Attestation = serde_json::from_str(&line)
+                .map_err(|e| format!("Line {} of {} is not an attestation: {}", number + 1, path.display(), e))?;
+            if attestation.network_id != network_id {
+                continue;
+            }
+            let anchor = Anchor {
+                height: attestation.height,
+                hash: attestation.hash,
+                anchored_ms: attestation.anchored_ms,
+                proof: AnchorProof::Signed { public_key: attestation.public_key, signature: attestation.signature },
+            };
+            checks.push(self.check_anchor(&network_id, anchor)?);
+        }
+        Ok(checks)
+    }
+
+    fn check_anchor(&self, network_id: &str, anchor: Anchor) -> Result<AnchorCheck, Box<dyn Error>> {
+        let statement = anchor.statement(network_id);
+        let (attested, proof) = match &anchor.proof {
+            AnchorProof::Signed { public_key, signature } => (public_key.clone(), self.check_attestation(public_key, signature, &statement)),
+            AnchorProof::Timestamp { token } => match TimestampToken::parse(token) {
+                Ok(token) => (token.time.clone(), token.check(&statement, None)),
+                Err(e) => (String::new(), Err(e)),
+            },
+        };
+        let problem = match proof {
+            Err(e) => Some(e.to_string()),
+            Ok(()) => match self.canonical_hash(anchor.height)? {
+                Some(hash) if hash == anchor.hash => None,
+                Some(hash) => Some(format!("Height {} now holds block {}", anchor.height, hash)),
+                None => Some(format!("The chain no longer reaches height {}", anchor.height)),
+            },
+        };
+        Ok(AnchorCheck { anchor, attested, problem })
+    }
+
+    // With an anchor key of its own, the node only takes attestations signed with it.
+    fn check_attestation(&self, public_key: &str, signature: &str, statement: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(signer) = &self.anchor_signer
+            && signer.public_key() != public_key
+        {
+            return Err(format!("Signed by {}, not this node's anchor key {}", public_key, signer.public_key()).into());
+        }
+        let signature = hex::decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or("The attestation's signature is malformed")?;
+        authority::verifying_key(public_key)?
+            .verify_strict(statement.as_bytes(), &signature)
+            .map_err(|_| "The attestation's signature does not match".into())
+    }
+}
+
+// Anchors of one tip after another at the same height sort by hash.
+fn anchor_key(height: u64, hash: &str) -> Vec<u8> {
+    let mut key = height.to_be_bytes().to_vec();
+    key.extend_from_slice(hash.as_bytes());
+    key
+}
+
+fn append_line(path: &Path, line: &str) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Cannot open anchor log {}: {}", path.display(), e))?;
+    file.write_all(format!("{}\n", line).as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+// DER, as far as time-stamp requests and tokens need it.
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OID: u8 = 0x06;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const EXPLICIT_0: u8 = 0xa0;
+// 2.16.840.1.101.3.4.2.1
+const SHA256_OID: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+// 1.2.840.113549.1.7.2
+const SIGNED_DATA_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+// 1.2.840.113549.1.9.16.1.4
+const TST_INFO_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x10, 0x01, 0x04];
+
+fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|&b| b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+// An element's tag, its contents and whatever follows it.
+type Element<'a> = (u8, &'a [u8], &'a [u8]);
+type Contents<'a> = (&'a [u8], &'a [u8]);
+
+fn element(bytes: &[u8]) -> Result<Element<'_>, Box<dyn Error>> {
+    let truncated = "The timestamp response is cut short";
+    let (&tag, rest) = bytes.split_first().ok_or(truncated)?;
+    let (&first, rest) = rest.split_first().ok_or(truncated)?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return Err("The timestamp response is not DER".into());
+        }
+        (rest[..count].iter().fold(0, |len, &b| len << 8 | b as usize), &rest[count..])
+    };
+    if rest.len() < len {
+        return Err(truncated.into());
+    }
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
+// The contents of an element with the given tag, and what follows it.
+fn expect<'a>(bytes: &'a [u8], tag: u8, what: &str) -> Result<Contents<'a>, Box<dyn Error>> {
+    match element(bytes)? {
+        (found, contents, rest) if found == tag => Ok((contents, rest)),
+        (found, _, _) => Err(format!("Expected the {} in the timestamp response, found tag {:#04x}", what, found).into()),
+    }
+}
+
+// The parts of a TimeStampToken (RFC 3161 section 2.4.2) that can be checked without
+// its signature.
+struct TimestampToken<'a> {
+    algorithm: &'a [u8],
+    imprint: &'a [u8],
+    time: String,
+    nonce: Option<&'a [u8]>,
+}
+
+impl<'a> TimestampToken<'a> {
+    fn parse(token: &'a [u8]) -> Result<TimestampToken<'a>, Box<dyn Error>> {
+        let (content_info, _) = expect(token, SEQUENCE, "token")?;
+        let (content_type, rest) = expect(content_info, OID, "content type")?;
+        if content_type != SIGNED_DATA_OID {
+            return Err("The timestamp token is not CMS signed data".into());
+        }
+        let (content, _) = expect(rest, EXPLICIT_0, "signed data")?;
+        let (signed_data, _) = expect(content, SEQUENCE, "signed data")?;
+        let (_version, rest) = expect(signed_data, INTEGER, "signed data version")?;
+        let (_digest_algorithms, rest) = expect(rest, SET, "digest algorithms")?;
+        let (encapsulated, _) = expect(rest, SEQUENCE, "encapsulated content")?;
+        let (content_type, rest) = expect(encapsulated, OID, "encapsulated content type")?;
+        if content_type != TST_INFO_OID {
+            return Err("The timestamp token does not hold timestamp info".into());
+        }
+        let (content, _) = expect(rest, EXPLICIT_0, "timestamp info")?;
+        let (tst_info, _) = expect(content, OCTET_STRING, "timestamp info")?;
+        let (tst_info, _) = expect(tst_info, SEQUENCE, "timestamp info")?;
+        let (_version, rest) = expect(tst_info, INTEGER, "timestamp info version")?;
+        let (_policy, rest) = expect(rest, OID, "policy")?;
+        let (message_imprint, rest) = expect(rest, SEQUENCE, "message imprint")?;
+        let (_serial, rest) = expect(rest, INTEGER, "serial number")?;
+        let (time, mut rest) = expect(rest, GENERALIZED_TIME, "time")?;
+        // After the time: accuracy and ordering, both optional, then the nonce if any.
+        let mut nonce = None;
+        while !rest.is_empty() {
+            let (tag, contents, next) = element(rest)?;
+            match tag {
+                INTEGER => {
+                    nonce = Some(contents);
+                    break;
+                }
+                SEQUENCE | BOOLEAN => rest = next,
+                _ => break,
+            }
+        }
+        let (algorithm, rest) = expect(message_imprint, SEQUENCE, "imprint algorithm")?;
+        let (algorithm, _) = expect(algorithm, OID, "imprint algorithm")?;
+        let (imprint, _) = expect(rest, OCTET_STRING, "imprint")?;
+        Ok(TimestampToken { algorithm, imprint, time: String::from_utf8_lossy(time).into_owned(), nonce })
+    }
+
+    fn check(&self, statement: &str, nonce: Option<&[u8]>) -> Result<(), Box<dyn Error>> {
+        if self.algorithm != SHA256_OID {
+            return Err("The timestamp token is not over a SHA-256 digest".into());
+        }
+        if self.imprint != Sha256::digest(statement.as_bytes()).as_slice() {
+            return Err("The timestamp token is over a different statement".into());
+        }
+        if nonce.is_some() && self.nonce != nonce {
+            return Err("The timestamp token does not carry the request's nonce".into());
+        }
+        Ok(())
+    }
+}
+
+// Asks the authority at `url` to timestamp the statement's SHA-256, returning the
+// token it grants.
+fn request_timestamp(url: &str, statement: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut nonce = [0u8; 8];
+    OsRng.fill_bytes(&mut nonce);
+    // Positive, and with no leading zero byte, so the authority echoes it byte for byte.
+    nonce[0] = nonce[0] & 0x7f | 0x40;
+    let algorithm = tlv(SEQUENCE, &[tlv(OID, SHA256_OID), vec![NULL, 0]].concat());
+    let imprint = tlv(SEQUENCE, &[algorithm, tlv(OCTET_STRING, &Sha256::digest(statement.as_bytes()))].concat());
+    // Version 1, the imprint, the nonce, and certReq so the token names its certificate.
+    let request = tlv(SEQUENCE, &[tlv(INTEGER, &[1]), imprint, tlv(INTEGER, &nonce), tlv(BOOLEAN, &[0xff])].concat());
+
+    let response = Endpoint::parse(url)?.post(&request).map_err(|e| format!("Timestamp authority {}: {}", url, e))?;
+    let (response, _) = expect(&response, SEQUENCE, "response")?;
+    let (status_info, rest) = expect(response, SEQUENCE, "status")?;
+    let (status, _) = expect(status_info, INTEGER, "status")?;
+    // 0 is granted, 1 granted with modifications.
+    if status != [0] && status != [1] {
+        return Err(format!("The timestamp authority refused the request (status {})", hex::encode(status)).into());
+    }
+    let (_, _, after) = element(rest)?;
+    let token = rest[..rest.len() - after.len()].to_vec();
+    TimestampToken::parse(&token)?.check(statement, Some(&nonce))?;
+    Ok(token)
+}
+
+// An http:// URL, taken apart.
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Endpoint, Box<dyn Error>> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Timestamp authority {} is not an http:// URL; put a TLS proxy in front for https", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("Timestamp authority {} has an invalid port", url))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Timestamp authority {} has no host", url).into());
+        }
+        Ok(Endpoint { host: host.to_string(), port, path: path.to_string() })
+    }
+
+    // The body of a 2xx answer to a time-stamp query.
+    fn post(&self, query: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("Cannot resolve {}", self.host))?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/timestamp-query\r\nAccept: application/timestamp-reply\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            query.len()
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(query)?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let split = response.windows(4).position(|window| window == b"\r\n\r\n").ok_or("No HTTP response")?;
+        let head = String::from_utf8_lossy(&response[..split]).to_ascii_lowercase();
+        let body = &response[split + 4..];
+        match head.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => {}
+            Some(status) => return Err(format!("The timestamp authority answered {}", status).into()),
+            None => return Err("No HTTP response".into()),
+        }
+        if head.lines().any(|line| line.starts_with("transfer-encoding:") && line.contains("chunked")) {
+            return dechunk(body);
+        }
+        Ok(body.to_vec())
+    }
+}
+
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    loop {
+        let end = body.windows(2).position(|window| window == b"\r\n").ok_or("Malformed chunked response")?;
+        let size = String::from_utf8_lossy(&body[..end]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .map_err(|_| "Malformed chunked response")?;
+        body = &body[end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        if body.len() < size {
+            return Err("The timestamp response is cut short".into());
+        }
+        out.extend_from_slice(&body[..size]);
+        body = body.get(size + 2..).unwrap_or_default();
+    }
+}
@@ -0,0 +1,246 @@
+use ed25519_dalek::{Signature, Verifier};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::authority;
+use crate::batch::ReadTrees;
+use crate::block::Block;
+use crate::blockchain::{block_work, BlockMeta, Blockchain, Payload};
+use crate::config::Config;
+use crate::encoding::{open_block, seal_block};
+use crate::genesis::GenesisConfig;
+use crate::header::BlockHeader;
+use crate::signer::{self, Signer};
+use crate::store::{BlockStore, TreeId};
+use tracing::{debug, info};
+
+// Approval mode (see `GenesisConfig::approval`): every block after genesis carries
+// ed25519 signatures of its hash from at least `threshold` of the approver keys. A
+// block is proposed first (`propose_block`), kept in the proposals tree while
+// approvers sign it (`approve`, or `add_approval` for a signature made elsewhere),
+// and connected as soon as it has enough. The approvals are not part of the hash
+// they sign, so a block keeps its hash however many approvers it collects; every
+// one must still be valid and from a different approver.
+
+// Read when the config names no key file.
+pub const KEY_ENV: &str = "LEDGER_APPROVER_KEY";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApprovalPolicy {
+    pub threshold: usize,
+    // ed25519 public keys, hex.
+    pub approvers: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Approval {
+    // One of `ApprovalPolicy::approvers`.
+    pub approver: String,
+    // Its signature of the block hash, hex.
+    pub signature: String,
+}
+
+// A block waiting for approval. Its id is the block hash.
+#[derive(Debug, Clone)]
+pub struct Proposal {
+    pub block: Block,
+    pub height: u64,
+    // How many approvals the block needs in all.
+    pub needed: usize,
+}
+
+impl Proposal {
+    pub fn id(&self) -> &str {
+        &self.block.hash
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApprovalOutcome {
+    Pending { approvals: usize, needed: usize },
+    // The block reached its threshold and is now the tip.
+    Committed { height: u64 },
+}
+
+// Signs a proposed block as an approver.
+pub fn sign_approval(signer: &dyn Signer, block_hash: &str) -> Result<Approval, Box<dyn Error>> {
+    Ok(Approval { approver: signer.public_key(), signature: hex::encode(signer.sign(block_hash.as_bytes())?.to_bytes()) })
+}
+
+// This node's approver: `Config::approver_signer`, or else the key (32 secret bytes,
+// hex) from `Config::approver_key_file` or the LEDGER_APPROVER_KEY variable.
+pub(crate) fn signer_from_config(config: &Config) -> Result<Option<Arc<dyn Signer>>, Box<dyn Error>> {
+    signer::from_config(config.approver_signer.as_ref(), config.approver_key_file.as_deref(), KEY_ENV, "approver")
+}
+
+pub(crate) fn check_policy(policy: &ApprovalPolicy) -> Result<(), Box<dyn Error>> {
+    if policy.threshold == 0 || policy.threshold > policy.approvers.len() {
+        return Err(format!("The approval threshold must be between 1 and the {} approvers", policy.approvers.len()).into());
+    }
+    let mut seen = HashSet::new();
+    for approver in &policy.approvers {
+        authority::verifying_key(approver)?;
+        if !seen.insert(approver) {
+            return Err(format!("Approver {} is listed twice", approver).into());
+        }
+    }
+    Ok(())
+}
+
+fn check_approval(policy: &ApprovalPolicy, hash: &str, approval: &Approval) -> Result<(), Box<dyn Error>> {
+    if !policy.approvers.contains(&approval.approver) {
+        return Err(format!("{} is not an approver of this chain", approval.approver).into());
+    }
+    let signature = hex::decode(&approval.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| format!("The approval of {} is not a valid signature", approval.approver))?;
+    if authority::verifying_key(&approval.approver)?.verify(hash.as_bytes(), &signature).is_err() {
+        return Err(format!("The approval of {} does not sign block {}", approval.approver, hash).into());
+    }
+    Ok(())
+}
+
+// Checks every approval a header carries, and that there are enough of them. Blocks
+// on chains without approvers may carry none.
+pub(crate) fn check_approvals(genesis: &GenesisConfig, header: &BlockHeader) -> Result<(), Box<dyn Error>> {
+    let Some(policy) = &genesis.approval else {
+        if !header.approvals.is_empty() {
+            return Err(format!("Block {} carries approvals, but this chain has no approvers", header.hash).into());
+        }
+        return Ok(());
+    };
+    let mut approvers = HashSet::new();
+    for approval in &header.approvals {
+        check_approval(policy, &header.hash, approval).map_err(|e| format!("Block {}: {}", header.hash, e))?;
+        if !approvers.insert(&approval.approver) {
+            return Err(format!("Block {} is approved twice by {}", header.hash, approval.approver).into());
+        }
+    }
+    if approvers.len() < policy.threshold {
+        return Err(format!(
+            "Block {} has {} of the {} approvals it needs",
+            header.hash, approvers.len(), policy.threshold
+        ).into());
+    }
+    Ok(())
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Public key (hex) of this node's approver, if it has one.
+    pub fn approver(&self) -> Option<String> {
+        self.approver_signer.as_ref().map(|signer| signer.public_key())
+    }
+
+    // Mines the next block like `add_block_with_metadata`, but holds it for approval
+    // instead of connecting it. Returns the proposal id. Once the tip moves on, the
+    // proposal can no longer be committed and has to be made again.
+    pub fn propose_block(
+        &self,
+        data: impl Into<Vec<u8>>,
+        content_type: Option<&str>,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<String, Box<dyn Error>> {
+        if self.genesis_config().approval.is_none() {
+            return Err("This chain has no approvers; add blocks directly".into());
+        }
+        let payload = Payload { data: data.into(), content_type: content_type.map(str::to_string), metadata };
+        self.shared.schemas.check(&payload.data, &payload.metadata)?;
+        let epoch = self.shared.mining_epoch.load(Ordering::Relaxed);
+        let (block, meta) = loop {
+            if let Some(mined) = self.mine_next(&payload, &[], epoch)? {
+                break mined;
+            }
+        };
+        let _writer = self.write_lock();
+        let mut batch = self.batch();
+        batch.insert(TreeId::Proposals, &block.hash, seal_block(&block, self.config.compression, self.trees.cipher.as_ref())?);
+        self.commit(batch)?;
+        info!(proposal = %block.hash, height = meta.height, "block proposed");
+        Ok(block.hash)
+    }
+
+    // Proposals waiting for approval, lowest height first.
+    pub fn proposals(&self) -> Result<Vec<Proposal>, Box<dyn Error>> {
+        let mut proposals = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::Proposals, &[]) {
+            let (_, bytes) = entry?;
+            proposals.push(self.to_proposal(open_block(&bytes, self.trees.cipher())?)?);
+        }
+        proposals.sort_by_key(|proposal| proposal.height);
+        Ok(proposals)
+    }
+
+    pub fn proposal(&self, id: &str) -> Result<Option<Proposal>, Box<dyn Error>> {
+        match self.store().get(TreeId::Proposals, id.as_bytes())? {
+            Some(bytes) => Ok(Some(self.to_proposal(open_block(&bytes, self.trees.cipher())?)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Drops a proposal. Returns false if there was none with that id.
+    pub fn discard_proposal(&self, id: &str) -> Result<bool, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        if self.store().get(TreeId::Proposals, id.as_bytes())?.is_none() {
+            return Ok(false);
+        }
+        let mut batch = self.batch();
+        batch.remove(TreeId::Proposals, id);
+        self.commit(batch)?;
+        Ok(true)
+    }
+
+    // Approves a proposal with this node's approver.
+    pub fn approve(&self, id: &str) -> Result<ApprovalOutcome, Box<dyn Error>> {
+        let signer = self
+            .approver_signer
+            .as_ref()
+            .ok_or_else(|| format!("Approving needs an approver key; set approver_key_file or {}", KEY_ENV))?;
+        self.add_approval(id, sign_approval(signer.as_ref(), id)?)
+    }
+
+    // Adds an approval made elsewhere (see `sign_approval`). Approving twice with the
+    // same key counts once. The approval that reaches the threshold connects the
+    // block, which must still build on the tip.
+    pub fn add_approval(&self, id: &str, approval: Approval) -> Result<ApprovalOutcome, Box<dyn Error>> {
+        let policy = self.genesis_config().approval.ok_or("This chain has no approvers")?;
+        let _writer = self.write_lock();
+        let mut proposal = self.proposal(id)?.ok_or_else(|| format!("No proposal {}", id))?;
+        check_approval(&policy, id, &approval)?;
+        let block = &mut proposal.block;
+        if !block.approvals.iter().any(|existing| existing.approver == approval.approver) {
+            debug!(proposal = %id, approver = %approval.approver, "approval added");
+            block.approvals.push(approval);
+        }
+        let approvals = block.approvals.len();
+
+        let mut batch = self.batch();
+        if approvals < policy.threshold {
+            batch.insert(TreeId::Proposals, id, seal_block(block, self.config.compression, self.trees.cipher.as_ref())?);
+            self.commit(batch)?;
+            return Ok(ApprovalOutcome::Pending { approvals, needed: policy.threshold });
+        }
+        let tip = self.current_hash();
+        if block.prev_hash != tip {
+            return Err(format!(
+                "Proposal {} builds on {}, but the tip has moved to {}; discard it and propose again",
+                id, block.prev_hash, tip
+            ).into());
+        }
+        let parent = batch.tip_meta()?;
+        let meta = BlockMeta { height: parent.height + 1, total_work: parent.total_work + block_work(block.difficulty) };
+        batch.remove(TreeId::Proposals, id);
+        self.connect_on_tip(batch, block, &meta)?;
+        info!(block = %id, height = meta.height, approvals, "proposal approved and connected");
+        Ok(ApprovalOutcome::Committed { height: meta.height })
+    }
+
+    fn to_proposal(&self, block: Block) -> Result<Proposal, Box<dyn Error>> {
+        let height = self.trees.block_meta(&block.prev_hash)?.map_or(0, |parent| parent.height + 1);
+        let needed = self.genesis_config().approval.map_or(0, |policy| policy.threshold);
+        Ok(Proposal { block, height, needed })
+    }
+}
@@ -0,0 +1,205 @@
+use std::error::Error;
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::block::Block;
+use crate::blockchain::{Blockchain, BlockStatus};
+#[cfg(feature = "sled")]
+use crate::config::Config;
+use crate::export::ExportFormat;
+#[cfg(feature = "sled")]
+use crate::genesis::GenesisConfig;
+use crate::state::Account;
+use crate::store::{BlockStore, DefaultStore};
+use crate::transaction::Transaction;
+use crate::txindex::TransactionInfo;
+use crate::validation::ValidationError;
+
+// Async variants of the blocking API for tokio-based services. Each call runs the
+// blocking method on a clone of the handle in tokio's blocking pool, so sled I/O and
+// mining never stall the runtime's worker threads. They need a tokio runtime.
+//
+// `Box<dyn Error>` is not `Send`, so errors cross back from the pool as their message.
+
+#[cfg(feature = "sled")]
+impl Blockchain {
+    pub async fn open_async(
+        path: &str,
+        expected_genesis: Option<&GenesisConfig>,
+        config: Config,
+    ) -> Result<Blockchain, Box<dyn Error>> {
+        let path = path.to_string();
+        let expected_genesis = expected_genesis.cloned();
+        run_blocking(move || Blockchain::open_with_config(&path, expected_genesis.as_ref(), config)).await
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    pub async fn add_block_async(&self, data: impl Into<Vec<u8>>) -> Result<String, Box<dyn Error>> {
+        let data = data.into();
+        self.spawn(move |chain| chain.add_block(data)).await
+    }
+
+    pub async fn add_document_async(&self, data: Vec<u8>, content_type: &str) -> Result<String, Box<dyn Error>> {
+        let content_type = content_type.to_string();
+        self.spawn(move |chain| chain.add_document(data, &content_type)).await
+    }
+
+    pub async fn add_block_with_transactions_async(
+        &self,
+        data: impl Into<Vec<u8>>,
+        transactions: Vec<Transaction>,
+    ) -> Result<String, Box<dyn Error>> {
+        let data = data.into();
+        self.spawn(move |chain| chain.add_block_with_transactions(data, transactions)).await
+    }
+
+    pub async fn put_blob_async(&self, content: Vec<u8>) -> Result<String, Box<dyn Error>> {
+        self.spawn(move |chain| chain.put_blob(&content)).await
+    }
+
+    pub async fn get_blob_async(&self, hash: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let hash = hash.to_string();
+        self.spawn(move |chain| chain.get_blob(&hash)).await
+    }
+
+    pub async fn receive_block_async(&self, block: Block) -> Result<BlockStatus, Box<dyn Error>> {
+        self.spawn(move |chain| chain.receive_block(block)).await
+    }
+
+    pub async fn add_block_if_absent_async(&self, block: Block) -> Result<BlockStatus, Box<dyn Error>> {
+        self.spawn(move |chain| chain.add_block_if_absent(block)).await
+    }
+
+    // The outer error only reports the blocking task failing; the verdict is inside.
+    pub async fn validate_candidate_async(&self, block: Block) -> Result<Result<(), ValidationError>, Box<dyn Error>> {
+        self.spawn(move |chain| Ok(chain.validate_candidate(&block))).await
+    }
+
+    pub async fn add_blocks_async(&self, blocks: Vec<Block>) -> Result<Vec<BlockStatus>, Box<dyn Error>> {
+        self.spawn(move |chain| chain.add_blocks(&blocks)).await
+    }
+
+    pub async fn submit_transaction_async(&self, transaction: Transaction) -> Result<String, Box<dyn Error>> {
+        self.spawn(move |chain| chain.submit_transaction(transaction)).await
+    }
+
+    pub async fn mine_block_async(&self, data: impl Into<Vec<u8>>) -> Result<String, Box<dyn Error>> {
+        let data = data.into();
+        self.spawn(move |chain| chain.mine_block(data)).await
+    }
+
+    pub async fn get_block_async(&self, hash: &str) -> Result<Option<Block>, Box<dyn Error>> {
+        let hash = hash.to_string();
+        self.spawn(move |chain| chain.get_block(&hash)).await
+    }
+
+    pub async fn get_blocks_range_async(&self, from_height: u64, to_height: u64) -> Result<Vec<Block>, Box<dyn Error>> {
+        self.spawn(move |chain| chain.get_blocks_range(from_height, to_height)).await
+    }
+
+    pub async fn get_blocks_since_async(&self, timestamp: u64) -> Result<Vec<Block>, Box<dyn Error>> {
+        self.spawn(move |chain| chain.get_blocks_since(timestamp)).await
+    }
+
+    pub async fn get_blocks_after_sequence_async(&self, sequence: u64) -> Result<Vec<Block>, Box<dyn Error>> {
+        self.spawn(move |chain| chain.get_blocks_after_sequence(sequence)).await
+    }
+
+    pub async fn get_account_async(&self, address: &str) -> Result<Account, Box<dyn Error>> {
+        let address = address.to_string();
+        self.spawn(move |chain| chain.get_account(&address)).await
+    }
+
+    pub async fn get_account_at_async(&self, address: &str, height: u64) -> Result<Account, Box<dyn Error>> {
+        let address = address.to_string();
+        self.spawn(move |chain| chain.get_account_at(&address, height)).await
+    }
+
+    pub async fn state_root_at_async(&self, height: u64) -> Result<String, Box<dyn Error>> {
+        self.spawn(move |chain| chain.state_root_at(height)).await
+    }
+
+    pub async fn get_transaction_async(&self, txid: &str) -> Result<Option<TransactionInfo>, Box<dyn Error>> {
+        let txid = txid.to_string();
+        self.spawn(move |chain| chain.get_transaction(&txid)).await
+    }
+
+    // Holds a blocking-pool thread while it waits.
+    pub async fn wait_for_confirmations_async(
+        &self,
+        txid: &str,
+        confirmations: u64,
+        timeout: Duration,
+    ) -> Result<TransactionInfo, Box<dyn Error>> {
+        let txid = txid.to_string();
+        self.spawn(move |chain| chain.wait_for_confirmations(&txid, confirmations, timeout)).await
+    }
+
+    pub async fn height_async(&self) -> Result<u64, Box<dyn Error>> {
+        self.spawn(|chain| chain.height()).await
+    }
+
+    pub async fn is_chain_valid_async(&self) -> Result<bool, Box<dyn Error>> {
+        self.spawn(|chain| chain.is_chain_valid()).await
+    }
+
+    // Takes the whole file in memory, since the reader would have to cross threads.
+    pub async fn import_async(&self, bytes: Vec<u8>, format: ExportFormat) -> Result<usize, Box<dyn Error>> {
+        self.spawn(move |chain| chain.import(Cursor::new(bytes), format)).await
+    }
+
+    // Canonical blocks from `height` upwards, one blocking read per block.
+    pub fn stream_blocks(&self, height: u64) -> BlockStream<S> {
+        BlockStream { chain: self.clone(), height }
+    }
+
+    pub(crate) async fn spawn<T: Send + 'static>(
+        &self,
+        call: impl FnOnce(Blockchain<S>) -> Result<T, Box<dyn Error>> + Send + 'static,
+    ) -> Result<T, Box<dyn Error>> {
+        let chain = self.clone();
+        run_blocking(move || call(chain)).await
+    }
+}
+
+// An async iterator over canonical blocks. It follows the tip as it moves: `next`
+// returns `None` once it has caught up, and yields again after more blocks arrive.
+pub struct BlockStream<S = DefaultStore> {
+    chain: Blockchain<S>,
+    height: u64,
+}
+
+impl<S: BlockStore> BlockStream<S> {
+    pub async fn next(&mut self) -> Option<Result<Block, Box<dyn Error>>> {
+        let height = self.height;
+        let block = self
+            .chain
+            .spawn(move |chain| match chain.canonical_hash(height)? {
+                Some(hash) => chain.get_block(&hash),
+                None => Ok(None),
+            })
+            .await;
+        match block {
+            Ok(Some(block)) => {
+                self.height += 1;
+                Some(Ok(block))
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    // Height of the block the next call to `next` returns.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+}
+
+async fn run_blocking<T: Send + 'static>(
+    call: impl FnOnce() -> Result<T, Box<dyn Error>> + Send + 'static,
+) -> Result<T, Box<dyn Error>> {
+    tokio::task::spawn_blocking(move || call().map_err(|e| e.to_string()))
+        .await?
+        .map_err(Into::into)
+}
@@ -0,0 +1,198 @@
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::config;
+#[cfg(feature = "sled")]
+use crate::config::Config;
+#[cfg(feature = "sled")]
+use crate::genesis::GenesisConfig;
+use crate::store::{BlockStore, DefaultStore};
+
+// Read when the config names no key file.
+pub const KEY_ENV: &str = "LEDGER_AUDIT_KEY";
+
+// Metadata keys of an audit entry's block.
+const ACTOR: &str = "actor";
+const ACTION: &str = "action";
+const RESOURCE: &str = "resource";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditEntry {
+    pub height: u64,
+    pub block: String,
+    pub timestamp: u64,
+    pub actor: String,
+    pub action: String,
+    pub resource: String,
+    pub details: String,
+}
+
+// One place where the chain is not what it should be.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TamperFinding {
+    pub height: u64,
+    // The hash the chain expects at this height; empty when even that is unknown.
+    pub block: String,
+    pub problem: String,
+}
+
+// Result of `AuditLedger::verify`. The signature is an HMAC-SHA256, keyed with the
+// audit key, over the JSON of the report with an empty signature, so a report
+// cannot be edited after the fact by anyone without the key. It stays empty when
+// the ledger has no key.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TamperReport {
+    pub generated_at: u64,
+    pub tip: String,
+    pub height: u64,
+    pub blocks_checked: u64,
+    pub intact: bool,
+    pub findings: Vec<TamperFinding>,
+    pub signature: String,
+}
+
+// The chain used as an append-only audit log. Only appending and reading are exposed,
+// and a config that would drop history (pruning) is refused.
+pub struct AuditLedger<S = DefaultStore> {
+    chain: Blockchain<S>,
+    key: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "sled")]
+impl AuditLedger {
+    pub fn open(path: &str, genesis: Option<&GenesisConfig>, config: Config) -> Result<AuditLedger, Box<dyn Error>> {
+        AuditLedger::new(Blockchain::open_with_config(path, genesis, config)?)
+    }
+}
+
+impl<S: BlockStore> AuditLedger<S> {
+    // The key is hex, from `Config::audit_key_file` or else the LEDGER_AUDIT_KEY
+    // variable. Without one, entries can still be appended and verified, but reports
+    // go unsigned and cannot be checked.
+    pub fn new(chain: Blockchain<S>) -> Result<AuditLedger<S>, Box<dyn Error>> {
+        if chain.config.prune_depth > 0 {
+            return Err("An audit ledger keeps every entry; set prune_depth to 0".into());
+        }
+        let key = config::read_key(chain.config.audit_key_file.as_deref(), KEY_ENV, "audit")?;
+        Ok(AuditLedger { chain, key })
+    }
+
+    // Appends one entry as its own block. Returns the block hash.
+    pub fn append(&self, actor: &str, action: &str, resource: &str, details: &str) -> Result<String, Box<dyn Error>> {
+        let metadata = BTreeMap::from([
+            (ACTOR.to_string(), actor.to_string()),
+            (ACTION.to_string(), action.to_string()),
+            (RESOURCE.to_string(), resource.to_string()),
+        ]);
+        self.chain.add_block_with_metadata(details, None, metadata)?;
+        Ok(self.chain.current_hash())
+    }
+
+    // Every entry on the canonical chain, oldest first.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+        let mut entries = Vec::new();
+        for block in self.chain.find_blocks(|block| block.metadata.contains_key(ACTION))? {
+            let height = self.chain.block_meta(&block.hash)?.map_or(0, |meta| meta.height);
+            entries.push(entry(block, height));
+        }
+        Ok(entries)
+    }
+
+    // Walks the whole canonical chain from genesis and lists every block that is
+    // missing, altered, stored under the wrong hash or linked to the wrong parent,
+    // instead of stopping at the first one like `is_chain_valid`.
+    pub fn verify(&self) -> Result<TamperReport, Box<dyn Error>> {
+        let tip = self.chain.current_hash();
+        let height = self.chain.height()?;
+        let mut findings = Vec::new();
+        let mut finding = |height, block: &str, problem: String| {
+            findings.push(TamperFinding { height, block: block.to_string(), problem });
+        };
+
+        let mut parent: Option<String> = None;
+        for h in 0..=height {
+            let Some(hash) = self.chain.canonical_hash(h)? else {
+                finding(h, "", "no block recorded at this height".to_string());
+                parent = None;
+                continue;
+            };
+            match self.chain.load_block(&hash) {
+                Ok(Some(block)) => {
+                    if block.hash != hash {
+                        finding(h, &hash, format!("stored record holds block {}", block.hash));
+                    } else if !self.chain.is_pruned(&hash)? && block.hash != block.calculate_hash() {
+                        finding(h, &hash, format!("contents altered, the block now hashes to {}", block.calculate_hash()));
+                    }
+                    match &parent {
+                        Some(parent) if block.prev_hash != *parent => {
+                            finding(h, &hash, format!("links to {} instead of {}", block.prev_hash, parent));
+                        }
+                        None if h == 0 && !block.is_genesis() => {
+                            finding(h, &hash, "first block is not a genesis block".to_string());
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(None) => finding(h, &hash, "block missing".to_string()),
+                Err(e) => finding(h, &hash, format!("unreadable record: {}", e)),
+            }
+            parent = Some(hash);
+        }
+        if parent.as_deref() != Some(tip.as_str()) {
+            finding(height, &tip, "the tip is not the last block of the chain".to_string());
+        }
+
+        let mut report = TamperReport {
+            generated_at: self.chain.clock.now_ms(),
+            tip,
+            height,
+            blocks_checked: height + 1,
+            intact: findings.is_empty(),
+            findings,
+            signature: String::new(),
+        };
+        if self.key.is_some() {
+            report.signature = hex::encode(self.mac(&report)?.finalize().into_bytes());
+        }
+        Ok(report)
+    }
+
+    // Whether `report` was signed with this ledger's key and left unchanged since.
+    pub fn check_report(&self, report: &TamperReport) -> Result<bool, Box<dyn Error>> {
+        let signature = hex::decode(&report.signature).unwrap_or_default();
+        let unsigned = TamperReport { signature: String::new(), ..report.clone() };
+        Ok(self.mac(&unsigned)?.verify_slice(&signature).is_ok())
+    }
+
+    pub fn chain(&self) -> &Blockchain<S> {
+        &self.chain
+    }
+
+    fn mac(&self, report: &TamperReport) -> Result<Hmac<Sha256>, Box<dyn Error>> {
+        let key = self
+            .key
+            .as_ref()
+            .ok_or_else(|| format!("Reports are signed with the audit key; set audit_key_file or {}", KEY_ENV))?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)?;
+        mac.update(&serde_json::to_vec(report)?);
+        Ok(mac)
+    }
+}
+
+fn entry(block: Block, height: u64) -> AuditEntry {
+    let tag = |key: &str| block.metadata.get(key).cloned().unwrap_or_default();
+    AuditEntry {
+        height,
+        timestamp: block.timestamp,
+        actor: tag(ACTOR),
+        action: tag(ACTION),
+        resource: tag(RESOURCE),
+        details: block.data_summary(),
+        block: block.hash,
+    }
+}
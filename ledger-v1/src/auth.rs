@@ -0,0 +1,109 @@
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+
+use crate::blockchain::Blockchain;
+use crate::store::BlockStore;
+use tracing::debug;
+
+// Access control for the gRPC, WebSocket and metrics servers. Clients present an API
+// key as "Authorization: Bearer <key>" (gRPC metadata or an HTTP header); the key's
+// role decides which calls it may make. Without `Config::api_keys` every call is
+// allowed, as before keys existed.
+
+// Each role may do everything the ones before it may.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // Reading blocks, headers, peers and metrics, and following the chain.
+    ReadOnly,
+    // Submitting transactions.
+    Submitter,
+    // Maintenance: snapshots and repair.
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Role::ReadOnly => "read_only",
+            Role::Submitter => "submitter",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+// The config holds only the key's SHA-256 (hex), so reading the config file does not
+// give the key away. `generate_api_key` makes both.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiKey {
+    // Who holds the key, for the logs.
+    pub name: String,
+    pub sha256: String,
+    pub role: Role,
+}
+
+// Why a call was refused. Returned boxed like any other error where it crosses the
+// `Box<dyn Error>` API.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    // No key, or one that is not configured.
+    Unauthenticated,
+    // A known key whose role is below the one the call needs.
+    Forbidden { name: String, role: Role, needed: Role },
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Unauthenticated => write!(f, "A valid API key is required"),
+            AuthError::Forbidden { name, role, needed } => {
+                write!(f, "API key {} has the {} role, but this call needs {}", name, role, needed)
+            }
+        }
+    }
+}
+
+impl Error for AuthError {}
+
+// A new random key (hex) and the SHA-256 to configure for it.
+pub fn generate_api_key() -> (String, String) {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    let key = hex::encode(key);
+    let sha256 = hex::encode(Sha256::digest(key.as_bytes()));
+    (key, sha256)
+}
+
+// The key in an "Authorization: Bearer <key>" header value.
+pub fn bearer(header: &str) -> Option<&str> {
+    let (scheme, key) = header.trim().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| key.trim())
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Checks that `key` (from `bearer`) may make a call needing `needed`. Callers
+    // without a key get `Config::anonymous_role`, if it is set.
+    pub fn authorize(&self, key: Option<&str>, needed: Role) -> Result<(), AuthError> {
+        let keys = &self.config.api_keys;
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let (name, role) = match key {
+            Some(key) => {
+                let sha256 = hex::encode(Sha256::digest(key.as_bytes()));
+                let api_key = keys.iter().find(|api_key| api_key.sha256.eq_ignore_ascii_case(&sha256)).ok_or(AuthError::Unauthenticated)?;
+                (api_key.name.as_str(), api_key.role)
+            }
+            None => ("anonymous", self.config.anonymous_role.ok_or(AuthError::Unauthenticated)?),
+        };
+        if role < needed {
+            debug!(key = name, %role, %needed, "call refused");
+            return Err(AuthError::Forbidden { name: name.to_string(), role, needed });
+        }
+        Ok(())
+    }
+}
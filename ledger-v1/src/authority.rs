@@ -0,0 +1,114 @@
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::genesis::Consensus;
+use crate::header::BlockHeader;
+use crate::signer::{self, Signer};
+use crate::store::BlockStore;
+
+// Proof of authority (see `genesis::Consensus`): the block at height h is signed by
+// authority (h - 1) mod n, with its ed25519 signature over the block hash.
+
+// Read when the config names no key file.
+pub const KEY_ENV: &str = "LEDGER_AUTHORITY_KEY";
+
+// A new secret key and its public key, both hex.
+pub fn generate_key() -> (String, String) {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    key_pair(secret)
+}
+
+// Like `generate_key`, but the same key for the same seed, so tests and examples
+// can sign reproducibly. Anyone knowing the seed has the key: never use one on a
+// real chain.
+pub fn generate_key_from_seed(seed: u64) -> (String, String) {
+    let mut hasher = Sha256::new();
+    hasher.update(b"ledger-v1 test key");
+    hasher.update(seed.to_be_bytes());
+    key_pair(hasher.finalize().into())
+}
+
+fn key_pair(secret: [u8; 32]) -> (String, String) {
+    let key = SigningKey::from_bytes(&secret);
+    (hex::encode(secret), hex::encode(key.verifying_key().as_bytes()))
+}
+
+// This node's signer: `Config::authority_signer`, or else the key (32 secret bytes,
+// hex) from `Config::authority_key_file` or the LEDGER_AUTHORITY_KEY variable.
+pub(crate) fn signer_from_config(config: &Config) -> Result<Option<Arc<dyn Signer>>, Box<dyn Error>> {
+    signer::from_config(config.authority_signer.as_ref(), config.authority_key_file.as_deref(), KEY_ENV, "authority")
+}
+
+pub(crate) fn check_authorities(authorities: &[String]) -> Result<(), Box<dyn Error>> {
+    if authorities.is_empty() {
+        return Err("Proof of authority needs at least one authority".into());
+    }
+    for authority in authorities {
+        verifying_key(authority)?;
+    }
+    Ok(())
+}
+
+// The authority whose turn it is at `height`.
+pub fn scheduled(authorities: &[String], height: u64) -> Option<&str> {
+    if authorities.is_empty() || height == 0 {
+        return None;
+    }
+    Some(&authorities[((height - 1) % authorities.len() as u64) as usize])
+}
+
+// Checks that the authority scheduled for `height` signed the header.
+pub(crate) fn check_signature(authorities: &[String], header: &BlockHeader, height: u64) -> Result<(), Box<dyn Error>> {
+    let authority = scheduled(authorities, height).ok_or("No authority is scheduled for the genesis block")?;
+    let signature = hex::decode(&header.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| format!("Block {} carries no valid authority signature", header.hash))?;
+    if verifying_key(authority)?.verify(header.hash.as_bytes(), &signature).is_err() {
+        return Err(format!("Block {} at height {} is not signed by its authority {}", header.hash, height, authority).into());
+    }
+    Ok(())
+}
+
+pub(crate) fn verifying_key(authority: &str) -> Result<VerifyingKey, Box<dyn Error>> {
+    let bytes: [u8; 32] = hex::decode(authority)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("Authority {} is not a hex-encoded ed25519 public key", authority))?;
+    Ok(VerifyingKey::from_bytes(&bytes).map_err(|_| format!("Authority {} is not a valid ed25519 public key", authority))?)
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Public key (hex) of this node's authority key, if it has one.
+    pub fn authority(&self) -> Option<String> {
+        self.authority_signer.as_ref().map(|signer| signer.public_key())
+    }
+
+    // On proof-of-authority chains, signs a finished block with the local signer,
+    // whose key has to be the one scheduled for `height`. Other chains leave the
+    // block alone.
+    pub(crate) fn sign_block(&self, block: &mut Block, height: u64) -> Result<(), Box<dyn Error>> {
+        let Consensus::ProofOfAuthority { authorities } = self.genesis_config().consensus else {
+            return Ok(());
+        };
+        let signer = self
+            .authority_signer
+            .as_ref()
+            .ok_or_else(|| format!("Blocks on this chain are signed by an authority; set authority_key_file or {}", KEY_ENV))?;
+        let local = signer.public_key();
+        let turn = scheduled(&authorities, height).unwrap_or_default();
+        if local != turn {
+            return Err(format!("Height {} is for authority {}, not this node ({})", height, turn, local).into());
+        }
+        block.signature = hex::encode(signer.sign(block.hash.as_bytes())?.to_bytes());
+        Ok(())
+    }
+}
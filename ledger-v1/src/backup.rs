@@ -0,0 +1,95 @@
+use serde::Serialize;
+use std::error::Error;
+use std::path::Path;
+
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::store::{BlockStore, SledStore, TreeId, Writes};
+
+// Copying the database directory of a running node can catch sled halfway through a
+// write. A backup is instead a new database holding every record of the chain, copied
+// while writes are held off, so it is consistent even while the node keeps serving.
+// Block records are copied as stored: an encrypted chain restores only with its key.
+
+// Records written to the copy per transaction.
+const COPY_BATCH: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub height: u64,
+    pub tip: String,
+    pub records: u64,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Writes a backup to a new database at `dest`. Blocks can't be added meanwhile;
+    // reads go on as usual.
+    pub fn backup(&self, dest: &str) -> Result<BackupInfo, Box<dyn Error>> {
+        if Path::new(dest).exists() {
+            return Err(format!("{} already exists; back up to a new path", dest).into());
+        }
+        let _writer = self.write_lock();
+        let target = SledStore::open(dest)?;
+        let records = copy_records(self.store(), &target)?;
+        target.flush()?;
+        Ok(BackupInfo { height: self.height()?, tip: self.current_hash(), records })
+    }
+}
+
+impl Blockchain {
+    // Creates the database at `dest` from the backup at `src`. The backup is checked
+    // record by record and link by link first, and the restored chain has to end at
+    // the same tip and validate; otherwise nothing is left at `dest`.
+    pub fn restore_backup(src: &str, dest: &str, config: Config) -> Result<BackupInfo, Box<dyn Error>> {
+        if Path::new(dest).exists() {
+            return Err(format!("{} already exists; restore to a new path", dest).into());
+        }
+        let backup = Blockchain::open_read_only_with_config(src, config.clone())?;
+        let report = backup.deep_validate()?;
+        if !report.is_consistent() {
+            return Err(format!("The backup at {} has {} damaged records", src, report.findings.len()).into());
+        }
+        if !backup.is_chain_valid()? {
+            return Err(format!("The backup at {} does not hold a valid chain", src).into());
+        }
+
+        let restored = restore_into(&backup, dest, config);
+        if restored.is_err() {
+            let _ = std::fs::remove_dir_all(dest);
+        }
+        restored
+    }
+}
+
+fn restore_into<S: BlockStore>(backup: &Blockchain<S>, dest: &str, config: Config) -> Result<BackupInfo, Box<dyn Error>> {
+    // The copy is closed before it is opened as a chain, since sled locks the directory.
+    let records = {
+        let target = SledStore::open(dest)?;
+        let records = copy_records(backup.store(), &target)?;
+        target.flush()?;
+        records
+    };
+    let restored = Blockchain::open_with_config(dest, None, config)?;
+    if restored.current_hash() != backup.current_hash() || !restored.is_chain_valid()? {
+        return Err(format!("The chain restored to {} does not match the backup", dest).into());
+    }
+    Ok(BackupInfo { height: restored.height()?, tip: restored.current_hash(), records })
+}
+
+pub(crate) fn copy_records(from: &impl BlockStore, to: &impl BlockStore) -> Result<u64, Box<dyn Error>> {
+    let mut writes = Writes::new();
+    let mut records = 0;
+    for tree in TreeId::ALL {
+        for entry in from.scan_prefix(tree, &[]) {
+            let (key, value) = entry?;
+            writes.insert((tree, key), Some(value));
+            records += 1;
+            if writes.len() >= COPY_BATCH {
+                to.apply(&writes)?;
+                writes.clear();
+            }
+        }
+    }
+    to.apply(&writes)?;
+    Ok(records)
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::block::{Block, BlockView};
+use crate::blockchain::BlockMeta;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::encoding::{open_block, open_header, view_block};
+use crate::encryption::BlockCipher;
+use crate::genesis::GenesisConfig;
+use crate::header::BlockHeader;
+use crate::hooks::Validator;
+use crate::state::Account;
+use crate::store::{BlockStore, TreeId, Writes};
+
+// The store a chain handle reads and writes, with the cipher block records are
+// sealed with.
+#[derive(Clone)]
+pub(crate) struct Trees<S> {
+    pub(crate) store: S,
+    pub(crate) cipher: Option<BlockCipher>,
+}
+
+// Typed point reads, shared by the stored trees and batches staged on top of them.
+pub(crate) trait ReadTrees {
+    fn get(&self, tree: TreeId, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+
+    fn cipher(&self) -> Option<&BlockCipher>;
+
+    // `BlockStore::get_with`.
+    fn get_with<T>(
+        &self,
+        tree: TreeId,
+        key: &[u8],
+        read: impl FnOnce(&[u8]) -> Result<T, Box<dyn Error>>,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        self.get(tree, key)?.map(|bytes| read(&bytes)).transpose()
+    }
+
+    fn contains(&self, tree: TreeId, key: &[u8]) -> Result<bool, Box<dyn Error>> {
+        Ok(self.get(tree, key)?.is_some())
+    }
+
+    fn load_block(&self, hash: &str) -> Result<Option<Block>, Box<dyn Error>> {
+        self.get_with(TreeId::Blocks, hash.as_bytes(), |bytes| open_block(bytes, self.cipher()))
+    }
+
+    // Hands `read` the block as a view into its stored record, which is decoded in
+    // place unless it is compressed, encrypted or legacy JSON (see `view_block`).
+    // For checks that read a block once and keep little of it.
+    fn read_block<T>(
+        &self,
+        hash: &str,
+        read: impl FnOnce(&BlockView) -> Result<T, Box<dyn Error>>,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        self.get_with(TreeId::Blocks, hash.as_bytes(), |bytes| view_block(bytes, self.cipher(), read))
+    }
+
+    // Reads only the header record, so the body is never decoded.
+    fn load_header(&self, hash: &str) -> Result<Option<BlockHeader>, Box<dyn Error>> {
+        self.get_with(TreeId::Headers, hash.as_bytes(), |bytes| open_header(bytes, self.cipher()))
+    }
+
+    fn block_meta(&self, hash: &str) -> Result<Option<BlockMeta>, Box<dyn Error>> {
+        self.get_with(TreeId::Meta, hash.as_bytes(), |bytes| Ok(serde_json::from_slice(bytes)?))
+    }
+
+    fn canonical_hash(&self, height: u64) -> Result<Option<String>, Box<dyn Error>> {
+        match self.get(TreeId::Heights, &height.to_be_bytes())? {
+            Some(bytes) => Ok(Some(String::from_utf8(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn canonical_block(&self, height: u64) -> Result<Block, Box<dyn Error>> {
+        let hash = self
+            .canonical_hash(height)?
+            .ok_or_else(|| format!("No canonical block at height {}", height))?;
+        self.load_block(&hash)?
+            .ok_or_else(|| format!("Broken link! Could not find block: {}", hash).into())
+    }
+
+    fn is_invalid(&self, hash: &str) -> Result<bool, Box<dyn Error>> {
+        self.contains(TreeId::Invalid, hash.as_bytes())
+    }
+
+    fn is_pruned(&self, hash: &str) -> Result<bool, Box<dyn Error>> {
+        self.contains(TreeId::Pruned, hash.as_bytes())
+    }
+
+    fn load_account(&self, address: &str) -> Result<Option<Account>, Box<dyn Error>> {
+        match self.get(TreeId::State, address.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<S: BlockStore> ReadTrees for Trees<S> {
+    fn get(&self, tree: TreeId, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        self.store.get(tree, key)
+    }
+
+    fn get_with<T>(
+        &self,
+        tree: TreeId,
+        key: &[u8],
+        read: impl FnOnce(&[u8]) -> Result<T, Box<dyn Error>>,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        self.store.get_with(tree, key, read)
+    }
+
+    fn cipher(&self) -> Option<&BlockCipher> {
+        self.cipher.as_ref()
+    }
+}
+
+// Writes staged for one atomic commit across all trees. Reads through the batch see
+// the staged writes first, so a multi-block update builds on its own earlier steps,
+// and dropping the batch discards it without touching the database.
+pub(crate) struct ChainBatch<S> {
+    trees: Trees<S>,
+    writes: Writes,
+    // The tip and genesis config as they will be after the commit.
+    pub(crate) tip: String,
+    pub(crate) genesis: GenesisConfig,
+    pub(crate) config: Config,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) validators: Vec<Arc<dyn Validator>>,
+    // Blocks on a branch that failed to connect. The caller records them as invalid
+    // even though the batch itself is dropped.
+    pub(crate) rejected: Vec<String>,
+    // Which multi-step operation the batch carries out, if any. Such batches are
+    // journaled when committed.
+    pub(crate) operation: Option<&'static str>,
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    pub(crate) fn new(
+        trees: Trees<S>,
+        tip: String,
+        genesis: GenesisConfig,
+        config: Config,
+        clock: Arc<dyn Clock>,
+        validators: Vec<Arc<dyn Validator>>,
+    ) -> ChainBatch<S> {
+        ChainBatch { trees, writes: Writes::new(), tip, genesis, config, clock, validators, rejected: Vec::new(), operation: None }
+    }
+
+    pub(crate) fn insert(&mut self, tree: TreeId, key: impl AsRef<[u8]>, value: impl Into<Vec<u8>>) {
+        self.writes.insert((tree, key.as_ref().to_vec()), Some(value.into()));
+    }
+
+    pub(crate) fn remove(&mut self, tree: TreeId, key: impl AsRef<[u8]>) {
+        self.writes.insert((tree, key.as_ref().to_vec()), None);
+    }
+
+    // Stages removal of every record in `tree`, stored or staged.
+    pub(crate) fn clear(&mut self, tree: TreeId) -> Result<(), Box<dyn Error>> {
+        let staged: Vec<Vec<u8>> = self
+            .writes
+            .keys()
+            .filter(|(staged_tree, _)| *staged_tree == tree)
+            .map(|(_, key)| key.clone())
+            .collect();
+        let stored: Vec<Vec<u8>> = self
+            .trees
+            .store
+            .scan_prefix(tree, &[])
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<_, _>>()?;
+        for key in staged.into_iter().chain(stored) {
+            self.remove(tree, key);
+        }
+        Ok(())
+    }
+
+    // Every record in `tree` as it will be after the commit.
+    pub(crate) fn scan(&self, tree: TreeId) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, Box<dyn Error>> {
+        let mut records = BTreeMap::new();
+        for entry in self.trees.store.scan_prefix(tree, &[]) {
+            let (key, value) = entry?;
+            records.insert(key, value);
+        }
+        for ((staged_tree, key), staged) in &self.writes {
+            if *staged_tree != tree {
+                continue;
+            }
+            match staged {
+                Some(value) => records.insert(key.clone(), value.clone()),
+                None => records.remove(key),
+            };
+        }
+        Ok(records)
+    }
+
+    pub(crate) fn writes(&self) -> &Writes {
+        &self.writes
+    }
+
+    // Applies every staged write atomically.
+    pub(crate) fn commit(&self) -> Result<(), Box<dyn Error>> {
+        self.trees.store.apply(&self.writes)
+    }
+}
+
+impl<S: BlockStore> ReadTrees for ChainBatch<S> {
+    fn get(&self, tree: TreeId, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match self.writes.get(&(tree, key.to_vec())) {
+            Some(staged) => Ok(staged.clone()),
+            None => self.trees.get(tree, key),
+        }
+    }
+
+    fn get_with<T>(
+        &self,
+        tree: TreeId,
+        key: &[u8],
+        read: impl FnOnce(&[u8]) -> Result<T, Box<dyn Error>>,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        match self.writes.get(&(tree, key.to_vec())) {
+            Some(staged) => staged.as_deref().map(read).transpose(),
+            None => self.trees.get_with(tree, key, read),
+        }
+    }
+
+    fn cipher(&self) -> Option<&BlockCipher> {
+        self.trees.cipher.as_ref()
+    }
+}
@@ -0,0 +1,172 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::blockchain::Blockchain;
+use crate::encoding::{is_block_key, view_block};
+use crate::gc::GcReport;
+use crate::hashing;
+use crate::store::{BlockStore, TreeId};
+use tracing::debug;
+
+// Attachments: documents kept next to the chain instead of in block bodies. Each is
+// stored once, as a blob keyed by the hash of its content (with the chain's hash
+// algorithm), and a block refers to it from its metadata as "attachment:NAME" =
+// HASH. The block hash commits to the metadata, so a block pins the exact bytes of
+// its attachments without carrying them.
+//
+// Blobs are sealed with the node's encryption key like block records. `deep_validate`
+// checks that every attachment of a block is stored and intact, and `gc` removes
+// blobs no block refers to any more, those of pruned blocks included, once they are
+// older than `Config::blob_grace_ms`. Peers sync blocks only, so a block received
+// from one refers to blobs that have to be copied over separately.
+//
+// A record is the upload time (milliseconds, big-endian), one envelope byte, then
+// the content.
+
+pub const ATTACHMENT_PREFIX: &str = "attachment:";
+
+const PLAIN: u8 = 0;
+const SEALED: u8 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlobInfo {
+    pub hash: String,
+    // Bytes in the store, which for a sealed blob is a little more than its content.
+    pub stored_bytes: u64,
+    pub uploaded_at: u64,
+}
+
+// Attachment names and blob hashes in a block's metadata.
+pub(crate) fn attachments<'a>(metadata: impl IntoIterator<Item = (&'a str, &'a str)>) -> BTreeMap<&'a str, &'a str> {
+    metadata.into_iter().filter_map(|(key, hash)| Some((key.strip_prefix(ATTACHMENT_PREFIX)?, hash))).collect()
+}
+
+fn uploaded_at(record: &[u8]) -> u64 {
+    record.get(..8).and_then(|bytes| bytes.try_into().ok()).map_or(0, u64::from_be_bytes)
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Stores `content` and returns its hash, for a block to refer to. Storing the same
+    // content again only renews its upload time.
+    pub fn put_blob(&self, content: &[u8]) -> Result<String, Box<dyn Error>> {
+        self.check_open()?;
+        let hash = hashing::data_hash(self.genesis_config().hash_algorithm, content);
+        let mut record = self.clock.now_ms().to_be_bytes().to_vec();
+        match self.trees.cipher() {
+            Some(cipher) => {
+                record.push(SEALED);
+                record.extend(cipher.seal(content)?);
+            }
+            None => {
+                record.push(PLAIN);
+                record.extend_from_slice(content);
+            }
+        }
+        self.store().insert(TreeId::Blobs, hash.as_bytes(), record)?;
+        debug!(%hash, bytes = content.len(), "blob stored");
+        Ok(hash)
+    }
+
+    // The content stored under `hash`. Fails if it no longer hashes to `hash`.
+    pub fn get_blob(&self, hash: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match self.store().get(TreeId::Blobs, hash.as_bytes())? {
+            Some(record) => self.open_blob(hash, &record).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn open_blob(&self, hash: &str, record: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let content = match record.get(8..).and_then(|rest| rest.split_first()) {
+            Some((&PLAIN, content)) => content.to_vec(),
+            Some((&SEALED, sealed)) => {
+                let cipher = self.trees.cipher().ok_or_else(|| format!("Blob {} is encrypted, but no encryption key is configured", hash))?;
+                cipher.open(sealed)?
+            }
+            Some((other, _)) => return Err(format!("Blob {} has unknown encoding {:#04x}", hash, other).into()),
+            None => return Err(format!("Blob {} is truncated", hash).into()),
+        };
+        let actual = hashing::data_hash(self.genesis_config().hash_algorithm, &content);
+        if actual != hash {
+            return Err(format!("Blob {} is damaged: its content hashes to {}", hash, actual).into());
+        }
+        Ok(content)
+    }
+
+    // Every stored blob, by hash.
+    pub fn list_blobs(&self) -> Result<Vec<BlobInfo>, Box<dyn Error>> {
+        let mut blobs = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::Blobs, &[]) {
+            let (key, record) = entry?;
+            blobs.push(BlobInfo { hash: String::from_utf8(key)?, stored_bytes: record.len() as u64, uploaded_at: uploaded_at(&record) });
+        }
+        Ok(blobs)
+    }
+
+    // Appends a block like `add_block_with_metadata`, with each of `attachments`
+    // stored as a blob and referred to by its name.
+    pub fn add_block_with_attachments(
+        &self,
+        data: impl Into<Vec<u8>>,
+        content_type: Option<&str>,
+        mut metadata: BTreeMap<String, String>,
+        attachments: BTreeMap<String, Vec<u8>>,
+    ) -> Result<String, Box<dyn Error>> {
+        for (name, content) in attachments {
+            let hash = self.put_blob(&content)?;
+            metadata.insert(format!("{}{}", ATTACHMENT_PREFIX, name), hash);
+        }
+        self.add_block_with_metadata(data, content_type, metadata)
+    }
+
+    // Attachment `name` of block `block_hash`, or None if the block has none by that
+    // name. Fails if the block refers to a blob that is missing or damaged.
+    pub fn get_attachment(&self, block_hash: &str, name: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let block = self.get_block(block_hash)?.ok_or_else(|| format!("Unknown block {}", block_hash))?;
+        let Some(hash) = block.attachments().get(name).map(|hash| hash.to_string()) else {
+            return Ok(None);
+        };
+        match self.get_blob(&hash)? {
+            Some(content) => Ok(Some(content)),
+            None => Err(format!("Attachment {} of block {} is missing (blob {})", name, block_hash, hash).into()),
+        }
+    }
+
+    // Stages the removal of blobs past the grace period that no block refers to,
+    // leaving out pruned blocks and those `report` removes.
+    pub(crate) fn collect_blobs(&self, batch: &mut ChainBatch<S>, report: &mut GcReport) -> Result<(), Box<dyn Error>> {
+        if self.store().is_empty(TreeId::Blobs)? {
+            return Ok(());
+        }
+        let removed: HashSet<&str> = report.removed.iter().map(String::as_str).collect();
+        let mut referenced = HashSet::new();
+        for entry in self.store().scan_prefix(TreeId::Blocks, &[]) {
+            let (key, record) = entry?;
+            if !is_block_key(&key) {
+                continue;
+            }
+            let hash = std::str::from_utf8(&key)?;
+            if removed.contains(hash) || self.is_pruned(hash)? {
+                continue;
+            }
+            view_block(&record, self.trees.cipher(), |view| {
+                referenced.extend(view.attachments().into_values().map(str::to_string));
+                Ok(())
+            })?;
+        }
+
+        let cutoff = self.clock.now_ms().saturating_sub(self.config.blob_grace_ms);
+        for entry in self.store().scan_prefix(TreeId::Blobs, &[]) {
+            let (key, record) = entry?;
+            let hash = String::from_utf8(key)?;
+            if referenced.contains(&hash) || uploaded_at(&record) > cutoff {
+                continue;
+            }
+            report.reclaimed_bytes += (hash.len() + record.len()) as u64;
+            batch.remove(TreeId::Blobs, &hash);
+            report.blobs.push(hash);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use chrono::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, Write};
+
+use crate::approval::Approval;
+use crate::blobs;
+use crate::hashing::{self, HashAlgorithm};
+use crate::transaction::Transaction;
+
+// 1. DEFINE BLOCK
+// New fields go at the end with a serde default, so older records still decode.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Block {
+    pub timestamp: u64,
+    // Any bytes; base64 in JSON.
+    #[serde(with = "crate::payload")]
+    pub data: Vec<u8>,
+    pub prev_hash: String,
+    pub hash: String,
+    // Which hashing scheme `hash` was computed with, see `hashing`.
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub transactions: Vec<Transaction>,
+    // Proof of work, see `pow`. Both stay 0 on chains without mining.
+    #[serde(default)]
+    pub difficulty: u32,
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    // MIME type of `data`, e.g. "application/pdf". Unset for plain text.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    // Free-form tags such as category or author, committed to by the hash.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    // The authority's signature of `hash` on proof-of-authority chains, hex. Not part
+    // of the hash, which it signs.
+    #[serde(default)]
+    pub signature: String,
+    // Position in the chain assigned when the block is made, one more than its
+    // parent's. Unlike the timestamp it never depends on a clock. 0 on blocks made
+    // before sequence numbers, which are hashed without one.
+    #[serde(default)]
+    pub sequence: u64,
+    // ID of the chain the block was made for (see `GenesisConfig::network_id`), so it
+    // can't be replayed onto another chain. Empty on genesis blocks and on blocks
+    // made before chain IDs, which are hashed without one.
+    #[serde(default)]
+    pub network_id: String,
+    // Root of the account state once the block is applied (see `state::state_root`).
+    // Empty on genesis blocks and on blocks made before state roots.
+    #[serde(default)]
+    pub state_root: String,
+    // Signatures of `hash` by the approvers, on chains that need them (see
+    // `approval`). Not part of the hash, like `signature`.
+    #[serde(default)]
+    pub approvals: Vec<Approval>,
+}
+
+impl Block {
+    pub fn new(data: impl Into<Vec<u8>>, prev_hash: String) -> Self {
+        let timestamp = Utc::now().timestamp_millis() as u64;
+        Self::new_with_timestamp(data, prev_hash, timestamp)
+    }
+
+    pub fn new_with_timestamp(data: impl Into<Vec<u8>>, prev_hash: String, timestamp: u64) -> Self {
+        let mut block = Block {
+            timestamp,
+            data: data.into(),
+            prev_hash,
+            hash: String::new(),
+            version: hashing::CURRENT_VERSION,
+            transactions: Vec::new(),
+            difficulty: 0,
+            nonce: 0,
+            hash_algorithm: HashAlgorithm::default(),
+            content_type: None,
+            metadata: BTreeMap::new(),
+            signature: String::new(),
+            sequence: 0,
+            network_id: String::new(),
+            state_root: String::new(),
+            approvals: Vec::new(),
+        };
+        block.hash = block.calculate_hash();
+        block
+    }
+
+    pub fn new_with_transactions(data: impl Into<Vec<u8>>, transactions: Vec<Transaction>, prev_hash: String) -> Self {
+        let mut block = Self::new(data, prev_hash);
+        block.transactions = transactions;
+        block.hash = block.calculate_hash();
+        block
+    }
+
+    pub fn calculate_hash(&self) -> String {
+        self.view().calculate_hash()
+    }
+
+    // `calculate_hash` for a block whose payload is kept in a file, or any seekable
+    // reader, instead of `data`. The reader is streamed, never loaded whole.
+    pub fn hash_reader<R: Read + Seek>(&self, reader: R) -> io::Result<String> {
+        hashing::block_hash_reader(&self.view(), reader)
+    }
+
+    pub fn is_genesis(&self) -> bool {
+        self.prev_hash == "0"
+    }
+
+    // Encoded size in bytes, which `Config::max_block_size` limits.
+    pub fn size(&self) -> usize {
+        self.view().size()
+    }
+
+    pub fn view(&self) -> BlockView<'_> {
+        BlockView {
+            timestamp: self.timestamp,
+            data: &self.data,
+            prev_hash: &self.prev_hash,
+            hash: &self.hash,
+            version: self.version,
+            transactions: Cow::Borrowed(&self.transactions),
+            difficulty: self.difficulty,
+            nonce: self.nonce,
+            hash_algorithm: self.hash_algorithm,
+            content_type: self.content_type.as_deref(),
+            metadata: self.metadata.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect(),
+            signature: &self.signature,
+            sequence: self.sequence,
+            network_id: &self.network_id,
+            state_root: &self.state_root,
+            approvals: Cow::Borrowed(&self.approvals),
+        }
+    }
+
+    // The blobs the block refers to (see `blobs`), by attachment name.
+    pub fn attachments(&self) -> BTreeMap<&str, &str> {
+        blobs::attachments(self.metadata.iter().map(|(key, hash)| (key.as_str(), hash.as_str())))
+    }
+
+    // The payload as text, if it is UTF-8.
+    pub fn data_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.data).ok()
+    }
+
+    // The payload for display: text as is, anything else as its size and type.
+    pub fn data_summary(&self) -> String {
+        match self.data_str() {
+            Some(text) => text.to_string(),
+            None => format!("<{} bytes of {}>", self.data.len(), self.content_type.as_deref().unwrap_or("binary data")),
+        }
+    }
+}
+
+// A block borrowed from the bytes it is read from: the data and the strings point
+// into the record, so checking a stored block (see `ReadTrees::read_block`) copies
+// nothing but its transactions and approvals out. `Block::view` borrows an owned
+// block the same way, which is how hashing and size checks see either. Decodes from
+// MessagePack only, where it is laid out like `Block`, field for field.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockView<'a> {
+    pub timestamp: u64,
+    #[serde(borrow, serialize_with = "crate::payload::serialize")]
+    pub data: &'a [u8],
+    pub prev_hash: &'a str,
+    pub hash: &'a str,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub transactions: Cow<'a, [Transaction]>,
+    #[serde(default)]
+    pub difficulty: u32,
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(borrow, default)]
+    pub content_type: Option<&'a str>,
+    #[serde(borrow, default)]
+    pub metadata: BTreeMap<&'a str, &'a str>,
+    #[serde(default)]
+    pub signature: &'a str,
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub network_id: &'a str,
+    #[serde(default)]
+    pub state_root: &'a str,
+    #[serde(default)]
+    pub approvals: Cow<'a, [Approval]>,
+}
+
+impl BlockView<'_> {
+    pub fn calculate_hash(&self) -> String {
+        hashing::block_hash(self)
+    }
+
+    pub fn is_genesis(&self) -> bool {
+        self.prev_hash == "0"
+    }
+
+    pub fn attachments(&self) -> BTreeMap<&str, &str> {
+        blobs::attachments(self.metadata.iter().map(|(key, hash)| (*key, *hash)))
+    }
+
+    // The size `Block::size` reports, counted as the block is encoded rather than
+    // by encoding it into a buffer.
+    pub fn size(&self) -> usize {
+        let mut counter = ByteCount(0);
+        rmp_serde::encode::write(&mut counter, self).map_or(0, |()| counter.0)
+    }
+
+    pub fn to_block(&self) -> Block {
+        Block {
+            timestamp: self.timestamp,
+            data: self.data.to_vec(),
+            prev_hash: self.prev_hash.to_string(),
+            hash: self.hash.to_string(),
+            version: self.version,
+            transactions: self.transactions.to_vec(),
+            difficulty: self.difficulty,
+            nonce: self.nonce,
+            hash_algorithm: self.hash_algorithm,
+            content_type: self.content_type.map(str::to_string),
+            metadata: self.metadata.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            signature: self.signature.to_string(),
+            sequence: self.sequence,
+            network_id: self.network_id.to_string(),
+            state_root: self.state_root.to_string(),
+            approvals: self.approvals.to_vec(),
+        }
+    }
+}
+
+struct ByteCount(usize);
+
+impl Write for ByteCount {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0 += bytes.len();
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,1527 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Instant;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::anchor;
+use crate::batch::{ChainBatch, ReadTrees, Trees};
+use crate::approval;
+use crate::authority;
+use crate::block::Block;
+use crate::checkpoint::{self, Checkpoint};
+use crate::clock::{Clock, SystemClock};
+use crate::hooks::{self, Validator};
+use crate::coinbase;
+use crate::conflicts;
+use crate::config::{Config, Durability, JournalRecovery, NodeMode};
+use crate::encoding::{is_block_key, is_current, open_block, seal_block, seal_header};
+use crate::encryption::BlockCipher;
+use crate::error::{ConsensusError, LedgerError};
+use crate::events::ChainEvent;
+use crate::genesis::GenesisConfig;
+use crate::header::BlockHeader;
+use crate::hashing;
+use crate::journal;
+use crate::limits;
+use crate::mempool::Mempool;
+use crate::orphans::OrphanPool;
+use crate::ratelimit::RateLimiter;
+use crate::schema::PayloadSchemas;
+use crate::signer::Signer;
+use crate::metrics::Metrics;
+use crate::miner::{MiningOutcome, MiningStats};
+use crate::pow;
+use crate::state;
+use crate::store::{BlockStore, DefaultStore, MemoryStore, TreeId};
+#[cfg(feature = "sled")]
+use crate::store::SledStore;
+use crate::transaction::Transaction;
+use crate::validation::{CancelToken, ValidationProgress};
+
+// Bookkeeping kept next to every stored block (canonical or not), so competing
+// branches can be compared without walking them back to genesis.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct BlockMeta {
+    pub(crate) height: u64,
+    pub(crate) total_work: u128,
+}
+
+// What happened to a block handed to `receive_block`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockStatus {
+    // The block was stored already, and nothing was written. Only `add_block_if_absent`
+    // and `add_blocks` report it; `receive_block` fails with
+    // `LedgerError::DuplicateBlock` instead.
+    AlreadyKnown,
+    // The block extended the canonical chain and is the new tip.
+    Extended,
+    // The block was stored on a fork that does not have more work than the tip.
+    SideChain,
+    // The block's branch overtook the canonical chain.
+    Reorged { disconnected: Vec<String>, connected: Vec<String> },
+    // The block's parent is unknown. It waits in the orphan pool and is added once
+    // the parent arrives.
+    Orphaned,
+}
+
+// Expected number of hashes behind the block. Without mining every block counts
+// the same, so "most work" is simply the longest chain.
+pub(crate) fn block_work(difficulty: u32) -> u128 {
+    1 << difficulty.min(127)
+}
+
+// A block carries the ID of the chain it was made for, and only header-hashed blocks
+// commit to one. Chains from before chain IDs go on without them until a block has
+// one; from then on every block must.
+pub(crate) fn check_network_id(header: &BlockHeader, parent: &BlockHeader, network_id: &str) -> Result<(), Box<dyn Error>> {
+    if header.network_id.is_empty() {
+        if !parent.network_id.is_empty() {
+            return Err(format!("Block {} carries no chain ID, but its parent does", header.hash).into());
+        }
+        return Ok(());
+    }
+    if header.version < hashing::HEADER_V2 {
+        return Err(format!("Block {} is version {}, which cannot carry a chain ID", header.hash, header.version).into());
+    }
+    if header.network_id != network_id {
+        return Err(format!("Block {} belongs to chain {}, not this chain ({})", header.hash, header.network_id, network_id).into());
+    }
+    Ok(())
+}
+
+// Timestamp rules for a non-genesis block whose parent was stamped at `parent_timestamp`,
+// checked at the time `now`.
+pub(crate) fn check_timestamp(
+    hash: &str,
+    timestamp: u64,
+    parent_timestamp: u64,
+    now: u64,
+    max_future_drift_ms: u64,
+) -> Result<(), Box<dyn Error>> {
+    if timestamp < parent_timestamp {
+        return Err(format!(
+            "Block {} is timestamped {}, before its parent ({})",
+            hash, timestamp, parent_timestamp
+        ).into());
+    }
+    if timestamp > now.saturating_add(max_future_drift_ms) {
+        return Err(format!("Block {} is timestamped {} ms ahead of the local clock", hash, timestamp - now).into());
+    }
+    Ok(())
+}
+
+// Sequence rules for a non-genesis block: once a chain has sequence numbers, each
+// block carries its parent's plus one. Only header-hashed blocks commit to one.
+pub(crate) fn check_sequence(header: &BlockHeader, parent: &BlockHeader) -> Result<(), Box<dyn Error>> {
+    if header.sequence == 0 && parent.sequence == 0 {
+        return Ok(());
+    }
+    if header.version < hashing::HEADER_V2 {
+        return Err(format!("Block {} is version {}, which cannot carry a sequence number", header.hash, header.version).into());
+    }
+    if header.sequence != parent.sequence + 1 {
+        return Err(format!(
+            "Block {} has sequence number {}, expected {}",
+            header.hash, header.sequence, parent.sequence + 1
+        ).into());
+    }
+    Ok(())
+}
+
+// The rules a non-genesis block at `height` on top of `parent` is held to whatever
+// the node: timestamps (no more than `max_future_drift_ms` past `now`), sequence
+// numbers, the chain ID, proof of work or signatures, the coinbase and
+// duplicate transactions. `load_header` looks up older ancestors. They need no
+// store, so exports are checked against them too (see `verify`).
+pub(crate) fn check_consensus(
+    genesis: &GenesisConfig,
+    block: &Block,
+    parent: &BlockHeader,
+    height: u64,
+    now: u64,
+    max_future_drift_ms: u64,
+    load_header: impl Fn(&str) -> Result<Option<BlockHeader>, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let view = block.view();
+    check_timestamp(&block.hash, block.timestamp, parent.timestamp, now, max_future_drift_ms)?;
+    let header = block.header();
+    check_sequence(&header, parent)?;
+    check_network_id(&header, parent, &genesis.network_id()?)?;
+    pow::check_work(genesis, &header, parent, height, load_header)?;
+    coinbase::check_coinbase(genesis, &view, height)?;
+    conflicts::check_duplicates(&view)
+}
+
+// 2. DEFINE BLOCKCHAIN
+// A handle to an open chain. Clones share the database, tip and mempool, so handles
+// can be passed to other threads: reads run concurrently, writes take turns.
+//
+// The store defaults to sled (see `DefaultStore`); see `BlockStore` for plugging in another backend.
+#[derive(Clone)]
+pub struct Blockchain<S = DefaultStore> {
+    pub(crate) trees: Trees<S>,
+    pub(crate) config: Config,
+    pub(crate) clock: Arc<dyn Clock>,
+    // See `hooks`.
+    pub(crate) validators: Vec<Arc<dyn Validator>>,
+    // See `signer`: signs blocks on proof-of-authority chains (`authority`), approves
+    // proposed blocks (`approval`) and attests anchored tips (`anchor`).
+    pub(crate) authority_signer: Option<Arc<dyn Signer>>,
+    pub(crate) approver_signer: Option<Arc<dyn Signer>>,
+    pub(crate) anchor_signer: Option<Arc<dyn Signer>>,
+    pub(crate) shared: Arc<Shared>,
+}
+
+// What every clone of a handle sees.
+pub(crate) struct Shared {
+    // Swapped by `commit` once a batch is on disk.
+    head: RwLock<Head>,
+    // Held for the whole of a write, from building the batch to emitting its events.
+    writer: Mutex<()>,
+    pub(crate) subscribers: Mutex<Vec<Sender<ChainEvent>>>,
+    pub(crate) mempool: Mutex<Mempool>,
+    pub(crate) orphans: Mutex<OrphanPool>,
+    pub(crate) rate_limiter: RateLimiter,
+    // Bumped by `cancel_mining`; a miner stops when it changes.
+    pub(crate) mining_epoch: AtomicU64,
+    // Set once the node is shutting down, see `shutdown`.
+    pub(crate) closing: Arc<AtomicBool>,
+    pub(crate) mining_stats: Mutex<Option<MiningStats>>,
+    pub(crate) metrics: Metrics,
+    // Signs and checks stored checkpoints, see `checkpoint`.
+    pub(crate) checkpoint_key: Option<Vec<u8>>,
+    // From `Config::payload_schema` and `Config::tag_schemas`.
+    pub(crate) schemas: PayloadSchemas,
+    // Blocks handed out to external miners, by template id; see `getwork`.
+    pub(crate) templates: Mutex<BTreeMap<String, Block>>,
+    // Commits since the last flush, and when that was; see `Durability`.
+    unflushed: AtomicU64,
+    last_flush: Mutex<Instant>,
+}
+
+// What a new block carries besides its transactions.
+#[derive(Default)]
+pub(crate) struct Payload {
+    pub(crate) data: Vec<u8>,
+    pub(crate) content_type: Option<String>,
+    pub(crate) metadata: BTreeMap<String, String>,
+}
+
+#[derive(Clone)]
+struct Head {
+    tip: String,
+    genesis: GenesisConfig,
+}
+
+// Fails to compile if a field ever stops the handle from being shared.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Blockchain>();
+};
+
+#[cfg(feature = "sled")]
+impl Blockchain {
+    pub fn new() -> Result<Blockchain, Box<dyn Error>> {
+        Self::open("my_db")
+    }
+
+    pub fn open(path: &str) -> Result<Blockchain, Box<dyn Error>> {
+        Self::open_with_config(path, None, Config::default())
+    }
+
+    // Like `open`, but a new database starts from `genesis`, and an existing one must
+    // have been created from the same config.
+    pub fn open_with_genesis(path: &str, genesis: &GenesisConfig) -> Result<Blockchain, Box<dyn Error>> {
+        Self::open_with_config(path, Some(genesis), Config::default())
+    }
+
+    pub fn open_with_config(
+        path: &str,
+        expected_genesis: Option<&GenesisConfig>,
+        config: Config,
+    ) -> Result<Blockchain, Box<dyn Error>> {
+        Self::open_store(SledStore::open(path)?, expected_genesis, config)
+    }
+
+    // Opens an existing chain for reading only: no genesis is created, no migration
+    // or index upkeep runs, and every write fails. See `SledStore::open_read_only`.
+    pub fn open_read_only(path: &str) -> Result<Blockchain, Box<dyn Error>> {
+        Self::open_read_only_with_config(path, Config::default())
+    }
+
+    pub fn open_read_only_with_config(path: &str, config: Config) -> Result<Blockchain, Box<dyn Error>> {
+        Self::open_store(SledStore::open_read_only(path)?, None, config)
+    }
+}
+
+impl Blockchain<MemoryStore> {
+    // A fresh chain that lives only in this process, so tests and examples can run in
+    // parallel without a database directory. Clones share the chain.
+    pub fn in_memory() -> Result<Blockchain<MemoryStore>, Box<dyn Error>> {
+        Self::open_store(MemoryStore::new(), None, Config::default())
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Opens a chain kept in `store`, which may be empty; see `open_with_config`.
+    pub fn open_store(
+        store: S,
+        expected_genesis: Option<&GenesisConfig>,
+        config: Config,
+    ) -> Result<Blockchain<S>, Box<dyn Error>> {
+        if config.mode == NodeMode::Light {
+            return Err("A light node keeps only headers; open it as a `HeaderChain`".into());
+        }
+        let config = config.for_mode()?;
+        journal::recover(&store, config.journal_recovery)?;
+        let trees = Trees { store, cipher: BlockCipher::from_config(&config)? };
+        let checkpoint_key = checkpoint::key_from_config(&config)?;
+        let authority_signer = authority::signer_from_config(&config)?;
+        let approver_signer = approval::signer_from_config(&config)?;
+        let anchor_signer = anchor::signer_from_config(&config)?;
+        let schemas = PayloadSchemas::from_config(&config)?;
+        let validators = hooks::from_config(&config)?;
+
+        let last_hash_bytes = trees.get(TreeId::Blocks, b"LAST")?;
+        let is_new = last_hash_bytes.is_none();
+        let read_only = trees.store.is_read_only();
+        if is_new && read_only {
+            return Err("The database holds no chain yet".into());
+        }
+        let current_hash = match last_hash_bytes {
+            Some(bytes) => String::from_utf8(bytes.to_vec())?,
+            None => String::new(),
+        };
+
+        let genesis_config = if is_new {
+            expected_genesis.cloned().unwrap_or_default()
+        } else {
+            match trees.get(TreeId::Blocks, b"GENESIS")? {
+                Some(bytes) => serde_json::from_slice(&bytes)?,
+                // Chains created before genesis configs existed used the defaults.
+                None => GenesisConfig::legacy(),
+            }
+        };
+
+        let mut chain = Blockchain {
+            trees,
+            config,
+            clock: Arc::new(SystemClock),
+            validators,
+            authority_signer,
+            approver_signer,
+            anchor_signer,
+            shared: Arc::new(Shared {
+                head: RwLock::new(Head { tip: current_hash, genesis: genesis_config }),
+                writer: Mutex::new(()),
+                subscribers: Mutex::new(Vec::new()),
+                mempool: Mutex::new(Mempool::default()),
+                orphans: Mutex::new(OrphanPool::default()),
+                rate_limiter: RateLimiter::default(),
+                mining_epoch: AtomicU64::new(0),
+                closing: Arc::new(AtomicBool::new(false)),
+                mining_stats: Mutex::new(None),
+                metrics: Metrics::default(),
+                checkpoint_key,
+                schemas,
+                templates: Mutex::new(BTreeMap::new()),
+                unflushed: AtomicU64::new(0),
+                last_flush: Mutex::new(Instant::now()),
+            }),
+        };
+
+        if chain.config.mode == NodeMode::Archive && !chain.trees.store.is_empty(TreeId::Pruned)? {
+            return Err("The database has pruned blocks, which an archive node needs; sync a new one from a peer".into());
+        }
+        if is_new {
+            // Handle the "Not Found" (First run) case
+            let mut batch = chain.batch();
+            let genesis = batch.genesis.genesis_block()?;
+            batch.insert(TreeId::Blocks, "GENESIS", serde_json::to_vec(&batch.genesis)?);
+            batch.store_block(&genesis, &BlockMeta { height: 0, total_work: block_work(genesis.difficulty) })?;
+            batch.connect_block(&genesis, 0)?;
+            batch.set_tip(&genesis.hash);
+            batch.mark_state_built();
+            batch.mark_history_built();
+            batch.mark_transaction_index_built();
+            batch.mark_schema_current();
+            chain.commit(batch)?;
+        } else if read_only {
+            chain.check_schema()?;
+        } else {
+            chain.migrate_schema()?;
+        }
+        if read_only {
+            // Use the search index as it is on disk.
+            chain.config.search_index = chain.trees.contains(TreeId::Blocks, b"SEARCH_BUILT")?;
+        } else if !chain.trees.store.is_empty(TreeId::Meta)? {
+            chain.sync_search_index()?;
+            chain.build_history_if_needed()?;
+        }
+
+        // Compare contents rather than hashes, which depend on the hash version the
+        // genesis block was written with.
+        if let Some(expected) = expected_genesis {
+            let expected_block = expected.genesis_block()?;
+            let stored = match chain.canonical_hash(0)? {
+                Some(hash) => chain.load_block(&hash)?,
+                None => None,
+            };
+            let matches = stored.is_some_and(|genesis| {
+                genesis.timestamp == expected_block.timestamp && genesis.data == expected_block.data
+            });
+            if !matches {
+                return Err(format!(
+                    "The database was created from a different genesis config (expected genesis {})",
+                    expected_block.hash
+                ).into());
+            }
+        }
+        if !read_only {
+            chain.restore_mempool()?;
+        }
+
+        Ok(chain)
+    }
+
+    // This handle, reading the time from `clock` instead of the wall clock. Clones of
+    // the result share the clock; other handles to the chain keep theirs.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Blockchain { clock: Arc::new(clock), ..self }
+    }
+
+    pub fn genesis_config(&self) -> GenesisConfig {
+        self.shared.head.read().unwrap().genesis.clone()
+    }
+
+    // See `GenesisConfig::network_id`.
+    pub fn network_id(&self) -> Result<String, Box<dyn Error>> {
+        self.genesis_config().network_id()
+    }
+
+    pub fn current_hash(&self) -> String {
+        self.shared.head.read().unwrap().tip.clone()
+    }
+
+    // Height of the canonical tip (genesis is 0).
+    pub fn height(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.tip_meta()?.height)
+    }
+
+    // A stored block, canonical or not.
+    pub fn get_block(&self, hash: &str) -> Result<Option<Block>, Box<dyn Error>> {
+        self.load_block(hash)
+    }
+
+    // Canonical blocks from `from_height` to `to_height`, both included, oldest first.
+    // Heights past the tip, or below a snapshot base, hold no block and are skipped.
+    pub fn get_blocks_range(&self, from_height: u64, to_height: u64) -> Result<Vec<Block>, Box<dyn Error>> {
+        let to_height = to_height.min(self.height()?);
+        let mut blocks = Vec::new();
+        for height in from_height..=to_height {
+            if let Some(block) = self.canonical_block_if_stored(height)? {
+                blocks.push(block);
+            }
+        }
+        Ok(blocks)
+    }
+
+    // Canonical blocks stamped at or after `timestamp` (milliseconds), oldest first.
+    // Timestamps never decrease along the chain, so the first one is found by bisection.
+    pub fn get_blocks_since(&self, timestamp: u64) -> Result<Vec<Block>, Box<dyn Error>> {
+        let tip = self.height()?;
+        let (mut low, mut high) = (0, tip + 1);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let older = match self.canonical_header(middle)? {
+                Some(header) => header.timestamp < timestamp,
+                None => true,
+            };
+            if older {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        self.get_blocks_range(low, tip)
+    }
+
+    // Canonical blocks numbered after `sequence`, oldest first, so a reader can pick up
+    // where it stopped without trusting timestamps. Blocks made before sequence
+    // numbers are never included.
+    pub fn get_blocks_after_sequence(&self, sequence: u64) -> Result<Vec<Block>, Box<dyn Error>> {
+        let tip = self.height()?;
+        let (mut low, mut high) = (0, tip + 1);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let older = match self.canonical_header(middle)? {
+                Some(header) => header.sequence <= sequence,
+                None => true,
+            };
+            if older {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        self.get_blocks_range(low, tip)
+    }
+
+    // Hash of the canonical block at `height`, if the chain is that long.
+    pub fn canonical_hash(&self, height: u64) -> Result<Option<String>, Box<dyn Error>> {
+        self.trees.canonical_hash(height)
+    }
+
+    // Every stored block, canonical or not, in hash order. Pruned blocks come without
+    // their body.
+    pub fn blocks(&self) -> impl Iterator<Item = Result<Block, Box<dyn Error>>> + '_ {
+        self.store().scan_prefix(TreeId::Blocks, &[]).filter_map(|entry| match entry {
+            Ok((key, _)) if !is_block_key(&key) => None,
+            Ok((_, bytes)) => Some(open_block(&bytes, self.trees.cipher())),
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    // All known branch tips, including the canonical one.
+    pub fn tips(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut tips = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::Tips, &[]) {
+            let (key, _) = entry?;
+            tips.push(String::from_utf8(key)?);
+        }
+        Ok(tips)
+    }
+
+    pub fn add_block(&self, data: impl Into<Vec<u8>>) -> Result<String, Box<dyn Error>> {
+        self.add_block_with_transactions(data, Vec::new())
+    }
+
+    // Appends a block holding a binary document such as a PDF, tagged with its MIME type.
+    pub fn add_document(&self, data: Vec<u8>, content_type: &str) -> Result<String, Box<dyn Error>> {
+        self.add_block_with_metadata(data, Some(content_type), BTreeMap::new())
+    }
+
+    // Appends a block tagged with `metadata` (category, reference number, author, ...),
+    // which its hash commits to. See `find_by_tag`.
+    pub fn add_block_with_metadata(
+        &self,
+        data: impl Into<Vec<u8>>,
+        content_type: Option<&str>,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<String, Box<dyn Error>> {
+        let payload = Payload { data: data.into(), content_type: content_type.map(str::to_string), metadata };
+        self.append_block(payload, Vec::new())
+    }
+
+    // Rejects the block, without storing anything, if a transfer overdraws its sender.
+    // When there is a block reward or fees to collect, the coinbase paying them to
+    // `Config::miner_address` is added in front of `transactions`.
+    // Mining runs without the write lock, so other writers and blocks from peers are
+    // not held up; if one of them moves the tip first, the block is rebuilt on top.
+    pub fn add_block_with_transactions(
+        &self,
+        data: impl Into<Vec<u8>>,
+        transactions: Vec<Transaction>,
+    ) -> Result<String, Box<dyn Error>> {
+        self.append_block(Payload { data: data.into(), ..Payload::default() }, transactions)
+    }
+
+    #[instrument(skip_all, fields(transactions = transactions.len()))]
+    fn append_block(&self, payload: Payload, transactions: Vec<Transaction>) -> Result<String, Box<dyn Error>> {
+        self.check_open()?;
+        if self.genesis_config().approval.is_some() {
+            return Err("Blocks on this chain need approval; propose them with `propose_block` instead".into());
+        }
+        self.shared.schemas.check(&payload.data, &payload.metadata)?;
+        let epoch = self.shared.mining_epoch.load(Ordering::Relaxed);
+        loop {
+            let Some((new_block, meta)) = self.mine_next(&payload, &transactions, epoch)? else {
+                continue;
+            };
+            let _writer = self.write_lock();
+            if self.current_hash() != new_block.prev_hash {
+                debug!("tip moved before the mined block was stored, starting over");
+                continue;
+            }
+            self.connect_on_tip(self.batch(), &new_block, &meta)?;
+            return Ok(new_block.hash);
+        }
+    }
+
+    // Mines and signs the next block on top of the tip. None if the tip moved while
+    // mining, so the caller should start over.
+    pub(crate) fn mine_next(
+        &self,
+        payload: &Payload,
+        transactions: &[Transaction],
+        epoch: u64,
+    ) -> Result<Option<(Block, BlockMeta)>, Box<dyn Error>> {
+        let (mut new_block, meta) = self.block_template(payload.data.clone(), transactions.to_vec())?;
+        new_block.content_type = payload.content_type.clone();
+        new_block.metadata = payload.metadata.clone();
+        // Refuse oversized blocks before spending work on them.
+        limits::check_size(&self.config, &new_block.view())?;
+        let parent = new_block.prev_hash.clone();
+        let cancelled = || self.shared.mining_epoch.load(Ordering::Relaxed) != epoch;
+        let outcome = self.miner().mine(&mut new_block, || {
+            cancelled() || self.is_shutting_down() || self.current_hash() != parent
+        });
+        match outcome {
+            MiningOutcome::Found(stats) => {
+                debug!(hashes = stats.hashes, elapsed_ms = stats.elapsed.as_millis() as u64, "block mined");
+                *self.shared.mining_stats.lock().unwrap() = Some(stats);
+            }
+            MiningOutcome::Stopped(_) if cancelled() => return Err("Mining cancelled".into()),
+            MiningOutcome::Stopped(_) if self.is_shutting_down() => return Err(Box::new(LedgerError::ShuttingDown)),
+            MiningOutcome::Stopped(_) => {
+                debug!("tip moved while mining, starting over");
+                return Ok(None);
+            }
+        }
+        self.sign_block(&mut new_block, meta.height)?;
+        Ok(Some((new_block, meta)))
+    }
+
+    // Checks a block built on the tip and makes it the new tip, along with whatever
+    // else `batch` holds. The caller holds the write lock and has made sure the tip
+    // is still the block's parent.
+    pub(crate) fn connect_on_tip(&self, mut batch: ChainBatch<S>, block: &Block, meta: &BlockMeta) -> Result<(), Box<dyn Error>> {
+        // The same content on the same parent in the same millisecond hashes alike;
+        // the second block must not overwrite the first.
+        if batch.contains(TreeId::Blocks, block.hash.as_bytes())? {
+            return Err(Box::new(LedgerError::DuplicateBlock { hash: block.hash.clone() }));
+        }
+        let staged = batch
+            .check_block(block, meta.height)
+            .and_then(|_| batch.store_block(block, meta))
+            .and_then(|_| batch.connect_block(block, meta.height));
+        if let Err(e) = staged {
+            self.announce_rejection(&block.hash, e.as_ref());
+            return Err(e);
+        }
+        batch.set_tip(&block.hash);
+        self.commit(batch)?;
+        self.announce_block(block, meta.height);
+        self.update_mempool(std::slice::from_ref(block), &[]);
+        self.tip_moved()
+    }
+
+    // The next block on top of the tip, ready to be mined.
+    pub(crate) fn block_template(&self, data: Vec<u8>, mut transactions: Vec<Transaction>) -> Result<(Block, BlockMeta), Box<dyn Error>> {
+        let batch = self.batch();
+        let parent = batch.tip_meta()?;
+        let fees = coinbase::block_fees(&transactions)?;
+        if coinbase::needs_coinbase(&batch.genesis, fees) {
+            if batch.config.miner_address.is_empty() {
+                return Err("Set a miner address to collect the block reward and fees".into());
+            }
+            let height = parent.height + 1;
+            let reward = batch.genesis.reward_at(height).checked_add(fees).ok_or("Transaction fees overflow")?;
+            transactions.insert(0, Transaction::coinbase(&batch.config.miner_address, reward, height));
+        }
+        let parent_block = batch
+            .load_block(&batch.tip)?
+            .ok_or_else(|| format!("Broken link! Could not find block: {}", batch.tip))?;
+        // A clock that went backwards must not produce a block older than its parent.
+        let timestamp = self.clock.now_ms().max(parent_block.timestamp);
+        let mut new_block = Block::new_with_timestamp(data, batch.tip.clone(), timestamp);
+        new_block.transactions = transactions;
+        new_block.hash_algorithm = batch.genesis.hash_algorithm;
+        new_block.sequence = parent_block.sequence + 1;
+        new_block.network_id = batch.genesis.network_id()?;
+        new_block.state_root = batch.state_root_with(&batch.state_changes(&new_block, parent.height + 1)?)?;
+        new_block.difficulty = pow::expected_difficulty(&batch.genesis, &parent_block.header(), parent.height + 1, |hash| {
+            batch.load_header(hash)
+        })?;
+        let meta = BlockMeta {
+            height: parent.height + 1,
+            total_work: parent.total_work + block_work(new_block.difficulty),
+        };
+        Ok((new_block, meta))
+    }
+
+    // Accepts a block produced elsewhere (e.g. by a peer). It is stored whether or not
+    // it ends up canonical; if its branch now has the most work the chain reorganizes.
+    // A block whose parent hasn't arrived yet is held back as `Orphaned` and added,
+    // along with any orphans building on it, once the parent is. A block stored
+    // already fails with `LedgerError::DuplicateBlock`.
+    pub fn receive_block(&self, block: Block) -> Result<BlockStatus, Box<dyn Error>> {
+        let hash = block.hash.clone();
+        match self.add_block_if_absent(block)? {
+            BlockStatus::AlreadyKnown => Err(Box::new(LedgerError::DuplicateBlock { hash })),
+            status => Ok(status),
+        }
+    }
+
+    // Like `receive_block`, but a block stored already is `AlreadyKnown` rather than an
+    // error, for sync code that may be handed the same block twice.
+    pub fn add_block_if_absent(&self, block: Block) -> Result<BlockStatus, Box<dyn Error>> {
+        let mut statuses = self.add_blocks(std::slice::from_ref(&block))?;
+        Ok(statuses.pop().expect("one status per block"))
+    }
+
+    // Like `add_block_if_absent` for each block in order, but everything is committed
+    // in one transaction: if any block is rejected, none of them is stored. Syncing
+    // nodes hand over whole batches this way instead of paying for a commit per block.
+    #[instrument(skip_all, fields(blocks = blocks.len()))]
+    pub fn add_blocks(&self, blocks: &[Block]) -> Result<Vec<BlockStatus>, Box<dyn Error>> {
+        self.check_open()?;
+        let _writer = self.write_lock();
+        let batch = self.batch();
+        self.receive_in(batch, blocks)
+    }
+
+    pub(crate) fn receive_in(&self, batch: ChainBatch<S>, blocks: &[Block]) -> Result<Vec<BlockStatus>, Box<dyn Error>> {
+        let statuses = self.receive_batch(batch, blocks)?;
+        let stored = blocks
+            .iter()
+            .zip(&statuses)
+            .filter(|(_, status)| !matches!(status, BlockStatus::AlreadyKnown | BlockStatus::Orphaned))
+            .map(|(block, _)| block.hash.clone())
+            .collect();
+        self.adopt_orphans(stored);
+        Ok(statuses)
+    }
+
+    pub(crate) fn receive_batch(&self, mut batch: ChainBatch<S>, blocks: &[Block]) -> Result<Vec<BlockStatus>, Box<dyn Error>> {
+        let mut statuses = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            if self.hold_orphan(&batch, block)? {
+                statuses.push(BlockStatus::Orphaned);
+                continue;
+            }
+            match batch.receive_block(block) {
+                Ok(status) => statuses.push(status),
+                Err(e) => {
+                    self.announce_rejection(&block.hash, e.as_ref());
+                    // Remember the branch that failed, even though nothing else is written.
+                    if !batch.rejected.is_empty() {
+                        self.mark_invalid(&batch.rejected)?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.commit(batch)?;
+
+        let mut connected_blocks = Vec::new();
+        let mut disconnected_blocks = Vec::new();
+        for (block, status) in blocks.iter().zip(&statuses) {
+            match status {
+                BlockStatus::Extended => {
+                    if let Some(meta) = self.block_meta(&block.hash)? {
+                        self.announce_block(block, meta.height);
+                    }
+                    connected_blocks.push(block.clone());
+                }
+                BlockStatus::Reorged { disconnected, connected } => {
+                    info!(disconnected = disconnected.len(), connected = connected.len(), "chain reorganized");
+                    self.emit(ChainEvent::ChainReorged {
+                        disconnected: disconnected.clone(),
+                        connected: connected.clone(),
+                    });
+                    for hash in disconnected {
+                        disconnected_blocks.extend(self.load_block(hash)?);
+                    }
+                    for hash in connected {
+                        if let (Some(block), Some(meta)) = (self.load_block(hash)?, self.block_meta(hash)?) {
+                            self.announce_block(&block, meta.height);
+                            connected_blocks.push(block);
+                        }
+                    }
+                }
+                BlockStatus::AlreadyKnown | BlockStatus::SideChain | BlockStatus::Orphaned => {}
+            }
+        }
+        if !connected_blocks.is_empty() {
+            self.update_mempool(&connected_blocks, &disconnected_blocks);
+            self.tip_moved()?;
+        }
+        Ok(statuses)
+    }
+
+    // Blocks on a branch that broke a consensus rule. They are no longer tips, and nothing
+    // building on them is accepted. The branch starts right after the fork point, which
+    // is canonical, so no other tip needs restoring.
+    fn mark_invalid(&self, hashes: &[String]) -> Result<(), Box<dyn Error>> {
+        let mut batch = self.batch();
+        for hash in hashes {
+            batch.insert(TreeId::Invalid, hash, []);
+            batch.remove(TreeId::Tips, hash);
+        }
+        self.commit(batch)
+    }
+
+    pub fn is_invalid(&self, hash: &str) -> Result<bool, Box<dyn Error>> {
+        self.trees.is_invalid(hash)
+    }
+
+    // Public methods that write hold this throughout; the pub(crate) helpers they
+    // call (`commit`, `receive_in`, ...) expect it to be held already.
+    pub(crate) fn write_lock(&self) -> MutexGuard<'_, ()> {
+        self.shared.writer.lock().unwrap()
+    }
+
+    // Starts a batch on top of the current tip. Nothing is written until `commit`.
+    pub(crate) fn batch(&self) -> ChainBatch<S> {
+        let head = self.shared.head.read().unwrap().clone();
+        ChainBatch::new(self.trees.clone(), head.tip, head.genesis, self.config.clone(), self.clock.clone(), self.validators.clone())
+    }
+
+    // Writes a batch atomically and adopts the tip and genesis config it ends with.
+    // Batches carrying a multi-step operation are journaled around the write.
+    pub(crate) fn commit(&self, batch: ChainBatch<S>) -> Result<(), Box<dyn Error>> {
+        let commit_started = Instant::now();
+        if let Some(operation) = batch.operation {
+            self.begin_journal(operation, &batch)?;
+        }
+        if let Err(e) = batch.commit() {
+            error!(error = %e, "commit failed");
+            // Undo whatever part of the writes the store got to, so a later open
+            // doesn't replay an operation the chain carried on without.
+            if batch.operation.is_some() {
+                journal::recover(self.store(), JournalRecovery::Rollback)?;
+            }
+            return Err(e);
+        }
+        self.shared.metrics.record_commit(commit_started.elapsed());
+        let unflushed = self.shared.unflushed.fetch_add(1, Ordering::Relaxed) + 1;
+        let due = match self.config.durability {
+            Durability::EveryBlock => true,
+            Durability::EveryN(n) => unflushed >= n,
+            Durability::IntervalMs(ms) => self.shared.last_flush.lock().unwrap().elapsed().as_millis() >= ms as u128,
+            Durability::Manual => false,
+        };
+        if due || batch.operation.is_some() {
+            self.flush()?;
+        }
+        if batch.operation.is_some() {
+            self.end_journal()?;
+        }
+        debug!(tip = %batch.tip, flushed = due, "batch committed");
+        *self.shared.head.write().unwrap() = Head { tip: batch.tip, genesis: batch.genesis };
+        Ok(())
+    }
+
+    // Puts every commit so far on disk, whatever the durability policy.
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let flush_started = Instant::now();
+        self.store().flush()?;
+        self.shared.metrics.record_flush(flush_started.elapsed());
+        self.shared.unflushed.store(0, Ordering::Relaxed);
+        *self.shared.last_flush.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    // Periodic maintenance that follows the canonical tip.
+    fn tip_moved(&self) -> Result<(), Box<dyn Error>> {
+        self.maybe_snapshot()?;
+        self.maybe_checkpoint()?;
+        self.maybe_anchor()?;
+        self.maybe_prune()?;
+        self.maybe_gc()?;
+        #[cfg(feature = "sled")]
+        self.maybe_refresh_replica()?;
+        Ok(())
+    }
+
+    pub(crate) fn store(&self) -> &S {
+        &self.trees.store
+    }
+
+    pub(crate) fn load_block(&self, hash: &str) -> Result<Option<Block>, Box<dyn Error>> {
+        self.trees.load_block(hash)
+    }
+
+    pub(crate) fn canonical_block(&self, height: u64) -> Result<Block, Box<dyn Error>> {
+        self.trees.canonical_block(height)
+    }
+
+    // Snapshot bootstraps leave the heights below their base without blocks.
+    pub(crate) fn canonical_block_if_stored(&self, height: u64) -> Result<Option<Block>, Box<dyn Error>> {
+        match self.canonical_hash(height)? {
+            Some(hash) => self.load_block(&hash),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn canonical_header(&self, height: u64) -> Result<Option<BlockHeader>, Box<dyn Error>> {
+        match self.canonical_hash(height)? {
+            Some(hash) => self.trees.load_header(&hash),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn block_meta(&self, hash: &str) -> Result<Option<BlockMeta>, Box<dyn Error>> {
+        self.trees.block_meta(hash)
+    }
+
+    pub(crate) fn tip_meta(&self) -> Result<BlockMeta, Box<dyn Error>> {
+        let tip = self.current_hash();
+        self.block_meta(&tip)?
+            .ok_or_else(|| format!("Tip {} is not indexed", tip).into())
+    }
+
+    // True while the chain holds nothing but its genesis block.
+    pub(crate) fn holds_only_genesis(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.height()? == 0 && self.tips()?.len() <= 1)
+    }
+
+    // Builds the fork-tracking index for a chain written before it existed. A chain
+    // with a broken link is left unindexed; `is_chain_valid` will report it.
+    pub(crate) fn reindex(&self) -> Result<(), Box<dyn Error>> {
+        let mut chain = Vec::new();
+        let tip = self.current_hash();
+        let mut search_hash = tip.clone();
+        while let Some(block) = self.load_block(&search_hash)? {
+            search_hash = block.prev_hash.clone();
+            let reached_genesis = block.is_genesis();
+            chain.push(block);
+            if reached_genesis {
+                break;
+            }
+        }
+        if !chain.last().is_some_and(Block::is_genesis) {
+            return Ok(());
+        }
+
+        let mut batch = self.batch();
+        let mut total_work = 0;
+        for (height, block) in chain.iter().rev().enumerate() {
+            total_work += block_work(block.difficulty);
+            let meta = BlockMeta { height: height as u64, total_work };
+            batch.insert(TreeId::Meta, &block.hash, serde_json::to_vec(&meta)?);
+            batch.connect_block(block, meta.height)?;
+        }
+        batch.insert(TreeId::Tips, &tip, []);
+        batch.mark_state_built();
+        batch.mark_history_built();
+        batch.mark_transaction_index_built();
+        self.commit(batch)
+    }
+
+    // Rewrites block records into the form the config asks for: JSON into the binary
+    // encoding, compressed or not per `Config::compression`, and encrypted, header
+    // records included, when a key is configured (sled may keep the old bytes in its
+    // log until it compacts).
+    // Returns how many records were converted; running it again is a no-op.
+    pub fn migrate_encoding(&self) -> Result<usize, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        let cipher = self.trees.cipher.as_ref();
+        let mut migrated = 0;
+        for entry in self.store().scan_prefix(TreeId::Blocks, &[]) {
+            let (key, bytes) = entry?;
+            if is_block_key(&key) && !is_current(&bytes, self.config.compression, cipher)? {
+                let block = open_block(&bytes, cipher)?;
+                self.store().insert(TreeId::Blocks, &key, seal_block(&block, self.config.compression, cipher)?)?;
+                migrated += 1;
+            }
+        }
+        self.store().flush()?;
+        Ok(migrated + self.seal_headers()?)
+    }
+
+    // Blocks written before the canonical preimage keep verifying under the legacy
+    // scheme. This rewrites the canonical chain so every block uses the current hash
+    // version, numbered by height and stamped with the chain ID and state root; since each hash changes, so does every prev_hash
+    // link and the tip.
+    // Only do this on a chain that is not shared with other nodes. Side branches are
+    // dropped because they commit to the old hashes. Returns the number of blocks rewritten.
+    #[instrument(skip_all)]
+    pub fn migrate_hashes(&self) -> Result<usize, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        let mut old_chain = Vec::new();
+        for height in 0..=self.height()? {
+            old_chain.push(self.canonical_block(height)?);
+        }
+        if old_chain.iter().all(|block| block.version == hashing::CURRENT_VERSION) {
+            return Ok(0);
+        }
+
+        // The old chain is replaced in one commit, so it stays intact if anything fails.
+        let mut batch = self.batch();
+        for entry in self.store().scan_prefix(TreeId::Blocks, &[]) {
+            let (key, _) = entry?;
+            if is_block_key(&key) {
+                batch.remove(TreeId::Blocks, key);
+            }
+        }
+        for tree in [TreeId::Headers, TreeId::Meta, TreeId::Heights, TreeId::Tips, TreeId::State, TreeId::StateNodes, TreeId::Undo, TreeId::Search, TreeId::History, TreeId::Transactions] {
+            batch.clear(tree)?;
+        }
+
+        let network_id = self.genesis_config().network_id()?;
+        let mut new_chain: Vec<Block> = Vec::with_capacity(old_chain.len());
+        let mut total_work = 0;
+        for (height, old) in old_chain.iter().enumerate() {
+            let mut block = old.clone();
+            block.version = hashing::CURRENT_VERSION;
+            block.sequence = height as u64;
+            if height > 0 {
+                block.network_id = network_id.clone();
+                block.state_root = batch.state_root_with(&batch.state_changes(&block, height as u64)?)?;
+            }
+            block.prev_hash = match new_chain.last() {
+                Some(parent) => parent.hash.clone(),
+                None => "0".to_string(),
+            };
+            block.hash = block.calculate_hash();
+
+            total_work += block_work(block.difficulty);
+            let meta = BlockMeta { height: height as u64, total_work };
+            batch.store_block(&block, &meta)?;
+            batch.connect_block(&block, meta.height)?;
+            new_chain.push(block);
+        }
+        let new_tip = new_chain.last().map(|block| block.hash.clone()).unwrap_or_default();
+        batch.set_tip(&new_tip);
+        self.commit(batch)?;
+
+        Ok(old_chain.iter().zip(&new_chain).filter(|(old, new)| old.hash != new.hash).count())
+    }
+
+    pub fn print_chain(&self) {
+        let mut search_hash = self.current_hash();
+        println!("--- CHAIN ON DISK ---");
+
+        while let Ok(Some(block)) = self.load_block(&search_hash) {
+            println!("Hash: {}", block.hash);
+            if self.is_pruned(&block.hash).unwrap_or(false) {
+                println!("Data: <pruned>");
+            } else {
+                println!("Data: {}", block.data_summary());
+            }
+            if !block.metadata.is_empty() {
+                let tags: Vec<String> = block.metadata.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+                println!("Tags: {}", tags.join(", "));
+            }
+            if block.sequence != 0 {
+                println!("Sequence: {}", block.sequence);
+                if !block.network_id.is_empty() {
+                    println!("Chain ID: {}", block.network_id);
+                }
+                if !block.state_root.is_empty() {
+                    println!("State root: {}", block.state_root);
+                }
+            }
+            println!("Prev: {}\n", block.prev_hash);
+
+            if block.is_genesis() {
+                break;
+            }
+            search_hash = block.prev_hash;
+        }
+    }
+
+    // Returns Ok(true) if valid, Ok(false) if corrupted. What was wrong is logged.
+    // With `Config::validate_from_checkpoint` the walk ends at the newest checkpoint.
+    pub fn is_chain_valid(&self) -> Result<bool, Box<dyn Error>> {
+        self.validate_with_progress(|_| {}, &CancelToken::new())
+    }
+
+    // `is_chain_valid`, calling `progress` after each block. Fails once `cancel` is
+    // cancelled, which is checked between blocks.
+    #[instrument(skip_all)]
+    pub fn validate_with_progress(
+        &self,
+        mut progress: impl FnMut(ValidationProgress),
+        cancel: &CancelToken,
+    ) -> Result<bool, Box<dyn Error>> {
+        let started = Instant::now();
+        let mut checked = 0;
+        let valid = self.walk_chain(
+            |status| {
+                checked = status.checked;
+                progress(status)
+            },
+            cancel,
+        );
+        self.shared.metrics.record_validation(checked, started.elapsed());
+        valid
+    }
+
+    fn walk_chain(&self, mut progress: impl FnMut(ValidationProgress), cancel: &CancelToken) -> Result<bool, Box<dyn Error>> {
+        let mut search_hash = self.current_hash();
+        let genesis = self.genesis_config();
+        let network_id = genesis.network_id()?;
+        let trusted_base = self.trusted_base()?;
+        // The header and height of the block the walk came from.
+        let mut child: Option<(BlockHeader, u64)> = None;
+
+        let tip_height = self.block_meta(&search_hash)?.map_or(0, |meta| meta.height);
+        let base_height = match &trusted_base {
+            Some(base) => self.block_meta(base)?.map_or(0, |meta| meta.height),
+            None => 0,
+        };
+        let checkpoints: BTreeMap<u64, Checkpoint> =
+            self.checkpoints()?.into_iter().map(|checkpoint| (checkpoint.height, checkpoint)).collect();
+        // Configured checkpoints below the one the walk stops at still have to match.
+        for checkpoint in checkpoints.values().filter(|checkpoint| checkpoint.is_configured() && checkpoint.height <= tip_height) {
+            if self.canonical_hash(checkpoint.height)?.as_deref() != Some(checkpoint.hash.as_str()) {
+                error!(height = checkpoint.height, expected = %checkpoint.hash, "checkpoint mismatch");
+                return Ok(false);
+            }
+        }
+        let mut status = ValidationProgress { checked: 0, total: tip_height.saturating_sub(base_height) + 1, height: tip_height };
+        let walk = Walk {
+            genesis: &genesis,
+            trusted_base: trusted_base.as_deref(),
+            checkpoints: &checkpoints,
+            validate_from_checkpoint: self.config.validate_from_checkpoint,
+            cancel,
+        };
+        let threads = match self.config.validation_threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n,
+        };
+
+        loop {
+            // 1. Get the blocks from the DB
+            // The next stretch of the chain is found through the header records, and
+            // what each block can be checked for on its own (CHECK 1, and the parts of
+            // CHECK 3 that need no parent) runs for the whole stretch across threads.
+            // The links between the blocks are then checked in order.
+            let walk_height = tip_height.saturating_sub(status.checked);
+            let hashes = self.next_stretch(&search_hash, walk_height, &walk)?;
+            let checked = self.check_stretch(&hashes, walk_height, &walk, threads);
+            if cancel.is_cancelled() {
+                return Err(format!("Validation cancelled after {} of {} blocks", status.checked, status.total).into());
+            }
+
+            for (index, checked) in checked.into_iter().enumerate() {
+                let (block, height) = match checked {
+                    BlockCheck::Valid { header, height } => (*header, height),
+                    BlockCheck::Invalid => return Ok(false),
+                    BlockCheck::Missing => {
+                        // We were looking for a block that should exist (because a prev_hash pointed to it)
+                        // but we couldn't find it. The chain is broken.
+                        error!(block = %hashes[index], "broken link, block not found");
+                        return Ok(false);
+                    }
+                    BlockCheck::Failed(e) => return Err(e.into()),
+                    // Blocks are taken in order, so any skipped come after the one that failed.
+                    BlockCheck::Skipped => unreachable!("a block was skipped before any failed"),
+                };
+
+                // CHECK 2: Link Integrity
+                // We are using 'prev_hash' to find the next block; if this pointer is wrong,
+                // the next DB lookup will fail or return the wrong block. The stretch was
+                // found through the header records, so each block must also name the
+                // next one itself.
+                if let Some(next) = hashes.get(index + 1)
+                    && *next != block.prev_hash
+                {
+                    error!(block = %block.hash, parent = %block.prev_hash, header_parent = %next, "header record disagrees with its block");
+                    return Ok(false);
+                }
+
+                // CHECK 3: Timestamps, sequence numbers, proof of work, coinbase and double spends
+                // The block we came from may not be older than this one, nor too far in the
+                // future, must follow its sequence number and must carry the difficulty its
+                // height calls for. Its seal, coinbase and transactions were checked with
+                // the stretch.
+                if let Some((child, height)) = &child {
+                    let checked = check_timestamp(&child.hash, child.timestamp, block.timestamp, self.clock.now_ms(), self.config.max_future_drift_ms)
+                        .and_then(|_| check_sequence(child, &block))
+                        .and_then(|_| check_network_id(child, &block, &network_id))
+                        .and_then(|_| pow::check_difficulty(&genesis, child, &block, *height, |hash| self.trees.load_header(hash)));
+                    if let Err(e) = checked {
+                        error!(block = %child.hash, height, error = %e, "invalid block");
+                        return Ok(false);
+                    }
+                }
+
+                status.checked += 1;
+                status.height = tip_height.saturating_sub(status.checked - 1);
+                progress(status);
+
+                // CHECK 4: Checkpoints
+                // A configured checkpoint must be on the chain. Any that is, recorded
+                // ones included, may end the walk.
+                if let Some(checkpoint) = checkpoints.get(&status.height) {
+                    if checkpoint.hash != block.hash {
+                        if checkpoint.is_configured() {
+                            error!(height = status.height, expected = %checkpoint.hash, block = %block.hash, "checkpoint mismatch");
+                            return Ok(false);
+                        }
+                    } else if self.config.validate_from_checkpoint {
+                        info!(height = status.height, checkpoint = %block.hash, "chain valid back to checkpoint");
+                        return Ok(true);
+                    }
+                }
+
+                // Stop at Genesis
+                if block.is_genesis() {
+                    info!("chain valid, genesis reached");
+                    return Ok(true);
+                }
+
+                // Or at the snapshot this node was bootstrapped from
+                if trusted_base.as_deref() == Some(block.hash.as_str()) {
+                    info!(base = %block.hash, "chain valid, trusted snapshot base reached");
+                    return Ok(true);
+                }
+
+                // Move backwards
+                search_hash = block.prev_hash.clone();
+                child = Some((block, height));
+            }
+        }
+    }
+
+    // Up to `STRETCH` hashes from `hash` down, `hash` at `walk_height`, following the
+    // header records. Ends early at the block the walk stops at, and at one without a
+    // header record, whose parent only its body names.
+    fn next_stretch(&self, hash: &str, walk_height: u64, walk: &Walk) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut hashes = vec![hash.to_string()];
+        while hashes.len() < STRETCH {
+            let last = &hashes[hashes.len() - 1];
+            let Some(header) = self.trees.load_header(last)? else {
+                break;
+            };
+            if walk.stops(last, header.is_genesis(), walk_height.saturating_sub(hashes.len() as u64 - 1)) {
+                break;
+            }
+            hashes.push(header.prev_hash);
+        }
+        Ok(hashes)
+    }
+
+    // Checks the blocks of a stretch on `threads` threads, each taking the next
+    // unchecked block, and gives what was found in the stretch's order. Once a
+    // block fails, or the walk is cancelled, the ones not yet taken are `Skipped`.
+    // One thread is the calling one, so validation also runs where threads can't be
+    // spawned (wasm32).
+    fn check_stretch(&self, hashes: &[String], walk_height: u64, walk: &Walk, threads: usize) -> Vec<BlockCheck> {
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let work = || {
+            let mut found = Vec::new();
+            while !failed.load(Ordering::Relaxed) && !walk.cancel.is_cancelled() {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(hash) = hashes.get(index) else {
+                    break;
+                };
+                let checked = self
+                    .check_body(hash, walk_height.saturating_sub(index as u64), walk)
+                    .unwrap_or_else(|e| BlockCheck::Failed(e.to_string()));
+                if !matches!(checked, BlockCheck::Valid { .. }) {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                found.push((index, checked));
+            }
+            found
+        };
+        let found: Vec<Vec<(usize, BlockCheck)>> = match threads.clamp(1, hashes.len().max(1)) {
+            1 => vec![work()],
+            threads => thread::scope(|scope| {
+                let workers: Vec<_> = (0..threads).map(|_| scope.spawn(work)).collect();
+                workers.into_iter().map(|worker| worker.join().expect("validation thread panicked")).collect()
+            }),
+        };
+
+        let mut checked: Vec<BlockCheck> = hashes.iter().map(|_| BlockCheck::Skipped).collect();
+        for (index, found) in found.into_iter().flatten() {
+            checked[index] = found;
+        }
+        checked
+    }
+
+    // The checks of one block that need nothing but the block: the hash and size
+    // (CHECK 1), and for blocks the walk goes on past, the proof of work or
+    // signatures, the coinbase and duplicate transactions (CHECK 3). The block is
+    // read as a view into its stored record, so the data is hashed where it lies
+    // rather than copied out; only the header is kept.
+    fn check_body(&self, hash: &str, walk_height: u64, walk: &Walk) -> Result<BlockCheck, Box<dyn Error>> {
+        let height = self.block_meta(hash)?.map_or(0, |meta| meta.height);
+        let pruned = self.is_pruned(hash)?;
+        let checked = self.trees.read_block(hash, |block| {
+            let header = block.header();
+
+            // CHECK 1: Data Integrity
+            // We recalculate the hash using the data inside the block.
+            // If the data was edited, this calculated hash won't match the stored hash.
+            // Pruned blocks lost the data, so only their header linkage can be checked.
+            if pruned {
+                if block.hash != hash {
+                    error!(key = %hash, block = %block.hash, "pruned header stored under the wrong key");
+                    return Ok(BlockCheck::Invalid);
+                }
+            } else if block.hash != hashing::block_hash_with_header(block, &header) {
+                error!(block = %block.hash, "hash mismatch");
+                return Ok(BlockCheck::Invalid);
+            } else if let Err(e) = limits::check_size(&self.config, block) {
+                error!(block = %block.hash, error = %e, "block too large");
+                return Ok(BlockCheck::Invalid);
+            }
+
+            if !walk.stops(block.hash, block.is_genesis(), walk_height) {
+                let checked = pow::check_seal(walk.genesis, &header, height).and_then(|_| match pruned {
+                    true => Ok(()),
+                    false => coinbase::check_coinbase(walk.genesis, block, height).and_then(|_| conflicts::check_duplicates(block)),
+                });
+                if let Err(e) = checked {
+                    error!(block = %block.hash, height, error = %e, "invalid block");
+                    return Ok(BlockCheck::Invalid);
+                }
+            }
+            Ok(BlockCheck::Valid { header: Box::new(header), height })
+        })?;
+        Ok(checked.unwrap_or(BlockCheck::Missing))
+    }
+}
+
+// Blocks `walk_chain` checks at a time, across its threads.
+const STRETCH: usize = 1024;
+
+// What the blocks of a `walk_chain` are checked against, shared by its threads.
+struct Walk<'a> {
+    genesis: &'a GenesisConfig,
+    trusted_base: Option<&'a str>,
+    checkpoints: &'a BTreeMap<u64, Checkpoint>,
+    validate_from_checkpoint: bool,
+    cancel: &'a CancelToken,
+}
+
+impl Walk<'_> {
+    // Whether the walk ends at block `hash`, at `walk_height`, so that no parent of it
+    // is checked and neither is what only its parent would show (CHECK 3).
+    fn stops(&self, hash: &str, is_genesis: bool, walk_height: u64) -> bool {
+        is_genesis
+            || self.trusted_base == Some(hash)
+            || (self.validate_from_checkpoint
+                && self.checkpoints.get(&walk_height).is_some_and(|checkpoint| checkpoint.hash == hash))
+    }
+}
+
+// What `check_body` found out about one block.
+enum BlockCheck {
+    Valid { header: Box<BlockHeader>, height: u64 },
+    // Logged already.
+    Invalid,
+    Missing,
+    // Reading it failed; kept as text to cross threads.
+    Failed(String),
+    Skipped,
+}
+
+// Chain updates, staged in a batch so each one commits atomically.
+impl<S: BlockStore> ChainBatch<S> {
+    pub(crate) fn receive_block(&mut self, block: &Block) -> Result<BlockStatus, Box<dyn Error>> {
+        if block.hash != block.calculate_hash() {
+            return Err(format!("Block {} has an invalid hash", block.hash).into());
+        }
+        if self.contains(TreeId::Blocks, block.hash.as_bytes())? {
+            return Ok(BlockStatus::AlreadyKnown);
+        }
+
+        if self.is_invalid(&block.hash)? {
+            return Err(format!("Block {} is on a branch that failed to connect", block.hash).into());
+        }
+        if self.is_invalid(&block.prev_hash)? {
+            return Err(format!("Block {} builds on invalid block {}", block.hash, block.prev_hash).into());
+        }
+        // Caught before the parent lookup, which would only report it as unknown.
+        let network_id = self.genesis.network_id()?;
+        if !block.network_id.is_empty() && block.network_id != network_id {
+            return Err(format!("Block {} belongs to chain {}, not this chain ({})", block.hash, block.network_id, network_id).into());
+        }
+
+        let parent = self
+            .block_meta(&block.prev_hash)?
+            .ok_or_else(|| format!("Unknown parent {} for block {}", block.prev_hash, block.hash))?;
+        // A new block at a final height can never become canonical.
+        if block.prev_hash != self.tip {
+            self.check_finality(&block.hash, parent.height)?;
+        }
+        let meta = BlockMeta {
+            height: parent.height + 1,
+            total_work: parent.total_work + block_work(block.difficulty),
+        };
+        self.check_block(block, meta.height)?;
+        let tip_work = self.tip_meta()?.total_work;
+        self.store_block(block, &meta)?;
+
+        // Side-chain transactions are only checked once their branch connects, since
+        // they depend on the state at their parent.
+        if meta.total_work <= tip_work {
+            Ok(BlockStatus::SideChain)
+        } else if block.prev_hash == self.tip {
+            self.connect_block(block, meta.height)?;
+            self.set_tip(&block.hash);
+            Ok(BlockStatus::Extended)
+        } else {
+            self.reorganize(block)
+        }
+    }
+
+    // Switches the canonical chain over to the branch ending at `new_tip`. If one of
+    // its blocks fails to connect the batch must be dropped. Only when it broke a
+    // consensus rule is the failed part of the branch left in `rejected`; anything
+    // else (a store error, say) leaves the branch to be tried again.
+    fn reorganize(&mut self, new_tip: &Block) -> Result<BlockStatus, Box<dyn Error>> {
+        self.operation.get_or_insert("reorg");
+        // Walk the new branch back until it meets the canonical chain.
+        let mut branch = Vec::new();
+        let mut cursor = new_tip.clone();
+        let fork_height = loop {
+            let meta = self
+                .block_meta(&cursor.hash)?
+                .ok_or_else(|| format!("Block {} is not indexed", cursor.hash))?;
+            if self.canonical_hash(meta.height)?.as_deref() == Some(cursor.hash.as_str()) {
+                break meta.height;
+            }
+            let prev_hash = cursor.prev_hash.clone();
+            branch.push((cursor, meta.height));
+            cursor = self
+                .load_block(&prev_hash)?
+                .ok_or_else(|| format!("Broken link! Could not find block: {}", prev_hash))?;
+        };
+        self.check_finality(&new_tip.hash, fork_height)?;
+
+        let mut losing = Vec::new();
+        for height in fork_height + 1..=self.tip_meta()?.height {
+            let block = self.canonical_block(height)?;
+            // Re-connecting it later would need its transactions.
+            if self.is_pruned(&block.hash)? {
+                return Err(format!("Reorg would roll back pruned block {}", block.hash).into());
+            }
+            losing.push((block, height));
+        }
+
+        // Roll back the losing branch, newest block first.
+        for (block, height) in losing.iter().rev() {
+            self.disconnect_block(block, *height)?;
+        }
+
+        // Re-apply the winning branch, oldest block first.
+        let winning: Vec<_> = branch.into_iter().rev().collect();
+        for (index, (block, height)) in winning.iter().enumerate() {
+            if let Err(e) = self.connect_block(block, *height) {
+                let message = format!("Reorg to {} failed: {}", new_tip.hash, e);
+                if e.downcast_ref::<ConsensusError>().is_none() {
+                    return Err(message.into());
+                }
+                self.rejected = winning[index..].iter().map(|(block, _)| block.hash.clone()).collect();
+                return Err(ConsensusError::wrap(message));
+            }
+        }
+
+        self.set_tip(&new_tip.hash);
+        Ok(BlockStatus::Reorged {
+            disconnected: losing.into_iter().rev().map(|(block, _)| block.hash).collect(),
+            connected: winning.into_iter().map(|(block, _)| block.hash).collect(),
+        })
+    }
+
+    // Consensus rules that only need the block and its ancestors: size limits and
+    // those of `check_consensus`. The validation hooks run last.
+    pub(crate) fn check_block(&self, block: &Block, height: u64) -> Result<(), Box<dyn Error>> {
+        limits::check_size(&self.config, &block.view())?;
+        let parent = self
+            .load_header(&block.prev_hash)?
+            .ok_or_else(|| format!("Broken link! Could not find block: {}", block.prev_hash))?;
+        let (now, drift) = (self.clock.now_ms(), self.config.max_future_drift_ms);
+        check_consensus(&self.genesis, block, &parent, height, now, drift, |hash| self.load_header(hash))?;
+        self.check_hooks(block, height)
+    }
+
+    // Anything derived from the canonical chain (indexes, state) is applied here and
+    // undone in `disconnect_block`, so a reorg can move it block by block.
+    // Either fails before staging anything or applies the block completely.
+    pub(crate) fn connect_block(&mut self, block: &Block, height: u64) -> Result<(), Box<dyn Error>> {
+        if self.is_pruned(&block.hash)? {
+            return Err(format!("Cannot apply pruned block {}", block.hash).into());
+        }
+        let changes = self.state_changes(block, height)?;
+        if !block.is_genesis() {
+            let parent = self
+                .load_header(&block.prev_hash)?
+                .ok_or_else(|| format!("No header for block {}", block.prev_hash))?;
+            let root = self.state_root_with(&changes)?;
+            state::check_state_root(&block.header(), &parent, &root).map_err(ConsensusError::wrap)?;
+        }
+        self.apply_state_changes(block, &changes)?;
+        self.insert(TreeId::Heights, height.to_be_bytes(), block.hash.as_bytes());
+        self.index_block(block);
+        self.record_history(block, height)?;
+        self.index_transactions(block, height);
+        Ok(())
+    }
+
+    pub(crate) fn disconnect_block(&mut self, block: &Block, height: u64) -> Result<(), Box<dyn Error>> {
+        self.revert_state_changes(&block.hash)?;
+        self.remove(TreeId::Heights, height.to_be_bytes());
+        self.unindex_block(block);
+        self.forget_history(block, height);
+        self.unindex_transactions(block, height);
+        Ok(())
+    }
+
+    // The header goes into its own tree next to the body, so header-only reads don't
+    // decode the payload, and it stays there when the body is pruned. On an encrypted
+    // chain it is sealed as well (see `seal_header`).
+    pub(crate) fn store_block(&mut self, block: &Block, meta: &BlockMeta) -> Result<(), Box<dyn Error>> {
+        self.store_block_record(block)?;
+        let header = seal_header(&block.header(), self.cipher())?;
+        self.insert(TreeId::Headers, &block.hash, header);
+        self.insert(TreeId::Meta, &block.hash, serde_json::to_vec(meta)?);
+        self.remove(TreeId::Tips, &block.prev_hash);
+        self.insert(TreeId::Tips, &block.hash, []);
+        Ok(())
+    }
+
+    pub(crate) fn store_block_record(&mut self, block: &Block) -> Result<(), Box<dyn Error>> {
+        let record = seal_block(block, self.config.compression, self.cipher())?;
+        self.insert(TreeId::Blocks, &block.hash, record);
+        Ok(())
+    }
+
+    pub(crate) fn set_tip(&mut self, hash: &str) {
+        self.insert(TreeId::Blocks, "LAST", hash.as_bytes());
+        self.tip = hash.to_string();
+    }
+
+    pub(crate) fn tip_meta(&self) -> Result<BlockMeta, Box<dyn Error>> {
+        self.block_meta(&self.tip)?
+            .ok_or_else(|| format!("Tip {} is not indexed", self.tip).into())
+    }
+
+    // Swaps out the genesis block of a chain that holds nothing else yet, e.g. when
+    // importing a chain into a freshly created database. Callers check
+    // `holds_only_genesis` first.
+    pub(crate) fn replace_genesis(&mut self, genesis: &Block) -> Result<(), Box<dyn Error>> {
+        let old_hash = self.tip.clone();
+        if let Some(old) = self.load_block(&old_hash)? {
+            self.disconnect_block(&old, 0)?;
+        }
+        self.remove(TreeId::Blocks, &old_hash);
+        self.remove(TreeId::Headers, &old_hash);
+        self.remove(TreeId::Meta, &old_hash);
+        self.remove(TreeId::Tips, &old_hash);
+
+        // Config-derived genesis blocks carry their config as data, and their
+        // allocations must be known before the block is connected.
+        match serde_json::from_slice::<GenesisConfig>(&genesis.data) {
+            Ok(config) => {
+                self.insert(TreeId::Blocks, "GENESIS", serde_json::to_vec(&config)?);
+                self.genesis = config;
+            }
+            Err(_) => {
+                self.remove(TreeId::Blocks, "GENESIS");
+                self.genesis = GenesisConfig::legacy();
+            }
+        }
+
+        let meta = BlockMeta { height: 0, total_work: block_work(genesis.difficulty) };
+        self.store_block(genesis, &meta)?;
+        self.connect_block(genesis, 0)?;
+        self.set_tip(&genesis.hash);
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use std::error::Error;
+use tracing::{info, warn};
+
+use crate::blockchain::Blockchain;
+use crate::config::{self, Config};
+use crate::store::{BlockStore, TreeId};
+
+// Read when the config names no key file.
+pub const KEY_ENV: &str = "LEDGER_CHECKPOINT_KEY";
+
+// A canonical block validation can stop at instead of walking back to genesis.
+// Checkpoints from the config are trusted as they are; recorded ones carry an
+// HMAC-SHA256 of "height:hash" under the checkpoint key, so editing the database
+// cannot plant one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+}
+
+impl Checkpoint {
+    // Whether it came from the config rather than the checkpoints tree.
+    pub fn is_configured(&self) -> bool {
+        self.signature.is_empty()
+    }
+}
+
+pub(crate) fn key_from_config(config: &Config) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let key = config::read_key(config.checkpoint_key_file.as_deref(), KEY_ENV, "checkpoint")?;
+    if config.checkpoint_interval > 0 && key.is_none() {
+        return Err(format!("checkpoint_interval needs a checkpoint key; set checkpoint_key_file or {}", KEY_ENV).into());
+    }
+    Ok(key)
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Records a signed checkpoint at the current tip.
+    pub fn create_checkpoint(&self) -> Result<Checkpoint, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        self.take_checkpoint()
+    }
+
+    fn take_checkpoint(&self) -> Result<Checkpoint, Box<dyn Error>> {
+        let height = self.height()?;
+        let hash = self.current_hash();
+        let mac = self.checkpoint_mac(height, &hash)?;
+        let checkpoint = Checkpoint { height, hash, signature: hex::encode(mac.finalize().into_bytes()) };
+        self.store().insert(TreeId::Checkpoints, &height.to_be_bytes(), rmp_serde::to_vec(&checkpoint)?)?;
+        self.store().flush()?;
+        info!(height, hash = %checkpoint.hash, "checkpoint recorded");
+        Ok(checkpoint)
+    }
+
+    // Called whenever the tip moves.
+    pub(crate) fn maybe_checkpoint(&self) -> Result<(), Box<dyn Error>> {
+        let interval = self.config.checkpoint_interval;
+        if interval > 0 && self.height()? % interval == 0 {
+            self.take_checkpoint()?;
+        }
+        Ok(())
+    }
+
+    // The configured checkpoints and the recorded ones whose signature checks out, by
+    // height. Recorded ones are skipped without a key, and logged if forged. Where both
+    // name a height, the configured one wins.
+    pub fn checkpoints(&self) -> Result<Vec<Checkpoint>, Box<dyn Error>> {
+        let mut checkpoints = self.config.checkpoints.clone();
+        if self.shared.checkpoint_key.is_some() {
+            for entry in self.store().scan_prefix(TreeId::Checkpoints, &[]) {
+                let (_, bytes) = entry?;
+                let checkpoint: Checkpoint = rmp_serde::from_slice(&bytes)?;
+                let signature = hex::decode(&checkpoint.signature).unwrap_or_default();
+                if self.checkpoint_mac(checkpoint.height, &checkpoint.hash)?.verify_slice(&signature).is_err() {
+                    warn!(height = checkpoint.height, hash = %checkpoint.hash, "ignoring checkpoint with a bad signature");
+                    continue;
+                }
+                if !checkpoints.iter().any(|configured| configured.height == checkpoint.height) {
+                    checkpoints.push(checkpoint);
+                }
+            }
+        }
+        checkpoints.sort_by_key(|checkpoint| checkpoint.height);
+        Ok(checkpoints)
+    }
+
+    fn checkpoint_mac(&self, height: u64, hash: &str) -> Result<Hmac<Sha256>, Box<dyn Error>> {
+        let key = self
+            .shared
+            .checkpoint_key
+            .as_ref()
+            .ok_or_else(|| format!("Checkpoints are signed with the checkpoint key; set checkpoint_key_file or {}", KEY_ENV))?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)?;
+        mac.update(format!("{}:{}", height, hash).as_bytes());
+        Ok(mac)
+    }
+}
@@ -0,0 +1,20 @@
+use chrono::Utc;
+
+// Where a chain reads the time: the timestamps of the blocks it makes, the rule
+// against blocks from the future, and the times stamped on snapshots and audit
+// reports. Tests and fuzzers swap in a clock they control (see
+// `test_utils::ManualClock`), so nothing they check depends on the wall clock.
+pub trait Clock: Send + Sync + 'static {
+    // Milliseconds since the Unix epoch.
+    fn now_ms(&self) -> u64;
+}
+
+// The wall clock, used unless `Blockchain::with_clock` says otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        Utc::now().timestamp_millis() as u64
+    }
+}
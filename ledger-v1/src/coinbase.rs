@@ -0,0 +1,55 @@
+use std::error::Error;
+
+use crate::block::BlockView;
+use crate::genesis::GenesisConfig;
+use crate::transaction::Transaction;
+
+// On chains with a block reward, every block but genesis starts with exactly one
+// coinbase that mints `reward_at(height)` for the block's height plus the fees of the
+// block's transactions. On other chains only blocks that collect fees have one.
+pub(crate) fn check_coinbase(genesis: &GenesisConfig, block: &BlockView, height: u64) -> Result<(), Box<dyn Error>> {
+    let coinbases: Vec<_> = block
+        .transactions
+        .iter()
+        .enumerate()
+        .filter(|(_, transaction)| matches!(transaction, Transaction::Coinbase { .. }))
+        .collect();
+
+    let fees = block_fees(&block.transactions)?;
+    if block.is_genesis() || !needs_coinbase(genesis, fees) {
+        if !coinbases.is_empty() {
+            return Err(format!("Block {} carries a coinbase but has no reward or fees to collect", block.hash).into());
+        }
+        return Ok(());
+    }
+
+    match coinbases.as_slice() {
+        [(0, Transaction::Coinbase { amount, height: coinbase_height, .. })] => {
+            let reward = genesis
+                .reward_at(height)
+                .checked_add(fees)
+                .ok_or_else(|| format!("Fees of block {} overflow", block.hash))?;
+            if *amount != reward {
+                return Err(format!("Coinbase of block {} mints {} instead of {}", block.hash, amount, reward).into());
+            }
+            if *coinbase_height != height {
+                return Err(format!("Coinbase of block {} is for height {}, not {}", block.hash, coinbase_height, height).into());
+            }
+            Ok(())
+        }
+        [] => Err(format!("Block {} has no coinbase", block.hash).into()),
+        [_] => Err(format!("The coinbase of block {} must be its first transaction", block.hash).into()),
+        _ => Err(format!("Block {} has {} coinbases", block.hash, coinbases.len()).into()),
+    }
+}
+
+pub(crate) fn needs_coinbase(genesis: &GenesisConfig, fees: u64) -> bool {
+    genesis.block_reward > 0 || fees > 0
+}
+
+pub(crate) fn block_fees(transactions: &[Transaction]) -> Result<u64, Box<dyn Error>> {
+    transactions
+        .iter()
+        .try_fold(0u64, |total, transaction| total.checked_add(transaction.fee()))
+        .ok_or_else(|| "Transaction fees overflow".into())
+}
@@ -0,0 +1,340 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::anchor::AnchorTarget;
+use crate::auth::{ApiKey, Role};
+use crate::checkpoint::Checkpoint;
+use crate::signer::RemoteSignerConfig;
+
+// What a node keeps, chosen at startup (see `Config::for_mode`). Full nodes keep the
+// state and whatever the rest of the config asks for; the other modes are profiles
+// that fix the settings they depend on.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeMode {
+    #[default]
+    Full,
+    // Every block, side branches included, and every index. Never prunes.
+    Archive,
+    // The state and the bodies of the newest `prune_depth` blocks (by default
+    // `DEFAULT_PRUNE_DEPTH`); older blocks keep their headers only.
+    Pruned,
+    // Headers only, kept by `HeaderChain` rather than `Blockchain`.
+    Light,
+}
+
+impl FromStr for NodeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(NodeMode::Full),
+            "archive" => Ok(NodeMode::Archive),
+            "pruned" => Ok(NodeMode::Pruned),
+            "light" => Ok(NodeMode::Light),
+            other => Err(format!("Unknown mode '{}' (expected full, archive, pruned or light)", other)),
+        }
+    }
+}
+
+// The `prune_depth` of pruned nodes whose config leaves it at 0.
+pub const DEFAULT_PRUNE_DEPTH: u64 = 1000;
+
+// How block records are compressed before they are written. Changing it leaves
+// existing records as they are until `Blockchain::migrate_encoding` runs.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+}
+
+// When committed blocks are flushed to disk. sled never loses the consistency of the
+// database in a crash: a commit is either entirely there afterwards or not at all,
+// and so is everything after it. What a policy trades away is how much of the newest
+// work a crash can undo; in exchange for that window, bulk loads skip most flushes.
+// The tip and announcements are updated at commit, so peers and subscribers may have
+// seen blocks the node no longer has after a crash. sled also flushes on its own in
+// the background about every 500 ms.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    // Every commit is on disk before it returns. A crash loses nothing.
+    #[default]
+    EveryBlock,
+    // Flush after every N commits; a crash loses up to the last N - 1.
+    EveryN(u64),
+    // Flush on the first commit at least this many milliseconds after the last flush.
+    IntervalMs(u64),
+    // Only `Blockchain::flush` and maintenance such as pruning or snapshots flush.
+    Manual,
+}
+
+// What opening a chain does with an operation the journal shows was interrupted
+// (see `journal`).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalRecovery {
+    // Write what the operation was about to, finishing it.
+    #[default]
+    Replay,
+    // Put back what it was about to replace, as if it never started.
+    Rollback,
+}
+
+// Local node settings. Unlike the genesis config these can differ between nodes
+// sharing a chain.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    // Take a state snapshot every N blocks; 0 disables periodic snapshots.
+    pub snapshot_interval: u64,
+    // Keep block bodies only for the newest N blocks; 0 keeps everything.
+    pub prune_depth: u64,
+    // Blocks off the canonical chain are garbage collected once their branch ends
+    // more than `gc_retention` blocks below the tip. `gc_interval` collects every N
+    // blocks; 0 leaves it to `Blockchain::gc`.
+    pub gc_retention: u64,
+    pub gc_interval: u64,
+    // Garbage collection also removes attachments no stored block refers to (see
+    // `blobs`), once they were uploaded at least this many milliseconds ago, so one
+    // uploaded for a block still being mined is kept.
+    pub blob_grace_ms: u64,
+    // Retention classes blocks and transactions can be tagged with (see `retention`),
+    // by name: how many milliseconds past its block's timestamp what a class tags is
+    // kept, 0 for indefinitely.
+    pub retention_classes: BTreeMap<String, u64>,
+    // How far (in milliseconds) a block's timestamp may be ahead of the local clock.
+    pub max_future_drift_ms: u64,
+    // Where the reward of blocks mined by this node goes.
+    pub miner_address: String,
+    // Blocks assembled from the mempool hold at most this many bytes of transactions.
+    pub max_block_bytes: u64,
+    // Limits on every block, checked when it is added and by `is_chain_valid`: the
+    // size of its data, and of its whole encoded record. 0 lifts a limit.
+    pub max_payload_bytes: u64,
+    pub max_block_size: u64,
+    pub mode: NodeMode,
+    // Threads searching for a block's nonce; 0 uses every core.
+    pub miner_threads: usize,
+    // Keep a token index so `Blockchain::lookup` doesn't scan the chain. With an
+    // encryption key it holds keyed hashes of the tokens (see `search`).
+    pub search_index: bool,
+    // Hex-encoded 32-byte key for encrypting block records at rest. Without it the
+    // LEDGER_ENCRYPTION_KEY variable is used, if set.
+    pub encryption_key_file: Option<PathBuf>,
+    pub compression: Compression,
+    // Hex-encoded key signing `AuditLedger` reports. Without it the LEDGER_AUDIT_KEY
+    // variable is used, if set.
+    pub audit_key_file: Option<PathBuf>,
+    // Blocks known to be canonical. Validation fails if the chain disagrees with one.
+    pub checkpoints: Vec<Checkpoint>,
+    // Record a signed checkpoint every N blocks; 0 disables them. Needs a checkpoint
+    // key, from `checkpoint_key_file` or else the LEDGER_CHECKPOINT_KEY variable.
+    pub checkpoint_interval: u64,
+    pub checkpoint_key_file: Option<PathBuf>,
+    // Have `is_chain_valid` stop at the newest checkpoint instead of genesis.
+    pub validate_from_checkpoint: bool,
+    // Publish the tip to `anchor_target` every N blocks; 0 leaves it to
+    // `Blockchain::anchor` (see `anchor`). Attestations written to a log are signed
+    // with the hex ed25519 secret key in `anchor_key_file`, or else the
+    // LEDGER_ANCHOR_KEY variable, or by `anchor_signer`.
+    pub anchor_interval: u64,
+    pub anchor_target: Option<AnchorTarget>,
+    pub anchor_key_file: Option<PathBuf>,
+    pub anchor_signer: Option<RemoteSignerConfig>,
+    // Threads `is_chain_valid` checks block hashes and signatures on; 0 uses every
+    // core.
+    pub validation_threads: usize,
+    // Hex-encoded ed25519 secret key this node signs blocks with on proof-of-authority
+    // chains. Without it the LEDGER_AUTHORITY_KEY variable is used, if set.
+    pub authority_key_file: Option<PathBuf>,
+    // Sign with a signing service instead of a key file (see `signer`).
+    pub authority_signer: Option<RemoteSignerConfig>,
+    // host:port other nodes reach this node's gRPC server at, shared with the peers
+    // it syncs from so they can pass it on.
+    pub advertise_address: Option<String>,
+    // Blocks this far below the tip are final and never reorganized away; 0 lets any
+    // reorg with more work through.
+    pub finality_depth: u64,
+    pub durability: Durability,
+    pub journal_recovery: JournalRecovery,
+    // Blocks received before their parent are held for up to `orphan_expiry_ms` (0
+    // keeps them until the pool is full), at most `max_orphans` at a time. With
+    // `max_orphans` at 0 such blocks are rejected.
+    pub max_orphans: usize,
+    pub orphan_expiry_ms: u64,
+    // Mempool limits: the lowest fee a transaction may pay, how many transactions one
+    // sender may have waiting, and how many the pool holds before the lowest fee rate
+    // ones give way. 0 lifts a limit.
+    pub min_fee: u64,
+    pub max_pending_per_sender: usize,
+    pub max_mempool_transactions: usize,
+    // Requests each client address may make per minute to the gRPC, WebSocket and
+    // metrics servers; 0 disables rate limiting.
+    pub rate_limit_per_minute: u32,
+    // WebSocket subscribers served at once; more are refused with 503 until one
+    // leaves. 0 lifts the limit.
+    pub max_websocket_clients: usize,
+    // gRPC block streams served at once; more are refused with RESOURCE_EXHAUSTED
+    // until one ends. 0 lifts the limit.
+    pub max_grpc_streams: usize,
+    // http:// URLs notified of new blocks, reorgs and rejected blocks (see `webhooks`).
+    // Deliveries are signed with the hex HMAC key in `webhook_key_file`, or else the
+    // LEDGER_WEBHOOK_KEY variable, if either is set, and retried up to
+    // `webhook_retries` times.
+    pub webhooks: Vec<String>,
+    pub webhook_key_file: Option<PathBuf>,
+    pub webhook_retries: u32,
+    // API keys the servers accept, each with a role (see `auth`). Without any, every
+    // call is allowed. Callers without a key get `anonymous_role`, or are refused
+    // when it is unset; "read_only" there lets peers without a key sync.
+    pub api_keys: Vec<ApiKey>,
+    pub anonymous_role: Option<Role>,
+    // JSON Schema files the payloads of blocks added through this node must meet (see
+    // `schema`): `payload_schema` for every block, and each of `tag_schemas` for
+    // blocks tagged with the "key=value" metadata entry it is listed under. With
+    // `deep_validate_schemas`, `deep_validate` also reports stored blocks that don't.
+    pub payload_schema: Option<PathBuf>,
+    pub tag_schemas: BTreeMap<String, PathBuf>,
+    pub deep_validate_schemas: bool,
+    // WebAssembly modules every block and transaction this node accepts is offered to
+    // (see `wasm_validator`, which needs the wasm-validators feature), each call
+    // limited to `validator_fuel` units of fuel.
+    pub validator_modules: Vec<PathBuf>,
+    pub validator_fuel: u64,
+    // Hex-encoded ed25519 secret key this node approves proposed blocks with, on
+    // chains with approvers. Without it the LEDGER_APPROVER_KEY variable is used, if
+    // set.
+    pub approver_key_file: Option<PathBuf>,
+    pub approver_signer: Option<RemoteSignerConfig>,
+    // Keep a copy of the database in this directory for readers while the node runs,
+    // refreshed every `read_replica_interval` blocks (see `replica`); 0 leaves it as
+    // it is.
+    pub read_replica: Option<PathBuf>,
+    pub read_replica_interval: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            snapshot_interval: 0,
+            prune_depth: 0,
+            gc_retention: 100,
+            gc_interval: 0,
+            blob_grace_ms: 60 * 60 * 1000,
+            retention_classes: BTreeMap::new(),
+            max_future_drift_ms: 2 * 60 * 60 * 1000,
+            miner_address: String::new(),
+            max_block_bytes: 1_000_000,
+            max_payload_bytes: 16 << 20,
+            max_block_size: 32 << 20,
+            mode: NodeMode::Full,
+            miner_threads: 0,
+            search_index: false,
+            encryption_key_file: None,
+            compression: Compression::None,
+            audit_key_file: None,
+            checkpoints: Vec::new(),
+            checkpoint_interval: 0,
+            checkpoint_key_file: None,
+            validate_from_checkpoint: false,
+            anchor_interval: 0,
+            anchor_target: None,
+            anchor_key_file: None,
+            anchor_signer: None,
+            validation_threads: 0,
+            authority_key_file: None,
+            authority_signer: None,
+            advertise_address: None,
+            finality_depth: 0,
+            durability: Durability::EveryBlock,
+            journal_recovery: JournalRecovery::Replay,
+            max_orphans: 100,
+            orphan_expiry_ms: 10 * 60 * 1000,
+            min_fee: 0,
+            max_pending_per_sender: 0,
+            max_mempool_transactions: 10_000,
+            rate_limit_per_minute: 0,
+            max_websocket_clients: 1_000,
+            max_grpc_streams: 1_000,
+            webhooks: Vec::new(),
+            webhook_key_file: None,
+            webhook_retries: 5,
+            api_keys: Vec::new(),
+            anonymous_role: None,
+            payload_schema: None,
+            tag_schemas: BTreeMap::new(),
+            deep_validate_schemas: false,
+            validator_modules: Vec::new(),
+            validator_fuel: 10_000_000,
+            approver_key_file: None,
+            approver_signer: None,
+            read_replica: None,
+            read_replica_interval: 0,
+        }
+    }
+}
+
+impl Config {
+    // Reads a `.toml` file, or JSON for any other extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)?,
+            _ => serde_json::from_str(&contents)?,
+        };
+        Ok(config)
+    }
+
+    // This config with the settings its mode implies filled in, failing if it sets
+    // one against the mode.
+    pub fn for_mode(mut self) -> Result<Config, Box<dyn Error>> {
+        match self.mode {
+            NodeMode::Full | NodeMode::Light => {}
+            NodeMode::Archive => {
+                if self.prune_depth > 0 {
+                    return Err("An archive node keeps every block; set prune_depth to 0".into());
+                }
+                if self.gc_interval > 0 {
+                    return Err("An archive node keeps side branches too; set gc_interval to 0".into());
+                }
+                self.search_index = true;
+            }
+            NodeMode::Pruned => {
+                // The index points at bodies that pruning drops, and rebuilding it
+                // would need them back.
+                if self.search_index {
+                    return Err("A pruned node cannot keep a search index; turn search_index off".into());
+                }
+                if self.prune_depth == 0 {
+                    self.prune_depth = DEFAULT_PRUNE_DEPTH;
+                }
+                // Old side branches go with the old bodies.
+                if self.gc_interval == 0 {
+                    self.gc_interval = self.gc_retention.max(1);
+                }
+            }
+        }
+        Ok(self)
+    }
+}
+
+// A hex-encoded key from `file`, or else the `env` variable. None if neither is set.
+pub(crate) fn read_key(file: Option<&Path>, env: &str, what: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let encoded = match file {
+        Some(path) => Some(
+            std::fs::read_to_string(path).map_err(|e| format!("Cannot read {} key {}: {}", what, path.display(), e))?,
+        ),
+        None => std::env::var(env).ok(),
+    };
+    match encoded {
+        Some(encoded) => Ok(Some(hex::decode(encoded.trim()).map_err(|_| format!("The {} key must be hex-encoded", what))?)),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+
+use crate::block::BlockView;
+use crate::blockchain::Blockchain;
+use crate::store::BlockStore;
+use crate::transaction::Transaction;
+
+// Balances are accounts rather than outputs, so spending the same funds twice means
+// two transfers that the sender's balance only covers one of. The mempool refuses the
+// second, and a block may not carry the same transaction twice; overdrawing blocks
+// already fail when their state changes are applied. Replaying a spend is caught by
+// its sender nonce.
+//
+// Why a transaction cannot be submitted. `submit_transaction` returns the first one
+// boxed, so callers can `downcast_ref::<Conflict>()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    // The same transaction is already waiting.
+    AlreadyPending { txid: String },
+    // The sender's balance covers this transfer, but not on top of `pending`, the
+    // sender's transfers already waiting in the mempool.
+    DoubleSpend { sender: String, balance: u64, needed: u128, pending: Vec<String> },
+    // The transfer alone spends more than the sender has.
+    InsufficientFunds { sender: String, balance: u64, needed: u128 },
+    // Another unlock of the same lock is already waiting.
+    LockClaimed { lock: String, pending: String },
+    // The spend's nonce is not the sender's next one (see `Blockchain::next_nonce`):
+    // used already, e.g. by a replay, or ahead of it. The mempool takes no spends
+    // without a nonce.
+    WrongNonce { sender: String, nonce: Option<u64>, expected: u64 },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Conflict::AlreadyPending { txid } => write!(f, "Transaction {} is already pending", txid),
+            Conflict::DoubleSpend { sender, balance, needed, pending } => write!(
+                f,
+                "Double spend: {} has {} but its pending transfers ({}) and this one need {}",
+                sender,
+                balance,
+                pending.join(", "),
+                needed
+            ),
+            Conflict::InsufficientFunds { sender, balance, needed } => {
+                write!(f, "{} has {} but the transfer needs {}", sender, balance, needed)
+            }
+            Conflict::LockClaimed { lock, pending } => {
+                write!(f, "Lock {} is already being unlocked by pending transaction {}", lock, pending)
+            }
+            Conflict::WrongNonce { sender, nonce: None, expected } => {
+                write!(f, "Transactions from {} need a nonce; the next is {}", sender, expected)
+            }
+            Conflict::WrongNonce { sender, nonce: Some(nonce), expected } if nonce < expected => {
+                write!(f, "Nonce {} of {} is already used; the next is {}", nonce, sender, expected)
+            }
+            Conflict::WrongNonce { sender, nonce: Some(nonce), expected } => {
+                write!(f, "Nonce {} of {} is ahead of the next one, {}", nonce, sender, expected)
+            }
+        }
+    }
+}
+
+impl Error for Conflict {}
+
+impl<S: BlockStore> Blockchain<S> {
+    // What stands between `transaction` and the mempool among spends of the same
+    // funds, against the canonical state and the other pending transactions. Empty if
+    // nothing does; other checks (e.g. lock conditions) may still refuse it. Coinbases
+    // never conflict here; the mempool refuses them outright.
+    pub fn conflicts(&self, transaction: &Transaction) -> Result<Vec<Conflict>, Box<dyn Error>> {
+        let txid = transaction.hash();
+        let mempool = self.shared.mempool.lock().unwrap();
+        let mut conflicts = Vec::new();
+        if mempool.contains(&txid) {
+            conflicts.push(Conflict::AlreadyPending { txid: txid.clone() });
+        }
+        if let Transaction::Unlock { lock, .. } = transaction
+            && let Some(pending) = mempool.unlocking(lock).filter(|pending| **pending != txid)
+        {
+            conflicts.push(Conflict::LockClaimed { lock: lock.clone(), pending: pending.clone() });
+        }
+        let Some((from, needed)) = transaction.spend() else {
+            return Ok(conflicts);
+        };
+        let expected = self.next_nonce_locked(from, &mempool)?;
+        if transaction.nonce() != Some(expected) && !mempool.contains(&txid) {
+            conflicts.push(Conflict::WrongNonce { sender: from.to_string(), nonce: transaction.nonce(), expected });
+        }
+
+        let balance = self.get_account(from)?.balance;
+        if needed > balance as u128 {
+            conflicts.push(Conflict::InsufficientFunds { sender: from.to_string(), balance, needed });
+            return Ok(conflicts);
+        }
+        let mut pending = Vec::new();
+        let mut total = needed;
+        for (pending_txid, transfer) in mempool.sent_by(from) {
+            if *pending_txid != txid {
+                total += transfer.spend().map_or(0, |(_, spent)| spent);
+                pending.push(pending_txid.clone());
+            }
+        }
+        if total > balance as u128 {
+            conflicts.push(Conflict::DoubleSpend { sender: from.to_string(), balance, needed: total, pending });
+        }
+        Ok(conflicts)
+    }
+}
+
+// A block spends each transaction once.
+pub(crate) fn check_duplicates(block: &BlockView) -> Result<(), Box<dyn Error>> {
+    let mut seen = HashSet::new();
+    for transaction in block.transactions.iter() {
+        let txid = transaction.hash();
+        if !seen.insert(txid.clone()) {
+            return Err(format!("Block {} carries transaction {} twice", block.hash, txid).into());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use crate::batch::ReadTrees;
+use crate::blockchain::Blockchain;
+use crate::encoding::{is_block_key, open_block};
+use crate::store::{BlockStore, TreeId};
+use tracing::instrument;
+
+// Named keys the chain keeps next to the blocks.
+const MARKERS: [&str; 10] = [
+    "LAST", "GENESIS", "SCHEMA", "BASE", "PRUNED_TO", "STATE_BUILT", "HISTORY_BUILT", "SEARCH_BUILT", "TXINDEX_BUILT",
+    "MEMPOOL",
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    // The record doesn't decode (damaged, or encrypted with another key).
+    Undecodable,
+    // The record holds a block with another hash than its key.
+    WrongKey,
+    // The block no longer hashes to its hash.
+    HashMismatch,
+    // The block has no height and work recorded.
+    MissingMeta,
+    // The block has no header record, or one that doesn't match the block.
+    MissingHeader,
+    // The block's parent is not stored.
+    Orphan,
+    // No tip leads to the block, so nothing will ever build on or find it.
+    Unreachable,
+    // A key in the blocks tree that is neither a block nor a known marker.
+    UnknownKey,
+    // The tip pointer names a block that is not stored.
+    MissingTip,
+    // There is not exactly one genesis block.
+    GenesisCount,
+    // The payload does not meet its schema; only checked with
+    // `Config::deep_validate_schemas`.
+    SchemaViolation,
+    // The block refers to an attachment whose blob is not stored (see `blobs`).
+    MissingBlob,
+    // The attachment's blob no longer hashes to its hash, or doesn't decode.
+    DamagedBlob,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Finding {
+    pub problem: Problem,
+    pub key: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ConsistencyReport {
+    // Block records examined.
+    pub blocks: u64,
+    pub genesis: Vec<String>,
+    // Sorted by problem, then key.
+    pub findings: Vec<Finding>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    fn add(&mut self, problem: Problem, key: &str, detail: impl Into<String>) {
+        self.findings.push(Finding { problem, key: key.to_string(), detail: detail.into() });
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Unlike `is_chain_valid`, which follows the canonical chain back from the tip,
+    // this reads every record in the blocks tree: each block must decode and hash to
+    // its key, have a matching header record and a stored parent, and be reachable
+    // from a tip, and there must be exactly one genesis block. Side branches are fine
+    // as long as a tip leads to them; blocks marked invalid are not reported as
+    // unreachable. The attachments of unpruned blocks must be stored and intact. With
+    // `Config::deep_validate_schemas` payloads are checked against the schemas too.
+    #[instrument(skip_all)]
+    pub fn deep_validate(&self) -> Result<ConsistencyReport, Box<dyn Error>> {
+        let mut report = ConsistencyReport::default();
+        let mut parents: HashMap<String, String> = HashMap::new();
+        for entry in self.store().scan_prefix(TreeId::Blocks, &[]) {
+            let (key, bytes) = entry?;
+            let key = String::from_utf8_lossy(&key).into_owned();
+            if !is_block_key(key.as_bytes()) {
+                if !MARKERS.contains(&key.as_str()) {
+                    report.add(Problem::UnknownKey, &key, format!("{} bytes", bytes.len()));
+                }
+                continue;
+            }
+            report.blocks += 1;
+            let block = match open_block(&bytes, self.trees.cipher()) {
+                Ok(block) => block,
+                Err(e) => {
+                    report.add(Problem::Undecodable, &key, e.to_string());
+                    continue;
+                }
+            };
+            if block.hash != key {
+                report.add(Problem::WrongKey, &key, format!("holds block {}", block.hash));
+            } else if !self.is_pruned(&key)? && block.hash != block.calculate_hash() {
+                report.add(Problem::HashMismatch, &key, format!("hashes to {}", block.calculate_hash()));
+            }
+            if self.block_meta(&key)?.is_none() {
+                report.add(Problem::MissingMeta, &key, "");
+            }
+            match self.trees.load_header(&key) {
+                Ok(Some(header)) if header.hash != key => {
+                    report.add(Problem::MissingHeader, &key, format!("header record is for {}", header.hash));
+                }
+                Ok(Some(header)) if header.prev_hash != block.prev_hash || (!self.is_pruned(&key)? && header != block.header()) => {
+                    report.add(Problem::MissingHeader, &key, "header record does not match the block");
+                }
+                Ok(Some(_)) => {}
+                // Pruned before headers were kept; there is nothing left to rebuild it from.
+                Ok(None) if self.is_pruned(&key)? => {}
+                Ok(None) => report.add(Problem::MissingHeader, &key, ""),
+                Err(e) => report.add(Problem::MissingHeader, &key, e.to_string()),
+            }
+            if let Err(e) = self.check_stored_payload(&block, self.is_pruned(&key)?) {
+                report.add(Problem::SchemaViolation, &key, e.to_string());
+            }
+            // A pruned block's attachments went with its body.
+            if !self.is_pruned(&key)? {
+                for (name, hash) in block.attachments() {
+                    match self.get_blob(hash) {
+                        Ok(Some(_)) => {}
+                        Ok(None) => report.add(Problem::MissingBlob, &key, format!("attachment {} (blob {})", name, hash)),
+                        Err(e) => report.add(Problem::DamagedBlob, &key, format!("attachment {}: {}", name, e)),
+                    }
+                }
+            }
+            if block.is_genesis() {
+                report.genesis.push(key.clone());
+            }
+            parents.insert(key, block.prev_hash);
+        }
+
+        // A snapshot bootstrap stores nothing below its base, genesis included.
+        let base = self.trusted_base()?;
+        for (hash, parent) in &parents {
+            if parent != "0" && !parents.contains_key(parent) && base.as_ref() != Some(hash) {
+                report.add(Problem::Orphan, hash, format!("parent {} is not stored", parent));
+            }
+        }
+        match report.genesis.len() {
+            1 => {}
+            0 if base.is_some() => {}
+            count => {
+                let genesis = report.genesis.join(", ");
+                report.add(Problem::GenesisCount, "", format!("{} genesis blocks: {}", count, genesis));
+            }
+        }
+
+        let tip = self.current_hash();
+        if !parents.contains_key(&tip) {
+            report.add(Problem::MissingTip, &tip, "");
+        }
+        let mut reached = HashSet::new();
+        for start in self.tips()?.into_iter().chain([tip]) {
+            let mut hash = start;
+            while let Some(parent) = parents.get(&hash) {
+                if !reached.insert(hash.clone()) {
+                    break;
+                }
+                hash = parent.clone();
+            }
+        }
+        for hash in parents.keys() {
+            if !reached.contains(hash) && !self.is_invalid(hash)? {
+                report.add(Problem::Unreachable, hash, "");
+            }
+        }
+
+        report.genesis.sort();
+        report.findings.sort_by(|a, b| (a.problem, &a.key).cmp(&(b.problem, &b.key)));
+        Ok(report)
+    }
+}
@@ -0,0 +1,184 @@
+use serde::Deserialize;
+use std::error::Error;
+
+use crate::block::{Block, BlockView};
+use crate::header::BlockHeader;
+use crate::config::Compression;
+use crate::encryption::BlockCipher;
+use crate::hashing::HashAlgorithm;
+use crate::transaction::Transaction;
+
+// On-disk block records start with an envelope byte naming the format. Records
+// written before the envelope existed are plain JSON and always start with '{'.
+const ENVELOPE_MSGPACK_V1: u8 = 1;
+// A whole record in one of the other envelopes, sealed by a `BlockCipher`.
+const ENVELOPE_ENCRYPTED: u8 = 2;
+// LZ4 of the MessagePack body, prefixed with its uncompressed size.
+const ENVELOPE_LZ4: u8 = 3;
+
+// A record from before the envelope. Its data is plain text rather than the base64
+// that JSON blocks carry now.
+#[derive(Deserialize)]
+struct JsonRecord {
+    timestamp: u64,
+    data: String,
+    prev_hash: String,
+    hash: String,
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    transactions: Vec<Transaction>,
+    #[serde(default)]
+    difficulty: u32,
+    #[serde(default)]
+    nonce: u64,
+}
+
+impl From<JsonRecord> for Block {
+    fn from(record: JsonRecord) -> Block {
+        Block {
+            timestamp: record.timestamp,
+            data: record.data.into_bytes(),
+            prev_hash: record.prev_hash,
+            hash: record.hash,
+            version: record.version,
+            transactions: record.transactions,
+            difficulty: record.difficulty,
+            nonce: record.nonce,
+            hash_algorithm: HashAlgorithm::Sha256,
+            content_type: None,
+            metadata: Default::default(),
+            signature: String::new(),
+            sequence: 0,
+            network_id: String::new(),
+            state_root: String::new(),
+            approvals: Vec::new(),
+        }
+    }
+}
+
+// MessagePack arrays carry their length, so fields appended to `Block` later (with
+// serde defaults) still decode from older records, unlike fixed-layout formats.
+pub fn encode_block(block: &Block) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = vec![ENVELOPE_MSGPACK_V1];
+    bytes.extend(rmp_serde::to_vec(block)?);
+    Ok(bytes)
+}
+
+pub fn decode_block(bytes: &[u8]) -> Result<Block, Box<dyn Error>> {
+    open_block(bytes, None)
+}
+
+// The stored form of a block: `encode_block`, compressed if the node is configured
+// to, then sealed when it has a key.
+pub(crate) fn seal_block(
+    block: &Block,
+    compression: Compression,
+    cipher: Option<&BlockCipher>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = encode_block(block)?;
+    if compression == Compression::Lz4 {
+        let mut compressed = vec![ENVELOPE_LZ4];
+        compressed.extend(lz4_flex::compress_prepend_size(&bytes[1..]));
+        bytes = compressed;
+    }
+    match cipher {
+        Some(cipher) => {
+            let mut sealed = vec![ENVELOPE_ENCRYPTED];
+            sealed.extend(cipher.seal(&bytes)?);
+            Ok(sealed)
+        }
+        None => Ok(bytes),
+    }
+}
+
+// Records in any older form still decode, so a database can switch compression or
+// encryption gradually (see `Blockchain::migrate_encoding`).
+pub(crate) fn open_block(bytes: &[u8], cipher: Option<&BlockCipher>) -> Result<Block, Box<dyn Error>> {
+    match bytes.first() {
+        Some(b'{') => Ok(serde_json::from_slice::<JsonRecord>(bytes)?.into()),
+        Some(&ENVELOPE_ENCRYPTED) => {
+            let cipher = cipher.ok_or("Block record is encrypted, but no encryption key is configured")?;
+            let plaintext = cipher.open(&bytes[1..])?;
+            if matches!(plaintext.first(), Some(&ENVELOPE_ENCRYPTED | b'{')) {
+                return Err("Unknown encoding inside an encrypted block record".into());
+            }
+            open_block(&plaintext, None)
+        }
+        Some(&ENVELOPE_MSGPACK_V1) => Ok(rmp_serde::from_slice(&bytes[1..])?),
+        Some(&ENVELOPE_LZ4) => Ok(rmp_serde::from_slice(&lz4_flex::decompress_size_prepended(&bytes[1..])?)?),
+        Some(other) => Err(format!("Unknown block encoding {:#04x}", other).into()),
+        None => Err("Empty block record".into()),
+    }
+}
+
+// Calls `read` with a view of the block in `bytes`. Plain MessagePack records are
+// viewed in place; the others have to be unpacked into a `Block` first.
+pub(crate) fn view_block<T>(
+    bytes: &[u8],
+    cipher: Option<&BlockCipher>,
+    read: impl FnOnce(&BlockView) -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    match bytes.first() {
+        Some(&ENVELOPE_MSGPACK_V1) => read(&rmp_serde::from_slice(&bytes[1..])?),
+        _ => read(&open_block(bytes, cipher)?.view()),
+    }
+}
+
+// Whether a record is already in the form `seal_block` would write. Encrypted records
+// count as current when there is no key to look inside them with.
+pub(crate) fn is_current(
+    bytes: &[u8],
+    compression: Compression,
+    cipher: Option<&BlockCipher>,
+) -> Result<bool, Box<dyn Error>> {
+    let compressed = |bytes: &[u8]| bytes.first() == Some(&ENVELOPE_LZ4);
+    match (bytes.first(), cipher) {
+        (Some(&ENVELOPE_ENCRYPTED), None) => Ok(true),
+        (Some(&ENVELOPE_ENCRYPTED), Some(cipher)) => {
+            Ok(compressed(&cipher.open(&bytes[1..])?) == (compression == Compression::Lz4))
+        }
+        (_, Some(_)) => Ok(false),
+        (_, None) => Ok(!is_legacy_json(bytes) && compressed(bytes) == (compression == Compression::Lz4)),
+    }
+}
+
+// Header records are plain MessagePack, or with a cipher sealed in the encrypted
+// envelope like block records, since their content type and metadata are the
+// block's to hide. A MessagePack header starts with an array marker, never the
+// envelope byte, so records written in the clear still decode.
+pub(crate) fn seal_header(header: &BlockHeader, cipher: Option<&BlockCipher>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let bytes = rmp_serde::to_vec(header)?;
+    match cipher {
+        Some(cipher) => {
+            let mut sealed = vec![ENVELOPE_ENCRYPTED];
+            sealed.extend(cipher.seal(&bytes)?);
+            Ok(sealed)
+        }
+        None => Ok(bytes),
+    }
+}
+
+pub(crate) fn open_header(bytes: &[u8], cipher: Option<&BlockCipher>) -> Result<BlockHeader, Box<dyn Error>> {
+    match bytes.first() {
+        Some(&ENVELOPE_ENCRYPTED) => {
+            let cipher = cipher.ok_or("Header record is encrypted, but no encryption key is configured")?;
+            Ok(rmp_serde::from_slice(&cipher.open(&bytes[1..])?)?)
+        }
+        _ => Ok(rmp_serde::from_slice(bytes)?),
+    }
+}
+
+pub(crate) fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&ENVELOPE_ENCRYPTED)
+}
+
+pub fn is_legacy_json(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&b'{')
+}
+
+// Blocks live in the default tree under their hex hash, next to a few named keys
+// such as "LAST".
+pub fn is_block_key(key: &[u8]) -> bool {
+    key.len() == 64 && key.iter().all(u8::is_ascii_hexdigit)
+}
@@ -0,0 +1,76 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::error::Error;
+
+use crate::config::Config;
+
+// Read when the config names no key file.
+pub const KEY_ENV: &str = "LEDGER_ENCRYPTION_KEY";
+
+const NONCE_LEN: usize = 12;
+
+// ChaCha20-Poly1305 over block records, with a fresh random nonce per record. Only
+// the stored bytes are encrypted; hashes are still computed over the plaintext block.
+// Index keys made from block contents (see `search`) are HMAC-SHA256 tags under a
+// key derived from the same one, which can be looked up but not read.
+#[derive(Clone)]
+pub(crate) struct BlockCipher {
+    cipher: ChaCha20Poly1305,
+    index_key: [u8; 32],
+}
+
+impl BlockCipher {
+    // The key is 32 bytes, hex-encoded (e.g. `openssl rand -hex 32`), from
+    // `Config::encryption_key_file` or else the LEDGER_ENCRYPTION_KEY variable. No key
+    // means blocks are stored in the clear.
+    pub(crate) fn from_config(config: &Config) -> Result<Option<BlockCipher>, Box<dyn Error>> {
+        let encoded = match &config.encryption_key_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("Cannot read encryption key {}: {}", path.display(), e))?,
+            None => match std::env::var(KEY_ENV) {
+                Ok(key) => key,
+                Err(_) => return Ok(None),
+            },
+        };
+        let key = hex::decode(encoded.trim()).ok().and_then(|key| key.try_into().ok());
+        let key = key.ok_or("The encryption key must be 32 bytes, hex-encoded")?;
+        Ok(Some(BlockCipher::new(&key)))
+    }
+
+    pub(crate) fn new(key: &[u8; 32]) -> BlockCipher {
+        let index_key = hmac(key, b"ledger-v1 index key");
+        BlockCipher { cipher: ChaCha20Poly1305::new(Key::from_slice(key)), index_key }
+    }
+
+    // What an index stores in place of `token`, hex.
+    pub(crate) fn index_token(&self, token: &str) -> String {
+        hex::encode(hmac(&self.index_key, token.as_bytes()))
+    }
+
+    // Nonce followed by the ciphertext and tag.
+    pub(crate) fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher.encrypt(&nonce, plaintext).map_err(|_| "Block encryption failed")?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    pub(crate) fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if sealed.len() < NONCE_LEN {
+            return Err("Truncated encrypted block record".into());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Cannot decrypt block record (wrong encryption key, or the record is damaged)".into())
+    }
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
@@ -0,0 +1,65 @@
+use std::error::Error;
+use std::fmt;
+
+// Failures callers are expected to tell apart, by downcasting the `Box<dyn Error>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LedgerError {
+    // Another process has the database open for writing. No pid if that process
+    // left no lock file.
+    AlreadyInUse { pid: Option<u32> },
+    // The lock file names a process on another host, which this one cannot check.
+    LockedElsewhere { host: String, pid: u32 },
+    // A block with this hash is stored already, so adding it again would overwrite
+    // it. Sync code, which may be handed a block twice, uses
+    // `Blockchain::add_block_if_absent` instead.
+    DuplicateBlock { hash: String },
+    // `Blockchain::request_shutdown` was called; the node takes no new blocks or
+    // transactions.
+    ShuttingDown,
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerError::AlreadyInUse { pid: Some(pid) } => write!(
+                f,
+                "The database is in use by process {}; stop it first, or read a backup or replica of it",
+                pid
+            ),
+            LedgerError::AlreadyInUse { pid: None } => write!(
+                f,
+                "The database is in use by another process; stop it first, or read a backup or replica of it"
+            ),
+            LedgerError::LockedElsewhere { host, pid } => write!(
+                f,
+                "The database is locked by process {} on {}; if that node is gone, force the lock open",
+                pid, host
+            ),
+            LedgerError::DuplicateBlock { hash } => write!(f, "Block {} is already stored", hash),
+            LedgerError::ShuttingDown => write!(f, "The node is shutting down"),
+        }
+    }
+}
+
+impl Error for LedgerError {}
+
+// A block breaks a rule of the chain, which every node checking it would find, so it
+// can never become canonical. Anything else a block fails on (reading the store,
+// this node's size limits or validation hooks) leaves it to be tried again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsensusError(pub String);
+
+impl ConsensusError {
+    // `e` as a rule violation, for `map_err` and `ok_or_else`.
+    pub(crate) fn wrap(e: impl fmt::Display) -> Box<dyn Error> {
+        Box::new(ConsensusError(e.to_string()))
+    }
+}
+
+impl fmt::Display for ConsensusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ConsensusError {}
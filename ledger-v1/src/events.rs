@@ -0,0 +1,62 @@
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver};
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::metrics::Metrics;
+use crate::store::BlockStore;
+use crate::transaction::Transaction;
+use tracing::{info, warn};
+
+// Emitted after the change is on disk; a rejection, which changes nothing, as soon as
+// the block is refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    // A block became part of the canonical chain (appended, or connected by a reorg).
+    BlockAdded { hash: String, height: u64 },
+    // The canonical chain switched branches. Disconnected blocks are newest first,
+    // connected ones oldest first; each connected block also gets a `BlockAdded`.
+    ChainReorged { disconnected: Vec<String>, connected: Vec<String> },
+    // A transaction was included in a canonical block.
+    TransactionConfirmed { txid: String, block_hash: String, height: u64 },
+    // A transaction was accepted into the mempool.
+    TransactionSubmitted { txid: String, transaction: Transaction },
+    // A block failed validation, whether made here or received.
+    BlockRejected { hash: String, error: String },
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Every subscriber gets its own channel. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.shared.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub(crate) fn emit(&self, event: ChainEvent) {
+        self.shared
+            .subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    pub(crate) fn announce_block(&self, block: &Block, height: u64) {
+        Metrics::count(&self.shared.metrics.blocks_added);
+        info!(hash = %block.hash, height, transactions = block.transactions.len(), "block added");
+        self.emit(ChainEvent::BlockAdded { hash: block.hash.clone(), height });
+        for transaction in &block.transactions {
+            self.emit(ChainEvent::TransactionConfirmed {
+                txid: transaction.hash(),
+                block_hash: block.hash.clone(),
+                height,
+            });
+        }
+    }
+
+    pub(crate) fn announce_rejection(&self, hash: &str, error: &dyn Error) {
+        Metrics::count(&self.shared.metrics.validation_failures);
+        warn!(block = %hash, %error, "block rejected");
+        self.emit(ChainEvent::BlockRejected { hash: hash.to_string(), error: error.to_string() });
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::DateTime;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block as Panel, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::error::Error;
+
+use ledger_v1::{Block, Blockchain};
+
+// The `explore` subcommand: canonical blocks on the left, newest first, and the
+// selected block (or transaction) on the right.
+
+const HELP: &str = "↑/↓ move  p parent  g/G tip/genesis  Tab transactions  v validate  q quit";
+
+#[derive(PartialEq)]
+enum Focus {
+    Blocks,
+    Transactions,
+}
+
+struct Explorer<'a> {
+    chain: &'a Blockchain,
+    // Canonical hashes, tip first.
+    hashes: Vec<String>,
+    blocks: ListState,
+    transactions: ListState,
+    focus: Focus,
+    // Result of the last full validation, if one was run.
+    chain_status: Option<String>,
+}
+
+pub fn run(chain: &Blockchain) -> Result<(), Box<dyn Error>> {
+    let tip_height = chain.height()?;
+    let mut hashes = Vec::new();
+    for height in (0..=tip_height).rev() {
+        // Heights below a snapshot base have no block; show the hole rather than hide it.
+        hashes.push(chain.canonical_hash(height)?.unwrap_or_default());
+    }
+    let mut explorer = Explorer {
+        chain,
+        hashes,
+        blocks: ListState::default().with_selected(Some(0)),
+        transactions: ListState::default(),
+        focus: Focus::Blocks,
+        chain_status: None,
+    };
+
+    let mut terminal = ratatui::init();
+    let result = explorer.event_loop(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl Explorer<'_> {
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
+        loop {
+            let block = self.selected_block()?;
+            terminal.draw(|frame| self.draw(frame, block.as_ref()))?;
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => self.step(-1, block.as_ref()),
+                KeyCode::Down | KeyCode::Char('j') => self.step(1, block.as_ref()),
+                KeyCode::Char('p') => self.select_block(self.selected_index() + 1),
+                KeyCode::Char('g') => self.select_block(0),
+                KeyCode::Char('G') => self.select_block(self.hashes.len() - 1),
+                KeyCode::Tab | KeyCode::Enter => self.toggle_focus(block.as_ref()),
+                KeyCode::Char('v') => {
+                    self.chain_status = Some(match self.chain.is_chain_valid() {
+                        Ok(true) => "valid".to_string(),
+                        Ok(false) => "INVALID (run `validate` for details)".to_string(),
+                        Err(e) => format!("error: {}", e),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn selected_index(&self) -> usize {
+        self.blocks.selected().unwrap_or(0)
+    }
+
+    fn selected_block(&self) -> Result<Option<Block>, Box<dyn Error>> {
+        self.chain.get_block(&self.hashes[self.selected_index()])
+    }
+
+    fn select_block(&mut self, index: usize) {
+        self.blocks.select(Some(index.min(self.hashes.len() - 1)));
+        self.transactions.select(None);
+        self.focus = Focus::Blocks;
+    }
+
+    fn step(&mut self, delta: isize, block: Option<&Block>) {
+        match self.focus {
+            Focus::Blocks => self.select_block(self.selected_index().saturating_add_signed(delta)),
+            Focus::Transactions => {
+                let count = block.map_or(0, |block| block.transactions.len());
+                let current = self.transactions.selected().unwrap_or(0);
+                self.transactions.select(Some(current.saturating_add_signed(delta).min(count.saturating_sub(1))));
+            }
+        }
+    }
+
+    fn toggle_focus(&mut self, block: Option<&Block>) {
+        if self.focus == Focus::Transactions {
+            self.focus = Focus::Blocks;
+            self.transactions.select(None);
+        } else if block.is_some_and(|block| !block.transactions.is_empty()) {
+            self.focus = Focus::Transactions;
+            self.transactions.select(Some(0));
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, block: Option<&Block>) {
+        let [main, footer] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, detail_area] =
+            Layout::horizontal([Constraint::Length(30), Constraint::Min(0)]).areas(main);
+
+        let tip_height = self.hashes.len() - 1;
+        let items: Vec<ListItem> = self
+            .hashes
+            .iter()
+            .enumerate()
+            .map(|(index, hash)| {
+                let short = if hash.is_empty() { "<not stored>" } else { &hash[..hash.len().min(16)] };
+                ListItem::new(format!("{:>7}  {}", tip_height - index, short))
+            })
+            .collect();
+        let title = match &self.chain_status {
+            Some(status) => format!(" Blocks · chain {} ", status),
+            None => " Blocks ".to_string(),
+        };
+        let list = List::new(items)
+            .block(Panel::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.blocks);
+
+        let height = tip_height - self.selected_index();
+        match block {
+            Some(block) if self.focus == Focus::Transactions => self.draw_transactions(frame, detail_area, block),
+            Some(block) => frame.render_widget(self.block_details(block, height), detail_area),
+            None => frame.render_widget(
+                Paragraph::new("This block is not stored (history before a snapshot base).")
+                    .block(Panel::default().borders(Borders::ALL).title(format!(" Block {} ", height))),
+                detail_area,
+            ),
+        }
+        frame.render_widget(Paragraph::new(HELP).style(Style::default().fg(Color::DarkGray)), footer);
+    }
+
+    fn block_details(&self, block: &Block, height: usize) -> Paragraph<'static> {
+        let pruned = self.chain.is_pruned(&block.hash).unwrap_or(false);
+        let (status, color) = if pruned {
+            ("pruned, header only".to_string(), Color::Yellow)
+        } else if block.hash == block.calculate_hash() {
+            ("hash ok".to_string(), Color::Green)
+        } else {
+            (format!("hash mismatch (computes to {})", block.calculate_hash()), Color::Red)
+        };
+        let time = DateTime::from_timestamp_millis(block.timestamp as i64)
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| block.timestamp.to_string());
+        let mut lines = vec![
+            Line::from(format!("Hash:        {}", block.hash)),
+            Line::from(format!("Prev:        {}", block.prev_hash)),
+            Line::from(format!("Time:        {}", time)),
+            Line::from(format!("Version:     {}", block.version)),
+            Line::from(format!("Difficulty:  {}   Nonce: {}", block.difficulty, block.nonce)),
+            Line::styled(format!("Status:      {}", status), Style::default().fg(color)),
+            Line::from(format!("Transactions: {}", block.transactions.len())),
+        ];
+        for (key, value) in &block.metadata {
+            lines.push(Line::from(format!("Tag:         {}={}", key, value)));
+        }
+        lines.push(Line::from(""));
+        if pruned {
+            lines.push(Line::from("<pruned>"));
+        } else {
+            lines.extend(block.data_summary().lines().map(|line| Line::from(line.to_string())));
+        }
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Panel::default().borders(Borders::ALL).title(format!(" Block {} ", height)))
+    }
+
+    fn draw_transactions(&mut self, frame: &mut Frame, area: ratatui::layout::Rect, block: &Block) {
+        let [list_area, detail_area] = Layout::vertical([Constraint::Percentage(40), Constraint::Min(0)]).areas(area);
+        let items: Vec<ListItem> = block.transactions.iter().map(|tx| ListItem::new(tx.hash())).collect();
+        let list = List::new(items)
+            .block(Panel::default().borders(Borders::ALL).title(" Transactions "))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.transactions);
+
+        let selected = self.transactions.selected().and_then(|index| block.transactions.get(index));
+        let text = selected
+            .map(|tx| serde_json::to_string_pretty(tx).unwrap_or_default())
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(text).wrap(Wrap { trim: false }).block(Panel::default().borders(Borders::ALL)),
+            detail_area,
+        );
+    }
+}
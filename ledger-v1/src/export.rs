@@ -0,0 +1,324 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+
+use crate::block::Block;
+use crate::blockchain::{Blockchain, BlockStatus};
+use crate::encoding::{decode_block, encode_block};
+use crate::hashing::HashAlgorithm;
+use crate::store::BlockStore;
+use tracing::instrument;
+
+// Binary snapshots: this magic, a u32 format version, then every block as a u32
+// length followed by its on-disk encoding.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"LDGRSNAP";
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    // A JSON array of blocks, genesis first.
+    Json,
+    // One row per block, for spreadsheets and other tools.
+    Csv,
+    // Compact snapshot in the on-disk block encoding.
+    Binary,
+    // A Graphviz graph of every stored block and its parent link, forks included.
+    // Export only.
+    Dot,
+    // Reports, export only (see `report`): a SQL script building SQLite tables of
+    // blocks and transactions, and a CSV of the transactions.
+    Sql,
+    TxCsv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "binary" | "bin" => Ok(ExportFormat::Binary),
+            "dot" => Ok(ExportFormat::Dot),
+            "sql" => Ok(ExportFormat::Sql),
+            "tx-csv" => Ok(ExportFormat::TxCsv),
+            other => Err(format!("Unknown format '{}' (expected json, csv, binary, dot, sql or tx-csv)", other)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CsvRow {
+    height: u64,
+    timestamp: u64,
+    version: u32,
+    hash: String,
+    prev_hash: String,
+    #[serde(with = "crate::payload")]
+    data: Vec<u8>,
+    // JSON array, empty for blocks without transactions.
+    #[serde(default)]
+    transactions: String,
+    #[serde(default)]
+    difficulty: u32,
+    #[serde(default)]
+    nonce: u64,
+    #[serde(default)]
+    hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    content_type: Option<String>,
+    // JSON object, empty for blocks without metadata.
+    #[serde(default)]
+    metadata: String,
+    #[serde(default)]
+    signature: String,
+    #[serde(default)]
+    sequence: u64,
+    #[serde(default)]
+    network_id: String,
+    #[serde(default)]
+    state_root: String,
+    // JSON array, empty for blocks without approvals.
+    #[serde(default)]
+    approvals: String,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Writes the canonical chain, genesis first; as DOT, every stored block. Reports
+    // cover the whole chain, see `export_report` to add to an earlier one.
+    pub fn export<W: Write>(&self, writer: W, format: ExportFormat) -> Result<(), Box<dyn Error>> {
+        let blocks = (0..=self.height()?).map(|height| -> Result<Block, Box<dyn Error>> {
+            let block = self.canonical_block(height)?;
+            if self.is_pruned(&block.hash)? {
+                return Err(format!("Block {} is pruned; only a full node can export its chain", block.hash).into());
+            }
+            Ok(block)
+        });
+
+        match format {
+            ExportFormat::Json => {
+                let mut writer = writer;
+                writer.write_all(b"[")?;
+                for (index, block) in blocks.enumerate() {
+                    if index > 0 {
+                        writer.write_all(b",")?;
+                    }
+                    writer.write_all(b"\n  ")?;
+                    serde_json::to_writer(&mut writer, &block?)?;
+                }
+                writer.write_all(b"\n]\n")?;
+                writer.flush()?;
+            }
+            ExportFormat::Csv => {
+                let mut csv = csv::Writer::from_writer(writer);
+                for (height, block) in blocks.enumerate() {
+                    let block = block?;
+                    csv.serialize(CsvRow {
+                        height: height as u64,
+                        timestamp: block.timestamp,
+                        version: block.version,
+                        hash: block.hash,
+                        prev_hash: block.prev_hash,
+                        data: block.data,
+                        transactions: if block.transactions.is_empty() {
+                            String::new()
+                        } else {
+                            serde_json::to_string(&block.transactions)?
+                        },
+                        difficulty: block.difficulty,
+                        nonce: block.nonce,
+                        hash_algorithm: block.hash_algorithm,
+                        content_type: block.content_type,
+                        metadata: if block.metadata.is_empty() {
+                            String::new()
+                        } else {
+                            serde_json::to_string(&block.metadata)?
+                        },
+                        signature: block.signature,
+                        sequence: block.sequence,
+                        network_id: block.network_id,
+                        state_root: block.state_root,
+                        approvals: if block.approvals.is_empty() {
+                            String::new()
+                        } else {
+                            serde_json::to_string(&block.approvals)?
+                        },
+                    })?;
+                }
+                csv.flush()?;
+            }
+            ExportFormat::Binary => {
+                let mut writer = writer;
+                writer.write_all(SNAPSHOT_MAGIC)?;
+                writer.write_all(&SNAPSHOT_VERSION.to_be_bytes())?;
+                for block in blocks {
+                    let bytes = encode_block(&block?)?;
+                    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+                    writer.write_all(&bytes)?;
+                }
+                writer.flush()?;
+            }
+            ExportFormat::Dot => self.export_dot(writer)?,
+            ExportFormat::Sql | ExportFormat::TxCsv => {
+                self.export_report(writer, format, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Each block is a node labelled with its height and short hash, with an edge from
+    // its parent. The canonical chain is filled and drawn bold, side branches left
+    // by forks are dashed, blocks of branches that failed to connect are red, and a
+    // parent that isn't stored shows up as a dotted "missing" node.
+    fn export_dot<W: Write>(&self, mut writer: W) -> Result<(), Box<dyn Error>> {
+        let mut missing = Vec::new();
+        writeln!(writer, "digraph chain {{")?;
+        writeln!(writer, "  rankdir=LR;")?;
+        writeln!(writer, "  node [shape=box, fontname=\"monospace\"];")?;
+        for block in self.blocks() {
+            let block = block?;
+            let height = self.block_meta(&block.hash)?.map(|meta| meta.height);
+            let canonical = match height {
+                Some(height) => self.canonical_hash(height)?.as_deref() == Some(block.hash.as_str()),
+                None => false,
+            };
+            let style = if canonical {
+                "style=\"filled,bold\", fillcolor=lightblue"
+            } else if self.is_invalid(&block.hash)? {
+                "style=dashed, color=red, fontcolor=red"
+            } else {
+                "style=dashed, color=gray40"
+            };
+            let height = height.map_or_else(|| "?".to_string(), |height| height.to_string());
+            writeln!(writer, "  \"{}\" [label=\"{}\\n{}\", {}];", block.hash, height, block.hash.get(..12).unwrap_or(&block.hash), style)?;
+            if block.is_genesis() {
+                continue;
+            }
+            if self.block_meta(&block.prev_hash)?.is_none() {
+                missing.push(block.prev_hash.clone());
+            }
+            let edge = if canonical { " [penwidth=2]" } else { "" };
+            writeln!(writer, "  \"{}\" -> \"{}\"{};", block.prev_hash, block.hash, edge)?;
+        }
+        missing.sort();
+        missing.dedup();
+        for hash in missing {
+            writeln!(writer, "  \"{}\" [label=\"missing\\n{}\", style=dotted];", hash, hash.get(..12).unwrap_or(&hash))?;
+        }
+        writeln!(writer, "}}")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Reads a chain written by `export`. Every block is checked (self-hash, links,
+    // genesis) before anything is written, and the import commits as a whole;
+    // blocks already present are skipped.
+    // A database that only holds its own genesis block adopts the imported genesis.
+    // Returns the number of blocks added.
+    #[instrument(skip_all)]
+    pub fn import<R: Read>(&self, reader: R, format: ExportFormat) -> Result<usize, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        let blocks = read_blocks(reader, format)?;
+
+        let genesis = blocks.first().ok_or("Import contains no blocks")?;
+        if !genesis.is_genesis() {
+            return Err(format!("Import does not start with a genesis block (first block {})", genesis.hash).into());
+        }
+        for (index, block) in blocks.iter().enumerate() {
+            if block.hash != block.calculate_hash() {
+                return Err(format!("Hash mismatch for imported block {}", block.hash).into());
+            }
+            if index > 0 && block.prev_hash != blocks[index - 1].hash {
+                return Err(format!("Broken link! Imported block {} does not follow {}", block.hash, blocks[index - 1].hash).into());
+            }
+        }
+
+        // Adopting the genesis and adding the blocks commit together.
+        let mut batch = self.batch();
+        batch.operation = Some("import");
+        let local_genesis = self.canonical_hash(0)?;
+        if local_genesis.as_deref() != Some(genesis.hash.as_str()) {
+            if !self.holds_only_genesis()? {
+                return Err(format!(
+                    "Import starts from genesis {} but this chain uses {} (chain ID {})",
+                    genesis.hash,
+                    local_genesis.unwrap_or_default(),
+                    self.network_id()?
+                ).into());
+            }
+            batch.replace_genesis(genesis)?;
+        }
+
+        let statuses = self.receive_in(batch, &blocks[1..])?;
+        Ok(statuses.iter().filter(|status| !matches!(status, BlockStatus::AlreadyKnown | BlockStatus::Orphaned)).count())
+    }
+}
+
+pub(crate) fn read_blocks<R: Read>(reader: R, format: ExportFormat) -> Result<Vec<Block>, Box<dyn Error>> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::from_reader(reader)?),
+        ExportFormat::Csv => {
+            let mut csv = csv::Reader::from_reader(reader);
+            let mut blocks = Vec::new();
+            for row in csv.deserialize() {
+                let row: CsvRow = row?;
+                blocks.push(Block {
+                    timestamp: row.timestamp,
+                    data: row.data,
+                    prev_hash: row.prev_hash,
+                    hash: row.hash,
+                    version: row.version,
+                    transactions: if row.transactions.is_empty() {
+                        Vec::new()
+                    } else {
+                        serde_json::from_str(&row.transactions)?
+                    },
+                    difficulty: row.difficulty,
+                    nonce: row.nonce,
+                    hash_algorithm: row.hash_algorithm,
+                    content_type: row.content_type,
+                    metadata: if row.metadata.is_empty() {
+                        Default::default()
+                    } else {
+                        serde_json::from_str(&row.metadata)?
+                    },
+                    signature: row.signature,
+                    sequence: row.sequence,
+                    network_id: row.network_id,
+                    state_root: row.state_root,
+                    approvals: if row.approvals.is_empty() {
+                        Vec::new()
+                    } else {
+                        serde_json::from_str(&row.approvals)?
+                    },
+                });
+            }
+            Ok(blocks)
+        }
+        ExportFormat::Binary => {
+            let mut reader = BufReader::new(reader);
+            let mut header = [0u8; 12];
+            reader.read_exact(&mut header)?;
+            if &header[..8] != SNAPSHOT_MAGIC {
+                return Err("Not a ledger snapshot".into());
+            }
+            let version = u32::from_be_bytes(header[8..].try_into()?);
+            if version != SNAPSHOT_VERSION {
+                return Err(format!("Unsupported snapshot version {}", version).into());
+            }
+
+            let mut blocks = Vec::new();
+            while !reader.fill_buf()?.is_empty() {
+                let mut len = [0u8; 4];
+                reader.read_exact(&mut len)?;
+                let mut bytes = vec![0u8; u32::from_be_bytes(len) as usize];
+                reader.read_exact(&mut bytes)?;
+                blocks.push(decode_block(&bytes)?);
+            }
+            Ok(blocks)
+        }
+        ExportFormat::Dot => Err("A DOT graph cannot be imported; use json, csv or binary".into()),
+        ExportFormat::Sql | ExportFormat::TxCsv => Err("Reports cannot be imported; use json, csv or binary".into()),
+    }
+}
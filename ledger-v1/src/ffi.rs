@@ -0,0 +1,246 @@
+// Unsafe functions here say what they need of their callers in the comments above
+// them, matching include/ledger.h.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::genesis::GenesisConfig;
+use crate::error::LedgerError;
+
+// The C ABI (with the ffi feature), for embedding the ledger in Python, Node and
+// other services through the cdylib. include/ledger.h declares it. The rules:
+//
+// - Every function returns one of the LEDGER_* codes below. On failure
+//   `ledger_last_error` describes it until the same thread's next failure.
+// - Arguments are only borrowed for the call. Strings are NUL-terminated UTF-8.
+// - A chain from `ledger_open` is the caller's, to be passed to `ledger_close`
+//   exactly once. Until then it may be used from several threads at the same time.
+// - A string a function hands back through an out pointer is the caller's, to be
+//   freed with `ledger_string_free` and nothing else.
+// - A panic never crosses into the caller: it is returned as LEDGER_ERR_PANIC, after
+//   which the chain should be closed.
+
+pub const LEDGER_OK: i32 = 0;
+// A required pointer was NULL.
+pub const LEDGER_ERR_NULL: i32 = 1;
+// A string argument is not UTF-8.
+pub const LEDGER_ERR_UTF8: i32 = 2;
+pub const LEDGER_ERR_NOT_FOUND: i32 = 3;
+// Another process has the database open for writing.
+pub const LEDGER_ERR_IN_USE: i32 = 4;
+// Anything else, e.g. an unreadable config or a block the chain refuses.
+pub const LEDGER_ERR_FAILED: i32 = 5;
+pub const LEDGER_ERR_PANIC: i32 = 6;
+// A block with the same hash is stored already.
+pub const LEDGER_ERR_DUPLICATE: i32 = 7;
+
+// An open chain, opaque to C.
+pub struct LedgerChain {
+    chain: Blockchain,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i32, message: impl Into<String>) -> Failure {
+        Failure { code, message: message.into() }
+    }
+
+    fn null(what: &str) -> Failure {
+        Failure::new(LEDGER_ERR_NULL, format!("{} is NULL", what))
+    }
+}
+
+impl From<Box<dyn Error>> for Failure {
+    fn from(e: Box<dyn Error>) -> Failure {
+        let code = match e.downcast_ref::<LedgerError>() {
+            Some(LedgerError::DuplicateBlock { .. }) => LEDGER_ERR_DUPLICATE,
+            Some(LedgerError::AlreadyInUse { .. } | LedgerError::LockedElsewhere { .. }) => LEDGER_ERR_IN_USE,
+            _ => LEDGER_ERR_FAILED,
+        };
+        Failure::new(code, e.to_string())
+    }
+}
+
+// Runs the body of an exported function, turning its failure or panic into a code.
+fn run(body: impl FnOnce() -> Result<(), Failure>) -> i32 {
+    let (code, message) = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return LEDGER_OK,
+        Ok(Err(failure)) => (failure.code, failure.message),
+        Err(panic) => {
+            let message = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+                (Some(message), _) => message.to_string(),
+                (_, Some(message)) => message.clone(),
+                _ => "unknown panic".to_string(),
+            };
+            (LEDGER_ERR_PANIC, format!("The ledger panicked: {}", message))
+        }
+    };
+    // A message with a NUL in it is cut short there.
+    let message = CString::new(message).unwrap_or_else(|e| {
+        let end = e.nul_position();
+        CString::new(&e.into_vec()[..end]).unwrap_or_default()
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+unsafe fn string<'a>(s: *const c_char, what: &str) -> Result<Option<&'a str>, Failure> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    let s = unsafe { CStr::from_ptr(s) };
+    s.to_str().map(Some).map_err(|_| Failure::new(LEDGER_ERR_UTF8, format!("{} is not UTF-8", what)))
+}
+
+unsafe fn required<'a>(s: *const c_char, what: &str) -> Result<&'a str, Failure> {
+    unsafe { string(s, what) }?.ok_or_else(|| Failure::null(what))
+}
+
+unsafe fn handle<'a>(chain: *const LedgerChain) -> Result<&'a Blockchain, Failure> {
+    unsafe { chain.as_ref() }.map(|handle| &handle.chain).ok_or_else(|| Failure::null("chain"))
+}
+
+unsafe fn put<T>(out: *mut T, value: T) {
+    if !out.is_null() {
+        unsafe { out.write(value) };
+    }
+}
+
+fn into_c(s: String) -> Result<*mut c_char, Failure> {
+    CString::new(s).map(CString::into_raw).map_err(|_| Failure::new(LEDGER_ERR_FAILED, "The result holds a NUL byte"))
+}
+
+// The message for the last failure on this thread, or NULL. Owned by the library and
+// valid until the thread's next failure.
+#[unsafe(no_mangle)]
+pub extern "C" fn ledger_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+// Opens the database at `path`, creating it if need be. `config_path` (a node config)
+// and `genesis_path` (used only when creating) may be NULL for the defaults. On
+// success `*out` is the chain; on failure it is NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ledger_open(
+    path: *const c_char,
+    config_path: *const c_char,
+    genesis_path: *const c_char,
+    out: *mut *mut LedgerChain,
+) -> i32 {
+    run(|| {
+        if out.is_null() {
+            return Err(Failure::null("out"));
+        }
+        unsafe { put(out, ptr::null_mut()) };
+        let path = unsafe { required(path, "path") }?;
+        let config = match unsafe { string(config_path, "config_path") }? {
+            Some(config_path) => Config::load(config_path)?,
+            None => Config::default(),
+        };
+        let genesis = unsafe { string(genesis_path, "genesis_path") }?.map(GenesisConfig::load).transpose()?;
+        let chain = Blockchain::open_with_config(path, genesis.as_ref(), config)?;
+        unsafe { put(out, Box::into_raw(Box::new(LedgerChain { chain }))) };
+        Ok(())
+    })
+}
+
+// Flushes and closes `chain`, which must not be used again whatever this returns.
+// NULL is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ledger_close(chain: *mut LedgerChain) -> i32 {
+    run(|| {
+        if chain.is_null() {
+            return Ok(());
+        }
+        let handle = unsafe { Box::from_raw(chain) };
+        handle.chain.flush()?;
+        Ok(())
+    })
+}
+
+// Appends a block holding the `len` bytes at `data` (NULL when `len` is 0). If
+// `hash_out` isn't NULL it receives the new block's hash.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ledger_add_block(chain: *const LedgerChain, data: *const u8, len: usize, hash_out: *mut *mut c_char) -> i32 {
+    run(|| {
+        unsafe { put(hash_out, ptr::null_mut()) };
+        let chain = unsafe { handle(chain) }?;
+        let data = match (data.is_null(), len) {
+            (_, 0) => Vec::new(),
+            (true, _) => return Err(Failure::null("data")),
+            (false, len) => unsafe { std::slice::from_raw_parts(data, len) }.to_vec(),
+        };
+        let hash = chain.add_block(data)?;
+        if !hash_out.is_null() {
+            unsafe { put(hash_out, into_c(hash)?) };
+        }
+        Ok(())
+    })
+}
+
+// The block with hash `hash` as JSON, in `*json_out`. LEDGER_ERR_NOT_FOUND if there is
+// none.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ledger_get_block(chain: *const LedgerChain, hash: *const c_char, json_out: *mut *mut c_char) -> i32 {
+    run(|| {
+        if json_out.is_null() {
+            return Err(Failure::null("json_out"));
+        }
+        unsafe { put(json_out, ptr::null_mut()) };
+        let chain = unsafe { handle(chain) }?;
+        let hash = unsafe { required(hash, "hash") }?;
+        let block = chain.get_block(hash)?.ok_or_else(|| Failure::new(LEDGER_ERR_NOT_FOUND, format!("Unknown block {}", hash)))?;
+        let json = serde_json::to_string(&block).map_err(|e| Failure::new(LEDGER_ERR_FAILED, e.to_string()))?;
+        unsafe { put(json_out, into_c(json)?) };
+        Ok(())
+    })
+}
+
+// Checks the integrity of the chain, as `ledger validate` does: `*valid_out` is false
+// if it fails. The error code only says whether the check could run.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ledger_validate(chain: *const LedgerChain, valid_out: *mut bool) -> i32 {
+    run(|| {
+        if valid_out.is_null() {
+            return Err(Failure::null("valid_out"));
+        }
+        let chain = unsafe { handle(chain) }?;
+        let valid = chain.is_chain_valid()?;
+        unsafe { put(valid_out, valid) };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ledger_height(chain: *const LedgerChain, height_out: *mut u64) -> i32 {
+    run(|| {
+        if height_out.is_null() {
+            return Err(Failure::null("height_out"));
+        }
+        let height = unsafe { handle(chain) }?.height()?;
+        unsafe { put(height_out, height) };
+        Ok(())
+    })
+}
+
+// Frees a string the library handed out. NULL is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ledger_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
@@ -0,0 +1,40 @@
+use std::error::Error;
+
+use crate::batch::ChainBatch;
+use crate::blockchain::Blockchain;
+use crate::store::BlockStore;
+
+// With `Config::finality_depth` N > 0, the canonical block N below the tip and every
+// block under it are final: no reorg may roll them back, and no new block may join
+// the chain at their heights.
+
+impl<S: BlockStore> Blockchain<S> {
+    // Height and hash of the newest final block. None while finality is off or the
+    // chain is not yet N blocks long.
+    pub fn finalized_tip(&self) -> Result<Option<(u64, String)>, Box<dyn Error>> {
+        let Some(height) = finalized_height(self.config.finality_depth, self.tip_meta()?.height) else {
+            return Ok(None);
+        };
+        Ok(self.canonical_hash(height)?.map(|hash| (height, hash)))
+    }
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    // Refuses a branch that would leave the canonical chain below `height`, the last
+    // height it shares with it.
+    pub(crate) fn check_finality(&self, hash: &str, height: u64) -> Result<(), Box<dyn Error>> {
+        if let Some(finalized) = finalized_height(self.config.finality_depth, self.tip_meta()?.height)
+            && height < finalized
+        {
+            return Err(format!(
+                "Block {} forks off at height {}, below the finalized height {} (finality depth {})",
+                hash, height, finalized, self.config.finality_depth
+            ).into());
+        }
+        Ok(())
+    }
+}
+
+fn finalized_height(depth: u64, tip_height: u64) -> Option<u64> {
+    (depth > 0 && tip_height >= depth).then(|| tip_height - depth)
+}
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::batch::ReadTrees;
+use crate::blockchain::Blockchain;
+use crate::config::NodeMode;
+use crate::encoding::is_block_key;
+use crate::store::{BlockStore, TreeId};
+use tracing::{info, instrument};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+    // Hashes of the blocks removed.
+    pub removed: Vec<String>,
+    // Blocks that would have gone but are held (see `retention`).
+    pub held: Vec<String>,
+    // Hashes of the blobs no block refers to any more, removed too (see `blobs`).
+    pub blobs: Vec<String>,
+    // Size of the records removed with them.
+    pub reclaimed_bytes: u64,
+    // The store's size on disk around the collection. sled hands space back as it
+    // rewrites its segments, so the second may lag behind `reclaimed_bytes`.
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Removes blocks off the canonical chain that are no longer worth keeping: side
+    // branches whose newest block is more than `Config::gc_retention` blocks below the
+    // tip, and blocks no tip leads to at all (e.g. left behind by `repair`) from below
+    // the same window. Canonical blocks are never touched; `prune` is for those. The
+    // invalid markers stay, so a removed block that failed to connect is still
+    // refused if it comes back. Held blocks stay too. Then blobs nothing refers to
+    // are removed, see `blobs`. Archive nodes keep everything and refuse.
+    #[instrument(skip_all)]
+    pub fn gc(&self) -> Result<GcReport, Box<dyn Error>> {
+        if self.config.mode == NodeMode::Archive {
+            return Err("An archive node keeps side branches too".into());
+        }
+        let _writer = self.write_lock();
+        self.collect_garbage(self.config.gc_retention)
+    }
+
+    fn collect_garbage(&self, retention: u64) -> Result<GcReport, Box<dyn Error>> {
+        let size_before = self.store().size_on_disk()?;
+        let horizon = self.height()?.saturating_sub(retention);
+
+        // Side branches still in the window, walked back from their tips to where they
+        // leave the canonical chain.
+        let mut keep = HashSet::new();
+        for entry in self.store().scan_prefix(TreeId::Tips, &[]) {
+            let (key, _) = entry?;
+            let tip = String::from_utf8(key)?;
+            if self.trees.block_meta(&tip)?.is_none_or(|meta| meta.height < horizon) {
+                continue;
+            }
+            let mut next = Some(tip);
+            while let Some(hash) = next.take() {
+                if self.is_canonical(&hash)? || !keep.insert(hash.clone()) {
+                    break;
+                }
+                next = self.trees.load_header(&hash)?.map(|header| header.prev_hash);
+            }
+        }
+
+        let holds = self.hold_index()?;
+        let mut batch = self.batch();
+        let mut report = GcReport { size_before, ..GcReport::default() };
+        for entry in self.store().scan_prefix(TreeId::Blocks, &[]) {
+            let (key, record) = entry?;
+            if !is_block_key(&key) {
+                continue;
+            }
+            let hash = String::from_utf8(key)?;
+            if keep.contains(&hash) || self.is_canonical(&hash)? {
+                continue;
+            }
+            if self.trees.block_meta(&hash)?.is_some_and(|meta| meta.height >= horizon) {
+                continue;
+            }
+            if !holds.is_empty()
+                && self.trees.read_block(&hash, |view| Ok(holds.holding(&hash, view.timestamp, view.txids()).is_some()))? == Some(true)
+            {
+                report.held.push(hash);
+                continue;
+            }
+            report.reclaimed_bytes += (hash.len() + record.len()) as u64;
+            for tree in [TreeId::Headers, TreeId::Meta, TreeId::Undo] {
+                if let Some(bytes) = self.trees.get(tree, hash.as_bytes())? {
+                    report.reclaimed_bytes += (hash.len() + bytes.len()) as u64;
+                }
+            }
+            for tree in [TreeId::Blocks, TreeId::Headers, TreeId::Meta, TreeId::Undo, TreeId::Tips, TreeId::Pruned] {
+                batch.remove(tree, &hash);
+            }
+            report.removed.push(hash);
+        }
+        self.collect_blobs(&mut batch, &mut report)?;
+        if report.removed.is_empty() && report.blobs.is_empty() {
+            report.size_after = size_before;
+            return Ok(report);
+        }
+        batch.commit()?;
+        self.store().flush()?;
+        report.size_after = self.store().size_on_disk()?;
+        info!(removed = report.removed.len(), blobs = report.blobs.len(), reclaimed_bytes = report.reclaimed_bytes, "garbage collected");
+        Ok(report)
+    }
+
+    fn is_canonical(&self, hash: &str) -> Result<bool, Box<dyn Error>> {
+        match self.trees.block_meta(hash)? {
+            Some(meta) => Ok(self.trees.canonical_hash(meta.height)?.as_deref() == Some(hash)),
+            None => Ok(false),
+        }
+    }
+
+    // Called whenever the tip moves.
+    pub(crate) fn maybe_gc(&self) -> Result<(), Box<dyn Error>> {
+        let interval = self.config.gc_interval;
+        if interval > 0 && self.height()? % interval == 0 {
+            self.collect_garbage(self.config.gc_retention)?;
+        }
+        Ok(())
+    }
+}
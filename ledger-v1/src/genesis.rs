@@ -0,0 +1,162 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use crate::approval::{self, ApprovalPolicy};
+use crate::authority;
+use crate::block::Block;
+use crate::hashing::HashAlgorithm;
+
+// How blocks earn their place. Proof of work is the default (with difficulty 0,
+// blocks need no work at all); under proof of authority the listed ed25519 public
+// keys (hex) sign blocks in turn, the first one at height 1.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Consensus {
+    #[default]
+    ProofOfWork,
+    ProofOfAuthority { authorities: Vec<String> },
+}
+
+impl Consensus {
+    pub fn is_proof_of_work(&self) -> bool {
+        *self == Consensus::ProofOfWork
+    }
+}
+
+// Everything that defines a chain. Two nodes with the same config derive the
+// same genesis block, so their chains are compatible.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct GenesisConfig {
+    pub chain_id: String,
+    // Milliseconds since the epoch, like every other block timestamp.
+    pub timestamp: u64,
+    // Proof-of-work difficulty of the first block, in leading zero bits.
+    pub difficulty: u32,
+    // Address -> premined balance.
+    pub allocations: BTreeMap<String, u64>,
+    // Retarget the difficulty every N blocks; 0 keeps it fixed. Settings added after
+    // the first release are left out of the genesis data while unset, so existing
+    // configs keep their genesis hash.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub retarget_interval: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub target_block_time_ms: u64,
+    // Minted by the coinbase of every block; 0 means blocks carry no coinbase.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub block_reward: u64,
+    // Halve the reward every N blocks; 0 never halves it.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub halving_interval: u64,
+    // Hash function of every block. Fixed for the life of the chain, since the genesis
+    // hash depends on it.
+    #[serde(skip_serializing_if = "HashAlgorithm::is_default")]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(skip_serializing_if = "Consensus::is_proof_of_work")]
+    pub consensus: Consensus,
+    // Approvers who must sign every block before it joins the chain, see `approval`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalPolicy>,
+    // Refuse transfers and locks without a sender nonce (see `Transaction`), so none
+    // can be replayed.
+    #[serde(skip_serializing_if = "is_false")]
+    pub require_nonces: bool,
+    // Refuse transfers and locks not signed by their sender (see `Transaction`). On
+    // unless a config turns it off, and left out of the genesis data while on, so the
+    // default config keeps the genesis hash it had before the setting existed.
+    //
+    // Migration: a stored config from before the setting reads as on as well. Such a
+    // chain may hold unsigned transfers from names that are not key addresses; its
+    // blocks stay as they are, but replaying them (reindex, repair) refuses those
+    // transfers. Only chains without a stored config run as `legacy()`.
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub require_signatures: bool,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        GenesisConfig {
+            chain_id: "ledger-v1".to_string(),
+            timestamp: 0,
+            difficulty: 0,
+            allocations: BTreeMap::new(),
+            retarget_interval: 0,
+            target_block_time_ms: 0,
+            block_reward: 0,
+            halving_interval: 0,
+            hash_algorithm: HashAlgorithm::default(),
+            consensus: Consensus::default(),
+            approval: None,
+            require_nonces: false,
+            require_signatures: true,
+        }
+    }
+}
+
+impl GenesisConfig {
+    // What chains from before genesis configs ran under: the defaults of the time.
+    pub fn legacy() -> Self {
+        GenesisConfig { require_signatures: false, ..GenesisConfig::default() }
+    }
+
+    // Reads a `.toml` file, or JSON for any other extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<GenesisConfig, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)?,
+            _ => serde_json::from_str(&contents)?,
+        };
+        Ok(config)
+    }
+
+    // What the coinbase of the block at `height` must mint.
+    pub fn reward_at(&self, height: u64) -> u64 {
+        let halvings = match self.halving_interval {
+            0 => 0,
+            interval => height / interval,
+        };
+        self.block_reward.checked_shr(halvings.try_into().unwrap_or(u32::MAX)).unwrap_or(0)
+    }
+
+    // The chain ID every other block carries: the first 8 bytes of the genesis hash,
+    // hex. Unlike `chain_id`, which is only a name, it changes with any setting.
+    pub fn network_id(&self) -> Result<String, Box<dyn Error>> {
+        let genesis = self.genesis_block()?;
+        Ok(genesis.hash.get(..16).unwrap_or(&genesis.hash).to_string())
+    }
+
+    // The genesis block stores the config itself as its data. Struct fields and the
+    // BTreeMap serialize in a fixed order, so the hash only depends on the values.
+    pub fn genesis_block(&self) -> Result<Block, Box<dyn Error>> {
+        if let Consensus::ProofOfAuthority { authorities } = &self.consensus {
+            authority::check_authorities(authorities)?;
+        }
+        if let Some(policy) = &self.approval {
+            approval::check_policy(policy)?;
+        }
+        let data = serde_json::to_string(self)?;
+        let mut block = Block::new_with_timestamp(data, "0".to_string(), self.timestamp);
+        block.hash_algorithm = self.hash_algorithm;
+        block.hash = block.calculate_hash();
+        Ok(block)
+    }
+}
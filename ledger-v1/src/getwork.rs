@@ -0,0 +1,115 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+
+use crate::blockchain::{block_work, BlockMeta, Blockchain};
+use crate::header::BlockHeader;
+use crate::limits;
+use crate::pow;
+use crate::store::BlockStore;
+use crate::transaction::Transaction;
+use tracing::info;
+
+// Mining outside the node: `get_block_template` hands out the next block as a header
+// with nonce 0, and `submit_block` takes the header back once a miner has found a
+// nonce meeting the target. Only the nonce may change; the node keeps the body of
+// each template it gave out, so a miner never needs to send the transactions back.
+// Templates are kept in memory, the newest few on the current tip.
+const MAX_TEMPLATES: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockTemplate {
+    // The hash the block would have with nonce 0, which names it.
+    pub id: String,
+    pub height: u64,
+    // Nonce 0 and no hash yet. Its merkle root commits to `transactions`.
+    pub header: BlockHeader,
+    // The hash, as a 256-bit number, may be at most this; hex. The same as
+    // `header.difficulty` leading zero bits.
+    pub target: String,
+    pub transactions: Vec<Transaction>,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // The next block on top of the tip, holding what `assemble_block` picks from the
+    // mempool, for a miner to find the nonce of. Proof-of-work chains only.
+    pub fn get_block_template(&self) -> Result<BlockTemplate, Box<dyn Error>> {
+        let genesis = self.genesis_config();
+        if !genesis.consensus.is_proof_of_work() {
+            return Err("Blocks on this chain are signed by an authority, not mined".into());
+        }
+        if genesis.approval.is_some() {
+            return Err("Blocks on this chain need approval; propose them with `propose_block` instead".into());
+        }
+        let (block, meta) = self.block_template(Vec::new(), self.assemble_block()?)?;
+        limits::check_size(&self.config, &block.view())?;
+        let mut header = block.header();
+        header.nonce = 0;
+        header.hash = String::new();
+        let id = header.calculate_hash();
+
+        let tip = self.current_hash();
+        let mut templates = self.shared.templates.lock().unwrap();
+        templates.retain(|_, template| template.prev_hash == tip);
+        if templates.len() >= MAX_TEMPLATES
+            && let Some(oldest) = templates.iter().min_by_key(|(_, template)| template.timestamp).map(|(id, _)| id.clone())
+        {
+            templates.remove(&oldest);
+        }
+        templates.insert(id.clone(), block.clone());
+        Ok(BlockTemplate {
+            id,
+            height: meta.height,
+            target: target(header.difficulty),
+            header,
+            transactions: block.transactions,
+        })
+    }
+
+    // Connects the block of a template whose nonce `solved` found. The hash may be
+    // left empty; if given it must be the header's. Returns the block's height. Fails
+    // once the tip has moved past the template, which is then stale.
+    pub fn submit_block(&self, solved: &BlockHeader) -> Result<u64, Box<dyn Error>> {
+        let mut unsolved = solved.clone();
+        unsolved.nonce = 0;
+        unsolved.hash = String::new();
+        let id = unsolved.calculate_hash();
+        let mut block = self
+            .shared
+            .templates
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or("No template matches this header; only its nonce may differ from the template's")?;
+        block.nonce = solved.nonce;
+        block.hash = block.calculate_hash();
+        if !solved.hash.is_empty() && solved.hash != block.hash {
+            return Err(format!("The header claims hash {}, but hashes to {}", solved.hash, block.hash).into());
+        }
+        if !pow::meets_difficulty(&block.hash, block.difficulty) {
+            return Err(format!("Block {} does not meet difficulty {}", block.hash, block.difficulty).into());
+        }
+
+        let _writer = self.write_lock();
+        let tip = self.current_hash();
+        if block.prev_hash != tip {
+            return Err(format!("Template {} builds on {}, but the tip has moved to {}; get a new one", id, block.prev_hash, tip).into());
+        }
+        let batch = self.batch();
+        let parent = batch.tip_meta()?;
+        let meta = BlockMeta { height: parent.height + 1, total_work: parent.total_work + block_work(block.difficulty) };
+        self.connect_on_tip(batch, &block, &meta)?;
+        self.shared.templates.lock().unwrap().remove(&id);
+        info!(block = %block.hash, height = meta.height, "externally mined block connected");
+        Ok(meta.height)
+    }
+}
+
+// The largest 256-bit hash with `difficulty` leading zero bits, hex.
+fn target(difficulty: u32) -> String {
+    let mut target = [0xff_u8; 32];
+    for bit in 0..difficulty.min(256) as usize {
+        target[bit / 8] &= !(0x80 >> (bit % 8));
+    }
+    hex::encode(target)
+}
@@ -0,0 +1,582 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::approval::{Approval, ApprovalOutcome};
+use crate::auth::{self, AuthError, Role};
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::events::ChainEvent;
+use crate::hashing::HashAlgorithm;
+use crate::header::BlockHeader;
+use crate::limits::MempoolLimitError;
+use crate::ratelimit::Slot;
+use crate::shutdown::SHUTDOWN_POLL;
+use crate::snapshot::SnapshotInfo;
+use crate::store::{BlockStore, DefaultStore};
+use crate::transaction::Transaction;
+use tracing::{debug, info};
+
+// Generated from proto/ledger.proto by build.rs.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("ledger");
+}
+
+use proto::ledger_server::{Ledger, LedgerServer};
+
+// Blocks a stream may run ahead of a slow client before it waits.
+const STREAM_BUFFER: usize = 16;
+// How often an idle stream checks whether its client went away or the chain is
+// shutting down.
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+// Caps on one `GetHeaders` / `GetBlocks` response. A block response stops before the
+// block that would take it past MAX_BLOCKS_BYTES of encoded blocks.
+const MAX_HEADERS: u32 = 2000;
+const MAX_BLOCKS: u32 = 500;
+const MAX_BLOCKS_BYTES: usize = 16 << 20;
+const MAX_SHARED_PEERS: usize = 100;
+
+// The gRPC `Ledger` service over a chain handle. Like the `_async` methods, calls
+// run on tokio's blocking pool.
+pub struct LedgerService<S = DefaultStore> {
+    chain: Blockchain<S>,
+    // `StreamBlocks` calls under way, at most `Config::max_grpc_streams`.
+    streams: Arc<AtomicUsize>,
+}
+
+impl<S: BlockStore> LedgerService<S> {
+    pub fn new(chain: Blockchain<S>) -> LedgerService<S> {
+        LedgerService { chain, streams: Arc::new(AtomicUsize::new(0)) }
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Serves the `Ledger` service until the server fails or the chain shuts down.
+    // Needs a multi-threaded tokio runtime. Calls over a client's rate limit fail
+    // with RESOURCE_EXHAUSTED, and calls the client's API key doesn't allow as
+    // described in the proto file.
+    pub async fn serve_grpc(&self, addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+        info!(%addr, "serving gRPC");
+        let chain = self.clone();
+        // tonic's interceptor signature, not ours.
+        #[allow(clippy::result_large_err)]
+        let rate_limit = move |request: Request<()>| match request.remote_addr() {
+            Some(addr) if !chain.allow_request(addr.ip()) => Err(Status::resource_exhausted("Rate limit exceeded")),
+            _ => Ok(request),
+        };
+        // Stops taking calls once the chain is shutting down (see `shutdown`) and
+        // returns when those under way are answered.
+        let (stop, stopped) = oneshot::channel();
+        let chain = self.clone();
+        thread::spawn(move || {
+            while !chain.is_shutting_down() && !stop.is_closed() {
+                thread::sleep(SHUTDOWN_POLL);
+            }
+            let _ = stop.send(());
+        });
+        tonic::transport::Server::builder()
+            .add_service(LedgerServer::with_interceptor(LedgerService::new(self.clone()), rate_limit))
+            .serve_with_shutdown(addr, async {
+                let _ = stopped.await;
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl<S: BlockStore> Ledger for LedgerService<S> {
+    async fn get_block(&self, request: Request<proto::GetBlockRequest>) -> Result<Response<proto::Block>, Status> {
+        use proto::get_block_request::By;
+        self.authorize(&request, Role::ReadOnly)?;
+        let by = request.into_inner().by.ok_or_else(|| Status::invalid_argument("Give a block hash or height"))?;
+        let block = self
+            .chain
+            .spawn(move |chain| {
+                let (hash, height) = match by {
+                    By::Hash(hash) => {
+                        let height = chain.block_meta(&hash)?.map(|meta| meta.height);
+                        (Some(hash), height)
+                    }
+                    By::Height(height) => (chain.canonical_hash(height)?, Some(height)),
+                };
+                match (hash, height) {
+                    (Some(hash), Some(height)) => Ok(chain.get_block(&hash)?.map(|block| (block, height))),
+                    _ => Ok(None),
+                }
+            })
+            .await
+            .map_err(internal)?;
+        match block {
+            Some((block, height)) => Ok(Response::new(to_proto(&block, height))),
+            None => Err(Status::not_found("Unknown block")),
+        }
+    }
+
+    async fn get_tip(&self, request: Request<proto::GetTipRequest>) -> Result<Response<proto::Tip>, Status> {
+        self.authorize(&request, Role::ReadOnly)?;
+        let (hash, meta, finalized, network_id) = self
+            .chain
+            .spawn(|chain| Ok((chain.current_hash(), chain.tip_meta()?, chain.finalized_tip()?, chain.network_id()?)))
+            .await
+            .map_err(internal)?;
+        let (finalized_height, finalized_hash) = finalized.unwrap_or_default();
+        Ok(Response::new(proto::Tip {
+            hash,
+            height: meta.height,
+            total_work: meta.total_work.to_string(),
+            finalized_hash,
+            finalized_height,
+            network_id,
+        }))
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        self.authorize(&request, Role::Submitter)?;
+        let transaction = from_proto(request.into_inner()).ok_or_else(|| Status::invalid_argument("Empty or malformed transaction"))?;
+        let txid = self
+            .chain
+            .submit_transaction_async(transaction)
+            .await
+            .map_err(|e| match e.downcast_ref::<MempoolLimitError>() {
+                Some(MempoolLimitError::PoolFull { .. }) => Status::resource_exhausted(e.to_string()),
+                _ => Status::invalid_argument(e.to_string()),
+            })?;
+        Ok(Response::new(proto::SubmitTransactionResponse { txid }))
+    }
+
+    async fn get_next_nonce(&self, request: Request<proto::GetNextNonceRequest>) -> Result<Response<proto::NextNonce>, Status> {
+        self.authorize(&request, Role::ReadOnly)?;
+        let address = request.into_inner().address;
+        let nonce = self.chain.spawn(move |chain| chain.next_nonce(&address)).await.map_err(internal)?;
+        Ok(Response::new(proto::NextNonce { nonce }))
+    }
+
+    type StreamBlocksStream = ReceiverStream<Result<proto::Block, Status>>;
+
+    async fn stream_blocks(
+        &self,
+        request: Request<proto::StreamBlocksRequest>,
+    ) -> Result<Response<Self::StreamBlocksStream>, Status> {
+        self.authorize(&request, Role::ReadOnly)?;
+        let slot = Slot::claim(&self.streams, self.chain.config.max_grpc_streams)
+            .ok_or_else(|| Status::resource_exhausted("Too many block streams, try again later"))?;
+        let from_height = request.into_inner().from_height;
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let chain = self.chain.clone();
+        // Subscriptions are blocking channels, so each stream gets a thread of its own,
+        // holding its slot until it ends.
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(e) = follow(&chain, from_height, &sender) {
+                let _ = sender.blocking_send(Err(internal(e)));
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_headers(&self, request: Request<proto::RangeRequest>) -> Result<Response<proto::Headers>, Status> {
+        self.authorize(&request, Role::ReadOnly)?;
+        let range = request.into_inner();
+        let headers = self
+            .chain
+            .spawn(move |chain| {
+                let mut headers = Vec::new();
+                for height in range.from_height..range.from_height.saturating_add(range.count.min(MAX_HEADERS) as u64) {
+                    let Some(hash) = chain.canonical_hash(height)? else { break };
+                    let header = chain.header(&hash)?.ok_or_else(|| format!("Block {} is missing", hash))?;
+                    headers.push(header_to_proto(&header));
+                }
+                Ok(headers)
+            })
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::Headers { headers }))
+    }
+
+    async fn get_blocks(&self, request: Request<proto::RangeRequest>) -> Result<Response<proto::Blocks>, Status> {
+        self.authorize(&request, Role::ReadOnly)?;
+        let range = request.into_inner();
+        let blocks = self
+            .chain
+            .spawn(move |chain| {
+                let mut blocks = Vec::new();
+                let mut size = 0;
+                for height in range.from_height..range.from_height.saturating_add(range.count.min(MAX_BLOCKS) as u64) {
+                    let Some(hash) = chain.canonical_hash(height)? else { break };
+                    if chain.is_pruned(&hash)? {
+                        return Err(format!("The block at height {} was pruned; sync from an archive node", height).into());
+                    }
+                    let block = chain.get_block(&hash)?.ok_or_else(|| format!("Block {} is missing", hash))?;
+                    size += block.size();
+                    if !blocks.is_empty() && size > MAX_BLOCKS_BYTES {
+                        break;
+                    }
+                    blocks.push(to_proto(&block, height));
+                }
+                Ok(blocks)
+            })
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::Blocks { blocks }))
+    }
+
+    async fn get_peers(&self, request: Request<proto::GetPeersRequest>) -> Result<Response<proto::Peers>, Status> {
+        self.authorize(&request, Role::ReadOnly)?;
+        let proto::GetPeersRequest { advertise, network_id } = request.into_inner();
+        let addresses = self
+            .chain
+            .spawn(move |chain| {
+                // Callers that predate chain IDs send none.
+                if !network_id.is_empty() && network_id != chain.network_id()? {
+                    return Ok(None);
+                }
+                // A read-only node cannot record the caller, but still answers.
+                if !advertise.is_empty()
+                    && let Err(e) = chain.learn_peers(&[advertise])
+                {
+                    debug!(error = %e, "advertised peer not recorded");
+                }
+                let mut addresses = chain.good_peers()?;
+                addresses.truncate(MAX_SHARED_PEERS);
+                Ok(Some(addresses))
+            })
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| Status::failed_precondition("The caller is on another chain"))?;
+        Ok(Response::new(proto::Peers { addresses }))
+    }
+
+    async fn approve(&self, request: Request<proto::ApproveRequest>) -> Result<Response<proto::ApproveResponse>, Status> {
+        self.authorize(&request, Role::Submitter)?;
+        let proto::ApproveRequest { proposal_id, approval } = request.into_inner();
+        let approval = approval_from_proto(approval.ok_or_else(|| Status::invalid_argument("No approval"))?);
+        let outcome = self
+            .chain
+            .spawn(move |chain| chain.add_approval(&proposal_id, approval))
+            .await
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        let needed = self.chain.genesis_config().approval.map_or(0, |policy| policy.threshold) as u32;
+        Ok(Response::new(match outcome {
+            ApprovalOutcome::Pending { approvals, .. } => {
+                proto::ApproveResponse { approvals: approvals as u32, needed, committed: false, height: 0 }
+            }
+            ApprovalOutcome::Committed { height } => proto::ApproveResponse { approvals: needed, needed, committed: true, height },
+        }))
+    }
+
+    async fn get_block_template(
+        &self,
+        request: Request<proto::GetBlockTemplateRequest>,
+    ) -> Result<Response<proto::BlockTemplate>, Status> {
+        self.authorize(&request, Role::Submitter)?;
+        let template = self
+            .chain
+            .spawn(|chain| chain.get_block_template())
+            .await
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        Ok(Response::new(proto::BlockTemplate {
+            id: template.id,
+            height: template.height,
+            header: Some(header_to_proto(&template.header)),
+            target: template.target,
+            transactions: template.transactions.iter().map(transaction_to_proto).collect(),
+        }))
+    }
+
+    async fn submit_block(&self, request: Request<proto::SubmitBlockRequest>) -> Result<Response<proto::SubmitBlockResponse>, Status> {
+        self.authorize(&request, Role::Submitter)?;
+        let header = request.into_inner().header.ok_or_else(|| Status::invalid_argument("No header"))?;
+        let header = header_from_proto(header).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let hash = header.calculate_hash();
+        let height = self
+            .chain
+            .spawn(move |chain| chain.submit_block(&header))
+            .await
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        Ok(Response::new(proto::SubmitBlockResponse { hash, height }))
+    }
+
+    async fn create_snapshot(&self, request: Request<proto::CreateSnapshotRequest>) -> Result<Response<proto::SnapshotInfo>, Status> {
+        self.authorize(&request, Role::Admin)?;
+        let info = self.chain.spawn(|chain| chain.create_snapshot()).await.map_err(internal)?;
+        Ok(Response::new(snapshot_to_proto(info)))
+    }
+
+    async fn restore_snapshot(&self, request: Request<proto::RestoreSnapshotRequest>) -> Result<Response<proto::SnapshotInfo>, Status> {
+        self.authorize(&request, Role::Admin)?;
+        let height = request.into_inner().height;
+        let info = self
+            .chain
+            .spawn(move |chain| chain.restore_snapshot(height))
+            .await
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        Ok(Response::new(snapshot_to_proto(info)))
+    }
+
+    async fn repair(&self, request: Request<proto::RepairRequest>) -> Result<Response<proto::RepairReport>, Status> {
+        self.authorize(&request, Role::Admin)?;
+        let dry_run = request.into_inner().dry_run;
+        let report = self.chain.spawn(move |chain| chain.repair(dry_run)).await.map_err(internal)?;
+        Ok(Response::new(proto::RepairReport {
+            last_valid: report.last_valid,
+            height: report.height,
+            quarantined: report.quarantined,
+        }))
+    }
+}
+
+impl<S: BlockStore> LedgerService<S> {
+    // Checks the call's "authorization" metadata against the role it needs. Fails with
+    // a `Status` like the handlers it guards.
+    #[allow(clippy::result_large_err)]
+    fn authorize<T>(&self, request: &Request<T>, needed: Role) -> Result<(), Status> {
+        let key = request.metadata().get("authorization").and_then(|value| value.to_str().ok()).and_then(auth::bearer);
+        self.chain.authorize(key, needed).map_err(|e| match e {
+            AuthError::Unauthenticated => Status::unauthenticated(e.to_string()),
+            AuthError::Forbidden { .. } => Status::permission_denied(e.to_string()),
+        })
+    }
+}
+
+fn snapshot_to_proto(info: SnapshotInfo) -> proto::SnapshotInfo {
+    proto::SnapshotInfo {
+        height: info.height,
+        tip: info.tip,
+        created_at: info.created_at,
+        entries: info.entries as u64,
+    }
+}
+
+// Sends the canonical blocks from `height`, then each block as it is added. Returns
+// once the client hangs up or the chain shuts down.
+fn follow<S: BlockStore>(
+    chain: &Blockchain<S>,
+    mut height: u64,
+    sender: &mpsc::Sender<Result<proto::Block, Status>>,
+) -> Result<(), Box<dyn Error>> {
+    // Subscribe first so nothing added during the catch-up is missed. Blocks the
+    // catch-up already sent are skipped when their events come in.
+    let events = chain.subscribe();
+    let mut caught_up = HashSet::new();
+    while let Some(hash) = chain.canonical_hash(height)? {
+        let Some(block) = chain.get_block(&hash)? else { break };
+        if sender.blocking_send(Ok(to_proto(&block, height))).is_err() {
+            return Ok(());
+        }
+        caught_up.insert(hash);
+        height += 1;
+    }
+
+    loop {
+        match events.recv_timeout(IDLE_CHECK) {
+            Ok(ChainEvent::BlockAdded { hash, height }) => {
+                if caught_up.remove(&hash) {
+                    continue;
+                }
+                let block = chain.get_block(&hash)?.ok_or_else(|| format!("Block {} is missing", hash))?;
+                if sender.blocking_send(Ok(to_proto(&block, height))).is_err() {
+                    return Ok(());
+                }
+            }
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                // Events are sent right after the commit, so any left from the
+                // catch-up have arrived by now.
+                caught_up.clear();
+                if sender.is_closed() || chain.is_shutting_down() {
+                    return Ok(());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+pub(crate) fn to_proto(block: &Block, height: u64) -> proto::Block {
+    proto::Block {
+        hash: block.hash.clone(),
+        prev_hash: block.prev_hash.clone(),
+        height,
+        timestamp: block.timestamp,
+        data: block.data.clone(),
+        version: block.version,
+        difficulty: block.difficulty,
+        nonce: block.nonce,
+        transactions: block.transactions.iter().map(transaction_to_proto).collect(),
+        hash_algorithm: block.hash_algorithm.name().to_string(),
+        content_type: block.content_type.clone().unwrap_or_default(),
+        metadata: block.metadata.clone().into_iter().collect(),
+        signature: block.signature.clone(),
+        sequence: block.sequence,
+        network_id: block.network_id.clone(),
+        state_root: block.state_root.clone(),
+        approvals: block.approvals.iter().map(approval_to_proto).collect(),
+    }
+}
+
+fn transaction_to_proto(transaction: &Transaction) -> proto::Transaction {
+    use proto::transaction::Kind;
+    let kind = match transaction {
+        Transaction::Transfer { from, to, amount, fee, nonce, public_key, signature } => Kind::Transfer(proto::Transfer {
+            from: from.clone(),
+            to: to.clone(),
+            amount: *amount,
+            fee: *fee,
+            nonce: *nonce,
+            public_key: public_key.clone(),
+            signature: signature.clone(),
+        }),
+        Transaction::Coinbase { to, amount, height } => Kind::Coinbase(proto::Coinbase {
+            to: to.clone(),
+            amount: *amount,
+            height: *height,
+        }),
+        Transaction::Lock { from, amount, fee, condition, nonce, public_key, signature } => Kind::Lock(proto::Lock {
+            from: from.clone(),
+            amount: *amount,
+            fee: *fee,
+            condition: serde_json::to_string(condition).unwrap_or_default(),
+            nonce: *nonce,
+            public_key: public_key.clone(),
+            signature: signature.clone(),
+        }),
+        Transaction::Unlock { lock, to, fee, witness } => Kind::Unlock(proto::Unlock {
+            lock: lock.clone(),
+            to: to.clone(),
+            fee: *fee,
+            witness: serde_json::to_string(witness).unwrap_or_default(),
+        }),
+    };
+    proto::Transaction { kind: Some(kind) }
+}
+
+pub(crate) fn block_from_proto(block: proto::Block) -> Result<Block, Box<dyn Error>> {
+    let transactions = block
+        .transactions
+        .into_iter()
+        .map(from_proto)
+        .collect::<Option<Vec<Transaction>>>()
+        .ok_or_else(|| format!("Block {} holds an empty or malformed transaction", block.hash))?;
+    Ok(Block {
+        timestamp: block.timestamp,
+        data: block.data,
+        prev_hash: block.prev_hash,
+        version: block.version,
+        transactions,
+        difficulty: block.difficulty,
+        nonce: block.nonce,
+        hash_algorithm: parse_algorithm(&block.hash_algorithm)?,
+        content_type: Some(block.content_type).filter(|content_type| !content_type.is_empty()),
+        metadata: block.metadata.into_iter().collect(),
+        signature: block.signature,
+        sequence: block.sequence,
+        network_id: block.network_id,
+        state_root: block.state_root,
+        approvals: block.approvals.into_iter().map(approval_from_proto).collect(),
+        hash: block.hash,
+    })
+}
+
+pub(crate) fn header_to_proto(header: &BlockHeader) -> proto::BlockHeader {
+    proto::BlockHeader {
+        version: header.version,
+        timestamp: header.timestamp,
+        prev_hash: header.prev_hash.clone(),
+        data_hash: header.data_hash.clone(),
+        merkle_root: header.merkle_root.clone(),
+        difficulty: header.difficulty,
+        nonce: header.nonce,
+        hash: header.hash.clone(),
+        hash_algorithm: header.hash_algorithm.name().to_string(),
+        content_type: header.content_type.clone().unwrap_or_default(),
+        metadata: header.metadata.clone().into_iter().collect(),
+        signature: header.signature.clone(),
+        sequence: header.sequence,
+        network_id: header.network_id.clone(),
+        state_root: header.state_root.clone(),
+        approvals: header.approvals.iter().map(approval_to_proto).collect(),
+    }
+}
+
+fn approval_to_proto(approval: &Approval) -> proto::Approval {
+    proto::Approval { approver: approval.approver.clone(), signature: approval.signature.clone() }
+}
+
+fn approval_from_proto(approval: proto::Approval) -> Approval {
+    Approval { approver: approval.approver, signature: approval.signature }
+}
+
+pub(crate) fn header_from_proto(header: proto::BlockHeader) -> Result<BlockHeader, Box<dyn Error>> {
+    Ok(BlockHeader {
+        version: header.version,
+        timestamp: header.timestamp,
+        prev_hash: header.prev_hash,
+        data_hash: header.data_hash,
+        merkle_root: header.merkle_root,
+        difficulty: header.difficulty,
+        nonce: header.nonce,
+        hash_algorithm: parse_algorithm(&header.hash_algorithm)?,
+        content_type: Some(header.content_type).filter(|content_type| !content_type.is_empty()),
+        metadata: header.metadata.into_iter().collect(),
+        signature: header.signature,
+        sequence: header.sequence,
+        network_id: header.network_id,
+        state_root: header.state_root,
+        approvals: header.approvals.into_iter().map(approval_from_proto).collect(),
+        hash: header.hash,
+    })
+}
+
+fn parse_algorithm(name: &str) -> Result<HashAlgorithm, Box<dyn Error>> {
+    HashAlgorithm::from_name(name).ok_or_else(|| format!("Unknown hash algorithm {:?}", name).into())
+}
+
+// None if the message is empty, or its condition or witness is not valid JSON.
+fn from_proto(transaction: proto::Transaction) -> Option<Transaction> {
+    use proto::transaction::Kind;
+    transaction.kind.and_then(|kind| match kind {
+        Kind::Transfer(transfer) => Some(Transaction::Transfer {
+            from: transfer.from,
+            to: transfer.to,
+            amount: transfer.amount,
+            fee: transfer.fee,
+            nonce: transfer.nonce,
+            public_key: transfer.public_key,
+            signature: transfer.signature,
+        }),
+        Kind::Coinbase(coinbase) => Some(Transaction::coinbase(&coinbase.to, coinbase.amount, coinbase.height)),
+        Kind::Lock(lock) => Some(Transaction::Lock {
+            from: lock.from,
+            amount: lock.amount,
+            fee: lock.fee,
+            condition: serde_json::from_str(&lock.condition).ok()?,
+            nonce: lock.nonce,
+            public_key: lock.public_key,
+            signature: lock.signature,
+        }),
+        Kind::Unlock(unlock) => Some(Transaction::Unlock {
+            lock: unlock.lock,
+            to: unlock.to,
+            fee: unlock.fee,
+            witness: if unlock.witness.is_empty() { Default::default() } else { serde_json::from_str(&unlock.witness).ok()? },
+        }),
+    })
+}
+
+fn internal(e: Box<dyn Error>) -> Status {
+    Status::internal(e.to_string())
+}
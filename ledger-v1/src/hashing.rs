@@ -0,0 +1,514 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use sha2::Sha256;
+use sha3::Sha3_256;
+
+use crate::block::BlockView;
+use crate::header::BlockHeader;
+use crate::script::Condition;
+use crate::state::Account;
+use crate::transaction::Transaction;
+
+// Hash versions recorded in `Block::version`.
+//
+// 0: the original scheme, SHA-256 of `serde_json::to_string(&(timestamp, data, prev_hash))`.
+//    Kept so chains written before the canonical preimage still verify. A legacy block
+//    carrying transactions (never written by this crate) hashes them as a fourth element.
+// 1: hash of the canonical preimage below.
+// 2: hash of the header preimage below, which commits to the data and the
+//    transactions through digests, so a header can be checked without the block body.
+pub const LEGACY_JSON: u32 = 0;
+pub const CANONICAL_V1: u32 = 1;
+pub const HEADER_V2: u32 = 2;
+pub const CURRENT_VERSION: u32 = HEADER_V2;
+
+// The hash function of a chain, set in its genesis config and carried by each block
+// and header. Block hashes, data hashes and merkle trees use it; transaction ids are
+// always SHA-256, since a transaction gets its id before it is in any chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha3,
+    Blake3,
+}
+
+// A hash function with 256-bit output.
+pub trait Hasher {
+    fn hash(bytes: &[u8]) -> [u8; 32];
+}
+
+impl Hasher for Sha256 {
+    fn hash(bytes: &[u8]) -> [u8; 32] {
+        <Sha256 as sha2::Digest>::digest(bytes).into()
+    }
+}
+
+impl Hasher for Sha3_256 {
+    fn hash(bytes: &[u8]) -> [u8; 32] {
+        <Sha3_256 as sha3::Digest>::digest(bytes).into()
+    }
+}
+
+impl Hasher for blake3::Hasher {
+    fn hash(bytes: &[u8]) -> [u8; 32] {
+        blake3::hash(bytes).into()
+    }
+}
+
+impl HashAlgorithm {
+    pub fn digest(self, bytes: &[u8]) -> [u8; 32] {
+        match self {
+            HashAlgorithm::Sha256 => Sha256::hash(bytes),
+            HashAlgorithm::Sha3 => Sha3_256::hash(bytes),
+            HashAlgorithm::Blake3 => blake3::Hasher::hash(bytes),
+        }
+    }
+
+    pub fn hex_digest(self, bytes: &[u8]) -> String {
+        hex::encode(self.digest(bytes))
+    }
+
+    pub fn hasher(self) -> StreamHasher {
+        match self {
+            HashAlgorithm::Sha256 => StreamHasher::Sha256(<Sha256 as sha2::Digest>::new()),
+            HashAlgorithm::Sha3 => StreamHasher::Sha3(Box::new(<Sha3_256 as sha3::Digest>::new())),
+            HashAlgorithm::Blake3 => StreamHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha3 => "sha3",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        [HashAlgorithm::Sha256, HashAlgorithm::Sha3, HashAlgorithm::Blake3].into_iter().find(|algorithm| algorithm.name() == name)
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == HashAlgorithm::default()
+    }
+}
+
+// A hash computed piece by piece, so large input never has to be gathered into one
+// buffer first. Writing to it (`io::Write`) feeds it too.
+pub enum StreamHasher {
+    Sha256(Sha256),
+    Sha3(Box<Sha3_256>),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl StreamHasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            StreamHasher::Sha256(hasher) => sha2::Digest::update(hasher, bytes),
+            StreamHasher::Sha3(hasher) => sha3::Digest::update(hasher.as_mut(), bytes),
+            StreamHasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+    }
+
+    // Feeds everything `reader` yields and returns how many bytes that was.
+    pub fn update_reader(&mut self, reader: impl Read) -> io::Result<u64> {
+        io::copy(&mut { reader }, self)
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        match self {
+            StreamHasher::Sha256(hasher) => sha2::Digest::finalize(hasher).into(),
+            StreamHasher::Sha3(hasher) => sha3::Digest::finalize(*hasher).into(),
+            StreamHasher::Blake3(hasher) => hasher.finalize().into(),
+        }
+    }
+
+    pub fn hex_finalize(self) -> String {
+        hex::encode(self.finalize())
+    }
+}
+
+impl io::Write for StreamHasher {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.update(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Where preimage bytes go: into a buffer for `block_preimage` and friends, or
+// straight into the hasher when only the hash is wanted.
+trait Sink {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+impl Sink for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+impl Sink for StreamHasher {
+    fn put(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+}
+
+// Domain separators, so a preimage can never be confused with other hashed data.
+const BLOCK_TAG: &[u8] = b"ledger-v1/block";
+const TRANSACTION_TAG: &[u8] = b"ledger-v1/tx";
+const ACCOUNT_TAG: &[u8] = b"ledger-v1/account";
+
+// Tags of the optional block fields.
+const FIELD_TRANSACTIONS: u8 = 1;
+const FIELD_WORK: u8 = 2;
+const FIELD_ALGORITHM: u8 = 3;
+const FIELD_CONTENT_TYPE: u8 = 4;
+const FIELD_METADATA: u8 = 5;
+const FIELD_SEQUENCE: u8 = 6;
+const FIELD_NETWORK: u8 = 7;
+const FIELD_STATE: u8 = 8;
+
+// Canonical preimage, fields in this fixed order, integers big-endian:
+//
+//   tag        length-prefixed BLOCK_TAG
+//   version    u32
+//   timestamp  u64
+//   prev_hash  length-prefixed UTF-8
+//   data       length-prefixed bytes
+//
+// followed by the optional fields, in tag order, each as a u8 tag and a
+// length-prefixed value. An optional field is left out entirely when it is empty,
+// so adding one does not change the hash of blocks that don't use it:
+//
+//   1 transactions   the transaction ids, concatenated
+//   2 work           difficulty u32 and nonce u64, left out when both are 0
+//   3 algorithm      name of the hash algorithm, left out for SHA-256 (headers only)
+//   4 content type   the MIME type of the data, left out when unset
+//   5 metadata       each key and value length-prefixed, in key order
+//   6 sequence       u64, left out when 0 (headers only)
+//   7 network        the chain ID, left out when empty (headers only)
+//   8 state          the hex state root, left out when empty (headers only)
+//
+// A length prefix is a u64 byte count, so no field can bleed into the next one.
+//
+// The header preimage (version 2) has the same layout with two substitutions: `data`
+// is replaced by the hex hash of the data, and the transactions field holds the
+// hex merkle root of the transaction ids (see `merkle`) instead of the ids.
+//
+// Every preimage is hashed with the block's `HashAlgorithm`.
+pub fn block_preimage(block: &BlockView) -> Vec<u8> {
+    match block.version {
+        LEGACY_JSON | CANONICAL_V1 => {
+            let mut preimage = Vec::with_capacity(64 + block.prev_hash.len() + block.data.len());
+            canonical_v1_head(&mut preimage, block);
+            push_bytes(&mut preimage, block.data);
+            canonical_v1_tail(&mut preimage, block);
+            preimage
+        }
+        _ => header_preimage(&block.header()),
+    }
+}
+
+pub fn header_preimage(header: &BlockHeader) -> Vec<u8> {
+    let mut preimage = Vec::with_capacity(192 + header.prev_hash.len());
+    write_header(&mut preimage, header);
+    preimage
+}
+
+fn write_header(preimage: &mut impl Sink, header: &BlockHeader) {
+    push_bytes(preimage, BLOCK_TAG);
+    preimage.put(&header.version.to_be_bytes());
+    preimage.put(&header.timestamp.to_be_bytes());
+    push_bytes(preimage, header.prev_hash.as_bytes());
+    push_bytes(preimage, header.data_hash.as_bytes());
+
+    if !header.merkle_root.is_empty() {
+        push_field(preimage, FIELD_TRANSACTIONS, header.merkle_root.as_bytes());
+    }
+    push_work(preimage, header.difficulty, header.nonce);
+    if !header.hash_algorithm.is_default() {
+        push_field(preimage, FIELD_ALGORITHM, header.hash_algorithm.name().as_bytes());
+    }
+    push_content_type(preimage, header.content_type.as_deref());
+    push_metadata(preimage, &header.metadata);
+    if header.sequence != 0 {
+        push_field(preimage, FIELD_SEQUENCE, &header.sequence.to_be_bytes());
+    }
+    if !header.network_id.is_empty() {
+        push_field(preimage, FIELD_NETWORK, header.network_id.as_bytes());
+    }
+    if !header.state_root.is_empty() {
+        push_field(preimage, FIELD_STATE, header.state_root.as_bytes());
+    }
+}
+
+// The version 1 preimage comes in two parts around the length-prefixed data, so
+// the data can be streamed in between.
+fn canonical_v1_head(preimage: &mut impl Sink, block: &BlockView) {
+    push_bytes(preimage, BLOCK_TAG);
+    preimage.put(&block.version.to_be_bytes());
+    preimage.put(&block.timestamp.to_be_bytes());
+    push_bytes(preimage, block.prev_hash.as_bytes());
+}
+
+fn canonical_v1_tail(preimage: &mut impl Sink, block: &BlockView) {
+    if !block.transactions.is_empty() {
+        let ids: String = block.transactions.iter().map(Transaction::hash).collect();
+        push_field(preimage, FIELD_TRANSACTIONS, ids.as_bytes());
+    }
+    push_work(preimage, block.difficulty, block.nonce);
+    push_content_type(preimage, block.content_type);
+    push_metadata(preimage, &block.metadata);
+}
+
+// Transaction preimage: length-prefixed TRANSACTION_TAG, then a kind byte and the
+// fields of that kind in declaration order, encoded like block fields. A transfer's
+// fee, added later, is only appended when it is not 0, and nonces only when set, so
+// older ids are unchanged. A transfer with a nonce always appends its fee first,
+// which keeps the two apart. Signed transfers and locks always append their fee
+// and then the public key and signature, length-prefixed, which makes them longer
+// than any unsigned one.
+pub fn transaction_preimage(transaction: &Transaction) -> Vec<u8> {
+    preimage(transaction, true)
+}
+
+// The preimage without the signature, which is what the sender signs (see
+// `Transaction::signing_message`).
+pub fn signing_preimage(transaction: &Transaction) -> Vec<u8> {
+    preimage(transaction, false)
+}
+
+fn preimage(transaction: &Transaction, with_signature: bool) -> Vec<u8> {
+    let mut preimage = Vec::new();
+    push_bytes(&mut preimage, TRANSACTION_TAG);
+    let push_signer = |preimage: &mut Vec<u8>, public_key: &str, signature: &str| {
+        push_bytes(preimage, public_key.as_bytes());
+        push_bytes(preimage, if with_signature { signature.as_bytes() } else { &[] });
+    };
+    match transaction {
+        Transaction::Transfer { from, to, amount, fee, nonce, public_key, signature } => {
+            preimage.push(0);
+            push_bytes(&mut preimage, from.as_bytes());
+            push_bytes(&mut preimage, to.as_bytes());
+            preimage.extend_from_slice(&amount.to_be_bytes());
+            if *fee != 0 || nonce.is_some() || !public_key.is_empty() {
+                preimage.extend_from_slice(&fee.to_be_bytes());
+            }
+            if let Some(nonce) = nonce {
+                preimage.extend_from_slice(&nonce.to_be_bytes());
+            }
+            if !public_key.is_empty() {
+                push_signer(&mut preimage, public_key, signature);
+            }
+        }
+        Transaction::Coinbase { to, amount, height } => {
+            preimage.push(1);
+            push_bytes(&mut preimage, to.as_bytes());
+            preimage.extend_from_slice(&amount.to_be_bytes());
+            preimage.extend_from_slice(&height.to_be_bytes());
+        }
+        // Conditions and witnesses go in as their JSON.
+        Transaction::Lock { from, amount, fee, condition, nonce, public_key, signature } => {
+            preimage.push(2);
+            push_bytes(&mut preimage, from.as_bytes());
+            preimage.extend_from_slice(&amount.to_be_bytes());
+            preimage.extend_from_slice(&fee.to_be_bytes());
+            push_bytes(&mut preimage, &serde_json::to_vec(condition).unwrap_or_default());
+            if let Some(nonce) = nonce {
+                preimage.extend_from_slice(&nonce.to_be_bytes());
+            }
+            if !public_key.is_empty() {
+                push_signer(&mut preimage, public_key, signature);
+            }
+        }
+        Transaction::Unlock { lock, to, fee, witness } => {
+            preimage.push(3);
+            push_bytes(&mut preimage, lock.as_bytes());
+            push_bytes(&mut preimage, to.as_bytes());
+            preimage.extend_from_slice(&fee.to_be_bytes());
+            push_bytes(&mut preimage, &serde_json::to_vec(witness).unwrap_or_default());
+        }
+    }
+    preimage
+}
+
+// What a state tree leaf commits to (see `state_tree`): length-prefixed ACCOUNT_TAG
+// and address, the balance and nonce, then 0, or 1 and the condition of a lock
+// account. A condition is a kind byte and its fields, with counts before lists and
+// nested conditions encoded the same way. A lock's `spent` flag is left out: locks
+// can't be empty, so a spent one is the one with no balance left.
+pub fn account_preimage(address: &str, account: &Account) -> Vec<u8> {
+    let mut preimage = Vec::new();
+    push_bytes(&mut preimage, ACCOUNT_TAG);
+    push_bytes(&mut preimage, address.as_bytes());
+    preimage.extend_from_slice(&account.balance.to_be_bytes());
+    preimage.extend_from_slice(&account.nonce.to_be_bytes());
+    match &account.condition {
+        Some(condition) => {
+            preimage.push(1);
+            push_condition(&mut preimage, condition);
+        }
+        None => preimage.push(0),
+    }
+    preimage
+}
+
+fn push_condition(preimage: &mut Vec<u8>, condition: &Condition) {
+    match condition {
+        Condition::MultiSig { threshold, keys } => {
+            preimage.push(0);
+            preimage.extend_from_slice(&(*threshold as u64).to_be_bytes());
+            preimage.extend_from_slice(&(keys.len() as u64).to_be_bytes());
+            for key in keys {
+                push_bytes(preimage, key.as_bytes());
+            }
+        }
+        Condition::After { height } => {
+            preimage.push(1);
+            preimage.extend_from_slice(&height.to_be_bytes());
+        }
+        Condition::HashLock { hash } => {
+            preimage.push(2);
+            push_bytes(preimage, hash.as_bytes());
+        }
+        Condition::All(branches) | Condition::Any(branches) => {
+            preimage.push(if matches!(condition, Condition::All(_)) { 3 } else { 4 });
+            preimage.extend_from_slice(&(branches.len() as u64).to_be_bytes());
+            for branch in branches {
+                push_condition(preimage, branch);
+            }
+        }
+    }
+}
+
+pub fn transaction_hash(transaction: &Transaction) -> String {
+    HashAlgorithm::Sha256.hex_digest(&transaction_preimage(transaction))
+}
+
+// Hashes stream their preimage into the hasher, so the data is read once and never
+// copied, however large it is.
+pub fn block_hash(block: &BlockView) -> String {
+    let mut hasher = block.hash_algorithm.hasher();
+    match block.version {
+        LEGACY_JSON => legacy_json(&mut hasher, block),
+        CANONICAL_V1 => {
+            canonical_v1_head(&mut hasher, block);
+            push_bytes(&mut hasher, block.data);
+            canonical_v1_tail(&mut hasher, block);
+        }
+        _ => write_header(&mut hasher, &block.header()),
+    }
+    hasher.hex_finalize()
+}
+
+// The hash `block` would have if its data were what `reader` yields instead of
+// `block.data`, for payloads kept in a file. Version 1 blocks prefix the data with
+// its length, hence the `Seek`; legacy blocks, which only ever held short text,
+// are not supported.
+pub fn block_hash_reader<R: Read + Seek>(block: &BlockView, mut reader: R) -> io::Result<String> {
+    let mut hasher = block.hash_algorithm.hasher();
+    match block.version {
+        LEGACY_JSON => {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "legacy blocks hash their data as JSON text"));
+        }
+        CANONICAL_V1 => {
+            let start = reader.stream_position()?;
+            let len = reader.seek(SeekFrom::End(0))? - start;
+            reader.seek(SeekFrom::Start(start))?;
+            canonical_v1_head(&mut hasher, block);
+            hasher.put(&len.to_be_bytes());
+            if hasher.update_reader(reader.take(len))? != len {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the data ended early"));
+            }
+            canonical_v1_tail(&mut hasher, block);
+        }
+        _ => {
+            let mut header = block.header();
+            header.data_hash = data_hash_reader(block.hash_algorithm, reader)?;
+            write_header(&mut hasher, &header);
+        }
+    }
+    Ok(hasher.hex_finalize())
+}
+
+// `block_hash` given the block's header, which version 2 and later hash instead of
+// the block, so a caller that needs the header too only hashes the data once.
+pub(crate) fn block_hash_with_header(block: &BlockView, header: &BlockHeader) -> String {
+    match block.version {
+        LEGACY_JSON | CANONICAL_V1 => block_hash(block),
+        _ => header_hash(header),
+    }
+}
+
+// Only meaningful for version 2 and later; older hashes need the whole block.
+pub fn header_hash(header: &BlockHeader) -> String {
+    let mut hasher = header.hash_algorithm.hasher();
+    write_header(&mut hasher, header);
+    hasher.hex_finalize()
+}
+
+pub fn data_hash(algorithm: HashAlgorithm, data: &[u8]) -> String {
+    algorithm.hex_digest(data)
+}
+
+pub fn data_hash_reader(algorithm: HashAlgorithm, reader: impl Read) -> io::Result<String> {
+    let mut hasher = algorithm.hasher();
+    hasher.update_reader(reader)?;
+    Ok(hasher.hex_finalize())
+}
+
+// Legacy blocks only ever held text. The JSON is written into the hasher as it is
+// produced rather than built as a string.
+fn legacy_json(hasher: &mut StreamHasher, block: &BlockView) {
+    let data = String::from_utf8_lossy(block.data);
+    // Writing to a hasher cannot fail, and neither can serializing these types.
+    let _ = if block.transactions.is_empty() {
+        serde_json::to_writer(hasher, &(block.timestamp, &data, &block.prev_hash))
+    } else {
+        serde_json::to_writer(hasher, &(block.timestamp, &data, &block.prev_hash, &block.transactions))
+    };
+}
+
+fn push_bytes(preimage: &mut impl Sink, bytes: &[u8]) {
+    preimage.put(&(bytes.len() as u64).to_be_bytes());
+    preimage.put(bytes);
+}
+
+fn push_field(preimage: &mut impl Sink, tag: u8, value: &[u8]) {
+    preimage.put(&[tag]);
+    push_bytes(preimage, value);
+}
+
+fn push_content_type(preimage: &mut impl Sink, content_type: Option<&str>) {
+    if let Some(content_type) = content_type {
+        push_field(preimage, FIELD_CONTENT_TYPE, content_type.as_bytes());
+    }
+}
+
+fn push_metadata<K: AsRef<str>, V: AsRef<str>>(preimage: &mut impl Sink, metadata: &BTreeMap<K, V>) {
+    if !metadata.is_empty() {
+        let mut value = Vec::new();
+        for (key, entry) in metadata {
+            push_bytes(&mut value, key.as_ref().as_bytes());
+            push_bytes(&mut value, entry.as_ref().as_bytes());
+        }
+        push_field(preimage, FIELD_METADATA, &value);
+    }
+}
+
+fn push_work(preimage: &mut impl Sink, difficulty: u32, nonce: u64) {
+    if difficulty != 0 || nonce != 0 {
+        let mut work = difficulty.to_be_bytes().to_vec();
+        work.extend_from_slice(&nonce.to_be_bytes());
+        push_field(preimage, FIELD_WORK, &work);
+    }
+}
@@ -0,0 +1,155 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::approval::Approval;
+use crate::batch::ReadTrees;
+use crate::block::{Block, BlockView};
+use crate::blockchain::Blockchain;
+use crate::encoding::{is_block_key, is_sealed, open_block, open_header, seal_header};
+use crate::hashing::{self, HashAlgorithm};
+use crate::merkle::{self, MerkleProof};
+use crate::store::{BlockStore, TreeId};
+use crate::transaction::Transaction;
+
+// Everything a version 2 block hash commits to, with the data and transactions
+// replaced by digests. Light clients keep only these.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub timestamp: u64,
+    pub prev_hash: String,
+    // Hash of the block data, hex.
+    pub data_hash: String,
+    // Root of the transaction ids, empty for blocks without transactions.
+    pub merkle_root: String,
+    pub difficulty: u32,
+    pub nonce: u64,
+    pub hash: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    pub signature: String,
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub network_id: String,
+    #[serde(default)]
+    pub state_root: String,
+    #[serde(default)]
+    pub approvals: Vec<Approval>,
+}
+
+impl BlockHeader {
+    pub fn calculate_hash(&self) -> String {
+        hashing::header_hash(self)
+    }
+
+    pub fn is_genesis(&self) -> bool {
+        self.prev_hash == "0"
+    }
+}
+
+impl Block {
+    pub fn header(&self) -> BlockHeader {
+        self.view().header()
+    }
+
+    pub fn txids(&self) -> Vec<String> {
+        self.view().txids()
+    }
+}
+
+impl BlockView<'_> {
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            version: self.version,
+            timestamp: self.timestamp,
+            prev_hash: self.prev_hash.to_string(),
+            data_hash: hashing::data_hash(self.hash_algorithm, self.data),
+            merkle_root: merkle::merkle_root(self.hash_algorithm, &self.txids()),
+            difficulty: self.difficulty,
+            nonce: self.nonce,
+            hash: self.hash.to_string(),
+            hash_algorithm: self.hash_algorithm,
+            content_type: self.content_type.map(str::to_string),
+            metadata: self.metadata.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            signature: self.signature.to_string(),
+            sequence: self.sequence,
+            network_id: self.network_id.to_string(),
+            state_root: self.state_root.to_string(),
+            approvals: self.approvals.to_vec(),
+        }
+    }
+
+    pub fn txids(&self) -> Vec<String> {
+        self.transactions.iter().map(Transaction::hash).collect()
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Header of a stored block, for serving light clients. Only the header record is
+    // read, never the body.
+    pub fn header(&self, hash: &str) -> Result<Option<BlockHeader>, Box<dyn Error>> {
+        match self.trees.load_header(hash)? {
+            Some(header) => Ok(Some(header)),
+            None if self.is_pruned(hash)? => Err(format!("Block {} was pruned before headers were kept", hash).into()),
+            None => Ok(None),
+        }
+    }
+
+    // Fills the headers tree for a database written before it existed. Bodies pruned
+    // back then left their header in the pruned tree; bodies pruned before that have
+    // none to recover. Returns the number of headers written; running it again is a no-op.
+    pub(crate) fn build_headers(&self) -> Result<usize, Box<dyn Error>> {
+        let mut built = 0;
+        for entry in self.store().scan_prefix(TreeId::Blocks, &[]) {
+            let (key, bytes) = entry?;
+            if !is_block_key(&key) || self.store().get(TreeId::Headers, &key)?.is_some() {
+                continue;
+            }
+            let header = match self.store().get(TreeId::Pruned, &key)? {
+                Some(pruned) if pruned.is_empty() => continue,
+                Some(pruned) => open_header(&pruned, None)?,
+                None => open_block(&bytes, self.trees.cipher())?.header(),
+            };
+            self.store().insert(TreeId::Headers, &key, seal_header(&header, self.trees.cipher())?)?;
+            built += 1;
+        }
+        self.store().flush()?;
+        Ok(built)
+    }
+
+    // Seals the header records an encrypted chain wrote in the clear before headers
+    // were encrypted. Returns the number sealed; without a key there is nothing to do.
+    pub(crate) fn seal_headers(&self) -> Result<usize, Box<dyn Error>> {
+        let Some(cipher) = self.trees.cipher() else { return Ok(0) };
+        let mut sealed = 0;
+        for entry in self.store().scan_prefix(TreeId::Headers, &[]) {
+            let (key, bytes) = entry?;
+            if is_sealed(&bytes) {
+                continue;
+            }
+            self.store().insert(TreeId::Headers, &key, seal_header(&open_header(&bytes, None)?, Some(cipher))?)?;
+            sealed += 1;
+        }
+        self.store().flush()?;
+        Ok(sealed)
+    }
+
+    // Merkle proof that the block holds the transaction, or None if it doesn't.
+    pub fn transaction_proof(&self, block_hash: &str, txid: &str) -> Result<Option<MerkleProof>, Box<dyn Error>> {
+        if self.is_pruned(block_hash)? {
+            return Err(format!("Block {} is pruned", block_hash).into());
+        }
+        let block = self
+            .load_block(block_hash)?
+            .ok_or_else(|| format!("Unknown block {}", block_hash))?;
+        let txids = block.txids();
+        Ok(txids.iter().position(|id| id == txid).and_then(|index| merkle::merkle_proof(block.hash_algorithm, &txids, index)))
+    }
+}
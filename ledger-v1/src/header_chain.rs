@@ -0,0 +1,302 @@
+use serde::{Serialize, Deserialize};
+use sled::Transactional;
+use std::error::Error;
+use std::io::Read;
+use std::sync::Arc;
+
+use crate::blockchain::{block_work, check_network_id, check_sequence, check_timestamp, BlockStatus};
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::export::{read_blocks, ExportFormat};
+use crate::genesis::GenesisConfig;
+use crate::hashing::HEADER_V2;
+use crate::header::BlockHeader;
+use crate::lockfile::{self, LockFile};
+use crate::merkle::{self, MerkleProof};
+use crate::pow;
+use crate::state::{self, Account};
+use crate::state_tree::AccountProof;
+use tracing::{error, info, instrument};
+
+// A light client: follows the chain with the most work by headers alone, checking
+// links, timestamps and proof of work, but holds no block bodies and no state.
+// Transactions are checked against the merkle roots of the headers instead, and
+// accounts against their state roots.
+pub struct HeaderChain {
+    db: sled::Db,
+    headers: sled::Tree, // block hash -> StoredHeader
+    heights: sled::Tree, // height (big-endian) -> hash of the best-chain header
+    genesis: GenesisConfig,
+    config: Config,
+    clock: Arc<dyn Clock>,
+    tip: String,
+    _lock: LockFile,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct StoredHeader {
+    header: BlockHeader,
+    height: u64,
+    total_work: u128,
+}
+
+impl HeaderChain {
+    // Like `Blockchain::open_with_config`: a new database starts from `genesis`, an
+    // existing one must have been created from the same config.
+    pub fn open(
+        path: &str,
+        expected_genesis: Option<&GenesisConfig>,
+        config: Config,
+    ) -> Result<HeaderChain, Box<dyn Error>> {
+        let (db, _lock) = lockfile::open_locked(path)?;
+        let headers = db.open_tree("headers")?;
+        let heights = db.open_tree("heights")?;
+
+        let genesis = match db.get("GENESIS")? {
+            Some(bytes) => {
+                let stored: GenesisConfig = serde_json::from_slice(&bytes)?;
+                if let Some(expected) = expected_genesis
+                    && *expected != stored
+                {
+                    return Err("Genesis mismatch: the database was created from a different genesis config".into());
+                }
+                stored
+            }
+            None => expected_genesis.cloned().unwrap_or_default(),
+        };
+
+        let tip = match db.get("LAST")? {
+            Some(bytes) => String::from_utf8(bytes.to_vec())?,
+            None => {
+                let header = genesis.genesis_block()?.header();
+                let stored = StoredHeader { height: 0, total_work: block_work(header.difficulty), header };
+                db.insert("GENESIS", serde_json::to_vec(&genesis)?)?;
+                headers.insert(&stored.header.hash, rmp_serde::to_vec(&stored)?)?;
+                heights.insert(0u64.to_be_bytes(), stored.header.hash.as_bytes())?;
+                db.insert("LAST", stored.header.hash.as_bytes())?;
+                db.flush()?;
+                stored.header.hash
+            }
+        };
+
+        Ok(HeaderChain { db, headers, heights, genesis, config, clock: Arc::new(SystemClock), tip, _lock })
+    }
+
+    // Checks header timestamps against `clock` instead of the wall clock.
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        HeaderChain { clock: Arc::new(clock), ..self }
+    }
+
+    pub fn tip(&self) -> &str {
+        &self.tip
+    }
+
+    pub fn height(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.stored(&self.tip)?.ok_or("The tip header is missing")?.height)
+    }
+
+    pub fn header(&self, hash: &str) -> Result<Option<BlockHeader>, Box<dyn Error>> {
+        Ok(self.stored(hash)?.map(|stored| stored.header))
+    }
+
+    // The best-chain header at `height`.
+    pub fn header_at(&self, height: u64) -> Result<Option<BlockHeader>, Box<dyn Error>> {
+        match self.heights.get(height.to_be_bytes())? {
+            Some(hash) => self.header(std::str::from_utf8(&hash)?),
+            None => Ok(None),
+        }
+    }
+
+    // Validates a header against its parent and stores it. Like
+    // `Blockchain::receive_block`, the branch with the most work wins.
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<BlockStatus, Box<dyn Error>> {
+        if self.headers.contains_key(&header.hash)? {
+            return Ok(BlockStatus::AlreadyKnown);
+        }
+        if header.version < HEADER_V2 {
+            return Err(format!(
+                "Block {} uses hash version {}, which does not commit to a header",
+                header.hash, header.version
+            ).into());
+        }
+        if header.hash != header.calculate_hash() {
+            return Err(format!("Hash mismatch for header {}", header.hash).into());
+        }
+        let parent = self
+            .stored(&header.prev_hash)?
+            .ok_or_else(|| format!("Header {} builds on unknown block {}", header.hash, header.prev_hash))?;
+        let height = parent.height + 1;
+        check_timestamp(&header.hash, header.timestamp, parent.header.timestamp, self.clock.now_ms(), self.config.max_future_drift_ms)?;
+        check_sequence(&header, &parent.header)?;
+        check_network_id(&header, &parent.header, &self.genesis.network_id()?)?;
+        pow::check_work(&self.genesis, &header, &parent.header, height, |hash| self.header(hash))?;
+
+        let tip = self.stored(&self.tip)?.ok_or("The tip header is missing")?;
+        let stored = StoredHeader {
+            height,
+            total_work: parent.total_work + block_work(header.difficulty),
+            header,
+        };
+        if stored.total_work <= tip.total_work {
+            self.headers.insert(&stored.header.hash, rmp_serde::to_vec(&stored)?)?;
+            return Ok(BlockStatus::SideChain);
+        }
+
+        // Walk the new branch back to the best chain, then swap the height index over
+        // to it in one transaction.
+        let mut connected = vec![stored.header.hash.clone()];
+        let mut fork = parent;
+        while self.heights.get(fork.height.to_be_bytes())?.as_deref() != Some(fork.header.hash.as_bytes()) {
+            connected.push(fork.header.hash.clone());
+            fork = self
+                .stored(&fork.header.prev_hash)?
+                .ok_or_else(|| format!("Broken link! Could not find header: {}", fork.header.prev_hash))?;
+        }
+        connected.reverse();
+        let mut disconnected = Vec::new();
+        for old_height in (fork.height + 1..=tip.height).rev() {
+            if let Some(hash) = self.heights.get(old_height.to_be_bytes())? {
+                disconnected.push(String::from_utf8(hash.to_vec())?);
+            }
+        }
+
+        let encoded = rmp_serde::to_vec(&stored)?;
+        let default_tree: &sled::Tree = &self.db;
+        (default_tree, &self.headers, &self.heights)
+            .transaction(|(db, headers, heights)| {
+                headers.insert(stored.header.hash.as_bytes(), encoded.as_slice())?;
+                for old_height in fork.height + 1..=tip.height {
+                    heights.remove(&old_height.to_be_bytes())?;
+                }
+                for (offset, hash) in connected.iter().enumerate() {
+                    heights.insert(&(fork.height + 1 + offset as u64).to_be_bytes(), hash.as_bytes())?;
+                }
+                db.insert("LAST", stored.header.hash.as_bytes())?;
+                Ok(())
+            })
+            .map_err(|e: sled::transaction::TransactionError| format!("Transaction failed: {}", e))?;
+        self.db.flush()?;
+        self.tip = stored.header.hash;
+
+        Ok(if disconnected.is_empty() {
+            BlockStatus::Extended
+        } else {
+            BlockStatus::Reorged { disconnected, connected }
+        })
+    }
+
+    // Adds headers parent first, stopping at the first invalid one.
+    pub fn add_headers(&mut self, headers: Vec<BlockHeader>) -> Result<Vec<BlockStatus>, Box<dyn Error>> {
+        headers.into_iter().map(|header| self.add_header(header)).collect()
+    }
+
+    // Takes the headers of an exported chain (see `Blockchain::export`). Returns the
+    // number of headers that were new.
+    pub fn import<R: Read>(&mut self, reader: R, format: ExportFormat) -> Result<usize, Box<dyn Error>> {
+        let headers: Vec<BlockHeader> = read_blocks(reader, format)?.iter().map(|block| block.header()).collect();
+        let statuses = self.add_headers(headers)?;
+        Ok(statuses.iter().filter(|status| **status != BlockStatus::AlreadyKnown).count())
+    }
+
+    // SPV check: true if the block is on the best chain and the proof (from
+    // `Blockchain::transaction_proof`) leads to its merkle root.
+    pub fn verify_transaction(&self, block_hash: &str, proof: &MerkleProof) -> Result<bool, Box<dyn Error>> {
+        let Some(stored) = self.stored(block_hash)? else {
+            return Ok(false);
+        };
+        let canonical = self.heights.get(stored.height.to_be_bytes())?;
+        if canonical.as_deref() != Some(block_hash.as_bytes()) {
+            return Ok(false);
+        }
+        Ok(merkle::verify_proof(stored.header.hash_algorithm, &stored.header.merkle_root, proof))
+    }
+
+    // Like `verify_transaction` for an account: true if the block is on the best chain
+    // and the proof (from `Blockchain::account_proof`) shows `address` holding
+    // `account` in the state its root commits to.
+    pub fn verify_account(&self, block_hash: &str, address: &str, account: &Account, proof: &AccountProof) -> Result<bool, Box<dyn Error>> {
+        let Some(stored) = self.stored(block_hash)? else {
+            return Ok(false);
+        };
+        let canonical = self.heights.get(stored.height.to_be_bytes())?;
+        if canonical.as_deref() != Some(block_hash.as_bytes()) || stored.header.state_root.is_empty() {
+            return Ok(false);
+        }
+        Ok(state::verify_account_proof(stored.header.hash_algorithm, &stored.header.state_root, address, account, proof))
+    }
+
+    // Blocks on top of the one holding a verified transaction, counting itself.
+    pub fn confirmations(&self, block_hash: &str) -> Result<u64, Box<dyn Error>> {
+        match self.stored(block_hash)? {
+            Some(stored) if self.header_at(stored.height)?.is_some_and(|header| header.hash == block_hash) => {
+                Ok(self.height()? - stored.height + 1)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    // Rechecks every best-chain header from the tip back to genesis.
+    #[instrument(skip_all)]
+    pub fn is_chain_valid(&self) -> Result<bool, Box<dyn Error>> {
+        let mut search_hash = self.tip.clone();
+        let network_id = self.genesis.network_id()?;
+        let mut child: Option<StoredHeader> = None;
+        loop {
+            let Some(stored) = self.stored(&search_hash)? else {
+                error!(header = %search_hash, "broken link, header not found");
+                return Ok(false);
+            };
+            let header = &stored.header;
+            if header.hash != search_hash || header.hash != header.calculate_hash() {
+                error!(header = %search_hash, "hash mismatch");
+                return Ok(false);
+            }
+            if let Some(child) = &child {
+                let checked = check_timestamp(&child.header.hash, child.header.timestamp, header.timestamp, self.clock.now_ms(), self.config.max_future_drift_ms)
+                    .and_then(|_| check_sequence(&child.header, header))
+                    .and_then(|_| check_network_id(&child.header, header, &network_id))
+                    .and_then(|_| pow::check_work(&self.genesis, &child.header, header, child.height, |hash| self.header(hash)));
+                if let Err(e) = checked {
+                    error!(header = %child.header.hash, error = %e, "invalid header");
+                    return Ok(false);
+                }
+            }
+            if header.is_genesis() {
+                if stored.height != 0 || header.hash != self.genesis.genesis_block()?.hash {
+                    error!(genesis = %header.hash, "header chain does not start at the configured genesis");
+                    return Ok(false);
+                }
+                info!("header chain valid, genesis reached");
+                return Ok(true);
+            }
+            search_hash = header.prev_hash.clone();
+            child = Some(stored);
+        }
+    }
+
+    pub fn print_chain(&self) {
+        let mut search_hash = self.tip.clone();
+        println!("--- HEADERS ON DISK ---");
+
+        while let Ok(Some(header)) = self.header(&search_hash) {
+            println!("Hash: {}", header.hash);
+            println!("Merkle root: {}", header.merkle_root);
+            if !header.state_root.is_empty() {
+                println!("State root: {}", header.state_root);
+            }
+            println!("Prev: {}\n", header.prev_hash);
+
+            if header.is_genesis() {
+                break;
+            }
+            search_hash = header.prev_hash;
+        }
+    }
+
+    fn stored(&self, hash: &str) -> Result<Option<StoredHeader>, Box<dyn Error>> {
+        match self.headers.get(hash)? {
+            Some(bytes) => Ok(Some(rmp_serde::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+}
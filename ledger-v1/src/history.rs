@@ -0,0 +1,114 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::encryption::BlockCipher;
+use crate::search;
+use crate::store::{BlockStore, TreeId};
+use crate::transaction::Transaction;
+
+// The "history" tree lists the canonical transactions touching each address. Keys are
+// the address, a zero byte, then the height and the position in the block (both
+// big-endian), so a prefix scan returns an address's history in chain order. On an
+// encrypted chain the address is replaced by its index token, as in `search`.
+
+// Marks a built index. Like the search index's marker, its value is empty for plain
+// keys, or else the HMAC of the empty token under the key in use.
+const BUILT_KEY: &str = "HISTORY_BUILT";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HistoryEntry {
+    pub height: u64,
+    pub block: String,
+    pub txid: String,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Canonical transactions sent or received by `address`, oldest first.
+    pub fn get_history(&self, address: &str) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+        let mut history = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::History, &address_prefix(self.trees.cipher(), address)) {
+            let (_, value) = entry?;
+            history.push(serde_json::from_slice(&value)?);
+        }
+        Ok(history)
+    }
+
+    // Chains written before the index existed get it built once, and it is built
+    // again when the encryption key changes, which changes its keys.
+    pub(crate) fn build_history_if_needed(&self) -> Result<(), Box<dyn Error>> {
+        let built = self.trees.get(TreeId::Blocks, BUILT_KEY.as_bytes())?;
+        if built.is_some_and(|value| value == fingerprint(self.trees.cipher()).as_bytes()) {
+            return Ok(());
+        }
+        let mut batch = self.batch();
+        batch.clear(TreeId::History)?;
+        for height in 0..=self.height()? {
+            let Some(hash) = self.canonical_hash(height)? else { continue };
+            if let Some(block) = self.load_block(&hash)? {
+                batch.record_history(&block, height)?;
+            }
+        }
+        batch.mark_history_built();
+        self.commit(batch)
+    }
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    // Called as a block joins or leaves the canonical chain.
+    pub(crate) fn record_history(&mut self, block: &Block, height: u64) -> Result<(), Box<dyn Error>> {
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            let entry = HistoryEntry { height, block: block.hash.clone(), txid: transaction.hash() };
+            let value = serde_json::to_vec(&entry)?;
+            for address in touched(transaction) {
+                let key = history_key(self.cipher(), address, height, position);
+                self.insert(TreeId::History, key, value.as_slice());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn forget_history(&mut self, block: &Block, height: u64) {
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            for address in touched(transaction) {
+                let key = history_key(self.cipher(), address, height, position);
+                self.remove(TreeId::History, key);
+            }
+        }
+    }
+
+    pub(crate) fn mark_history_built(&mut self) {
+        let fingerprint = fingerprint(self.cipher());
+        self.insert(TreeId::Blocks, BUILT_KEY, fingerprint);
+    }
+}
+
+// A self-transfer is listed once.
+fn touched(transaction: &Transaction) -> Vec<&str> {
+    match transaction {
+        Transaction::Transfer { from, to, .. } if from == to => vec![from],
+        Transaction::Transfer { from, to, .. } => vec![from, to],
+        Transaction::Coinbase { to, .. } => vec![to],
+        Transaction::Lock { from, .. } => vec![from],
+        Transaction::Unlock { to, .. } => vec![to],
+    }
+}
+
+fn fingerprint(cipher: Option<&BlockCipher>) -> String {
+    cipher.map(|cipher| cipher.index_token("")).unwrap_or_default()
+}
+
+fn address_prefix(cipher: Option<&BlockCipher>, address: &str) -> Vec<u8> {
+    let mut prefix = search::index_token(cipher, address).into_bytes();
+    prefix.push(0);
+    prefix
+}
+
+fn history_key(cipher: Option<&BlockCipher>, address: &str, height: u64, position: usize) -> Vec<u8> {
+    let mut key = address_prefix(cipher, address);
+    key.extend_from_slice(&height.to_be_bytes());
+    key.extend_from_slice(&(position as u32).to_be_bytes());
+    key
+}
@@ -0,0 +1,84 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::batch::ChainBatch;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::store::BlockStore;
+use crate::transaction::Transaction;
+
+// Custom acceptance rules on top of the consensus ones. Every block is offered to the
+// hooks after it passes `ChainBatch::check_block`'s own checks and before it is
+// stored, along with each of its transactions; a transaction is offered again when it
+// is submitted to the mempool. Any error rejects it, and a block rejected this way is
+// marked invalid like one that broke a consensus rule.
+//
+// Hooks are meant to run untrusted logic, so they see only the block or transaction
+// and cannot touch the store. With the wasm-validators feature, each module in
+// `Config::validator_modules` is loaded as a `WasmValidator`, which runs it in a
+// sandbox with a fuel limit and refuses whatever runs out of fuel or traps, the same
+// as an invalid block. Hooks of those modules run before any added in code.
+pub trait Validator: Send + Sync + 'static {
+    // `height` is the one the block will have once stored.
+    fn check_block(&self, _block: &Block, _height: u64) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn check_transaction(&self, _transaction: &Transaction) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+// The hooks of `Config::validator_modules`.
+#[cfg(feature = "wasm-validators")]
+pub(crate) fn from_config(config: &Config) -> Result<Vec<Arc<dyn Validator>>, Box<dyn Error>> {
+    config
+        .validator_modules
+        .iter()
+        .map(|path| Ok(Arc::new(crate::wasm_validator::WasmValidator::load(path, config.validator_fuel)?) as Arc<dyn Validator>))
+        .collect()
+}
+
+#[cfg(not(feature = "wasm-validators"))]
+pub(crate) fn from_config(config: &Config) -> Result<Vec<Arc<dyn Validator>>, Box<dyn Error>> {
+    if !config.validator_modules.is_empty() {
+        return Err("The config names validator modules, but this build has no wasm-validators feature".into());
+    }
+    Ok(Vec::new())
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // This handle, also running `validator` on what it accepts, after any hooks added
+    // before. Clones of the result share the hooks; other handles to the chain keep
+    // theirs, so every handle that accepts blocks or transactions needs them.
+    pub fn with_validator(mut self, validator: impl Validator) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    pub(crate) fn check_transaction_hooks(&self, transaction: &Transaction) -> Result<(), Box<dyn Error>> {
+        for validator in &self.validators {
+            validator
+                .check_transaction(transaction)
+                .map_err(|e| format!("Transaction {} rejected by a validation hook: {}", transaction.hash(), e))?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    pub(crate) fn check_hooks(&self, block: &Block, height: u64) -> Result<(), Box<dyn Error>> {
+        for validator in self.validators.iter() {
+            validator
+                .check_block(block, height)
+                .map_err(|e| format!("Block {} rejected by a validation hook: {}", block.hash, e))?;
+            for transaction in &block.transactions {
+                validator.check_transaction(transaction).map_err(|e| {
+                    format!("Block {} rejected by a validation hook: transaction {}: {}", block.hash, transaction.hash(), e)
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
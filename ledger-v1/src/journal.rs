@@ -0,0 +1,112 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::blockchain::Blockchain;
+use crate::config::JournalRecovery;
+use crate::store::{BlockStore, TreeId, Writes};
+use tracing::{info, warn};
+
+// Reorgs, imports and repairs rewrite many records across the trees at once. Before
+// such a batch is applied, the journal records the operation with every write it
+// is about to make and what each key held until then, and flushes that record. The
+// record is removed once the writes are on disk, so one left behind on the next open
+// means the operation was interrupted, and `Config::journal_recovery` decides what
+// happens: replaying the writes finishes it, rolling back restores what they
+// replaced. Either way the chain opens with the operation wholly done or not done
+// at all, whether or not the store managed to apply the batch atomically.
+//
+// Journaled commits are flushed whatever the durability policy, so a reorg that
+// has returned is never lost.
+
+const PENDING: &[u8] = b"PENDING";
+
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+    operation: String,
+    started_ms: u64,
+    redo: Vec<JournalWrite>,
+    // What the keys of `redo` held before.
+    undo: Vec<JournalWrite>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalWrite {
+    tree: String,
+    #[serde(with = "crate::payload")]
+    key: Vec<u8>,
+    // None removes the key.
+    value: Option<JournalValue>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalValue(#[serde(with = "crate::payload")] Vec<u8>);
+
+impl JournalWrite {
+    fn new(tree: TreeId, key: &[u8], value: Option<&[u8]>) -> JournalWrite {
+        JournalWrite { tree: tree.name().to_string(), key: key.to_vec(), value: value.map(|value| JournalValue(value.to_vec())) }
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Records `batch` as under way, durably, before it is applied.
+    pub(crate) fn begin_journal(&self, operation: &str, batch: &ChainBatch<S>) -> Result<(), Box<dyn Error>> {
+        let mut entry = JournalEntry { operation: operation.to_string(), started_ms: self.clock.now_ms(), redo: Vec::new(), undo: Vec::new() };
+        for ((tree, key), value) in batch.writes() {
+            entry.redo.push(JournalWrite::new(*tree, key, value.as_deref()));
+            entry.undo.push(JournalWrite::new(*tree, key, self.trees.get(*tree, key)?.as_deref()));
+        }
+        self.store().insert(TreeId::Journal, PENDING, rmp_serde::to_vec(&entry)?)?;
+        self.store().flush()
+    }
+
+    // Clears the journal once the operation's writes are on disk.
+    pub(crate) fn end_journal(&self) -> Result<(), Box<dyn Error>> {
+        self.store().apply(&Writes::from([((TreeId::Journal, PENDING.to_vec()), None)]))?;
+        self.store().flush()
+    }
+
+    // The operation the journal shows under way, if any: only while one is being
+    // committed, or after a crash on a database opened read-only, which cannot
+    // recover it.
+    pub fn pending_operation(&self) -> Result<Option<String>, Box<dyn Error>> {
+        pending(self.store())
+    }
+}
+
+fn pending(store: &impl BlockStore) -> Result<Option<String>, Box<dyn Error>> {
+    store.get_with(TreeId::Journal, PENDING, |bytes| Ok(rmp_serde::from_slice::<JournalEntry>(bytes)?.operation))
+}
+
+// Finishes or undoes an operation interrupted before its journal entry was cleared,
+// as `recovery` says, when a chain is opened.
+pub(crate) fn recover(store: &impl BlockStore, recovery: JournalRecovery) -> Result<(), Box<dyn Error>> {
+    let Some(bytes) = store.get(TreeId::Journal, PENDING)? else {
+        return Ok(());
+    };
+    if store.is_read_only() {
+        warn!("the database was left in the middle of an operation; open it read-write to recover");
+        return Ok(());
+    }
+    let entry: JournalEntry = rmp_serde::from_slice(&bytes)?;
+    let journaled = match recovery {
+        JournalRecovery::Replay => &entry.redo,
+        JournalRecovery::Rollback => &entry.undo,
+    };
+    let mut writes = Writes::new();
+    for write in journaled {
+        let tree = TreeId::ALL
+            .into_iter()
+            .find(|tree| tree.name() == write.tree)
+            .ok_or_else(|| format!("The journal writes to an unknown tree '{}'", write.tree))?;
+        writes.insert((tree, write.key.clone()), write.value.as_ref().map(|value| value.0.clone()));
+    }
+    writes.insert((TreeId::Journal, PENDING.to_vec()), None);
+    store.apply(&writes)?;
+    store.flush()?;
+    match recovery {
+        JournalRecovery::Replay => info!(operation = %entry.operation, started_ms = entry.started_ms, "interrupted operation replayed"),
+        JournalRecovery::Rollback => info!(operation = %entry.operation, started_ms = entry.started_ms, "interrupted operation rolled back"),
+    }
+    Ok(())
+}
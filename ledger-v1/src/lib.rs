@@ -0,0 +1,148 @@
+#[cfg(feature = "async")]
+pub mod async_api;
+pub mod anchor;
+pub mod approval;
+pub mod audit;
+pub mod auth;
+pub mod authority;
+#[cfg(feature = "sled")]
+pub mod backup;
+pub(crate) mod batch;
+pub mod blobs;
+pub mod block;
+pub mod blockchain;
+pub mod checkpoint;
+pub mod clock;
+pub(crate) mod coinbase;
+pub mod config;
+pub mod conflicts;
+pub mod consistency;
+pub mod encoding;
+pub(crate) mod encryption;
+pub mod error;
+pub mod events;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod finality;
+pub mod gc;
+pub mod genesis;
+pub mod getwork;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hashing;
+pub mod history;
+pub mod journal;
+pub mod hooks;
+pub mod header;
+#[cfg(feature = "sled")]
+pub mod header_chain;
+pub mod limits;
+pub mod listing;
+#[cfg(feature = "sled")]
+pub mod lockfile;
+pub mod mempool;
+pub mod merkle;
+pub mod metrics;
+pub mod migrations;
+pub mod miner;
+pub mod orphans;
+pub mod peers;
+pub(crate) mod payload;
+pub mod pow;
+pub mod pruning;
+pub mod ratelimit;
+#[cfg(feature = "sled")]
+pub mod registry;
+pub mod repair;
+pub mod replay;
+#[cfg(feature = "sled")]
+pub mod replica;
+pub mod retention;
+pub mod report;
+pub mod script;
+pub mod schema;
+pub mod search;
+pub mod shutdown;
+pub mod signer;
+pub mod snapshot;
+pub mod state;
+pub mod state_tree;
+pub mod stats;
+pub mod store;
+#[cfg(feature = "grpc")]
+pub mod sync;
+#[cfg(feature = "test_utils")]
+pub mod test_utils;
+pub mod timefmt;
+pub mod transaction;
+pub mod txindex;
+pub mod validation;
+pub mod verify;
+pub mod webhooks;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+#[cfg(feature = "wasm-validators")]
+pub mod wasm_validator;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use anchor::{Anchor, AnchorCheck, AnchorProof, AnchorTarget};
+pub use approval::{Approval, ApprovalOutcome, ApprovalPolicy, Proposal};
+pub use audit::{AuditEntry, AuditLedger, TamperFinding, TamperReport};
+pub use auth::{ApiKey, AuthError, Role};
+#[cfg(feature = "sled")]
+pub use backup::BackupInfo;
+pub use blobs::BlobInfo;
+pub use block::{Block, BlockView};
+pub use blockchain::{Blockchain, BlockStatus};
+pub use checkpoint::Checkpoint;
+pub use clock::{Clock, SystemClock};
+pub use config::{Compression, Config, Durability, JournalRecovery, NodeMode, DEFAULT_PRUNE_DEPTH};
+pub use conflicts::Conflict;
+pub use consistency::ConsistencyReport;
+pub use error::{ConsensusError, LedgerError};
+pub use events::ChainEvent;
+pub use export::ExportFormat;
+pub use gc::GcReport;
+pub use genesis::{Consensus, GenesisConfig};
+pub use getwork::BlockTemplate;
+pub use hashing::HashAlgorithm;
+pub use header::BlockHeader;
+pub use history::HistoryEntry;
+pub use hooks::Validator;
+#[cfg(feature = "sled")]
+pub use header_chain::HeaderChain;
+pub use limits::{MempoolLimitError, SizeLimitError};
+pub use listing::BlockSummary;
+pub use merkle::MerkleProof;
+pub use metrics::MetricsSnapshot;
+pub use miner::{Miner, MiningOutcome, MiningStats};
+pub use peers::{Misbehavior, PeerRecord};
+#[cfg(feature = "sled")]
+pub use registry::ChainInfo;
+pub use repair::RepairReport;
+pub use replay::{ReplayedBlock, ReplayToken};
+pub use retention::{Hold, HoldTarget, LegalHold};
+pub use schema::Schema;
+pub use search::SearchHit;
+pub use signer::{LocalSigner, Signer};
+#[cfg(feature = "remote-signer")]
+pub use signer::RemoteSigner;
+pub use snapshot::SnapshotInfo;
+pub use state::Account;
+pub use state_tree::AccountProof;
+pub use stats::ChainStats;
+pub use store::{BlockStore, DefaultStore, MemoryStore, TreeId};
+#[cfg(feature = "sled")]
+pub use store::SledStore;
+#[cfg(feature = "grpc")]
+pub use sync::{SyncReport, SyncState};
+pub use timefmt::TimeZone;
+pub use transaction::Transaction;
+pub use txindex::TransactionInfo;
+pub use validation::{CancelToken, ValidationError, ValidationProgress};
+pub use verify::VerifiedExport;
+#[cfg(feature = "wasm-validators")]
+pub use wasm_validator::WasmValidator;
+pub use webhooks::WebhookWorker;
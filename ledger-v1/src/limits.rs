@@ -0,0 +1,74 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::block::BlockView;
+use crate::config::Config;
+
+// Why a block was refused for its size. Returned boxed like any other error, so
+// callers that care can `downcast_ref::<SizeLimitError>()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SizeLimitError {
+    PayloadTooLarge { size: u64, limit: u64 },
+    BlockTooLarge { size: u64, limit: u64 },
+}
+
+impl fmt::Display for SizeLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SizeLimitError::PayloadTooLarge { size, limit } => {
+                write!(f, "Block data is {} bytes, over the limit of {}", size, limit)
+            }
+            SizeLimitError::BlockTooLarge { size, limit } => {
+                write!(f, "Block is {} bytes, over the limit of {}", size, limit)
+            }
+        }
+    }
+}
+
+impl Error for SizeLimitError {}
+
+// Why the mempool turned a transaction away, whatever its merits: limits that keep a
+// public node's pool from being flooded. Returned boxed, like `SizeLimitError`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MempoolLimitError {
+    // The fee is below `Config::min_fee`.
+    FeeTooLow { fee: u64, minimum: u64 },
+    // The sender already has `Config::max_pending_per_sender` transactions waiting.
+    TooManyPending { sender: String, limit: usize },
+    // The pool holds `Config::max_mempool_transactions` and none pays a lower fee rate.
+    PoolFull { limit: usize },
+}
+
+impl fmt::Display for MempoolLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolLimitError::FeeTooLow { fee, minimum } => {
+                write!(f, "Fee {} is below the minimum of {}", fee, minimum)
+            }
+            MempoolLimitError::TooManyPending { sender, limit } => {
+                write!(f, "{} already has {} transactions pending", sender, limit)
+            }
+            MempoolLimitError::PoolFull { limit } => {
+                write!(f, "The mempool is full ({} transactions) and they all pay at least this fee rate", limit)
+            }
+        }
+    }
+}
+
+impl Error for MempoolLimitError {}
+
+// Checks `Config::max_payload_bytes` and `Config::max_block_size`. The payload goes
+// first, since it is cheap to measure.
+pub(crate) fn check_size(config: &Config, block: &BlockView) -> Result<(), SizeLimitError> {
+    let payload = block.data.len() as u64;
+    if config.max_payload_bytes > 0 && payload > config.max_payload_bytes {
+        return Err(SizeLimitError::PayloadTooLarge { size: payload, limit: config.max_payload_bytes });
+    }
+    if config.max_block_size > 0 {
+        let size = block.size() as u64;
+        if size > config.max_block_size {
+            return Err(SizeLimitError::BlockTooLarge { size, limit: config.max_block_size });
+        }
+    }
+    Ok(())
+}
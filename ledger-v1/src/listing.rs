@@ -0,0 +1,98 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+
+use crate::blockchain::Blockchain;
+use crate::store::BlockStore;
+use crate::timefmt::TimeZone;
+
+// One row of a block listing, as `print` and `GET /blocks` show it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockSummary {
+    pub height: u64,
+    pub hash: String,
+    pub prev_hash: String,
+    // Milliseconds since the epoch.
+    pub timestamp: u64,
+    pub transactions: usize,
+    // The payload as text, a placeholder for binary data, or "<pruned>".
+    pub data: String,
+    // `timestamp` in RFC 3339, for listings asked to show it in a time zone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+}
+
+impl BlockSummary {
+    pub fn in_zone(self, zone: TimeZone) -> BlockSummary {
+        BlockSummary { time: Some(zone.format(self.timestamp)), ..self }
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // A page of the canonical chain by height, oldest first: `limit` blocks from height
+    // `offset`, or all of them from there when `limit` is None. A block keeps its
+    // height as the chain grows, so the same page always lists the same blocks, reorgs
+    // aside.
+    pub fn list_blocks(&self, offset: u64, limit: Option<u64>) -> Result<Vec<BlockSummary>, Box<dyn Error>> {
+        let last = match limit {
+            Some(0) => return Ok(Vec::new()),
+            Some(limit) => offset.saturating_add(limit - 1),
+            None => u64::MAX,
+        };
+        let mut page = Vec::new();
+        for height in offset..=last.min(self.height()?) {
+            let Some(block) = self.canonical_block_if_stored(height)? else { continue };
+            let data = if self.is_pruned(&block.hash)? { "<pruned>".to_string() } else { block.data_summary() };
+            page.push(BlockSummary {
+                height,
+                hash: block.hash,
+                prev_hash: block.prev_hash,
+                timestamp: block.timestamp,
+                transactions: block.transactions.len(),
+                data,
+                time: None,
+            });
+        }
+        Ok(page)
+    }
+
+    // Like `list_blocks`, but only the blocks timestamped from `since` up to, not
+    // including, `until` (milliseconds since the epoch), and from height `offset` on.
+    // A block is never timestamped before its parent, so both ends are found by
+    // bisecting the heights.
+    pub fn list_blocks_between(
+        &self,
+        since: Option<u64>,
+        until: Option<u64>,
+        offset: u64,
+        limit: Option<u64>,
+    ) -> Result<Vec<BlockSummary>, Box<dyn Error>> {
+        let offset = match since {
+            Some(since) => self.first_height_at(since)?.max(offset),
+            None => offset,
+        };
+        let limit = match until {
+            Some(until) => {
+                let in_range = self.first_height_at(until)?.saturating_sub(offset);
+                Some(limit.map_or(in_range, |limit| limit.min(in_range)))
+            }
+            None => limit,
+        };
+        self.list_blocks(offset, limit)
+    }
+
+    // The lowest canonical height timestamped at `timestamp` or later; one above the
+    // tip if there is none. Heights without a header, below a snapshot base, count
+    // as earlier.
+    fn first_height_at(&self, timestamp: u64) -> Result<u64, Box<dyn Error>> {
+        let (mut low, mut high) = (0, self.height()? + 1);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.canonical_header(middle)?.is_some_and(|header| header.timestamp >= timestamp) {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        Ok(low)
+    }
+}
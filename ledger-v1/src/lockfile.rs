@@ -0,0 +1,168 @@
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::warn;
+
+pub use crate::error::LedgerError;
+
+// One writer per database. sled takes an OS lock on its files on Linux, macOS and
+// Windows, but only reports a failed one as an I/O error, and elsewhere takes none.
+// So a node also claims the database with a lock file in its directory, holding an
+// OS advisory lock on it (flock, or LockFileEx on Windows) for as long as the
+// database is open, and writing its process and host into it. A second writer is
+// refused with `LedgerError::AlreadyInUse`, and so is a reader, since a copy of the
+// files taken under a writer may be torn. The OS drops the lock when its process
+// exits, however it exits, so a lock file left behind by a crash is simply taken
+// over; pids are only reported, never probed. One naming another host, whose
+// filesystem may not share locks with this one, needs `force_unlock`.
+const LOCK_FILE: &str = "ledger.lock";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct Owner {
+    pid: u32,
+    host: String,
+    // Milliseconds since the epoch.
+    opened_at: u64,
+}
+
+impl Owner {
+    fn this_process() -> Owner {
+        Owner { pid: std::process::id(), host: host_name(), opened_at: Utc::now().timestamp_millis() as u64 }
+    }
+
+    // Hosts whose name can't be found are taken to be this one.
+    fn is_local(&self) -> bool {
+        let local = host_name();
+        self.host == local || self.host.is_empty() || local.is_empty()
+    }
+}
+
+// This process's claim on a database directory. Dropping it empties the lock file
+// and releases the OS lock. The file itself stays, so a process that opened it a
+// moment earlier can never end up locking a file that is no longer there.
+pub struct LockFile {
+    file: File,
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+// Claims the database at `path`, then opens it.
+pub fn open_locked(path: &str) -> Result<(sled::Db, LockFile), Box<dyn Error>> {
+    let lock = claim(Path::new(path))?;
+    let db = sled::open(path).map_err(|e| -> Box<dyn Error> {
+        match is_lock_error(&e) {
+            true => Box::new(LedgerError::AlreadyInUse { pid: None }),
+            false => e.into(),
+        }
+    })?;
+    Ok((db, lock))
+}
+
+fn claim(dir: &Path) -> Result<LockFile, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(LOCK_FILE);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+    try_lock(&file, &path)?;
+    if let Some(owner) = read_locked(&mut file) {
+        if !owner.is_local() {
+            return Err(Box::new(LedgerError::LockedElsewhere { host: owner.host, pid: owner.pid }));
+        }
+        warn!(pid = owner.pid, "taking over the lock of a process that is gone");
+    }
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&serde_json::to_vec(&Owner::this_process())?)?;
+    file.sync_all()?;
+    Ok(LockFile { file })
+}
+
+// Takes the OS lock on `file`, or fails with the pid its holder wrote.
+fn try_lock(file: &File, path: &Path) -> Result<(), Box<dyn Error>> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => {
+            let pid = fs::read(path).ok().and_then(|bytes| parse_owner(&bytes)).map(|owner| owner.pid);
+            Err(Box::new(LedgerError::AlreadyInUse { pid }))
+        }
+        Err(TryLockError::Error(e)) => Err(format!("Cannot lock {}: {}", path.display(), e).into()),
+    }
+}
+
+// Clears the lock file of the database at `path`, e.g. one left by a node on another
+// host that is gone for good. Returns the pid it named, if there was one. Refused
+// while a process holds the lock, or has sled's files open, whatever pid the file names.
+pub fn force_unlock(path: &str) -> Result<Option<u32>, Box<dyn Error>> {
+    let file = Path::new(path).join(LOCK_FILE);
+    let mut lock = match OpenOptions::new().read(true).write(true).open(&file) {
+        Ok(lock) => lock,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Cannot open {}: {}", file.display(), e).into()),
+    };
+    try_lock(&lock, &file)?;
+    // sled's own lock tells whether anyone still has the files open.
+    if let Err(e) = sled::open(path)
+        && is_lock_error(&e)
+    {
+        return Err(Box::new(LedgerError::AlreadyInUse { pid: None }));
+    }
+    let owner = read_locked(&mut lock);
+    lock.set_len(0)?;
+    if let Some(owner) = &owner {
+        warn!(pid = owner.pid, host = %owner.host, "lock removed by force");
+    }
+    Ok(owner.map(|owner| owner.pid))
+}
+
+// Fails with `LedgerError::AlreadyInUse` while a process holds the database at `path`.
+pub(crate) fn check_unlocked(path: &Path) -> Result<(), Box<dyn Error>> {
+    let file = path.join(LOCK_FILE);
+    match File::open(&file) {
+        Ok(lock) => try_lock(&lock, &file),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Cannot open {}: {}", file.display(), e).into()),
+    }
+}
+
+pub(crate) fn is_lock_error(e: &sled::Error) -> bool {
+    // sled wraps the failed OS lock in an error of its own.
+    matches!(e, sled::Error::Io(io) if io.to_string().contains("could not acquire lock"))
+}
+
+// What the holder of the lock wrote. Read through the locked handle itself, since
+// Windows keeps other handles out of a locked file. A record cut short by a crash
+// names nobody.
+fn read_locked(file: &mut File) -> Option<Owner> {
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_end(&mut bytes).ok()?;
+    parse_owner(&bytes)
+}
+
+fn parse_owner(bytes: &[u8]) -> Option<Owner> {
+    serde_json::from_slice(bytes).ok()
+}
+
+fn host_name() -> String {
+    // Shells set HOSTNAME without exporting it, so the files come first.
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
@@ -1,178 +1,1386 @@
-use chrono::prelude::*;
-use sha2::{Sha256, Digest};
-use serde::{Serialize, Deserialize};
-use std::error::Error; // We need this for the return type
-
-// 1. DEFINE BLOCK
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Block {
-    timestamp: u64,
-    data: String,
-    prev_hash: String,
-    hash: String,
-}
-
-impl Block {
-    fn new(data: String, prev_hash: String) -> Self {
-        let timestamp = Utc::now().timestamp_millis() as u64;
-        let mut block = Block {
-            timestamp,
-            data,
-            prev_hash,
-            hash: String::new(),
-        };
-        block.hash = block.calculate_hash();
-        block
-    }
+use clap::{Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use tracing_subscriber::EnvFilter;
 
-    fn calculate_hash(&self) -> String {
-        let input = (self.timestamp, &self.data, &self.prev_hash);
-        let input_json = serde_json::to_string(&input).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(input_json);
-        hex::encode(hasher.finalize())
-    }
+mod explore;
+mod repl;
+
+use ledger_v1::{AnchorProof, Approval, ApprovalOutcome, AuditLedger, Blockchain, BlockStore, BlockSummary, CancelToken, ChainStats, Clock, Config, Consensus, ExportFormat, GenesisConfig, HeaderChain, HoldTarget, LedgerError, LocalSigner, MemoryStore, MerkleProof, NodeMode, ReplayedBlock, SearchHit, SledStore, SystemClock, TamperReport, TimeZone, Transaction, ValidationProgress};
+use ledger_v1::{auth, authority, lockfile, registry, script, transaction};
+
+#[derive(Parser)]
+#[command(version, about = "A small blockchain ledger stored in sled")]
+struct Cli {
+    /// Path of the sled database
+    #[arg(long, default_value = "my_db", global = true)]
+    db: String,
+
+    /// Genesis config (TOML or JSON) used when creating a new database
+    #[arg(long, global = true)]
+    genesis: Option<PathBuf>,
+
+    /// Node config (TOML or JSON)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Use the named chain NAME of the database instead of its default chain
+    #[arg(long, value_name = "NAME", global = true)]
+    chain: Option<String>,
+
+    /// Open an existing database without writing to it. While a node has it open, the
+    /// read replica its config names is read instead (see `read_replica`)
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Remove a lock left on the database by a node that is gone, e.g. one on another host
+    #[arg(long, global = true)]
+    force_unlock: bool,
+
+    /// Run as a full, archive, pruned or light node, overriding `mode` in the config
+    #[arg(long, global = true)]
+    mode: Option<NodeMode>,
+
+    /// Show times in this zone (utc, local, or an offset such as +02:00), which is also
+    /// the zone --since and --until are read in
+    #[arg(long, global = true)]
+    tz: Option<TimeZone>,
+
+    /// Log format on stderr; filter with RUST_LOG (default "warn")
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-// 2. DEFINE BLOCKCHAIN
-struct Blockchain {
-    db: sled::Db, 
-    current_hash: String,
+#[derive(Subcommand)]
+enum Command {
+    /// Append a block holding DATA, or the contents of a file
+    Add {
+        #[arg(required_unless_present = "file", conflicts_with = "file")]
+        data: Option<String>,
+        /// Store this file (e.g. a PDF) as the block payload
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// MIME type of the payload; for a file it is guessed from the extension by default
+        #[arg(long)]
+        content_type: Option<String>,
+        /// Tag the block, e.g. --tag category=invoice (repeatable)
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+        /// Store FILE as a blob and attach it to the block under its file name (repeatable)
+        #[arg(long = "attach", value_name = "FILE")]
+        attachments: Vec<PathBuf>,
+    },
+    /// Append a block transferring AMOUNT from one account to another
+    Transfer {
+        from: String,
+        to: String,
+        amount: u64,
+        /// Paid by the sender to the miner (needs a miner address in the config)
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        /// The sender's nonce; defaults to its next one
+        #[arg(long)]
+        nonce: Option<u64>,
+        /// File holding the sender's hex secret key to sign with; FROM must be its address
+        #[arg(long)]
+        key: Option<PathBuf>,
+    },
+    /// Append a block locking AMOUNT of FROM's balance under a spend condition
+    Lock {
+        from: String,
+        amount: u64,
+        /// The condition as JSON, e.g. '{"after":{"height":100}}'
+        #[arg(long)]
+        condition: String,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        /// The sender's nonce; defaults to its next one
+        #[arg(long)]
+        nonce: Option<u64>,
+        /// File holding the sender's hex secret key to sign with; FROM must be its address
+        #[arg(long)]
+        key: Option<PathBuf>,
+    },
+    /// Append a block paying out lock LOCK to TO
+    Unlock {
+        lock: String,
+        to: String,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        /// File holding a hex secret key to sign with (repeatable)
+        #[arg(long = "key")]
+        keys: Vec<PathBuf>,
+        /// A signature made elsewhere, hex (repeatable)
+        #[arg(long = "signature")]
+        signatures: Vec<String>,
+        /// A hash-lock preimage, hex (repeatable)
+        #[arg(long = "preimage")]
+        preimages: Vec<String>,
+    },
+    /// Propose a block holding DATA, to be added once enough approvers sign it
+    Propose {
+        data: String,
+        #[arg(long)]
+        content_type: Option<String>,
+        /// Tag the block, e.g. --tag category=invoice (repeatable)
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+    },
+    /// Approve a proposed block with this node's approver key
+    Approve {
+        proposal_id: String,
+        /// Add a signature made elsewhere by this approver (public key, hex) instead
+        #[arg(long, requires = "signature")]
+        approver: Option<String>,
+        #[arg(long, requires = "approver")]
+        signature: Option<String>,
+    },
+    /// List the proposed blocks waiting for approval
+    Proposals,
+    /// Drop a proposed block
+    Discard { proposal_id: String },
+    /// Show the balance and nonce of an account
+    Account {
+        address: String,
+        /// As of the canonical block at this height instead of the tip
+        #[arg(long)]
+        at: Option<u64>,
+    },
+    /// Show the state root at the tip, or at a past height
+    StateRoot {
+        #[arg(long)]
+        at: Option<u64>,
+    },
+    /// List the canonical transactions sent or received by an address
+    History { address: String },
+    /// Show a transaction, the block holding it and its confirmations
+    Tx {
+        txid: String,
+        /// Wait until it has this many confirmations
+        #[arg(long)]
+        wait: Option<u64>,
+        /// Give up waiting after this many seconds
+        #[arg(long, default_value_t = 600)]
+        timeout: u64,
+    },
+    /// List the canonical blocks by height, oldest first
+    Print {
+        /// Show at most this many blocks
+        #[arg(long)]
+        limit: Option<u64>,
+        /// Start at this height
+        #[arg(long, default_value_t = 0)]
+        offset: u64,
+        /// Only blocks from this time on, e.g. "2024-01-01", "2024-01-01 12:00" or "2d ago"
+        #[arg(long)]
+        since: Option<String>,
+        /// Only blocks before this time
+        #[arg(long)]
+        until: Option<String>,
+        #[arg(long, value_enum, default_value_t = PrintFormat::Table)]
+        format: PrintFormat,
+    },
+    /// Browse the chain in an interactive terminal UI
+    Explore,
+    /// Run commands at a prompt, keeping the chain open between them
+    Repl,
+    /// Check the integrity of the chain
+    Validate {
+        /// Read every stored record, reporting orphaned, unreachable and damaged blocks
+        #[arg(long)]
+        deep: bool,
+    },
+    /// Upgrade blocks written by older versions
+    Migrate {
+        /// Also rewrite legacy-hashed blocks with the canonical preimage (changes every hash)
+        #[arg(long)]
+        hashes: bool,
+    },
+    /// Write the chain to a file, or stdout
+    Export {
+        /// json, csv, binary, dot for a Graphviz graph of all blocks and forks, or a
+        /// report: sql for a SQLite script of block and transaction tables, tx-csv for
+        /// a CSV of the transactions
+        #[arg(long, default_value = "json")]
+        format: ExportFormat,
+        /// Report only the blocks added since the last report exported under NAME
+        #[arg(long, value_name = "NAME")]
+        since: Option<String>,
+        output: Option<PathBuf>,
+    },
+    /// Validate and add the blocks from an exported chain
+    Import {
+        #[arg(long, default_value = "json")]
+        format: ExportFormat,
+        input: PathBuf,
+    },
+    /// Drop the bodies of all but the newest KEEP blocks
+    Prune {
+        #[arg(long)]
+        keep: u64,
+    },
+    /// Remove side branches and unreachable blocks older than the retention window
+    Gc,
+    /// Re-apply canonical blocks, printing each with the accounts it changed as a JSON line
+    Replay {
+        #[arg(long, default_value_t = 0, conflicts_with = "name")]
+        from: u64,
+        /// Last height to replay; the tip by default
+        #[arg(long)]
+        to: Option<u64>,
+        /// Resume the replay saved under NAME, continuing after the block it stopped at
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Truncate the chain to its last intact block, setting damaged blocks aside
+    Repair {
+        /// Only report what would be quarantined
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Use the chain as an append-only audit log
+    Audit {
+        #[command(subcommand)]
+        action: AuditCommand,
+    },
+    /// Manage state snapshots
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotCommand,
+    },
+    /// Copy the chain to a new database at DEST, consistently even while it is in use
+    Backup { dest: String },
+    /// Create the database (--db) from the backup at SRC, after checking the backup
+    Restore { src: String },
+    /// Manage the named chains of the database
+    Chains {
+        #[command(subcommand)]
+        action: ChainsCommand,
+    },
+    /// Proof-of-authority keys and schedule
+    Authority {
+        #[command(subcommand)]
+        action: AuthorityCommand,
+    },
+    /// Known peers and bans
+    Peers {
+        #[command(subcommand)]
+        action: PeersCommand,
+    },
+    /// Manage validation checkpoints
+    Checkpoint {
+        #[command(subcommand)]
+        action: CheckpointCommand,
+    },
+    /// Attachments stored as blobs by content hash
+    Blob {
+        #[command(subcommand)]
+        action: BlobCommand,
+    },
+    /// Retention classes and legal holds, which keep blocks from pruning and gc
+    Hold {
+        #[command(subcommand)]
+        action: HoldCommand,
+    },
+    /// Publish the tip to the configured anchor target, and check past anchors
+    Anchor {
+        #[command(subcommand)]
+        action: AnchorCommand,
+    },
+    /// Print a merkle proof (JSON) that block BLOCK holds transaction TXID
+    Proof { block: String, txid: String },
+    /// Check a merkle proof written by `proof` against the stored headers
+    Verify { block: String, proof: PathBuf },
+    /// Print a new API key and the SHA-256 to list for it under api_keys in the node config
+    ApiKey,
+    /// Print Prometheus metrics, or serve them at /metrics along with a block list at /blocks
+    Metrics {
+        /// Address to serve on, e.g. 127.0.0.1:9898
+        #[arg(long)]
+        listen: Option<String>,
+    },
+    /// Serve the gRPC API (proto/ledger.proto)
+    #[cfg(feature = "grpc")]
+    Grpc {
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: std::net::SocketAddr,
+    },
+    /// Download blocks from gRPC peers until this node has their best chain
+    #[cfg(feature = "grpc")]
+    Sync {
+        /// Peer to sync from, e.g. 10.0.0.2:50051 (repeatable); known peers are tried too
+        #[arg(long = "peer")]
+        peers: Vec<String>,
+        /// Block batches to fetch at once
+        #[arg(long, default_value_t = 4)]
+        parallel: usize,
+    },
+    /// Push new blocks and mempool transactions to WebSocket clients as JSON
+    #[cfg(feature = "websocket")]
+    Websocket {
+        #[arg(long, default_value = "127.0.0.1:8546")]
+        listen: String,
+    },
+    /// Find canonical blocks and transactions containing TEXT
+    Search {
+        text: String,
+        /// Match whole words, addresses and transaction ids only (uses the search index)
+        #[arg(long)]
+        term: bool,
+    },
+    /// List the canonical blocks carrying every given tag
+    Tagged {
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag, required = true)]
+        tags: Vec<(String, String)>,
+    },
+    /// Show block, transaction and storage statistics
+    Stats {
+        #[arg(long, value_enum, default_value_t = StatsFormat::Table)]
+        format: StatsFormat,
+    },
+    /// Time adding and validating a synthetic chain, in a scratch database that is
+    /// removed afterwards. The node config (durability, compression, ...) applies
+    Bench {
+        /// Blocks to add
+        #[arg(long, default_value_t = 1000)]
+        blocks: u64,
+        /// Payload bytes per block
+        #[arg(long, default_value_t = 256)]
+        payload: usize,
+        /// Keep the chain in memory instead of a sled database
+        #[arg(long)]
+        memory: bool,
+    },
 }
 
-impl Blockchain {
-    // FIX 1: Return Result<Blockchain, ...> instead of Self
-    // This allows us to use the '?' operator inside.
-    fn new() -> Result<Blockchain, Box<dyn Error>> {
-        let db = sled::open("my_db")?;
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
 
-        let last_hash_bytes = db.get("LAST")?;
+#[derive(Clone, Copy, ValueEnum)]
+enum StatsFormat {
+    Table,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PrintFormat {
+    Table,
+    Json,
+    Yaml,
+}
+
+#[derive(Subcommand)]
+enum AuditCommand {
+    /// Record that ACTOR performed ACTION on RESOURCE
+    Append {
+        #[arg(long)]
+        actor: String,
+        #[arg(long)]
+        action: String,
+        #[arg(long)]
+        resource: String,
+        /// Free-form details stored as the block data
+        #[arg(default_value = "")]
+        details: String,
+    },
+    /// List the audit entries, oldest first
+    List {
+        /// Only entries from this time on, e.g. "2024-01-01" or "12h ago"
+        #[arg(long)]
+        since: Option<String>,
+        /// Only entries before this time
+        #[arg(long)]
+        until: Option<String>,
+    },
+    /// Check every block and list where integrity breaks
+    Verify {
+        /// Also write the signed report (JSON) here
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Check the signature of a report written by `verify`
+    CheckReport { report: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum AuthorityCommand {
+    /// Print a new secret key, its public key (for the genesis authorities or approvers list) and the address it owns
+    Keygen,
+    /// Show this node's authority key and whose turn the next block is
+    Status,
+}
 
-        let current_hash = match last_hash_bytes {
-            // FIX 2: Handle the "Found" case correctly
-            Some(bytes) => {
-                // Convert bytes to String
-                String::from_utf8(bytes.to_vec())?
-            },
-            // Handle the "Not Found" (First run) case
-            None => {
-                let genesis = Block::new("Genesis Block".to_string(), "0".to_string());
-                let genesis_hash = genesis.hash.clone();
-                let genesis_json = serde_json::to_string(&genesis)?;
+#[derive(Subcommand)]
+enum ChainsCommand {
+    /// List the named chains
+    List,
+    /// Delete a named chain and all of its blocks
+    Remove { name: String },
+}
+
+#[derive(Subcommand)]
+enum PeersCommand {
+    /// List known peers, best score first
+    List,
+    /// Record a peer to sync from
+    Add { address: String },
+    /// Stop syncing from and sharing a peer
+    Ban {
+        address: String,
+        #[arg(long, default_value_t = 24)]
+        hours: u64,
+        #[arg(long, default_value = "banned by operator")]
+        reason: String,
+    },
+    /// Lift a ban and reset the peer's score
+    Unban { address: String },
+}
 
-                db.insert(genesis.hash.as_bytes(), genesis_json.as_bytes())?;
-                db.insert("LAST", genesis.hash.as_bytes())?;
+#[derive(Subcommand)]
+enum CheckpointCommand {
+    /// Record a signed checkpoint at the current tip
+    Create,
+    /// List configured and recorded checkpoints
+    List,
+}
 
-                genesis_hash
+#[derive(Subcommand)]
+enum BlobCommand {
+    /// Store FILE as a blob and print its hash, for a block to refer to
+    Put { file: PathBuf },
+    /// Write the blob HASH, or attachment NAME of block HASH, to a file or stdout
+    Get {
+        hash: String,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// List stored blobs
+    List,
+}
+
+#[derive(Subcommand)]
+enum HoldCommand {
+    /// Put TARGET (block:HASH or tx:TXID) under legal hold
+    Place {
+        target: HoldTarget,
+        #[arg(long)]
+        reason: String,
+    },
+    /// Release the legal hold on TARGET
+    Release { target: HoldTarget },
+    /// Tag TARGET with a retention class from the node config, or clear its class
+    Retain { target: HoldTarget, class: Option<String> },
+    /// List everything held now
+    List,
+}
+
+#[derive(Subcommand)]
+enum AnchorCommand {
+    /// Anchor the current tip now
+    Create,
+    /// List recorded anchors
+    List,
+    /// Check recorded anchors, or the attestations in an anchor log
+    Verify {
+        #[arg(long)]
+        log: Option<PathBuf>,
+    },
+    /// Write the timestamp token anchoring block HASH to OUTPUT, for `openssl ts -verify`
+    Token { hash: String, output: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Snapshot the state at the current tip
+    Create,
+    /// List stored snapshots
+    List,
+    /// Rebuild the state from the snapshot at HEIGHT, or bootstrap an empty database from --file
+    Restore {
+        height: Option<u64>,
+        #[arg(long, conflicts_with = "height")]
+        file: Option<PathBuf>,
+    },
+    /// Write the snapshot at HEIGHT to a file for bootstrapping another node
+    Export { height: u64, output: PathBuf },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    // Log lines would draw over the explorer's screen.
+    if !matches!(cli.command, Some(Command::Explore)) {
+        init_logging(cli.log_format);
+    }
+    if let Err(e) = run(cli) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// Library events go to stderr, so the command's own output on stdout stays readable.
+fn init_logging(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let logger = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => logger.init(),
+        LogFormat::Json => logger.json().init(),
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let genesis = cli.genesis.as_ref().map(GenesisConfig::load).transpose()?;
+    let mut config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(mode) = cli.mode {
+        config.mode = mode;
+    }
+    if cli.force_unlock && let Some(pid) = lockfile::force_unlock(&cli.db)? {
+        eprintln!("Removed the lock of process {} on {}", pid, cli.db);
+    }
+    if let Some(Command::Restore { src }) = &cli.command {
+        if cli.chain.is_some() {
+            return Err("A backup restores to the default chain of a new database; leave out --chain".into());
+        }
+        let info = Blockchain::restore_backup(src, &cli.db, config)?;
+        println!("Restored {} records to {}: height {}, tip {}", info.records, cli.db, info.height, info.tip);
+        return Ok(());
+    }
+    if let Some(Command::Chains { action }) = &cli.command {
+        return run_chains(&cli.db, action, cli.tz.unwrap_or_default());
+    }
+    if let Some(Command::Bench { blocks, payload, memory }) = cli.command {
+        return run_bench(blocks, payload, memory, genesis.as_ref(), config);
+    }
+    if let Some(Command::Authority { action: AuthorityCommand::Keygen }) = &cli.command {
+        let (secret, public) = authority::generate_key();
+        println!("secret:  {}\npublic:  {}\naddress: {}", secret, public, transaction::key_address(&public)?);
+        return Ok(());
+    }
+    if let Some(Command::ApiKey) = &cli.command {
+        let (key, sha256) = auth::generate_api_key();
+        println!("key:    {}\nsha256: {}", key, sha256);
+        return Ok(());
+    }
+    if config.mode == NodeMode::Light {
+        return run_light(cli, genesis, config);
+    }
+    // Held as long as a named chain is open.
+    let _lock;
+    let chain = match (&cli.chain, cli.read_only) {
+        (Some(name), true) => Blockchain::open_store(SledStore::open_chain_read_only(&cli.db, name)?, None, config)?,
+        (Some(name), false) => {
+            let (db, lock) = lockfile::open_locked(&cli.db)?;
+            _lock = lock;
+            Blockchain::open_chain_with_config(&db, name, genesis.as_ref(), config)?
+        }
+        (None, true) => open_read_only(&cli.db, config)?,
+        (None, false) => Blockchain::open_with_config(&cli.db, genesis.as_ref(), config)?,
+    };
+    // Held until the command is done, however it ends.
+    let _webhooks = chain.start_webhooks()?;
+
+    let zone = cli.tz.unwrap_or_default();
+    match cli.command {
+        None => run_demo(&chain)?,
+        Some(Command::Add { data, file, content_type, tags, attachments }) => {
+            let (data, content_type) = match file {
+                Some(path) => {
+                    let content_type = content_type.unwrap_or_else(|| guess_content_type(&path).to_string());
+                    (std::fs::read(&path)?, Some(content_type))
+                }
+                None => (data.unwrap_or_default().into_bytes(), content_type),
+            };
+            let mut attached = BTreeMap::new();
+            for path in attachments {
+                let name = path.file_name().ok_or_else(|| format!("{} names no file", path.display()))?;
+                attached.insert(name.to_string_lossy().into_owned(), std::fs::read(&path)?);
             }
+            let hash = chain.add_block_with_attachments(data, content_type.as_deref(), tags.into_iter().collect(), attached)?;
+            println!("Added block {}", hash);
+            print_mining_stats(&chain);
+        }
+        Some(Command::Transfer { from, to, amount, fee, nonce, key }) => {
+            let nonce = match nonce {
+                Some(nonce) => nonce,
+                None => chain.next_nonce(&from)?,
+            };
+            let mut transfer = Transaction::transfer_with_fee(&from, &to, amount, fee).with_nonce(nonce);
+            if let Some(key) = key {
+                transfer = transfer.signed(&LocalSigner::from_file(&key)?, &chain.network_id()?)?;
+            }
+            let hash = chain.add_block_with_transactions(String::new(), vec![transfer])?;
+            println!("Added block {}", hash);
+            print_mining_stats(&chain);
+        }
+        Some(Command::Lock { from, amount, condition, fee, nonce, key }) => {
+            let condition: script::Condition = serde_json::from_str(&condition)?;
+            let nonce = match nonce {
+                Some(nonce) => nonce,
+                None => chain.next_nonce(&from)?,
+            };
+            let mut lock = Transaction::lock(&from, amount, fee, condition).with_nonce(nonce);
+            if let Some(key) = key {
+                lock = lock.signed(&LocalSigner::from_file(&key)?, &chain.network_id()?)?;
+            }
+            let txid = lock.hash();
+            chain.add_block_with_transactions(String::new(), vec![lock])?;
+            println!("Locked {} in lock {}", amount, txid);
+        }
+        Some(Command::Unlock { lock, to, fee, keys, mut signatures, preimages }) => {
+            let network_id = chain.network_id()?;
+            for key in keys {
+                signatures.push(script::sign_unlock(&LocalSigner::from_file(&key)?, &network_id, &lock, &to, fee)?);
+            }
+            let unlock = Transaction::unlock(&lock, &to, fee, script::Witness { signatures, preimages });
+            chain.add_block_with_transactions(String::new(), vec![unlock])?;
+            println!("Unlocked {} to {}", lock, to);
+        }
+        Some(Command::Propose { data, content_type, tags }) => {
+            let id = chain.propose_block(data, content_type.as_deref(), tags.into_iter().collect())?;
+            println!("Proposed block {}", id);
+        }
+        Some(Command::Approve { proposal_id, approver, signature }) => {
+            let outcome = match (approver, signature) {
+                (Some(approver), Some(signature)) => chain.add_approval(&proposal_id, Approval { approver, signature })?,
+                _ => chain.approve(&proposal_id)?,
+            };
+            match outcome {
+                ApprovalOutcome::Pending { approvals, needed } => println!("Approved: {} of {} approvals", approvals, needed),
+                ApprovalOutcome::Committed { height } => println!("Approved and added block {} at height {}", proposal_id, height),
+            }
+        }
+        Some(Command::Proposals) => {
+            for proposal in chain.proposals()? {
+                let stale = if proposal.block.prev_hash == chain.current_hash() { "" } else { " (stale: the tip has moved)" };
+                println!(
+                    "{} height {}: {} of {} approvals{}",
+                    proposal.id(), proposal.height, proposal.block.approvals.len(), proposal.needed, stale
+                );
+                println!("  {}", proposal.block.data_summary());
+            }
+        }
+        Some(Command::Discard { proposal_id }) => match chain.discard_proposal(&proposal_id)? {
+            true => println!("Discarded proposal {}", proposal_id),
+            false => return Err(format!("No proposal {}", proposal_id).into()),
+        },
+        Some(Command::Account { address, at }) => {
+            let account = match at {
+                Some(height) => chain.get_account_at(&address, height)?,
+                None => chain.get_account(&address)?,
+            };
+            println!("{}: balance {}, nonce {}", address, account.balance, account.nonce);
+        }
+        Some(Command::StateRoot { at }) => {
+            let height = match at {
+                Some(height) => height,
+                None => chain.height()?,
+            };
+            println!("State root at height {}: {}", height, chain.state_root_at(height)?);
+        }
+        Some(Command::History { address }) => {
+            for entry in chain.get_history(&address)? {
+                println!("{:>8}  {}  tx {}", entry.height, entry.block, entry.txid);
+            }
+        }
+        Some(Command::Tx { txid, wait, timeout }) => {
+            let info = match wait {
+                Some(confirmations) => chain.wait_for_confirmations(&txid, confirmations, Duration::from_secs(timeout))?,
+                None => chain.get_transaction(&txid)?.ok_or_else(|| format!("Unknown transaction {}", txid))?,
+            };
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        }
+        Some(Command::Print { limit, offset, since, until, format }) => {
+            let mut blocks = match (since, until) {
+                (None, None) => chain.list_blocks(offset, limit)?,
+                (since, until) => chain.list_blocks_between(parse_time(since, zone)?, parse_time(until, zone)?, offset, limit)?,
+            };
+            if cli.tz.is_some() {
+                blocks = blocks.into_iter().map(|block| block.in_zone(zone)).collect();
+            }
+            match format {
+                PrintFormat::Table => print_blocks(&blocks, zone),
+                PrintFormat::Json => println!("{}", serde_json::to_string_pretty(&blocks)?),
+                PrintFormat::Yaml => print_yaml(&blocks)?,
+            }
+        }
+        Some(Command::Explore) => explore::run(&chain)?,
+        Some(Command::Repl) => repl::run(&chain, zone)?,
+        Some(Command::Validate { deep: true }) => {
+            let report = chain.deep_validate()?;
+            for finding in &report.findings {
+                println!("{:<14}  {}  {}", format!("{:?}", finding.problem), finding.key, finding.detail);
+            }
+            if !report.is_consistent() {
+                return Err(format!("{} problems in {} block records", report.findings.len(), report.blocks).into());
+            }
+            println!("All {} block records consistent.", report.blocks);
+        }
+        Some(Command::Validate { deep: false }) => {
+            let valid = if std::io::stderr().is_terminal() {
+                let mut shown = None;
+                let valid = chain.validate_with_progress(|status| draw_progress(status, &mut shown), &CancelToken::new());
+                eprintln!();
+                valid?
+            } else {
+                chain.is_chain_valid()?
+            };
+            if !valid {
+                return Err("Integrity check failed".into());
+            }
+            println!("Chain valid.");
+        }
+        Some(Command::Migrate { hashes }) => {
+            let migrated = chain.migrate_encoding()?;
+            println!("Migrated {} blocks to the current encoding.", migrated);
+            if hashes {
+                let rewritten = chain.migrate_hashes()?;
+                println!("Rehashed {} blocks. New tip: {}", rewritten, chain.current_hash());
+            }
+        }
+        Some(Command::Export { format, since: Some(name), output }) => {
+            let written = match output {
+                Some(path) => chain.export_report(BufWriter::new(File::create(path)?), format, Some(&name))?,
+                None => chain.export_report(std::io::stdout().lock(), format, Some(&name))?,
+            };
+            match written {
+                Some((from, to)) => eprintln!("Exported heights {} to {}", from, to),
+                None => eprintln!("No blocks since the last export"),
+            }
+        }
+        Some(Command::Export { format, since: None, output }) => match output {
+            Some(path) => chain.export(BufWriter::new(File::create(path)?), format)?,
+            None => chain.export(std::io::stdout().lock(), format)?,
+        },
+        Some(Command::Import { format, input }) => {
+            let imported = chain.import(File::open(input)?, format)?;
+            println!("Imported {} blocks. Current tip: {}", imported, chain.current_hash());
+        }
+        Some(Command::Prune { keep }) => {
+            let pruned = chain.prune(keep)?;
+            println!("Pruned {} blocks.", pruned);
+        }
+        Some(Command::Gc) => {
+            let report = chain.gc()?;
+            println!("Removed {} blocks, {} bytes.", report.removed.len(), report.reclaimed_bytes);
+            if !report.held.is_empty() {
+                println!("Kept {} held blocks.", report.held.len());
+            }
+            if !report.blobs.is_empty() {
+                println!("Removed {} unreferenced blobs.", report.blobs.len());
+            }
+            println!("Size on disk: {} -> {} bytes", report.size_before, report.size_after);
+        }
+        Some(Command::Replay { from, to, name }) => {
+            let to = match to {
+                Some(to) => to,
+                None => chain.height()?,
+            };
+            let mut out = std::io::stdout().lock();
+            let mut print = |replayed: &ReplayedBlock| -> Result<(), Box<dyn Error>> {
+                serde_json::to_writer(&mut out, replayed)?;
+                writeln!(out)?;
+                Ok(())
+            };
+            let token = match name {
+                Some(name) => chain.resume_replay(&name, to, &mut print)?,
+                None => Some(chain.replay(from, to, &mut print)?),
+            };
+            if let Some(token) = token {
+                eprintln!("Replayed to height {} ({})", token.height, token.hash);
+            }
+        }
+        Some(Command::Repair { dry_run }) => {
+            let report = chain.repair(dry_run)?;
+            if report.quarantined.is_empty() {
+                println!("No damage found. Tip: {}", report.last_valid);
+            } else {
+                let verb = if dry_run { "Would quarantine" } else { "Quarantined" };
+                println!("{} {} blocks:", verb, report.quarantined.len());
+                for hash in &report.quarantined {
+                    println!("  {}", hash);
+                }
+                println!("Last valid block: {} (height {})", report.last_valid, report.height);
+            }
+        }
+        Some(Command::Audit { action }) => run_audit(&AuditLedger::new(chain.clone())?, action, zone)?,
+        Some(Command::Snapshot { action }) => run_snapshot(&chain, action, zone)?,
+        Some(Command::Checkpoint { action }) => run_checkpoint(&chain, action)?,
+        Some(Command::Anchor { action }) => run_anchor(&chain, action, zone)?,
+        Some(Command::Blob { action }) => run_blob(&chain, action, zone)?,
+        Some(Command::Hold { action }) => run_hold(&chain, action, zone)?,
+        Some(Command::Peers { action }) => run_peers(&chain, action, zone)?,
+        Some(Command::Backup { dest }) => {
+            let info = chain.backup(&dest)?;
+            println!("Backed up {} records to {}: height {}, tip {}", info.records, dest, info.height, info.tip);
+        }
+        Some(Command::Chains { .. })
+        | Some(Command::Restore { .. })
+        | Some(Command::Bench { .. })
+        | Some(Command::Authority { action: AuthorityCommand::Keygen })
+        | Some(Command::ApiKey) => {
+            unreachable!("handled before a chain is opened")
+        }
+        Some(Command::Authority { action: AuthorityCommand::Status }) => {
+            println!("This node: {}", chain.authority().as_deref().unwrap_or("no authority key"));
+            match chain.genesis_config().consensus {
+                Consensus::ProofOfAuthority { authorities } => {
+                    let height = chain.height()? + 1;
+                    println!("Next block (height {}): {}", height, authority::scheduled(&authorities, height).unwrap_or_default());
+                }
+                Consensus::ProofOfWork => println!("The chain uses proof of work."),
+            }
+        }
+        Some(Command::Proof { block, txid }) => {
+            let proof = chain
+                .transaction_proof(&block, &txid)?
+                .ok_or_else(|| format!("Block {} does not hold transaction {}", block, txid))?;
+            println!("{}", serde_json::to_string_pretty(&proof)?);
+        }
+        Some(Command::Metrics { listen: Some(addr) }) => {
+            println!("Serving metrics on http://{0}/metrics and blocks on http://{0}/blocks", addr);
+            serve_until_signalled(&chain, || chain.serve_metrics(addr.as_str()))?;
+        }
+        Some(Command::Metrics { listen: None }) => print!("{}", chain.metrics()?.render()),
+        #[cfg(feature = "grpc")]
+        Some(Command::Grpc { listen }) => {
+            println!("Serving gRPC on {}", listen);
+            let runtime = tokio::runtime::Runtime::new()?;
+            serve_until_signalled(&chain, || runtime.block_on(chain.serve_grpc(listen)))?;
+        }
+        #[cfg(feature = "grpc")]
+        Some(Command::Sync { peers, parallel }) => {
+            let report = tokio::runtime::Runtime::new()?.block_on(chain.sync(&peers, parallel))?;
+            match report.peer {
+                Some(peer) => println!(
+                    "Synced from {}: {} headers, {} blocks, height {}",
+                    peer, report.headers, report.blocks, report.height
+                ),
+                None => return Err("None of the peers could be reached".into()),
+            }
+        }
+        #[cfg(feature = "websocket")]
+        Some(Command::Websocket { listen }) => {
+            println!("Serving WebSocket notifications on ws://{}", listen);
+            serve_until_signalled(&chain, || chain.serve_websocket(listen.as_str()))?;
+        }
+        Some(Command::Search { text, term }) => {
+            let hits = if term { chain.lookup(&text)? } else { chain.search(&text)? };
+            print_hits(&hits);
+        }
+        Some(Command::Tagged { tags }) => {
+            let blocks = chain.find_by_tags(&tags.into_iter().collect())?;
+            for block in &blocks {
+                println!("{}  {}", block.hash, block.data_summary());
+            }
+            println!("{} blocks.", blocks.len());
+        }
+        Some(Command::Stats { format }) => {
+            let stats = chain.stats()?;
+            match format {
+                StatsFormat::Table => print_stats(&stats),
+                StatsFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+            }
+        }
+        Some(Command::Verify { block, proof }) => {
+            let proof: MerkleProof = serde_json::from_reader(File::open(proof)?)?;
+            let header = chain.header(&block)?.ok_or_else(|| format!("Unknown block {}", block))?;
+            verify_result(ledger_v1::merkle::verify_proof(header.hash_algorithm, &header.merkle_root, &proof))?;
+        }
+    }
+
+    // Whatever the durability policy, nothing the command wrote stays unflushed.
+    chain.flush()?;
+    std::io::stdout().flush()?;
+    Ok(())
+}
+
+// Runs a server until SIGINT or SIGTERM, then shuts the chain down: the block being
+// committed is finished and the mempool saved for the next start. A second signal
+// exits at once.
+fn serve_until_signalled(chain: &Blockchain, serve: impl FnOnce() -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    for signal in [SIGINT, SIGTERM] {
+        flag::register_conditional_shutdown(signal, 1, chain.shutdown_flag())?;
+        flag::register(signal, chain.shutdown_flag())?;
+    }
+    let served = serve();
+    let saved = chain.shutdown()?;
+    println!("Shut down; saved {} mempool transactions.", saved);
+    served
+}
+
+fn print_hits(hits: &[SearchHit]) {
+    for hit in hits {
+        match &hit.transaction {
+            Some(txid) => println!("{:>8}  {}  tx {}", hit.height, hit.block, txid),
+            None => println!("{:>8}  {}", hit.height, hit.block),
+        }
+    }
+    println!("{} matches.", hits.len());
+}
+
+fn print_blocks(blocks: &[BlockSummary], zone: TimeZone) {
+    const DATA_WIDTH: usize = 40;
+    for block in blocks {
+        let time = zone.format(block.timestamp);
+        // One line per block, whatever the payload holds.
+        let mut data: String = block.data.chars().take(DATA_WIDTH).map(|c| if c.is_control() { ' ' } else { c }).collect();
+        if block.data.chars().count() > DATA_WIDTH {
+            data.push('…');
+        }
+        println!("{:>8}  {}  {}  {:>4} tx  {}", block.height, block.hash, time, block.transactions, data);
+    }
+}
+
+// A YAML sequence of mappings. JSON scalars are valid YAML, so each value is written
+// as its JSON.
+fn print_yaml(blocks: &[BlockSummary]) -> Result<(), Box<dyn Error>> {
+    if blocks.is_empty() {
+        println!("[]");
+    }
+    for block in blocks {
+        let serde_json::Value::Object(fields) = serde_json::to_value(block)? else {
+            return Err("A block summary is not a JSON object".into());
         };
-        
-        // FIX 3: Wrap the return struct in Ok()
-        Ok(Blockchain { db, current_hash })
+        for (index, (key, value)) in fields.iter().enumerate() {
+            println!("{} {}: {}", if index == 0 { "-" } else { " " }, key, value);
+        }
     }
+    Ok(())
+}
 
-    // FIX 4: Return Result<(), ...> so we can use '?'
-    fn add_block(&mut self, data: String) -> Result<(), Box<dyn Error>> {
-        let new_block = Block::new(data, self.current_hash.clone());
-        let new_hash = new_block.hash.clone();
-        let new_block_json = serde_json::to_string(&new_block)?;
+fn print_stats(stats: &ChainStats) {
+    println!("{:<24}{}", "Blocks", stats.blocks);
+    println!("{:<24}{}", "Transactions", stats.transactions);
+    match stats.average_block_interval_ms {
+        Some(interval) => println!("{:<24}{:.1}s", "Average block interval", interval as f64 / 1000.0),
+        None => println!("{:<24}-", "Average block interval"),
+    }
+    println!("{:<24}{} bytes", "Size on disk", stats.size_on_disk);
+    if !stats.blocks_per_day.is_empty() {
+        println!("\nBlocks per day:");
+        for (day, blocks) in &stats.blocks_per_day {
+            println!("  {}  {:>8}", day, blocks);
+        }
+    }
+}
+
+fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {:?}", tag)),
+    }
+}
 
-        self.db.insert(new_block.hash.as_bytes(), new_block_json.as_bytes())?;
-        self.db.insert("LAST", new_block.hash.as_bytes())?;
+fn guess_content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    match extension.to_lowercase().as_str() {
+        "pdf" => "application/pdf",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "zip" => "application/zip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        _ => "application/octet-stream",
+    }
+}
 
-        self.current_hash = new_hash;
-        self.db.flush()?; // Ensure save to disk
+// Chains without proof of work find a block with the first hash; nothing to report.
+fn print_mining_stats(chain: &Blockchain) {
+    if let Some(stats) = chain.last_mining_stats()
+        && stats.hashes > 1
+    {
+        println!(
+            "Mined on {} threads: {} hashes in {:.2?} ({:.0} H/s)",
+            stats.threads,
+            stats.hashes,
+            stats.elapsed,
+            stats.hash_rate()
+        );
+    }
+}
 
-        Ok(())
+// sled lets no one else open the database of a running node, so a node keeping a
+// read replica is read through that.
+fn open_read_only(db: &str, config: Config) -> Result<Blockchain, Box<dyn Error>> {
+    let Some(replica) = config.read_replica.clone() else {
+        return Blockchain::open_read_only_with_config(db, config);
+    };
+    match Blockchain::open_read_only_with_config(db, config.clone()) {
+        Err(e) if matches!(e.downcast_ref::<LedgerError>(), Some(LedgerError::AlreadyInUse { .. })) => {
+            let chain = Blockchain::open_replica(&replica, config)?;
+            eprintln!("{} is in use; reading its replica at {}, height {}", db, replica.display(), chain.height()?);
+            Ok(chain)
+        }
+        opened => opened,
     }
+}
 
-    fn print_chain(&self) {
-        let mut search_hash = self.current_hash.clone();
-        println!("--- CHAIN ON DISK ---");
+// Light mode keeps only headers, so only the commands that work on them are available.
+fn run_light(cli: Cli, genesis: Option<GenesisConfig>, config: Config) -> Result<(), Box<dyn Error>> {
+    if cli.read_only || cli.chain.is_some() {
+        return Err("--read-only and --chain are not supported for light nodes".into());
+    }
+    let mut headers = HeaderChain::open(&cli.db, genesis.as_ref(), config)?;
+    match cli.command {
+        Some(Command::Print { .. }) => headers.print_chain(),
+        Some(Command::Validate { .. }) => {
+            if !headers.is_chain_valid()? {
+                return Err("Integrity check failed".into());
+            }
+            println!("Header chain valid.");
+        }
+        Some(Command::Import { format, input }) => {
+            let imported = headers.import(File::open(input)?, format)?;
+            println!("Imported {} headers. Current tip: {}", imported, headers.tip());
+        }
+        Some(Command::Verify { block, proof }) => {
+            let proof: MerkleProof = serde_json::from_reader(File::open(proof)?)?;
+            verify_result(headers.verify_transaction(&block, &proof)?)?;
+            println!("Confirmations: {}", headers.confirmations(&block)?);
+        }
+        _ => return Err("This command needs the blocks; run as a full, archive or pruned node (--mode)".into()),
+    }
+    std::io::stdout().flush()?;
+    Ok(())
+}
 
-        loop {
-            match self.db.get(search_hash.as_bytes()) {
-                Ok(Some(bytes)) => {
-                    let block_json = String::from_utf8(bytes.to_vec()).unwrap();
-                    let block: Block = serde_json::from_str(&block_json).unwrap();
+fn verify_result(valid: bool) -> Result<(), Box<dyn Error>> {
+    if !valid {
+        return Err("The proof does not match the block's merkle root".into());
+    }
+    println!("Proof verified: ✅");
+    Ok(())
+}
 
-                    println!("Hash: {}", block.hash);
-                    println!("Data: {}", block.data);
-                    println!("Prev: {}\n", block.prev_hash);
+// A --since or --until time, read in `zone`.
+fn parse_time(text: Option<String>, zone: TimeZone) -> Result<Option<u64>, Box<dyn Error>> {
+    text.map(|text| zone.parse(&text, SystemClock.now_ms())).transpose()
+}
 
-                    if block.prev_hash == "0" {
-                        break;
-                    }
-                    search_hash = block.prev_hash;
-                },
-                _ => break,
+fn run_audit(audit: &AuditLedger, action: AuditCommand, zone: TimeZone) -> Result<(), Box<dyn Error>> {
+    match action {
+        AuditCommand::Append { actor, action, resource, details } => {
+            println!("Recorded in block {}", audit.append(&actor, &action, &resource, &details)?);
+        }
+        AuditCommand::List { since, until } => {
+            let (since, until) = (parse_time(since, zone)?, parse_time(until, zone)?);
+            for entry in audit.entries()? {
+                if since.is_some_and(|since| entry.timestamp < since) || until.is_some_and(|until| entry.timestamp >= until) {
+                    continue;
+                }
+                let time = zone.format(entry.timestamp);
+                println!("{:>8}  {}  {} {} {}  {}", entry.height, time, entry.actor, entry.action, entry.resource, entry.details);
+            }
+        }
+        AuditCommand::Verify { report: path } => {
+            let report = audit.verify()?;
+            for finding in &report.findings {
+                println!("{:>8}  {}  {}", finding.height, finding.block, finding.problem);
+            }
+            if let Some(path) = path {
+                if report.signature.is_empty() {
+                    return Err(format!("Set audit_key_file or {} to sign the report", ledger_v1::audit::KEY_ENV).into());
+                }
+                serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), &report)?;
+                println!("Signed report written to {}", path.display());
+            }
+            if !report.intact {
+                return Err(format!("Integrity broken at {} blocks", report.findings.len()).into());
             }
+            println!("All {} blocks intact.", report.blocks_checked);
         }
+        AuditCommand::CheckReport { report } => {
+            let report: TamperReport = serde_json::from_reader(File::open(report)?)?;
+            if !audit.check_report(&report)? {
+                return Err("The report's signature does not match; it was altered or signed with another key".into());
+            }
+            println!("Report signature valid (generated at {}, tip {}).", report.generated_at, report.tip);
+        }
+    }
+    Ok(())
+}
+
+fn run_bench(blocks: u64, payload: usize, memory: bool, genesis: Option<&GenesisConfig>, config: Config) -> Result<(), Box<dyn Error>> {
+    if memory {
+        return bench_chain(&Blockchain::open_store(MemoryStore::new(), genesis, config)?, blocks, payload);
     }
+    let path = std::env::temp_dir().join(format!("ledger-bench-{}", std::process::id()));
+    let path = path.to_str().ok_or("The temporary directory is not valid UTF-8")?;
+    let result = Blockchain::open_with_config(path, genesis, config).and_then(|chain| bench_chain(&chain, blocks, payload));
+    let _ = std::fs::remove_dir_all(path);
+    result
+}
 
+fn bench_chain<S: BlockStore>(chain: &Blockchain<S>, blocks: u64, payload: usize) -> Result<(), Box<dyn Error>> {
+    let started = std::time::Instant::now();
+    for i in 0..blocks {
+        let mut data = format!("bench block {} ", i).into_bytes();
+        data.resize(payload.max(data.len()), b'.');
+        chain.add_block(data)?;
+    }
+    chain.flush()?;
+    let added = started.elapsed();
 
-    // Returns Ok(true) if valid, Ok(false) if corrupted
-    fn is_chain_valid(&self) -> Result<bool, Box<dyn Error>> {
-        let mut search_hash = self.current_hash.clone();
-        
-        loop {
-            // 1. Get the block bytes from the DB
-            match self.db.get(search_hash.as_bytes())? {
-                Some(bytes) => {
-                    let block_json = String::from_utf8(bytes.to_vec())?;
-                    let block: Block = serde_json::from_str(&block_json)?;
+    let started = std::time::Instant::now();
+    if !chain.is_chain_valid()? {
+        return Err("The synthetic chain did not validate".into());
+    }
+    let validated = started.elapsed();
 
-                    // CHECK 1: Data Integrity
-                    // We recalculate the hash using the data inside the block.
-                    // If the data was edited, this calculated hash won't match the stored hash.
-                    if block.hash != block.calculate_hash() {
-                        println!("ERROR: Hash mismatch for block {}", block.hash);
-                        return Ok(false);
-                    }
+    let metrics = chain.metrics()?;
+    let rate = |count: u64, took: Duration| count as f64 / took.as_secs_f64().max(f64::EPSILON);
+    println!("Added     {} blocks in {:.2?} ({:.0} blocks/s)", blocks, added, rate(blocks, added));
+    println!("Validated {} blocks in {:.2?} ({:.0} blocks/s)", metrics.blocks_validated, validated, rate(metrics.blocks_validated, validated));
+    println!("Commits   {} taking {:.3}s, flushes {} taking {:.3}s", metrics.commits, metrics.commit_seconds, metrics.db_flushes, metrics.db_flush_seconds);
+    Ok(())
+}
 
-                    // CHECK 2: Link Integrity
-                    // (Implicit) We are using 'prev_hash' to find the next block. 
-                    // If this pointer is wrong, the next DB lookup will fail or return the wrong block.
-                    
-                    // Stop at Genesis
-                    if block.prev_hash == "0" {
-                        println!("Chain valid. Genesis reached.");
-                        break;
+fn run_chains(path: &str, action: &ChainsCommand, zone: TimeZone) -> Result<(), Box<dyn Error>> {
+    let (db, _lock) = lockfile::open_locked(path)?;
+    match action {
+        ChainsCommand::List => {
+            for info in registry::list_chains(&db)? {
+                println!("{:<24}  created {}", info.name, zone.format(info.created_at));
+            }
+        }
+        ChainsCommand::Remove { name } => {
+            registry::remove_chain(&db, name)?;
+            println!("Removed chain {}", name);
+        }
+    }
+    Ok(())
+}
+
+fn run_peers(chain: &Blockchain, action: PeersCommand, zone: TimeZone) -> Result<(), Box<dyn Error>> {
+    match action {
+        PeersCommand::List => {
+            let format_time = |ms: u64| zone.format(ms);
+            for peer in chain.peers()? {
+                let seen = peer.last_seen.map_or_else(|| "never".to_string(), format_time);
+                let status = match (&peer.banned_until, &peer.ban_reason) {
+                    (Some(until), reason) if peer.is_banned() => {
+                        format!("banned until {} ({})", format_time(*until), reason.as_deref().unwrap_or("no reason"))
                     }
-                    
-                    // Move backwards
-                    search_hash = block.prev_hash;
-                },
-                None => {
-                    // We were looking for a block that should exist (because a prev_hash pointed to it)
-                    // but we couldn't find it. The chain is broken.
-                    println!("ERROR: Broken link! Could not find block: {}", search_hash);
-                    return Ok(false);
+                    _ => "ok".to_string(),
+                };
+                println!("{:<28} score {:>4}  seen {}  {}", peer.address, peer.score, seen, status);
+            }
+        }
+        PeersCommand::Add { address } => {
+            if chain.add_peer(&address)? {
+                println!("Added peer {}", address);
+            } else {
+                println!("Peer {} is already known", address);
+            }
+        }
+        PeersCommand::Ban { address, hours, reason } => {
+            chain.ban_peer(&address, Duration::from_secs(hours * 60 * 60), &reason)?;
+            println!("Banned {} for {} hours", address, hours);
+        }
+        PeersCommand::Unban { address } => {
+            chain.unban_peer(&address)?;
+            println!("Unbanned {}", address);
+        }
+    }
+    Ok(())
+}
+
+fn run_checkpoint(chain: &Blockchain, action: CheckpointCommand) -> Result<(), Box<dyn Error>> {
+    match action {
+        CheckpointCommand::Create => {
+            let checkpoint = chain.create_checkpoint()?;
+            println!("Checkpoint recorded at height {}: {}", checkpoint.height, checkpoint.hash);
+        }
+        CheckpointCommand::List => {
+            for checkpoint in chain.checkpoints()? {
+                let source = if checkpoint.is_configured() { "configured" } else { "recorded" };
+                println!("{:>8}  {}  {}", checkpoint.height, checkpoint.hash, source);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_blob(chain: &Blockchain, action: BlobCommand, zone: TimeZone) -> Result<(), Box<dyn Error>> {
+    match action {
+        BlobCommand::Put { file } => println!("{}", chain.put_blob(&std::fs::read(file)?)?),
+        BlobCommand::Get { hash, name, output } => {
+            let content = match name {
+                Some(name) => chain
+                    .get_attachment(&hash, &name)?
+                    .ok_or_else(|| format!("Block {} has no attachment {}", hash, name))?,
+                None => chain.get_blob(&hash)?.ok_or_else(|| format!("Unknown blob {}", hash))?,
+            };
+            match output {
+                Some(path) => std::fs::write(path, content)?,
+                None => std::io::stdout().lock().write_all(&content)?,
+            }
+        }
+        BlobCommand::List => {
+            let blobs = chain.list_blobs()?;
+            for blob in &blobs {
+                println!("{}  {:>10} bytes  {}", blob.hash, blob.stored_bytes, zone.format(blob.uploaded_at));
+            }
+            println!("{} blobs.", blobs.len());
+        }
+    }
+    Ok(())
+}
+
+fn run_hold(chain: &Blockchain, action: HoldCommand, zone: TimeZone) -> Result<(), Box<dyn Error>> {
+    match action {
+        HoldCommand::Place { target, reason } => {
+            chain.place_legal_hold(&target, &reason)?;
+            println!("{} is under legal hold", target);
+        }
+        HoldCommand::Release { target } => {
+            chain.release_legal_hold(&target)?;
+            println!("Released the legal hold on {}", target);
+        }
+        HoldCommand::Retain { target, class } => {
+            chain.set_retention_class(&target, class.as_deref())?;
+            match class {
+                Some(class) => println!("{} is retained as {}", target, class),
+                None => println!("Cleared the retention class of {}", target),
+            }
+        }
+        HoldCommand::List => {
+            for hold in chain.holds()? {
+                let class = hold.retention_class.as_deref().unwrap_or("-");
+                match &hold.legal_hold {
+                    Some(legal) => println!("{}  {}  legal hold since {}: {}", hold.target, class, zone.format(legal.placed_ms), legal.reason),
+                    None => println!("{}  {}", hold.target, class),
                 }
             }
         }
-        
-        Ok(true)
     }
+    Ok(())
 }
 
-fn main() {
-    // We unwrap here because if the DB fails to load, we want to crash and see why.
-    let mut chain = Blockchain::new().unwrap();
-    println!("Blockchain loaded. Current tip: {}", chain.current_hash);
+fn run_anchor(chain: &Blockchain, action: AnchorCommand, zone: TimeZone) -> Result<(), Box<dyn Error>> {
+    match action {
+        AnchorCommand::Create => {
+            let anchor = chain.anchor()?;
+            println!("Anchored height {}: {}", anchor.height, anchor.hash);
+        }
+        AnchorCommand::List => {
+            for anchor in chain.anchors()? {
+                let kind = match anchor.proof {
+                    AnchorProof::Signed { .. } => "log",
+                    AnchorProof::Timestamp { .. } => "timestamp",
+                };
+                println!("{:>8}  {}  {}  {}", anchor.height, anchor.hash, zone.format(anchor.anchored_ms), kind);
+            }
+        }
+        AnchorCommand::Verify { log } => {
+            let checks = match log {
+                Some(path) => chain.verify_anchor_log(path)?,
+                None => chain.verify_anchors()?,
+            };
+            for check in &checks {
+                let outcome = check.problem.as_deref().unwrap_or("ok");
+                println!("{:>8}  {}  {}  {}", check.anchor.height, check.anchor.hash, check.attested, outcome);
+            }
+            let failed = checks.iter().filter(|check| !check.holds()).count();
+            if failed > 0 {
+                return Err(format!("{} of {} anchors do not hold up", failed, checks.len()).into());
+            }
+            println!("All {} anchors hold up.", checks.len());
+        }
+        AnchorCommand::Token { hash, output } => {
+            let anchor = chain
+                .anchors()?
+                .into_iter()
+                .rfind(|anchor| anchor.hash == hash)
+                .ok_or_else(|| format!("Block {} was never anchored", hash))?;
+            let AnchorProof::Timestamp { token } = &anchor.proof else {
+                return Err(format!("Block {} was anchored to a log, not a timestamp authority", hash).into());
+            };
+            std::fs::write(&output, token)?;
+            println!("Wrote the token to {}; it is over the digest {}", output.display(), anchor.digest(&chain.network_id()?));
+        }
+    }
+    Ok(())
+}
+
+fn run_snapshot(chain: &Blockchain, action: SnapshotCommand, zone: TimeZone) -> Result<(), Box<dyn Error>> {
+    match action {
+        SnapshotCommand::Create => {
+            let info = chain.create_snapshot()?;
+            println!("Snapshot taken at height {} ({} state records)", info.height, info.entries);
+        }
+        SnapshotCommand::List => {
+            for info in chain.list_snapshots()? {
+                println!("{:>8}  {}  {} records  created {}", info.height, info.tip, info.entries, zone.format(info.created_at));
+            }
+        }
+        SnapshotCommand::Restore { height: Some(height), .. } => {
+            let info = chain.restore_snapshot(height)?;
+            println!("State restored from height {} and replayed to {}", info.height, chain.height()?);
+        }
+        SnapshotCommand::Restore { file: Some(path), .. } => {
+            let info = chain.bootstrap_from_snapshot(File::open(path)?)?;
+            println!("Bootstrapped from snapshot at height {}. Current tip: {}", info.height, chain.current_hash());
+        }
+        SnapshotCommand::Restore { .. } => return Err("Give a snapshot height or --file".into()),
+        SnapshotCommand::Export { height, output } => {
+            chain.export_snapshot(height, BufWriter::new(File::create(output)?))?;
+        }
+    }
+    Ok(())
+}
+
+// Redraws the bar on stderr whenever the whole percentage changes.
+fn draw_progress(status: ValidationProgress, shown: &mut Option<u64>) {
+    const WIDTH: usize = 40;
+    let percent = status.percent() as u64;
+    if *shown == Some(percent) {
+        return;
+    }
+    *shown = Some(percent);
+    let filled = WIDTH * percent as usize / 100;
+    eprint!("\r[{}{}] {:>3}%  {}/{} blocks", "#".repeat(filled), " ".repeat(WIDTH - filled), percent, status.checked, status.total);
+    let _ = std::io::stderr().flush();
+}
+
+// What the binary did before it had subcommands: check, append a sample block, print.
+fn run_demo(chain: &Blockchain) -> Result<(), Box<dyn Error>> {
+    println!("Blockchain loaded. Current tip: {}", chain.current_hash());
 
     // 1. Check validity on load
     match chain.is_chain_valid() {
         Ok(true) => println!("Integrity check passed: ✅"),
         Ok(false) => {
             println!("Integrity check failed: ❌");
-            return; // Stop the program if the DB is corrupted
-        }, 
+            return Ok(()); // Stop the program if the DB is corrupted
+        },
         Err(e) => println!("Error during validation: {}", e),
     }
 
     // 2. Add a new block
-    chain.add_block("Transaction: User A -> User B".to_string()).unwrap();
+    chain.add_block("Transaction: User A -> User B".to_string())?;
     println!("Added new block.");
     chain.print_chain();
-}
\ No newline at end of file
+    Ok(())
+}
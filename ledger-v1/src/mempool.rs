@@ -0,0 +1,273 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+
+use crate::batch::ChainBatch;
+use crate::block::Block;
+use crate::conflicts::Conflict;
+use crate::blockchain::Blockchain;
+use crate::events::ChainEvent;
+use crate::limits::MempoolLimitError;
+use crate::state::StateChanges;
+use crate::store::BlockStore;
+use crate::transaction::Transaction;
+use tracing::debug;
+
+// Transactions waiting to be mined, by txid. Kept in memory; `shutdown` saves them
+// for the next open, but a node that crashes starts with an empty pool.
+#[derive(Default)]
+pub(crate) struct Mempool {
+    transactions: BTreeMap<String, Transaction>,
+}
+
+impl Mempool {
+    pub(crate) fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub(crate) fn contains(&self, txid: &str) -> bool {
+        self.transactions.contains_key(txid)
+    }
+
+    // Pending transfers and locks paid for by `sender`, by txid.
+    pub(crate) fn sent_by<'a>(&'a self, sender: &'a str) -> impl Iterator<Item = (&'a String, &'a Transaction)> + 'a {
+        self.transactions
+            .iter()
+            .filter(move |(_, transaction)| transaction.spend().is_some_and(|(from, _)| from == sender))
+    }
+
+    // Transactions no other pending one depends on, so they can be dropped without
+    // stranding any: each sender's highest nonce, leaving out locks a pending unlock
+    // pays out, and unlocks.
+    fn leaves(&self) -> impl Iterator<Item = (&String, &Transaction)> + '_ {
+        let mut last: HashMap<&str, Option<u64>> = HashMap::new();
+        let mut unlocked = HashSet::new();
+        for transaction in self.transactions.values() {
+            if let Some((from, _)) = transaction.spend() {
+                let nonce = last.entry(from).or_insert(transaction.nonce());
+                *nonce = (*nonce).max(transaction.nonce());
+            }
+            if let Transaction::Unlock { lock, .. } = transaction {
+                unlocked.insert(lock.as_str());
+            }
+        }
+        self.transactions.iter().filter(move |(txid, transaction)| match transaction.spend() {
+            Some((from, _)) => last[from] == transaction.nonce() && !unlocked.contains(txid.as_str()),
+            None => true,
+        })
+    }
+
+    // The pending unlock of `lock`, if any.
+    pub(crate) fn unlocking(&self, lock: &str) -> Option<&String> {
+        self.transactions
+            .iter()
+            .find(|(_, transaction)| matches!(transaction, Transaction::Unlock { lock: pending, .. } if pending == lock))
+            .map(|(txid, _)| txid)
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Checks a transaction against the canonical state and the sender's other pending
+    // transactions, then queues it for the next mined block. Returns its id. A
+    // transaction refused for a `Conflict` (see `conflicts`) fails with that conflict.
+    pub fn submit_transaction(&self, transaction: Transaction) -> Result<String, Box<dyn Error>> {
+        self.check_open()?;
+        if matches!(transaction, Transaction::Coinbase { .. }) {
+            return Err("Coinbase transactions are created by miners".into());
+        }
+        transaction.check_signature(&self.genesis_config())?;
+        self.check_transaction_hooks(&transaction)?;
+        let txid = transaction.hash();
+        // Checked before taking the lock, which `conflicts` needs too. A transfer
+        // that slips in between still meets the full check below.
+        if let Some(conflict) = self.conflicts(&transaction)?.into_iter().next() {
+            return Err(Box::new(conflict));
+        }
+        if transaction.fee() < self.config.min_fee {
+            return Err(Box::new(MempoolLimitError::FeeTooLow { fee: transaction.fee(), minimum: self.config.min_fee }));
+        }
+        let mut mempool = self.shared.mempool.lock().unwrap();
+        if mempool.transactions.contains_key(&txid) {
+            return Err(Box::new(Conflict::AlreadyPending { txid }));
+        }
+        if let Some((sender, _)) = transaction.spend() {
+            let limit = self.config.max_pending_per_sender;
+            if limit > 0 && mempool.sent_by(sender).count() >= limit {
+                return Err(Box::new(MempoolLimitError::TooManyPending { sender: sender.to_string(), limit }));
+            }
+        }
+
+        let batch = self.batch();
+        let height = self.height()? + 1;
+        let mut changes = StateChanges::new();
+        let mut competing: Vec<&Transaction> =
+            mempool.transactions.values().filter(|pending| competes(pending, &transaction)).collect();
+        competing.sort_by_key(|pending| pending.nonce());
+        for pending in competing {
+            try_apply(&batch, &mut changes, pending, height);
+        }
+        batch.apply_transaction(&mut changes, &transaction, height)?;
+
+        // A full pool makes room by dropping its lowest fee rate transaction, if that
+        // pays less than this one. Only one no other depends on can go, and not one
+        // this one builds on.
+        let limit = self.config.max_mempool_transactions;
+        if limit > 0 && mempool.transactions.len() >= limit {
+            let lowest = mempool
+                .leaves()
+                .filter(|(txid, pending)| !builds_on(&transaction, txid, pending))
+                .max_by(|(_, a), (_, b)| by_fee_rate(a, b))
+                .filter(|(_, lowest)| by_fee_rate(&transaction, lowest) == Ordering::Less)
+                .map(|(txid, _)| txid.clone());
+            match lowest {
+                Some(lowest) => {
+                    debug!(txid = %lowest, "mempool full, evicting the lowest fee rate transaction");
+                    mempool.transactions.remove(&lowest);
+                }
+                None => return Err(Box::new(MempoolLimitError::PoolFull { limit })),
+            }
+        }
+
+        mempool.transactions.insert(txid.clone(), transaction.clone());
+        drop(mempool);
+        self.emit(ChainEvent::TransactionSubmitted { txid: txid.clone(), transaction });
+        Ok(txid)
+    }
+
+    // The nonce the next transfer or lock from `address` must carry: the number of
+    // transactions it has sent on the canonical chain, or past its last pending one.
+    pub fn next_nonce(&self, address: &str) -> Result<u64, Box<dyn Error>> {
+        let mempool = self.shared.mempool.lock().unwrap();
+        self.next_nonce_locked(address, &mempool)
+    }
+
+    pub(crate) fn next_nonce_locked(&self, address: &str, mempool: &Mempool) -> Result<u64, Box<dyn Error>> {
+        let confirmed = self.get_account(address)?.nonce;
+        Ok(mempool.sent_by(address).filter_map(|(_, pending)| pending.nonce()).map(|nonce| nonce + 1).fold(confirmed, u64::max))
+    }
+
+    // Pending transactions, highest fee rate first.
+    pub fn mempool(&self) -> Vec<Transaction> {
+        let mut transactions: Vec<Transaction> = self.shared.mempool.lock().unwrap().transactions.values().cloned().collect();
+        transactions.sort_by(by_fee_rate);
+        transactions
+    }
+
+    // Picks the transactions for the next block: highest fee rate first, though each
+    // sender's own in nonce order, skipping any that no longer apply to the state or
+    // would push the block past `Config::max_block_bytes` (the coinbase is not counted).
+    pub fn assemble_block(&self) -> Result<Vec<Transaction>, Box<dyn Error>> {
+        let batch = self.batch();
+        let height = self.height()? + 1;
+        let mut changes = StateChanges::new();
+        let mut selected = Vec::new();
+        let mut size = 0;
+        for transaction in in_nonce_order(self.mempool()) {
+            let tx_size = transaction.size() as u64;
+            if size + tx_size > self.config.max_block_bytes {
+                continue;
+            }
+            if try_apply(&batch, &mut changes, &transaction, height) {
+                size += tx_size;
+                selected.push(transaction);
+            }
+        }
+        Ok(selected)
+    }
+
+    // Mines a block holding `assemble_block` on top of the tip.
+    pub fn mine_block(&self, data: impl Into<Vec<u8>>) -> Result<String, Box<dyn Error>> {
+        let transactions = self.assemble_block()?;
+        self.add_block_with_transactions(data, transactions)
+    }
+
+    // Keeps the pool in step with the canonical chain: transactions of disconnected
+    // blocks wait again, those of connected blocks are done, and so are spends whose
+    // nonce a connected block used up.
+    pub(crate) fn update_mempool(&self, connected: &[Block], disconnected: &[Block]) {
+        let mut mempool = self.shared.mempool.lock().unwrap();
+        // Transactions coming back from disconnected blocks were accepted once, so the
+        // pool limits don't apply to them.
+        for block in disconnected {
+            for transaction in &block.transactions {
+                if !matches!(transaction, Transaction::Coinbase { .. }) {
+                    mempool.transactions.insert(transaction.hash(), transaction.clone());
+                }
+            }
+        }
+        for block in connected {
+            for transaction in &block.transactions {
+                mempool.transactions.remove(&transaction.hash());
+            }
+        }
+        if connected.is_empty() {
+            return;
+        }
+        mempool.transactions.retain(|txid, transaction| {
+            let (Some((from, _)), Some(nonce)) = (transaction.spend(), transaction.nonce()) else {
+                return true;
+            };
+            match self.get_account(from) {
+                Ok(account) if nonce < account.nonce => {
+                    debug!(txid = %txid, "nonce used up, dropping the pending transaction");
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+}
+
+// Applies `transaction` only if it succeeds completely.
+fn try_apply<S: BlockStore>(batch: &ChainBatch<S>, changes: &mut StateChanges, transaction: &Transaction, height: u64) -> bool {
+    let mut trial = changes.clone();
+    if batch.apply_transaction(&mut trial, transaction, height).is_err() {
+        return false;
+    }
+    *changes = trial;
+    true
+}
+
+// Keeps the fee rate order, but gives each sender's places to its transactions in
+// nonce order, since a later nonce cannot go first.
+fn in_nonce_order(mut transactions: Vec<Transaction>) -> Vec<Transaction> {
+    let mut by_sender: HashMap<String, Vec<Transaction>> = HashMap::new();
+    for transaction in &transactions {
+        if let Some((from, _)) = transaction.spend() {
+            by_sender.entry(from.to_string()).or_default().push(transaction.clone());
+        }
+    }
+    for sent in by_sender.values_mut() {
+        sent.sort_by_key(|transaction| Reverse(transaction.nonce()));
+    }
+    for slot in &mut transactions {
+        if let Some(from) = slot.spend().map(|(from, _)| from.to_string()) {
+            *slot = by_sender.get_mut(&from).and_then(Vec::pop).expect("one per place");
+        }
+    }
+    transactions
+}
+
+// Whether `transaction` needs `pending` (with id `txid`) to be mined first: an earlier
+// nonce of its sender, or the lock it pays out.
+fn builds_on(transaction: &Transaction, txid: &str, pending: &Transaction) -> bool {
+    if let Transaction::Unlock { lock, .. } = transaction {
+        return lock == txid;
+    }
+    competes(transaction, pending) && pending.nonce() < transaction.nonce()
+}
+
+// Whether both draw on the same funds: one sender's balance, or one lock.
+fn competes(a: &Transaction, b: &Transaction) -> bool {
+    match (a, b) {
+        (Transaction::Unlock { lock: a, .. }, Transaction::Unlock { lock: b, .. }) => a == b,
+        _ => matches!((a.spend(), b.spend()), (Some((a, _)), Some((b, _))) if a == b),
+    }
+}
+
+// Descending fee per byte, compared without dividing.
+fn by_fee_rate(a: &Transaction, b: &Transaction) -> Ordering {
+    let rate_a = a.fee() as u128 * b.size() as u128;
+    let rate_b = b.fee() as u128 * a.size() as u128;
+    rate_b.cmp(&rate_a)
+}
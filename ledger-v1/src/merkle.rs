@@ -0,0 +1,96 @@
+use serde::{Serialize, Deserialize};
+
+use crate::hashing::HashAlgorithm;
+
+// Merkle tree over a block's transaction ids, committed to by version 2 headers.
+//
+// Leaves are H(0x00 || hex txid) and inner nodes H(0x01 || left || right), with H the
+// chain's hash algorithm, so a leaf can never pass for a node. A node without a
+// sibling is carried up a level unchanged rather than paired with itself, which would
+// let two different transaction lists share a root.
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+
+// One level of a proof: the sibling's hash and which side it sits on.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MerkleStep {
+    pub hash: String,
+    pub left: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MerkleProof {
+    pub txid: String,
+    // Leaf to root.
+    pub steps: Vec<MerkleStep>,
+}
+
+// Hex root of the tree, or an empty string when there are no transactions.
+pub fn merkle_root(algorithm: HashAlgorithm, txids: &[String]) -> String {
+    let mut level: Vec<[u8; 32]> = txids.iter().map(|txid| leaf(algorithm, txid)).collect();
+    if level.is_empty() {
+        return String::new();
+    }
+    while level.len() > 1 {
+        level = parent_level(algorithm, &level);
+    }
+    hex::encode(level[0])
+}
+
+// Proof that `txids[index]` is part of the tree.
+pub fn merkle_proof(algorithm: HashAlgorithm, txids: &[String], index: usize) -> Option<MerkleProof> {
+    let txid = txids.get(index)?.clone();
+    let mut level: Vec<[u8; 32]> = txids.iter().map(|txid| leaf(algorithm, txid)).collect();
+    let mut position = index;
+    let mut steps = Vec::new();
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if let Some(hash) = level.get(sibling) {
+            steps.push(MerkleStep { hash: hex::encode(hash), left: sibling < position });
+        }
+        level = parent_level(algorithm, &level);
+        position /= 2;
+    }
+    Some(MerkleProof { txid, steps })
+}
+
+pub fn verify_proof(algorithm: HashAlgorithm, root: &str, proof: &MerkleProof) -> bool {
+    let mut hash = leaf(algorithm, &proof.txid);
+    for step in &proof.steps {
+        let Some(sibling) = decode(&step.hash) else {
+            return false;
+        };
+        hash = if step.left { node(algorithm, &sibling, &hash) } else { node(algorithm, &hash, &sibling) };
+    }
+    hex::encode(hash) == root
+}
+
+fn parent_level(algorithm: HashAlgorithm, level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(algorithm, left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn leaf(algorithm: HashAlgorithm, txid: &str) -> [u8; 32] {
+    let mut preimage = vec![LEAF];
+    preimage.extend_from_slice(txid.as_bytes());
+    algorithm.digest(&preimage)
+}
+
+fn node(algorithm: HashAlgorithm, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut preimage = Vec::with_capacity(65);
+    preimage.push(NODE);
+    preimage.extend_from_slice(left);
+    preimage.extend_from_slice(right);
+    algorithm.digest(&preimage)
+}
+
+fn decode(hash: &str) -> Option<[u8; 32]> {
+    hex::decode(hash).ok()?.try_into().ok()
+}
@@ -0,0 +1,331 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::auth::{self, AuthError, Role};
+use crate::blockchain::Blockchain;
+use crate::store::BlockStore;
+use crate::timefmt::TimeZone;
+use tracing::debug;
+
+// Blocks per `GET /blocks` page when the request sets no limit, and the most it may ask for.
+const DEFAULT_PAGE: u64 = 100;
+const MAX_PAGE: u64 = 1000;
+
+// Counters shared by every handle of a chain. Gauges such as the mempool size are
+// read when the metrics are taken instead.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    pub(crate) blocks_added: AtomicU64,
+    pub(crate) validation_failures: AtomicU64,
+    pub(crate) db_flushes: AtomicU64,
+    pub(crate) db_flush_nanos: AtomicU64,
+    pub(crate) commits: AtomicU64,
+    pub(crate) commit_nanos: AtomicU64,
+    pub(crate) validations: AtomicU64,
+    pub(crate) validation_nanos: AtomicU64,
+    pub(crate) blocks_validated: AtomicU64,
+    pub(crate) peers: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_flush(&self, took: Duration) {
+        self.db_flushes.fetch_add(1, Ordering::Relaxed);
+        self.db_flush_nanos.fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_commit(&self, took: Duration) {
+        self.commits.fetch_add(1, Ordering::Relaxed);
+        self.commit_nanos.fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_validation(&self, blocks: u64, took: Duration) {
+        self.validations.fetch_add(1, Ordering::Relaxed);
+        self.blocks_validated.fetch_add(blocks, Ordering::Relaxed);
+        self.validation_nanos.fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MetricsSnapshot {
+    // Blocks that became canonical since the chain was opened, reorgs included.
+    pub blocks_added: u64,
+    // Blocks rejected by validation since the chain was opened.
+    pub validation_failures: u64,
+    pub mempool_size: u64,
+    // Blocks waiting for their parent.
+    pub orphans: u64,
+    pub height: u64,
+    pub db_flushes: u64,
+    pub db_flush_seconds: f64,
+    // Batches written, and the time spent writing them (flushes not included).
+    pub commits: u64,
+    pub commit_seconds: f64,
+    // Full-chain validations run, the blocks they checked and the time they took.
+    pub validations: u64,
+    pub blocks_validated: u64,
+    pub validation_seconds: f64,
+    pub peers: u64,
+    // Client addresses the rate limiter keeps a budget for, at most
+    // `ratelimit::MAX_TRACKED`.
+    pub rate_limited_addresses: u64,
+}
+
+impl MetricsSnapshot {
+    // Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let metrics: [(&str, &str, &str, String); 14] = [
+            ("ledger_blocks_added_total", "counter", "Blocks that became canonical", self.blocks_added.to_string()),
+            ("ledger_validation_failures_total", "counter", "Blocks rejected by validation", self.validation_failures.to_string()),
+            ("ledger_mempool_transactions", "gauge", "Transactions waiting to be mined", self.mempool_size.to_string()),
+            ("ledger_orphan_blocks", "gauge", "Blocks waiting for their parent", self.orphans.to_string()),
+            ("ledger_chain_height", "gauge", "Height of the canonical tip", self.height.to_string()),
+            ("ledger_db_flushes_total", "counter", "Database flushes after a commit", self.db_flushes.to_string()),
+            ("ledger_db_flush_seconds_total", "counter", "Time spent in those flushes", self.db_flush_seconds.to_string()),
+            ("ledger_db_commits_total", "counter", "Batches written to the database", self.commits.to_string()),
+            ("ledger_db_commit_seconds_total", "counter", "Time spent writing those batches", self.commit_seconds.to_string()),
+            ("ledger_validations_total", "counter", "Full-chain validations run", self.validations.to_string()),
+            ("ledger_blocks_validated_total", "counter", "Blocks checked by those validations", self.blocks_validated.to_string()),
+            ("ledger_validation_seconds_total", "counter", "Time spent in those validations", self.validation_seconds.to_string()),
+            ("ledger_peers", "gauge", "Connected peers", self.peers.to_string()),
+            ("ledger_rate_limited_addresses", "gauge", "Client addresses the rate limiter tracks", self.rate_limited_addresses.to_string()),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        }
+        out
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    pub fn metrics(&self) -> Result<MetricsSnapshot, Box<dyn Error>> {
+        let metrics = &self.shared.metrics;
+        Ok(MetricsSnapshot {
+            blocks_added: metrics.blocks_added.load(Ordering::Relaxed),
+            validation_failures: metrics.validation_failures.load(Ordering::Relaxed),
+            mempool_size: self.shared.mempool.lock().unwrap().len() as u64,
+            orphans: self.shared.orphans.lock().unwrap().len() as u64,
+            height: self.height()?,
+            db_flushes: metrics.db_flushes.load(Ordering::Relaxed),
+            db_flush_seconds: metrics.db_flush_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+            commits: metrics.commits.load(Ordering::Relaxed),
+            commit_seconds: metrics.commit_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+            validations: metrics.validations.load(Ordering::Relaxed),
+            blocks_validated: metrics.blocks_validated.load(Ordering::Relaxed),
+            validation_seconds: metrics.validation_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+            peers: metrics.peers.load(Ordering::Relaxed),
+            rate_limited_addresses: self.shared.rate_limiter.tracked() as u64,
+        })
+    }
+
+    // For embedders that manage peers themselves.
+    pub fn set_peer_count(&self, peers: u64) {
+        self.shared.metrics.peers.store(peers, Ordering::Relaxed);
+    }
+
+    // Answers `GET /metrics` on `addr` until the listener fails or the chain shuts
+    // down (see `shutdown`), and `GET /blocks` with the JSON of `list_blocks`, paged
+    // by `?offset=M&limit=N`, or of `list_blocks_between` when `since` or `until`
+    // times (see `timefmt`) narrow it as well; `tz` adds each block's time in that zone. Requests are
+    // handled one at a time, which is plenty for a scraper; a client that is slow,
+    // sends too much or sends garbage is answered or dropped without holding up the
+    // rest for long. One over its rate limit gets 429. With API keys configured both
+    // need the read_only role, from an "Authorization: Bearer <key>" header.
+    pub fn serve_metrics(&self, addr: impl ToSocketAddrs) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(addr)?;
+        while let Some(stream) = self.accept(&listener)? {
+            let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+            // One client's failure is no reason to stop serving the others.
+            if let Err(e) = self.answer_http(stream) {
+                debug!(%peer, error = %e, "metrics request failed");
+            }
+        }
+        Ok(())
+    }
+
+    fn answer_http(&self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let response = match read_request(&stream) {
+            Ok(request) => self.respond(&stream, &request),
+            Err(RequestError::Io(e)) => return Err(e.into()),
+            Err(RequestError::Malformed(e)) => http_response("400 Bad Request", TEXT, &format!("{}\n", e)),
+            Err(RequestError::TooLarge) => http_response("431 Request Header Fields Too Large", TEXT, "Request too large\n"),
+        };
+        // A scraper that hung up early is not our problem.
+        let _ = stream.write_all(response.as_bytes());
+        Ok(())
+    }
+
+    fn respond(&self, stream: &TcpStream, request: &HttpRequest) -> String {
+        let allowed = stream.peer_addr().map_or(true, |peer| self.allow_request(peer.ip()));
+        let authorized = self.authorize(request.key.as_deref(), Role::ReadOnly);
+        let target = request.line.split_whitespace().nth(1).unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        match path {
+            _ if !allowed => http_response("429 Too Many Requests", TEXT, "Rate limit exceeded\n"),
+            _ if let Err(e) = &authorized => match e {
+                AuthError::Unauthenticated => http_response("401 Unauthorized", TEXT, &format!("{}\n", e)),
+                AuthError::Forbidden { .. } => http_response("403 Forbidden", TEXT, &format!("{}\n", e)),
+            },
+            "/metrics" => match self.metrics() {
+                Ok(metrics) => http_response("200 OK", TEXT, &metrics.render()),
+                Err(e) => http_response("500 Internal Server Error", TEXT, &e.to_string()),
+            },
+            "/blocks" => match parse_page(query, self.clock.now_ms()) {
+                Ok(page) => match self.block_page(&page) {
+                    Ok(body) => http_response("200 OK", JSON, &body),
+                    Err(e) => http_response("500 Internal Server Error", TEXT, &e.to_string()),
+                },
+                Err(e) => http_response("400 Bad Request", TEXT, &format!("{}\n", e)),
+            },
+            _ => http_response("404 Not Found", TEXT, "Not found\n"),
+        }
+    }
+
+    fn block_page(&self, page: &Page) -> Result<String, Box<dyn Error>> {
+        let mut blocks = match (page.since, page.until) {
+            (None, None) => self.list_blocks(page.offset, Some(page.limit))?,
+            (since, until) => self.list_blocks_between(since, until, page.offset, Some(page.limit))?,
+        };
+        if let Some(zone) = page.zone {
+            blocks = blocks.into_iter().map(|block| block.in_zone(zone)).collect();
+        }
+        Ok(serde_json::to_string(&blocks)?)
+    }
+}
+
+// Requests are answered one at a time, so a client gets this long to send its
+// request line and headers, at most MAX_REQUEST_HEAD bytes of them, and to take
+// the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_HEAD: u64 = 8 << 10;
+
+// The parts of a request the server looks at.
+struct HttpRequest {
+    line: String,
+    key: Option<String>,
+}
+
+enum RequestError {
+    Io(io::Error),
+    Malformed(String),
+    TooLarge,
+}
+
+fn read_request(stream: &TcpStream) -> Result<HttpRequest, RequestError> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_HEAD));
+    let mut read_line = || -> Result<String, RequestError> {
+        // The timeout bounds each read; the deadline bounds them all, so a client
+        // trickling bytes in can't hold the server either.
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(RequestError::Io(io::ErrorKind::TimedOut.into()));
+        }
+        stream.set_read_timeout(Some(left)).map_err(RequestError::Io)?;
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line).map_err(RequestError::Io)?;
+        if line.last() != Some(&b'\n') {
+            return Err(match reader.get_ref().limit() {
+                0 => RequestError::TooLarge,
+                _ => RequestError::Malformed("The request ends before its headers do".to_string()),
+            });
+        }
+        String::from_utf8(line).map_err(|_| RequestError::Malformed("The request is not UTF-8".to_string()))
+    };
+    let line = read_line()?;
+    let mut key = None;
+    loop {
+        let header = read_line()?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("authorization")
+        {
+            key = auth::bearer(value).map(str::to_string);
+        }
+    }
+    Ok(HttpRequest { line, key })
+}
+
+const TEXT: &str = "text/plain; version=0.0.4";
+const JSON: &str = "application/json";
+
+fn http_response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+// A `/blocks` query string.
+#[derive(Default)]
+struct Page {
+    offset: u64,
+    limit: u64,
+    since: Option<u64>,
+    until: Option<u64>,
+    zone: Option<TimeZone>,
+}
+
+// Other parameters are ignored. Times are read in `tz`, UTC by default.
+fn parse_page(query: &str, now_ms: u64) -> Result<Page, String> {
+    let mut page = Page { limit: DEFAULT_PAGE, ..Page::default() };
+    let mut times = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        let parsed = || value.parse::<u64>().map_err(|_| format!("Invalid {}: {:?}", key, value));
+        match key {
+            "offset" => page.offset = parsed()?,
+            "limit" => page.limit = parsed()?,
+            "since" | "until" => times.push((key, value)),
+            "tz" => page.zone = Some(value.parse()?),
+            _ => {}
+        }
+    }
+    for (key, value) in times {
+        let time = page.zone.unwrap_or_default().parse(&value, now_ms).map_err(|e| e.to_string())?;
+        match key {
+            "since" => page.since = Some(time),
+            _ => page.until = Some(time),
+        }
+    }
+    if page.limit > MAX_PAGE {
+        return Err(format!("The limit is at most {}", MAX_PAGE));
+    }
+    Ok(page)
+}
+
+// "%2B02%3A00" and "2024-01-01+12:00" as they were typed; `+` stands for a space.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' if let Some(byte) = value.get(index + 1..index + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) => {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
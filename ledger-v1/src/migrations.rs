@@ -0,0 +1,98 @@
+use std::error::Error;
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::blockchain::Blockchain;
+use crate::store::{BlockStore, TreeId};
+use tracing::{info, warn};
+
+// What is stored, and how. Bump it whenever a release changes that, and add the step
+// upgrading the previous version to `migrate_schema`.
+pub const SCHEMA_VERSION: u32 = 9;
+
+// Databases written before the key existed count as version 0.
+const SCHEMA_KEY: &str = "SCHEMA";
+
+impl<S: BlockStore> Blockchain<S> {
+    pub fn schema_version(&self) -> Result<u32, Box<dyn Error>> {
+        match self.trees.get(TreeId::Blocks, SCHEMA_KEY.as_bytes())? {
+            Some(bytes) => Ok(u32::from_be_bytes(bytes.as_slice().try_into()?)),
+            None => Ok(0),
+        }
+    }
+
+    // Runs on open. Each step is recorded as soon as it finishes, so an interrupted
+    // upgrade carries on from there next time. Databases written by a newer release
+    // are refused before anything reads them.
+    pub(crate) fn migrate_schema(&self) -> Result<(), Box<dyn Error>> {
+        let mut version = self.schema_version()?;
+        if version > SCHEMA_VERSION {
+            return Err(format!(
+                "The database has schema version {}, but this build only understands up to version {}. Upgrade ledger-v1 to open it.",
+                version, SCHEMA_VERSION
+            ).into());
+        }
+        while version < SCHEMA_VERSION {
+            let next = version + 1;
+            match next {
+                // 1: the fork-tracking index (block meta, heights, tips).
+                1 => {
+                    if self.trees.store.is_empty(TreeId::Meta)? {
+                        self.reindex()?;
+                    }
+                    // Nothing further can be built on a chain with a broken link.
+                    if self.trees.store.is_empty(TreeId::Meta)? {
+                        warn!("chain could not be indexed, leaving the database at schema version 0");
+                        return Ok(());
+                    }
+                }
+                // 2: blocks in the binary encoding instead of JSON.
+                2 => {
+                    self.migrate_encoding()?;
+                }
+                // 3: account state.
+                3 => self.rebuild_state_if_needed()?,
+                // 4: per-address transaction history.
+                4 => self.build_history_if_needed()?,
+                // 5: txid -> block index.
+                5 => self.build_transaction_index_if_needed()?,
+                // 6: headers stored apart from the block bodies.
+                6 => {
+                    self.build_headers()?;
+                }
+                // 7: the state tree behind state roots.
+                7 => self.build_state_tree()?,
+                // 8: header records sealed on encrypted chains.
+                8 => {
+                    self.seal_headers()?;
+                }
+                // 9: lock accounts marked once paid out.
+                9 => self.mark_spent_locks()?,
+                _ => unreachable!("no migration to schema version {}", next),
+            }
+            self.trees.store.insert(TreeId::Blocks, SCHEMA_KEY.as_bytes(), next.to_be_bytes().to_vec())?;
+            self.trees.store.flush()?;
+            info!(version = next, "database migrated");
+            version = next;
+        }
+        Ok(())
+    }
+
+    // For read-only opens, which cannot migrate.
+    pub(crate) fn check_schema(&self) -> Result<(), Box<dyn Error>> {
+        let version = self.schema_version()?;
+        if version != SCHEMA_VERSION {
+            return Err(format!(
+                "The database has schema version {}, but this build reads version {}. Open it read-write once to migrate it.",
+                version, SCHEMA_VERSION
+            ).into());
+        }
+        Ok(())
+    }
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    // New databases start out current.
+    pub(crate) fn mark_schema_current(&mut self) {
+        self.insert(TreeId::Blocks, SCHEMA_KEY, SCHEMA_VERSION.to_be_bytes());
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::hashing::HEADER_V2;
+use crate::pow::meets_difficulty;
+use crate::store::BlockStore;
+
+// Nonces a worker tries between checks of the stop condition.
+const CHECK_EVERY: u64 = 1 << 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MiningStats {
+    pub hashes: u64,
+    pub elapsed: Duration,
+    pub threads: usize,
+}
+
+impl MiningStats {
+    // Hashes per second over the whole run.
+    pub fn hash_rate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 { self.hashes as f64 / seconds } else { 0.0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MiningOutcome {
+    // The block's nonce and hash now meet its difficulty.
+    Found(MiningStats),
+    // The stop condition fired first; the block is unchanged.
+    Stopped(MiningStats),
+}
+
+// Proof-of-work search spread over several threads. Worker `i` of `n` tries the
+// nonces i, i + n, i + 2n, ..., so no nonce is tried twice.
+#[derive(Debug, Clone, Copy)]
+pub struct Miner {
+    threads: usize,
+}
+
+impl Miner {
+    // 0 threads means one per available core.
+    pub fn new(threads: usize) -> Miner {
+        let threads = match threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n,
+        };
+        Miner { threads }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    // Searches for a nonce meeting the block's difficulty. `stop` is polled every few
+    // thousand hashes per worker, e.g. to give up when a competing block arrives.
+    pub fn mine(&self, block: &mut Block, stop: impl Fn() -> bool + Sync) -> MiningOutcome {
+        let started = Instant::now();
+        // Version 2 hashes commit to the header, so the merkle root and data hash are
+        // computed once instead of per nonce.
+        let header = (block.version >= HEADER_V2).then(|| block.header());
+        let found = AtomicBool::new(false);
+        let winner: Mutex<Option<(u64, String)>> = Mutex::new(None);
+        let hashes = AtomicU64::new(0);
+
+        thread::scope(|scope| {
+            for worker in 0..self.threads as u64 {
+                let (mut block, mut header) = (block.clone(), header.clone());
+                let (found, winner, hashes, stop) = (&found, &winner, &hashes, &stop);
+                let stride = self.threads as u64;
+                scope.spawn(move || {
+                    let mut nonce = worker;
+                    let mut tried = 0;
+                    while !found.load(Ordering::Relaxed) {
+                        let hash = match &mut header {
+                            Some(header) => {
+                                header.nonce = nonce;
+                                header.calculate_hash()
+                            }
+                            None => {
+                                block.nonce = nonce;
+                                block.calculate_hash()
+                            }
+                        };
+                        tried += 1;
+                        if meets_difficulty(&hash, block.difficulty) {
+                            if !found.swap(true, Ordering::Relaxed) {
+                                *winner.lock().unwrap() = Some((nonce, hash));
+                            }
+                            break;
+                        }
+                        if tried % CHECK_EVERY == 0 && stop() {
+                            break;
+                        }
+                        nonce = nonce.wrapping_add(stride);
+                    }
+                    hashes.fetch_add(tried, Ordering::Relaxed);
+                });
+            }
+        });
+
+        let stats = MiningStats { hashes: hashes.into_inner(), elapsed: started.elapsed(), threads: self.threads };
+        match winner.into_inner().unwrap() {
+            Some((nonce, hash)) => {
+                block.nonce = nonce;
+                block.hash = hash;
+                MiningOutcome::Found(stats)
+            }
+            None => MiningOutcome::Stopped(stats),
+        }
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Makes every block being mined by this node (through any handle) give up.
+    pub fn cancel_mining(&self) {
+        self.shared.mining_epoch.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // How the last block mined by this node went.
+    pub fn last_mining_stats(&self) -> Option<MiningStats> {
+        *self.shared.mining_stats.lock().unwrap()
+    }
+
+    pub(crate) fn miner(&self) -> Miner {
+        Miner::new(self.config.miner_threads)
+    }
+}
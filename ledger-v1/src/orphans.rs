@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::store::{BlockStore, TreeId};
+use tracing::debug;
+
+// Blocks that arrived before their parent, by hash. Kept in memory only, like the
+// mempool: a restarted node asks its peers again. Entries leave when their parent
+// arrives, when they outlive `Config::orphan_expiry_ms`, or to make room once the
+// pool holds `Config::max_orphans`, oldest first.
+#[derive(Default)]
+pub(crate) struct OrphanPool {
+    blocks: BTreeMap<String, Orphan>,
+}
+
+struct Orphan {
+    block: Block,
+    // When it was received, in milliseconds.
+    received: u64,
+}
+
+impl OrphanPool {
+    pub(crate) fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn insert(&mut self, block: Block, now: u64, max_orphans: usize, expiry_ms: u64) {
+        self.expire(now, expiry_ms);
+        while self.blocks.len() >= max_orphans {
+            let Some(oldest) = self.blocks.iter().min_by_key(|(_, orphan)| orphan.received).map(|(hash, _)| hash.clone()) else {
+                break;
+            };
+            debug!(block = %oldest, "orphan pool full, dropping the oldest block");
+            self.blocks.remove(&oldest);
+        }
+        self.blocks.insert(block.hash.clone(), Orphan { block, received: now });
+    }
+
+    fn expire(&mut self, now: u64, expiry_ms: u64) {
+        if expiry_ms > 0 {
+            self.blocks.retain(|_, orphan| now.saturating_sub(orphan.received) < expiry_ms);
+        }
+    }
+
+    // Removes and returns the orphans building on `parent`, oldest first.
+    fn take_children(&mut self, parent: &str) -> Vec<Block> {
+        let hashes: Vec<String> = self
+            .blocks
+            .iter()
+            .filter(|(_, orphan)| orphan.block.prev_hash == parent)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        let mut children: Vec<Orphan> = hashes.iter().filter_map(|hash| self.blocks.remove(hash)).collect();
+        children.sort_by_key(|orphan| orphan.received);
+        children.into_iter().map(|orphan| orphan.block).collect()
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Hashes of the blocks waiting for their parent.
+    pub fn orphans(&self) -> Vec<String> {
+        self.shared.orphans.lock().unwrap().blocks.keys().cloned().collect()
+    }
+
+    // Pools a block whose parent `batch` doesn't know. Returns false if the block must
+    // go through validation instead: it is known, has a bad hash, builds on an invalid
+    // block, belongs to another chain, or the pool is disabled.
+    pub(crate) fn hold_orphan(&self, batch: &ChainBatch<S>, block: &Block) -> Result<bool, Box<dyn Error>> {
+        if self.config.max_orphans == 0
+            || block.is_genesis()
+            || batch.block_meta(&block.prev_hash)?.is_some()
+            || batch.contains(TreeId::Blocks, block.hash.as_bytes())?
+            || batch.is_invalid(&block.prev_hash)?
+            || (!block.network_id.is_empty() && block.network_id != batch.genesis.network_id()?)
+            || block.hash != block.calculate_hash()
+        {
+            return Ok(false);
+        }
+        debug!(block = %block.hash, parent = %block.prev_hash, "parent unknown, holding block as an orphan");
+        self.shared.orphans.lock().unwrap().insert(
+            block.clone(),
+            self.clock.now_ms(),
+            self.config.max_orphans,
+            self.config.orphan_expiry_ms,
+        );
+        Ok(true)
+    }
+
+    // Orphans whose parent is now stored, and orphans of those in turn. Each is added
+    // on its own, so one that fails validation takes nothing else with it.
+    pub(crate) fn adopt_orphans(&self, mut parents: Vec<String>) {
+        while let Some(parent) = parents.pop() {
+            let children = {
+                let mut pool = self.shared.orphans.lock().unwrap();
+                pool.expire(self.clock.now_ms(), self.config.orphan_expiry_ms);
+                pool.take_children(&parent)
+            };
+            for child in children {
+                debug!(block = %child.hash, parent = %parent, "connecting orphan block");
+                // Rejections are counted and logged by `receive_batch`, which marks
+                // those breaking a consensus rule invalid.
+                if self.receive_batch(self.batch(), std::slice::from_ref(&child)).is_ok() {
+                    parents.push(child.hash);
+                }
+            }
+        }
+    }
+}
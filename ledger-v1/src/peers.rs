@@ -0,0 +1,212 @@
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::time::Duration;
+
+use crate::blockchain::Blockchain;
+use crate::store::{BlockStore, TreeId};
+use tracing::warn;
+
+// Peers this node has heard of: from the command line, from sync, and from other
+// peers sharing theirs (`GetPeers`). They live in the "peers" tree:
+// address -> JSON PeerRecord.
+//
+// A peer caught misbehaving loses score, and is banned for BAN_DURATION once its
+// score falls to BAN_SCORE. Banned peers are neither synced from nor shared.
+
+pub const BAN_SCORE: i32 = -100;
+pub const BAN_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_SCORE: i32 = 100;
+// Addresses learned from other peers stop being recorded past this many.
+const MAX_PEERS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerRecord {
+    // host:port of its gRPC server.
+    pub address: String,
+    pub score: i32,
+    // Milliseconds since the epoch, like the other times here.
+    pub added_at: u64,
+    pub last_seen: Option<u64>,
+    pub banned_until: Option<u64>,
+    pub ban_reason: Option<String>,
+}
+
+impl PeerRecord {
+    pub fn is_banned(&self) -> bool {
+        self.banned_until.is_some_and(|until| until > now())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Misbehavior {
+    // Sent a header or block that fails validation.
+    InvalidBlock,
+    // Claimed blocks or headers it then would not send.
+    Unresponsive,
+    // Flooded this node with requests or addresses.
+    Spam,
+    // Follows a chain with another chain ID.
+    WrongChain,
+}
+
+impl Misbehavior {
+    fn penalty(self) -> i32 {
+        match self {
+            Misbehavior::InvalidBlock => 100,
+            Misbehavior::Unresponsive => 10,
+            Misbehavior::Spam => 20,
+            Misbehavior::WrongChain => 100,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Misbehavior::InvalidBlock => "sent an invalid block",
+            Misbehavior::Unresponsive => "stopped responding",
+            Misbehavior::Spam => "spam",
+            Misbehavior::WrongChain => "is on another chain",
+        }
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Every known peer, best score first.
+    pub fn peers(&self) -> Result<Vec<PeerRecord>, Box<dyn Error>> {
+        let mut peers = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::Peers, &[]) {
+            let (_, bytes) = entry?;
+            peers.push(serde_json::from_slice::<PeerRecord>(&bytes)?);
+        }
+        peers.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.address.cmp(&b.address)));
+        Ok(peers)
+    }
+
+    // Addresses of the peers that are not banned, best first.
+    pub fn good_peers(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.peers()?.into_iter().filter(|peer| !peer.is_banned()).map(|peer| peer.address).collect())
+    }
+
+    pub fn peer(&self, address: &str) -> Result<Option<PeerRecord>, Box<dyn Error>> {
+        match self.store().get(TreeId::Peers, normalize_address(address).as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn is_banned(&self, address: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.peer(address)?.is_some_and(|peer| peer.is_banned()))
+    }
+
+    // Records `address` if it is new. Returns whether it was.
+    pub fn add_peer(&self, address: &str) -> Result<bool, Box<dyn Error>> {
+        let address = normalize_address(address);
+        check_address(&address)?;
+        if self.peer(&address)?.is_some() {
+            return Ok(false);
+        }
+        self.save_peer(&PeerRecord {
+            address,
+            score: 0,
+            added_at: now(),
+            last_seen: None,
+            banned_until: None,
+            ban_reason: None,
+        })?;
+        Ok(true)
+    }
+
+    // Records addresses shared by another peer, skipping malformed ones and stopping
+    // once MAX_PEERS are known. Returns how many were new.
+    pub fn learn_peers(&self, addresses: &[String]) -> Result<usize, Box<dyn Error>> {
+        let mut known = self.store().scan_prefix(TreeId::Peers, &[]).count();
+        let mut added = 0;
+        for address in addresses {
+            if known >= MAX_PEERS {
+                break;
+            }
+            if check_address(&normalize_address(address)).is_err() {
+                continue;
+            }
+            if self.add_peer(address)? {
+                known += 1;
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    // Notes a successful exchange with the peer, which slowly earns back score.
+    pub fn peer_seen(&self, address: &str) -> Result<(), Box<dyn Error>> {
+        self.add_peer(address)?;
+        let mut peer = self.peer(address)?.ok_or("Peer record vanished")?;
+        peer.last_seen = Some(now());
+        peer.score = (peer.score + 1).min(MAX_SCORE);
+        self.save_peer(&peer)
+    }
+
+    // Lowers the peer's score, banning it once the score reaches BAN_SCORE. Returns
+    // whether it is now banned.
+    pub fn penalize_peer(&self, address: &str, misbehavior: Misbehavior) -> Result<bool, Box<dyn Error>> {
+        self.add_peer(address)?;
+        let mut peer = self.peer(address)?.ok_or("Peer record vanished")?;
+        peer.score = (peer.score - misbehavior.penalty()).max(BAN_SCORE);
+        if peer.score <= BAN_SCORE && !peer.is_banned() {
+            warn!(peer = %peer.address, reason = misbehavior.describe(), "banning peer");
+            peer.banned_until = Some(now() + BAN_DURATION.as_millis() as u64);
+            peer.ban_reason = Some(misbehavior.describe().to_string());
+        }
+        let banned = peer.is_banned();
+        self.save_peer(&peer)?;
+        Ok(banned)
+    }
+
+    pub fn ban_peer(&self, address: &str, duration: Duration, reason: &str) -> Result<(), Box<dyn Error>> {
+        self.add_peer(address)?;
+        let mut peer = self.peer(address)?.ok_or("Peer record vanished")?;
+        peer.banned_until = Some(now() + duration.as_millis() as u64);
+        peer.ban_reason = Some(reason.to_string());
+        self.save_peer(&peer)
+    }
+
+    // Lifts a ban and resets the peer's score.
+    pub fn unban_peer(&self, address: &str) -> Result<(), Box<dyn Error>> {
+        let mut peer = self.peer(address)?.ok_or_else(|| format!("Unknown peer {}", address))?;
+        peer.banned_until = None;
+        peer.ban_reason = None;
+        peer.score = 0;
+        self.save_peer(&peer)
+    }
+
+    fn save_peer(&self, peer: &PeerRecord) -> Result<(), Box<dyn Error>> {
+        self.store().insert(TreeId::Peers, peer.address.as_bytes(), serde_json::to_vec(peer)?)?;
+        self.store().flush()
+    }
+}
+
+// Peers are keyed by host:port, whatever scheme they were given with.
+pub(crate) fn normalize_address(address: &str) -> String {
+    let address = address.trim();
+    let address = address.split_once("://").map_or(address, |(_, rest)| rest);
+    address.trim_end_matches('/').to_string()
+}
+
+fn check_address(address: &str) -> Result<(), Box<dyn Error>> {
+    let valid = match address.rsplit_once(':') {
+        Some((host, port)) => {
+            !host.is_empty()
+                && port.parse::<u16>().is_ok()
+                && address.len() <= 255
+                && !address.contains(|c: char| c.is_whitespace() || c == '/')
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(format!("'{}' is not a peer address (expected host:port)", address).into());
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
@@ -0,0 +1,131 @@
+use std::error::Error;
+
+use crate::approval;
+use crate::authority;
+use crate::block::Block;
+use crate::genesis::{Consensus, GenesisConfig};
+use crate::header::BlockHeader;
+
+// Proof of work: a block's hash must start with `difficulty` zero bits, found by
+// trying nonces. Difficulty 0 accepts any hash, which is what chains without mining use.
+
+pub fn leading_zero_bits(hash: &str) -> u32 {
+    let mut bits = 0;
+    for c in hash.chars() {
+        match c.to_digit(16) {
+            Some(0) => bits += 4,
+            Some(nibble) => return bits + nibble.leading_zeros() - 28,
+            None => break,
+        }
+    }
+    bits
+}
+
+pub fn meets_difficulty(hash: &str, difficulty: u32) -> bool {
+    leading_zero_bits(hash) >= difficulty
+}
+
+// Searches nonces until the block's hash meets its difficulty.
+pub fn mine(block: &mut Block) {
+    block.nonce = 0;
+    block.hash = block.calculate_hash();
+    while !meets_difficulty(&block.hash, block.difficulty) {
+        block.nonce += 1;
+        block.hash = block.calculate_hash();
+    }
+}
+
+// Difficulty a block at `height` on top of `parent` must carry. It stays constant
+// except every `retarget_interval` blocks, where it moves one bit towards keeping
+// blocks `target_block_time_ms` apart: up if the last window was produced in less
+// than half the target time, down if it took more than twice as long. The window
+// never includes the genesis block, whose timestamp comes from the config.
+// `load_header` looks up the ancestors in the window.
+pub(crate) fn expected_difficulty(
+    genesis: &GenesisConfig,
+    parent: &BlockHeader,
+    height: u64,
+    load_header: impl Fn(&str) -> Result<Option<BlockHeader>, Box<dyn Error>>,
+) -> Result<u32, Box<dyn Error>> {
+    if height <= 1 {
+        return Ok(genesis.difficulty);
+    }
+    let interval = genesis.retarget_interval;
+    if interval == 0 || !height.is_multiple_of(interval) {
+        return Ok(parent.difficulty);
+    }
+
+    // Walk back from the parent (height - 1) to the first block of the window.
+    let first_height = height.saturating_sub(interval).max(1);
+    let mut first = parent.clone();
+    for _ in first_height..height - 1 {
+        first = load_header(&first.prev_hash)?
+            .ok_or_else(|| format!("Broken link! Could not find block: {}", first.prev_hash))?;
+    }
+
+    let gaps = height - 1 - first_height;
+    if gaps == 0 {
+        return Ok(parent.difficulty);
+    }
+    let elapsed = parent.timestamp.saturating_sub(first.timestamp);
+    let expected = gaps * genesis.target_block_time_ms;
+    Ok(if elapsed.saturating_mul(2) < expected {
+        parent.difficulty + 1
+    } else if elapsed > expected.saturating_mul(2) {
+        parent.difficulty.saturating_sub(1)
+    } else {
+        parent.difficulty
+    })
+}
+
+// Checks the difficulty a header claims and that its hash meets it. The hash must
+// also use the chain's algorithm, so nobody can switch to a cheaper one. On
+// proof-of-authority chains the scheduled authority must have signed it, and on
+// chains with approvers enough of them must have approved it.
+pub(crate) fn check_work(
+    genesis: &GenesisConfig,
+    header: &BlockHeader,
+    parent: &BlockHeader,
+    height: u64,
+    load_header: impl Fn(&str) -> Result<Option<BlockHeader>, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    check_difficulty(genesis, header, parent, height, load_header)?;
+    check_seal(genesis, header, height)
+}
+
+// The part of `check_work` that needs the ancestors: the difficulty claimed.
+pub(crate) fn check_difficulty(
+    genesis: &GenesisConfig,
+    header: &BlockHeader,
+    parent: &BlockHeader,
+    height: u64,
+    load_header: impl Fn(&str) -> Result<Option<BlockHeader>, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let expected = expected_difficulty(genesis, parent, height, load_header)?;
+    if header.difficulty != expected {
+        return Err(format!(
+            "Block {} claims difficulty {} but height {} requires {}",
+            header.hash, header.difficulty, height, expected
+        ).into());
+    }
+    Ok(())
+}
+
+// The part of `check_work` the header holds on its own: the algorithm, that the
+// hash meets the difficulty, and the signatures. Headers can be checked this way
+// in any order, on any thread.
+pub(crate) fn check_seal(genesis: &GenesisConfig, header: &BlockHeader, height: u64) -> Result<(), Box<dyn Error>> {
+    if header.hash_algorithm != genesis.hash_algorithm {
+        return Err(format!(
+            "Block {} is hashed with {} but the chain uses {}",
+            header.hash, header.hash_algorithm.name(), genesis.hash_algorithm.name()
+        ).into());
+    }
+    if !meets_difficulty(&header.hash, header.difficulty) {
+        return Err(format!("Block {} does not meet its difficulty {}", header.hash, header.difficulty).into());
+    }
+    if let Consensus::ProofOfAuthority { authorities } = &genesis.consensus {
+        authority::check_signature(authorities, header, height)?;
+    }
+    approval::check_approvals(genesis, header)
+}
@@ -0,0 +1,91 @@
+use std::error::Error;
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::store::{BlockStore, TreeId};
+use tracing::{info, instrument};
+
+impl<S: BlockStore> Blockchain<S> {
+    // Drops the bodies of canonical blocks more than `keep` blocks below the tip.
+    // Headers, the state and the undo records needed for reorgs are kept, and the
+    // genesis block and held blocks (see `retention`) are never pruned. Returns the
+    // number of blocks pruned.
+    #[instrument(skip_all)]
+    pub fn prune(&self, keep: u64) -> Result<usize, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        self.prune_blocks(keep)
+    }
+
+    fn prune_blocks(&self, keep: u64) -> Result<usize, Box<dyn Error>> {
+        let tip_height = self.height()?;
+        if tip_height <= keep {
+            return Ok(0);
+        }
+        let last = tip_height - keep;
+
+        let holds = self.hold_index()?;
+        let first = self.pruned_to()?;
+        let mut batch = self.batch();
+        let mut pruned = 0;
+        let mut held = 0;
+        for height in first..=last {
+            let block = self.canonical_block(height)?;
+            if self.is_pruned(&block.hash)? {
+                continue;
+            }
+            if !holds.is_empty() && holds.holding(&block.hash, block.timestamp, block.txids()).is_some() {
+                held += 1;
+                continue;
+            }
+            prune_block(&mut batch, block)?;
+            pruned += 1;
+        }
+        // Blocks passed over before, whose holds have lapsed since.
+        for block in self.lapsed_holds(&holds, first)? {
+            prune_block(&mut batch, block)?;
+            pruned += 1;
+        }
+        for target in holds.untagged() {
+            batch.remove(TreeId::Holds, target.to_string());
+        }
+        batch.insert(TreeId::Blocks, "PRUNED_TO", (last + 1).to_be_bytes());
+        batch.commit()?;
+        self.store().flush()?;
+        if held > 0 {
+            info!(held, "held blocks kept whole");
+        }
+        Ok(pruned)
+    }
+
+    pub fn is_pruned(&self, hash: &str) -> Result<bool, Box<dyn Error>> {
+        self.trees.is_pruned(hash)
+    }
+
+    // Called whenever the tip moves.
+    pub(crate) fn maybe_prune(&self) -> Result<(), Box<dyn Error>> {
+        if self.config.prune_depth > 0 {
+            self.prune_blocks(self.config.prune_depth)?;
+        }
+        Ok(())
+    }
+
+    // First height that has not been considered for pruning yet. Held blocks below it
+    // are looked at again on every pass.
+    fn pruned_to(&self) -> Result<u64, Box<dyn Error>> {
+        match self.trees.get(TreeId::Blocks, b"PRUNED_TO")? {
+            Some(bytes) => Ok(u64::from_be_bytes(bytes.as_slice().try_into()?)),
+            None => Ok(1),
+        }
+    }
+}
+
+// The header record stays, so its data hash and merkle root are still known once
+// they can no longer be derived from the block.
+fn prune_block<S: BlockStore>(batch: &mut ChainBatch<S>, mut block: Block) -> Result<(), Box<dyn Error>> {
+    block.data.clear();
+    block.transactions.clear();
+    batch.store_block_record(&block)?;
+    batch.insert(TreeId::Pruned, &block.hash, []);
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+#[cfg(any(feature = "grpc", feature = "websocket"))]
+use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+use crate::blockchain::Blockchain;
+use crate::store::BlockStore;
+use tracing::debug;
+
+// Per-address request budgets shared by the gRPC, WebSocket and metrics servers. Each
+// address gets a bucket of `Config::rate_limit_per_minute` requests that refills
+// evenly over a minute, so a client may burst up to the limit but not sustain more.
+const WINDOW_MS: u64 = 60_000;
+// The most addresses tracked at once. A new one beyond it takes the place of the
+// address heard from least recently, whose bucket is the likeliest to be full again.
+pub const MAX_TRACKED: usize = 10_000;
+
+#[derive(Default)]
+pub(crate) struct RateLimiter {
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    // (refilled, ip) of every bucket, oldest first.
+    by_age: BTreeSet<(u64, IpAddr)>,
+}
+
+struct Bucket {
+    // Requests left, in thousandths so the refill doesn't round away.
+    tokens: u64,
+    // When the bucket was last refilled, in milliseconds.
+    refilled: u64,
+}
+
+impl RateLimiter {
+    fn allow(&self, ip: IpAddr, now: u64, per_minute: u32) -> bool {
+        let capacity = per_minute as u64 * 1000;
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_sub(bucket.refilled).min(WINDOW_MS);
+            (bucket.tokens + elapsed * capacity / WINDOW_MS).min(capacity)
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { by_ip, by_age } = &mut *buckets;
+        if !by_ip.contains_key(&ip)
+            && by_ip.len() >= MAX_TRACKED
+            && let Some((_, oldest)) = by_age.pop_first()
+        {
+            by_ip.remove(&oldest);
+        }
+        let bucket = by_ip.entry(ip).or_insert(Bucket { tokens: capacity, refilled: now });
+        by_age.remove(&(bucket.refilled, ip));
+        by_age.insert((now, ip));
+        bucket.tokens = refill(bucket);
+        bucket.refilled = now;
+        if bucket.tokens < 1000 {
+            return false;
+        }
+        bucket.tokens -= 1000;
+        true
+    }
+
+    pub(crate) fn tracked(&self) -> usize {
+        self.buckets.lock().unwrap().by_ip.len()
+    }
+}
+
+// A place under one of the servers' caps on work under way, such as
+// `Config::max_websocket_clients`, given back on drop.
+#[cfg(any(feature = "grpc", feature = "websocket"))]
+pub(crate) struct Slot(Arc<AtomicUsize>);
+
+#[cfg(any(feature = "grpc", feature = "websocket"))]
+impl Slot {
+    // None when `limit` places are taken already; 0 lifts the limit.
+    pub(crate) fn claim(taken: &Arc<AtomicUsize>, limit: usize) -> Option<Slot> {
+        taken
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| (limit == 0 || count < limit).then_some(count + 1))
+            .ok()
+            .map(|_| Slot(taken.clone()))
+    }
+}
+
+#[cfg(any(feature = "grpc", feature = "websocket"))]
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Takes one request from `ip`'s budget. False once it is spent; always true when
+    // `Config::rate_limit_per_minute` is 0. For embedders serving the chain over
+    // surfaces of their own.
+    pub fn allow_request(&self, ip: IpAddr) -> bool {
+        let per_minute = self.config.rate_limit_per_minute;
+        if per_minute == 0 || self.shared.rate_limiter.allow(ip, self.clock.now_ms(), per_minute) {
+            return true;
+        }
+        debug!(%ip, "rate limit exceeded");
+        false
+    }
+}
@@ -0,0 +1,77 @@
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::genesis::GenesisConfig;
+use crate::store::{self, SledStore, TreeId};
+
+// A sled database can hold named chains next to its default one, e.g. one ledger
+// per tenant. Each keeps its records in trees of its own (see
+// `SledStore::for_chain`), and this tree lists them: name -> JSON ChainInfo.
+const REGISTRY_TREE: &str = "chains";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChainInfo {
+    pub name: String,
+    // Milliseconds since the epoch.
+    pub created_at: u64,
+}
+
+impl Blockchain {
+    // Opens the chain `name` in `db`, creating and registering it on first use.
+    pub fn open_chain(db: &sled::Db, name: &str) -> Result<Blockchain, Box<dyn Error>> {
+        Self::open_chain_with_config(db, name, None, Config::default())
+    }
+
+    // Like `open_with_config`, for a named chain.
+    pub fn open_chain_with_config(
+        db: &sled::Db,
+        name: &str,
+        expected_genesis: Option<&GenesisConfig>,
+        config: Config,
+    ) -> Result<Blockchain, Box<dyn Error>> {
+        let chain = Self::open_store(SledStore::for_chain(db.clone(), name)?, expected_genesis, config)?;
+        let registry = db.open_tree(REGISTRY_TREE)?;
+        if !registry.contains_key(name)? {
+            let info = ChainInfo { name: name.to_string(), created_at: Utc::now().timestamp_millis() as u64 };
+            registry.insert(name, serde_json::to_vec(&info)?)?;
+            db.flush()?;
+        }
+        Ok(chain)
+    }
+}
+
+// The named chains in `db`, by name. The default chain is not listed.
+pub fn list_chains(db: &sled::Db) -> Result<Vec<ChainInfo>, Box<dyn Error>> {
+    let mut chains = Vec::new();
+    for entry in db.open_tree(REGISTRY_TREE)?.iter() {
+        let (_, bytes) = entry?;
+        chains.push(serde_json::from_slice(&bytes)?);
+    }
+    Ok(chains)
+}
+
+// Deletes the chain `name` and all its records. Handles still open on it keep
+// working on the dropped trees, so close them first.
+pub fn remove_chain(db: &sled::Db, name: &str) -> Result<(), Box<dyn Error>> {
+    let registry = db.open_tree(REGISTRY_TREE)?;
+    if !registry.contains_key(name)? {
+        return Err(format!("No chain named {}", name).into());
+    }
+    for id in TreeId::ALL {
+        db.drop_tree(store::chain_tree_name(name, id))?;
+    }
+    registry.remove(name)?;
+    db.flush()?;
+    Ok(())
+}
+
+// Names end up in tree names, so keep them to letters, digits, '-' and '_'.
+pub(crate) fn check_name(name: &str) -> Result<(), Box<dyn Error>> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid chain name {:?}: use letters, digits, '-' and '_'", name).into());
+    }
+    Ok(())
+}
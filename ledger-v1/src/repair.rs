@@ -0,0 +1,106 @@
+use std::error::Error;
+
+use crate::batch::ReadTrees;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::store::{BlockStore, TreeId};
+use tracing::instrument;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepairReport {
+    // The block the chain is (or would be) truncated to.
+    pub last_valid: String,
+    pub height: u64,
+    // Canonical blocks above it, tip first. Their records, where they still exist,
+    // are moved to the "quarantine" tree.
+    pub quarantined: Vec<String>,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Walks back from the tip to find the longest intact prefix of the canonical chain
+    // and truncates the chain to it. Everything above the first damaged block is set
+    // aside, including intact blocks, since they build on it. With `dry_run` only the
+    // report is produced.
+    #[instrument(skip_all)]
+    pub fn repair(&self, dry_run: bool) -> Result<RepairReport, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        let tip = self.current_hash();
+        let tip_height = match self.block_meta(&tip)? {
+            Some(meta) => meta.height,
+            None => self.last_indexed_height()?,
+        };
+
+        // Tip first. A block that decodes leads on through its prev_hash; past one that
+        // doesn't, the height index does.
+        let mut walked = Vec::new();
+        let mut next = Some(tip.clone());
+        for height in (0..=tip_height).rev() {
+            let hash = match next.take() {
+                Some(hash) => hash,
+                None => self
+                    .canonical_hash(height)?
+                    .ok_or_else(|| format!("Cannot repair: nothing indexed at height {}", height))?,
+            };
+            let block = self.load_block(&hash).ok().flatten();
+            let intact = match &block {
+                Some(block) => self.is_intact(block, &hash, height)?,
+                None => false,
+            };
+            walked.push((hash, intact));
+            next = block.map(|block| block.prev_hash);
+        }
+
+        let Some(first_damaged) = walked.iter().rposition(|(_, intact)| !intact) else {
+            return Ok(RepairReport { last_valid: tip, height: tip_height, quarantined: Vec::new() });
+        };
+        if first_damaged == walked.len() - 1 {
+            return Err("Cannot repair: the genesis block is damaged".into());
+        }
+        let report = RepairReport {
+            last_valid: walked[first_damaged + 1].0.clone(),
+            height: tip_height - first_damaged as u64 - 1,
+            quarantined: walked[..=first_damaged].iter().map(|(hash, _)| hash.clone()).collect(),
+        };
+        if dry_run {
+            return Ok(report);
+        }
+
+        // Newest first, so the undo records put the state back block by block.
+        let mut batch = self.batch();
+        batch.operation = Some("repair");
+        for (offset, hash) in report.quarantined.iter().enumerate() {
+            batch.revert_state_changes(hash)?;
+            if let Ok(Some(block)) = batch.load_block(hash) {
+                batch.unindex_block(&block);
+                batch.forget_history(&block, tip_height - offset as u64);
+                batch.unindex_transactions(&block, tip_height - offset as u64);
+            }
+            batch.remove(TreeId::Heights, (tip_height - offset as u64).to_be_bytes());
+            if let Some(bytes) = batch.get(TreeId::Blocks, hash.as_bytes())? {
+                batch.insert(TreeId::Quarantine, hash, bytes);
+                batch.remove(TreeId::Blocks, hash);
+            }
+            batch.remove(TreeId::Headers, hash);
+            batch.remove(TreeId::Meta, hash);
+            batch.remove(TreeId::Tips, hash);
+        }
+        batch.insert(TreeId::Tips, &report.last_valid, []);
+        batch.set_tip(&report.last_valid);
+        self.commit(batch)?;
+        Ok(report)
+    }
+
+    // Same checks as `is_chain_valid`, for the block stored under `key`.
+    fn is_intact(&self, block: &Block, key: &str, height: u64) -> Result<bool, Box<dyn Error>> {
+        let hash_matches = self.is_pruned(key)? || block.hash == block.calculate_hash();
+        Ok(block.hash == key && hash_matches && block.is_genesis() == (height == 0))
+    }
+
+    fn last_indexed_height(&self) -> Result<u64, Box<dyn Error>> {
+        let (key, _) = self
+            .store()
+            .last(TreeId::Heights)?
+            .ok_or("Cannot repair: the chain is not indexed")?;
+        Ok(u64::from_be_bytes(key.as_slice().try_into()?))
+    }
+}
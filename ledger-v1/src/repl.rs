@@ -0,0 +1,277 @@
+use ratatui::crossterm::cursor::MoveToColumn;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::queue;
+use ratatui::crossterm::style::Print;
+use ratatui::crossterm::terminal::{self, Clear, ClearType};
+use std::error::Error;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use ledger_v1::{Blockchain, TimeZone};
+
+// The `repl` subcommand: the chain stays open while commands are typed at a prompt,
+// each run like its subcommand. Lines can be edited, Up and Down walk the history
+// (kept in ~/.ledger_history across sessions), and Tab completes command names and
+// the block hashes `get` takes. Without a terminal the commands are read from
+// stdin, one per line, so a script can pipe them in.
+
+const PROMPT: &str = "ledger> ";
+const HISTORY_FILE: &str = ".ledger_history";
+const MAX_HISTORY: usize = 1000;
+
+const COMMANDS: &[(&str, &str)] = &[
+    ("add", "add DATA          append a block holding DATA"),
+    ("get", "get HASH|HEIGHT   show a block"),
+    ("print", "print [FROM] [N]  list N canonical blocks from height FROM, all by default"),
+    ("validate", "validate          check the integrity of the chain"),
+    ("stats", "stats             show block, transaction and storage statistics"),
+    ("search", "search TEXT       find blocks and transactions mentioning TEXT"),
+    ("history", "history           list the commands typed so far"),
+    ("help", "help              list the commands"),
+    ("exit", "exit              leave, as Ctrl-D does"),
+];
+
+pub fn run(chain: &Blockchain, zone: TimeZone) -> Result<(), Box<dyn Error>> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        for line in std::io::stdin().lock().lines() {
+            if !execute(chain, zone, &line?, &[])? {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut editor = Editor::load();
+    println!("Chain at height {}; type help for the commands.", chain.height()?);
+    while let Some(line) = editor.read_line(|before| complete(chain, before))? {
+        let line = line.trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        if editor.history.last() != Some(&line) {
+            editor.history.push(line.clone());
+        }
+        if !execute(chain, zone, &line, &editor.history)? {
+            break;
+        }
+    }
+    editor.save();
+    Ok(())
+}
+
+// Runs one line. A failing command only prints its error; false means leave.
+fn execute(chain: &Blockchain, zone: TimeZone, line: &str, history: &[String]) -> Result<bool, Box<dyn Error>> {
+    let line = line.trim();
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let result = match command {
+        "" => Ok(()),
+        "exit" | "quit" => return Ok(false),
+        "help" => {
+            for (_, usage) in COMMANDS {
+                println!("{}", usage);
+            }
+            Ok(())
+        }
+        "history" => {
+            for (index, line) in history.iter().enumerate() {
+                println!("{:>5}  {}", index + 1, line);
+            }
+            Ok(())
+        }
+        "add" => chain.add_block(rest).map(|hash| println!("Added block {}", hash)),
+        "get" => get(chain, rest),
+        "print" => print(chain, zone, rest),
+        "validate" => chain.is_chain_valid().map(|valid| match valid {
+            true => println!("Chain valid."),
+            false => println!("Integrity check failed"),
+        }),
+        "stats" => chain.stats().map(|stats| crate::print_stats(&stats)),
+        "search" if rest.is_empty() => Err("search needs the TEXT to look for".into()),
+        "search" => chain.search(rest).map(|hits| crate::print_hits(&hits)),
+        other => Err(format!("Unknown command '{}'; type help for the commands", other).into()),
+    };
+    if let Err(e) = result {
+        println!("Error: {}", e);
+    }
+    std::io::stdout().flush()?;
+    Ok(true)
+}
+
+fn get(chain: &Blockchain, target: &str) -> Result<(), Box<dyn Error>> {
+    let hash = match target.parse::<u64>() {
+        Ok(height) => chain.canonical_hash(height)?.ok_or_else(|| format!("No canonical block at height {}", height))?,
+        Err(_) if target.is_empty() => return Err("get needs a block hash or height".into()),
+        Err(_) => target.to_string(),
+    };
+    let block = chain.get_block(&hash)?.ok_or_else(|| format!("Unknown block {}", hash))?;
+    println!("{}", serde_json::to_string_pretty(&block)?);
+    Ok(())
+}
+
+fn print(chain: &Blockchain, zone: TimeZone, args: &str) -> Result<(), Box<dyn Error>> {
+    let mut numbers = args.split_whitespace().map(|arg| arg.parse::<u64>().map_err(|_| format!("Not a number: {}", arg)));
+    let offset = numbers.next().transpose()?.unwrap_or(0);
+    let limit = numbers.next().transpose()?;
+    crate::print_blocks(&chain.list_blocks(offset, limit)?, zone);
+    Ok(())
+}
+
+// Candidates for the word the cursor is at the end of, given the line before it.
+fn complete(chain: &Blockchain, before: &str) -> Vec<String> {
+    let words: Vec<&str> = before.split_whitespace().collect();
+    let typed = if before.ends_with(char::is_whitespace) { "" } else { words.last().copied().unwrap_or_default() };
+    let position = if typed.is_empty() { words.len() } else { words.len() - 1 };
+    match (position, words.first()) {
+        (0, _) => COMMANDS.iter().map(|(name, _)| name.to_string()).filter(|name| name.starts_with(typed)).collect(),
+        (1, Some(&"get")) if !typed.is_empty() => {
+            let tip = chain.height().unwrap_or(0);
+            (0..=tip)
+                .rev()
+                .filter_map(|height| chain.canonical_hash(height).ok().flatten())
+                .filter(|hash| hash.starts_with(typed))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+struct Editor {
+    history: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl Editor {
+    fn load() -> Editor {
+        let path = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        let history = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Editor { history, path }
+    }
+
+    // Losing the history is not worth failing over.
+    fn save(&self) {
+        if let Some(path) = &self.path {
+            let start = self.history.len().saturating_sub(MAX_HISTORY);
+            let _ = std::fs::write(path, self.history[start..].iter().map(|line| format!("{}\n", line)).collect::<String>());
+        }
+    }
+
+    // One line from the terminal, None once Ctrl-D is pressed on an empty line. The
+    // terminal is raw only while the line is edited, so commands print as usual.
+    fn read_line(&mut self, complete: impl Fn(&str) -> Vec<String>) -> Result<Option<String>, Box<dyn Error>> {
+        terminal::enable_raw_mode()?;
+        let line = self.edit(complete);
+        terminal::disable_raw_mode()?;
+        line
+    }
+
+    fn edit(&mut self, complete: impl Fn(&str) -> Vec<String>) -> Result<Option<String>, Box<dyn Error>> {
+        let mut out = std::io::stdout();
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Which history entry is shown; `history.len()` is the line being typed,
+        // kept in `draft` while older ones are looked at.
+        let mut recalled = self.history.len();
+        let mut draft = Vec::new();
+        loop {
+            redraw(&mut out, &line, cursor)?;
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Enter => {
+                    write!(out, "\r\n")?;
+                    return Ok(Some(line.into_iter().collect()));
+                }
+                KeyCode::Char('d') if ctrl && line.is_empty() => {
+                    write!(out, "\r\n")?;
+                    return Ok(None);
+                }
+                KeyCode::Char('c') if ctrl => {
+                    write!(out, "^C\r\n")?;
+                    line.clear();
+                    cursor = 0;
+                    recalled = self.history.len();
+                }
+                KeyCode::Char('a') if ctrl => cursor = 0,
+                KeyCode::Char('e') if ctrl => cursor = line.len(),
+                KeyCode::Char('u') if ctrl => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                KeyCode::Char(c) if !ctrl => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                KeyCode::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                KeyCode::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                KeyCode::Left if cursor > 0 => cursor -= 1,
+                KeyCode::Right if cursor < line.len() => cursor += 1,
+                KeyCode::Home => cursor = 0,
+                KeyCode::End => cursor = line.len(),
+                KeyCode::Up if recalled > 0 => {
+                    if recalled == self.history.len() {
+                        draft = line;
+                    }
+                    recalled -= 1;
+                    line = self.history[recalled].chars().collect();
+                    cursor = line.len();
+                }
+                KeyCode::Down if recalled < self.history.len() => {
+                    recalled += 1;
+                    line = match self.history.get(recalled) {
+                        Some(entry) => entry.chars().collect(),
+                        None => draft.clone(),
+                    };
+                    cursor = line.len();
+                }
+                KeyCode::Tab => {
+                    let before: String = line[..cursor].iter().collect();
+                    let typed = before.rsplit(char::is_whitespace).next().unwrap_or_default().chars().count();
+                    let candidates = complete(&before);
+                    let mut completion: Vec<char> = common_prefix(&candidates).chars().skip(typed).collect();
+                    if candidates.len() == 1 {
+                        completion.push(' ');
+                    } else if completion.is_empty() && candidates.len() > 1 {
+                        write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+                    }
+                    let added = completion.len();
+                    line.splice(cursor..cursor, completion);
+                    cursor += added;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn redraw(out: &mut impl Write, line: &[char], cursor: usize) -> Result<(), Box<dyn Error>> {
+    let text: String = line.iter().collect();
+    queue!(out, MoveToColumn(0), Clear(ClearType::CurrentLine), Print(PROMPT), Print(text))?;
+    queue!(out, MoveToColumn((PROMPT.len() + cursor) as u16))?;
+    out.flush()?;
+    Ok(())
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut prefix = first.as_str();
+    for candidate in &candidates[1..] {
+        let shared = prefix.chars().zip(candidate.chars()).take_while(|(a, b)| a == b).map(|(c, _)| c.len_utf8()).sum();
+        prefix = &prefix[..shared];
+    }
+    prefix.to_string()
+}
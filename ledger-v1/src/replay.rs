@@ -0,0 +1,161 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::state::Account;
+use crate::store::{BlockStore, TreeId, Writes};
+use tracing::{info, instrument};
+
+// Replays re-apply the canonical chain, block by block, to the state as it stood
+// below the first one, and hand each block with what it changed to a visitor. An
+// indexer outside the node (SQL tables, its own search index) builds its projection
+// from them; the same heights always give the same blocks and changes, unless a
+// reorg replaces them, which a replay stops at rather than skip over.
+//
+// Named replays keep where they got to in the "replays" tree, as a token of the last
+// block visited, so a restarted indexer resumes after it. The token is saved after
+// each block but flushed when the replay ends: after a crash the last few blocks may
+// be visited again, so visitors should overwrite what they wrote for a height rather
+// than add to it.
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReplayToken {
+    pub height: u64,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayedBlock {
+    pub height: u64,
+    pub block: Block,
+    // Accounts the block touched: address -> (before, after). None before means
+    // the account did not exist yet.
+    pub changes: BTreeMap<String, (Option<Account>, Account)>,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Visits the canonical blocks from `from_height` to `to_height`, both included.
+    // Returns the token of the last. Fails at a block whose body was pruned, and at
+    // whatever the visitor fails with.
+    #[instrument(skip(self, visitor))]
+    pub fn replay(
+        &self,
+        from_height: u64,
+        to_height: u64,
+        visitor: impl FnMut(&ReplayedBlock) -> Result<(), Box<dyn Error>>,
+    ) -> Result<ReplayToken, Box<dyn Error>> {
+        if from_height > to_height {
+            return Err(format!("Cannot replay from height {} down to {}", from_height, to_height).into());
+        }
+        self.replay_blocks(from_height, to_height, None, visitor, |_| Ok(()))
+    }
+
+    // Continues the replay called `name` up to `to_height`, from after the block it
+    // last visited, or from genesis the first time. Returns where it got to, which
+    // is also saved. Fails if a reorg has since replaced that block: the projection
+    // then holds blocks no longer canonical, and must be rebuilt after
+    // `reset_replay`.
+    #[instrument(skip(self, visitor))]
+    pub fn resume_replay(
+        &self,
+        name: &str,
+        to_height: u64,
+        visitor: impl FnMut(&ReplayedBlock) -> Result<(), Box<dyn Error>>,
+    ) -> Result<Option<ReplayToken>, Box<dyn Error>> {
+        let saved = self.replay_token(name)?;
+        let from_height = saved.as_ref().map_or(0, |token| token.height + 1);
+        if from_height > to_height {
+            return Ok(saved);
+        }
+        let result = self.replay_blocks(from_height, to_height, saved.as_ref(), visitor, |token| {
+            self.store().insert(TreeId::Replays, name.as_bytes(), serde_json::to_vec(token)?)
+        });
+        self.store().flush()?;
+        let token = result?;
+        info!(name, height = token.height, "replay saved");
+        Ok(Some(token))
+    }
+
+    // The last block the replay called `name` visited, if it has run.
+    pub fn replay_token(&self, name: &str) -> Result<Option<ReplayToken>, Box<dyn Error>> {
+        match self.store().get(TreeId::Replays, name.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Forgets where `name` got to, so it starts over from genesis.
+    pub fn reset_replay(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        self.store().apply(&Writes::from([((TreeId::Replays, name.as_bytes().to_vec()), None)]))?;
+        self.store().flush()
+    }
+
+    // `after` is the block just below `from_height` a resumed replay visited last.
+    // `saved` is called with each block's token once the visitor is done with it.
+    fn replay_blocks(
+        &self,
+        from_height: u64,
+        to_height: u64,
+        after: Option<&ReplayToken>,
+        mut visitor: impl FnMut(&ReplayedBlock) -> Result<(), Box<dyn Error>>,
+        mut saved: impl FnMut(&ReplayToken) -> Result<(), Box<dyn Error>>,
+    ) -> Result<ReplayToken, Box<dyn Error>> {
+        // The starting state and the block it belongs to are read together, so no
+        // block can connect in between.
+        let (state, mut parent) = {
+            let _writer = self.write_lock();
+            let tip = self.height()?;
+            if to_height > tip {
+                return Err(format!("Height {} is above the tip at {}", to_height, tip).into());
+            }
+            match from_height.checked_sub(1) {
+                Some(below) => {
+                    let hash = self.canonical_hash(below)?.ok_or_else(|| format!("No canonical block at height {}", below))?;
+                    if let Some(after) = after
+                        && after.hash != hash
+                    {
+                        return Err(format!(
+                            "Block {} at height {}, where the replay stopped, is no longer canonical; reset it and replay again",
+                            after.hash, after.height
+                        ).into());
+                    }
+                    (self.rolled_back_state(below)?, Some(hash))
+                }
+                None => (BTreeMap::new(), None),
+            }
+        };
+
+        // Re-applied in a batch that is never committed, holding the state as of
+        // the block before.
+        let mut batch = self.batch();
+        batch.clear(TreeId::State)?;
+        for (address, account) in &state {
+            batch.insert(TreeId::State, address, serde_json::to_vec(account)?);
+        }
+        drop(state);
+
+        let mut last = None;
+        for height in from_height..=to_height {
+            let hash = self.canonical_hash(height)?.ok_or_else(|| format!("No canonical block at height {}", height))?;
+            let block = self.load_block(&hash)?.ok_or_else(|| format!("Broken link! Could not find block: {}", hash))?;
+            if parent.as_ref().is_some_and(|parent| *parent != block.prev_hash) {
+                return Err(format!("The chain reorganized at height {} during the replay", height).into());
+            }
+            if self.is_pruned(&hash)? {
+                return Err(format!("Block {} at height {} was pruned; a replay needs its body", hash, height).into());
+            }
+            let changes = batch.state_changes(&block, height)?;
+            for (address, (_, after)) in &changes {
+                batch.insert(TreeId::State, address, serde_json::to_vec(after)?);
+            }
+            visitor(&ReplayedBlock { height, block, changes })?;
+            let token = ReplayToken { height, hash: hash.clone() };
+            saved(&token)?;
+            last = Some(token);
+            parent = Some(hash);
+        }
+        Ok(last.expect("the range holds at least one height"))
+    }
+}
@@ -0,0 +1,89 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::backup::{self, BackupInfo};
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::store::{BlockStore, SledStore};
+
+// sled lets one process at a time open a database, so nothing can read the database
+// of a running node, not even read-only. A node with `Config::read_replica` keeps a
+// copy of it there instead, refreshed every `read_replica_interval` blocks, which
+// `open_replica` reads while the node goes on writing (one reader at a time, as with
+// any sled database).
+//
+// Each refresh is a backup into the next numbered directory under the replica path.
+// Only once it is complete does the CURRENT file, replaced in one rename, point
+// readers at it; the generation before it stays for readers still opening that one.
+const CURRENT: &str = "CURRENT";
+
+impl<S: BlockStore> Blockchain<S> {
+    // Copies the chain into a new generation at `dir`. Like `backup`, but the caller
+    // holds the write lock, as for the rest of `tip_moved`.
+    pub(crate) fn write_replica(&self, dir: &Path) -> Result<BackupInfo, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let next = current_generation(dir)?.map_or(1, |generation| generation + 1);
+        let target = dir.join(next.to_string());
+        // Left by a refresh that was cut short.
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        let records = {
+            let store = SledStore::open(path_str(&target)?)?;
+            let records = backup::copy_records(self.store(), &store)?;
+            store.flush()?;
+            records
+        };
+        let staged = dir.join(format!("{}.tmp", CURRENT));
+        fs::write(&staged, next.to_string())?;
+        fs::rename(&staged, dir.join(CURRENT))?;
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let Some(generation) = entry.file_name().to_str().and_then(|name| name.parse::<u64>().ok()) else { continue };
+            if generation + 1 < next
+                && let Err(e) = fs::remove_dir_all(entry.path())
+            {
+                warn!(generation, error = %e, "cannot remove an old read replica");
+            }
+        }
+        Ok(BackupInfo { height: self.height()?, tip: self.current_hash(), records })
+    }
+
+    // Called whenever the tip moves.
+    pub(crate) fn maybe_refresh_replica(&self) -> Result<(), Box<dyn Error>> {
+        let interval = self.config.read_replica_interval;
+        if let Some(dir) = &self.config.read_replica
+            && interval > 0
+            && self.height()? % interval == 0
+        {
+            let info = self.write_replica(dir)?;
+            info!(height = info.height, records = info.records, "read replica refreshed");
+        }
+        Ok(())
+    }
+}
+
+impl Blockchain {
+    // The newest complete generation of the replica at `dir`, read-only.
+    pub fn open_replica(dir: &Path, config: Config) -> Result<Blockchain, Box<dyn Error>> {
+        let generation = current_generation(dir)?.ok_or_else(|| format!("No read replica at {}", dir.display()))?;
+        Blockchain::open_read_only_with_config(path_str(&dir.join(generation.to_string()))?, config)
+    }
+}
+
+fn current_generation(dir: &Path) -> Result<Option<u64>, Box<dyn Error>> {
+    match fs::read_to_string(dir.join(CURRENT)) {
+        Ok(generation) => Ok(Some(
+            generation.trim().parse().map_err(|_| format!("Damaged read replica pointer in {}", dir.display()))?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str, Box<dyn Error>> {
+    path.to_str().ok_or_else(|| format!("{} is not valid UTF-8", path.display()).into())
+}
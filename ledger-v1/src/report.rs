@@ -0,0 +1,287 @@
+use serde::Serialize;
+use std::error::Error;
+use std::io::Write;
+
+use crate::batch::ReadTrees;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::export::ExportFormat;
+use crate::replay::ReplayToken;
+use crate::script;
+use crate::store::{BlockStore, TreeId};
+use crate::transaction::Transaction;
+use tracing::{info, instrument};
+
+// Reporting exports lay the canonical chain out as tables for finance tooling,
+// rather than as blocks to import again:
+//
+//   sql     a SQL script for `sqlite3 report.db < report.sql`, creating a "blocks"
+//           and a "transactions" table with their indexes. It runs as one
+//           transaction and first deletes the heights it covers, so a dump can be
+//           loaded again, or on top of an earlier one.
+//   tx-csv  one row per transaction, with the columns of its block, for
+//           spreadsheets.
+//
+// Named exports are incremental: each writes only the blocks after those the last
+// export under the name covered, whose block is kept in the "reports" tree, apart
+// from the tokens of named replays (see `replay`), so the two never share one.
+// Amounts and other counts are u64, but SQLite integers are i64: a SQL export fails
+// at a value above i64::MAX rather than let SQLite store it as an inexact REAL.
+
+const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS blocks (
+  height INTEGER PRIMARY KEY,
+  hash TEXT NOT NULL UNIQUE,
+  prev_hash TEXT NOT NULL,
+  timestamp INTEGER NOT NULL,
+  version INTEGER NOT NULL,
+  sequence INTEGER NOT NULL,
+  difficulty INTEGER NOT NULL,
+  nonce INTEGER NOT NULL,
+  content_type TEXT,
+  data BLOB NOT NULL,
+  transaction_count INTEGER NOT NULL,
+  state_root TEXT NOT NULL,
+  signature TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS transactions (
+  height INTEGER NOT NULL REFERENCES blocks (height),
+  position INTEGER NOT NULL,
+  txid TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  sender TEXT,
+  recipient TEXT,
+  amount INTEGER,
+  fee INTEGER NOT NULL,
+  nonce INTEGER,
+  lock TEXT,
+  PRIMARY KEY (height, position)
+);
+CREATE INDEX IF NOT EXISTS blocks_timestamp ON blocks (timestamp);
+CREATE INDEX IF NOT EXISTS transactions_txid ON transactions (txid);
+CREATE INDEX IF NOT EXISTS transactions_sender ON transactions (sender);
+CREATE INDEX IF NOT EXISTS transactions_recipient ON transactions (recipient);
+";
+
+// A transaction as the reports show it. Unlocks pay out what their lock holds, so
+// their amount is left out; the lock's address is their sender.
+#[derive(Serialize)]
+struct TransactionRow {
+    height: u64,
+    block_hash: String,
+    timestamp: u64,
+    position: usize,
+    txid: String,
+    kind: &'static str,
+    sender: Option<String>,
+    recipient: Option<String>,
+    amount: Option<u64>,
+    fee: u64,
+    nonce: Option<u64>,
+    lock: Option<String>,
+}
+
+impl TransactionRow {
+    fn new(height: u64, block: &Block, position: usize, transaction: &Transaction) -> TransactionRow {
+        let (kind, sender, recipient, amount, lock) = match transaction {
+            Transaction::Transfer { from, to, amount, .. } => ("transfer", Some(from.clone()), Some(to.clone()), Some(*amount), None),
+            Transaction::Coinbase { to, amount, .. } => ("coinbase", None, Some(to.clone()), Some(*amount), None),
+            Transaction::Lock { from, amount, .. } => {
+                ("lock", Some(from.clone()), Some(script::lock_address(&transaction.hash())), Some(*amount), None)
+            }
+            Transaction::Unlock { lock, to, .. } => {
+                ("unlock", Some(script::lock_address(lock)), Some(to.clone()), None, Some(lock.clone()))
+            }
+        };
+        TransactionRow {
+            height,
+            block_hash: block.hash.clone(),
+            timestamp: block.timestamp,
+            position,
+            txid: transaction.hash(),
+            kind,
+            sender,
+            recipient,
+            amount,
+            fee: transaction.fee(),
+            nonce: transaction.nonce(),
+            lock,
+        }
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Writes a reporting export (`ExportFormat::Sql` or `TxCsv`) of the canonical
+    // chain, or with `name`, of the blocks added since the last export under that
+    // name. Returns the range of heights written, None if there was nothing new.
+    // After a reorg below the last named export, a SQL script starts again where
+    // the chains part; a CSV has rows that cannot be taken back, and fails.
+    #[instrument(skip(self, writer))]
+    pub fn export_report<W: Write>(
+        &self,
+        mut writer: W,
+        format: ExportFormat,
+        name: Option<&str>,
+    ) -> Result<Option<(u64, u64)>, Box<dyn Error>> {
+        if !matches!(format, ExportFormat::Sql | ExportFormat::TxCsv) {
+            return Err("Reports are written as sql or tx-csv".into());
+        }
+        let saved = name.map(|name| self.report_token(name)).transpose()?.flatten();
+        let from_height = match &saved {
+            None => 0,
+            Some(token) if self.canonical_hash(token.height)?.as_deref() == Some(token.hash.as_str()) => token.height + 1,
+            Some(token) if format == ExportFormat::Sql => self.fork_height(token)? + 1,
+            Some(token) => {
+                return Err(format!(
+                    "Block {} at height {}, where the last export stopped, is no longer canonical; export again from scratch",
+                    token.hash, token.height
+                ).into());
+            }
+        };
+        let to_height = self.height()?;
+
+        let last = if format == ExportFormat::Sql {
+            match from_height <= to_height {
+                true => writeln!(writer, "-- Chain {} from height {} to {}", self.network_id()?, from_height, to_height)?,
+                false => writeln!(writer, "-- Chain {}: no blocks above height {}", self.network_id()?, to_height)?,
+            }
+            writeln!(writer, "BEGIN;")?;
+            writer.write_all(SCHEMA.as_bytes())?;
+            writeln!(writer, "DELETE FROM transactions WHERE height >= {};", from_height)?;
+            writeln!(writer, "DELETE FROM blocks WHERE height >= {};", from_height)?;
+            let last = self.replay_rows(from_height, to_height, |height, block, rows| {
+                write_block_row(&mut writer, height, block)?;
+                for row in rows {
+                    write_transaction_row(&mut writer, &row)?;
+                }
+                Ok(())
+            })?;
+            writeln!(writer, "COMMIT;")?;
+            writer.flush()?;
+            last
+        } else {
+            // Rows added to an earlier export come without the header again.
+            let mut csv = csv::WriterBuilder::new().has_headers(from_height == 0).from_writer(writer);
+            let last = self.replay_rows(from_height, to_height, |_, _, rows| {
+                for row in rows {
+                    csv.serialize(row)?;
+                }
+                Ok(())
+            })?;
+            csv.flush()?;
+            last
+        };
+
+        let Some(last) = last else {
+            return Ok(None);
+        };
+        if let Some(name) = name {
+            self.store().insert(TreeId::Reports, name.as_bytes(), serde_json::to_vec(&last)?)?;
+            self.store().flush()?;
+        }
+        info!(from_height, to_height, "report exported");
+        Ok(Some((from_height, to_height)))
+    }
+
+    // The last block the report called `name` covered, if it has been exported.
+    pub fn report_token(&self, name: &str) -> Result<Option<ReplayToken>, Box<dyn Error>> {
+        match self.store().get(TreeId::Reports, name.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Each block from `from_height` to `to_height` with the rows of its transactions,
+    // if the range holds any.
+    fn replay_rows(
+        &self,
+        from_height: u64,
+        to_height: u64,
+        mut write: impl FnMut(u64, &Block, Vec<TransactionRow>) -> Result<(), Box<dyn Error>>,
+    ) -> Result<Option<ReplayToken>, Box<dyn Error>> {
+        if from_height > to_height {
+            return Ok(None);
+        }
+        let last = self.replay(from_height, to_height, |replayed| {
+            let block = &replayed.block;
+            let rows = block
+                .transactions
+                .iter()
+                .enumerate()
+                .map(|(position, transaction)| TransactionRow::new(replayed.height, block, position, transaction))
+                .collect();
+            write(replayed.height, block, rows)
+        })?;
+        Ok(Some(last))
+    }
+
+    // Highest height the branch ending at `token` shares with the canonical chain.
+    fn fork_height(&self, token: &ReplayToken) -> Result<u64, Box<dyn Error>> {
+        let mut hash = token.hash.clone();
+        loop {
+            let header = self.trees.load_header(&hash)?.ok_or_else(|| {
+                format!("Block {}, where the last export stopped, is gone; export again from scratch", token.hash)
+            })?;
+            let height = self.block_meta(&hash)?.ok_or_else(|| format!("No metadata for block {}", hash))?.height;
+            if self.canonical_hash(height)?.as_deref() == Some(hash.as_str()) {
+                return Ok(height);
+            }
+            hash = header.prev_hash;
+        }
+    }
+}
+
+fn write_block_row(writer: &mut impl Write, height: u64, block: &Block) -> Result<(), Box<dyn Error>> {
+    let checked = |column: &str, value: u64| integer(&format!("Block {}", block.hash), column, value);
+    writeln!(
+        writer,
+        "INSERT INTO blocks VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, X'{}', {}, {}, {});",
+        checked("height", height)?,
+        text(&block.hash),
+        text(&block.prev_hash),
+        checked("timestamp", block.timestamp)?,
+        block.version,
+        checked("sequence", block.sequence)?,
+        block.difficulty,
+        checked("nonce", block.nonce)?,
+        block.content_type.as_deref().map_or_else(|| "NULL".to_string(), text),
+        hex::encode(&block.data),
+        block.transactions.len(),
+        text(&block.state_root),
+        text(&block.signature),
+    )?;
+    Ok(())
+}
+
+fn write_transaction_row(writer: &mut impl Write, row: &TransactionRow) -> Result<(), Box<dyn Error>> {
+    let checked = |column: &str, value: u64| integer(&format!("Transaction {}", row.txid), column, value);
+    let optional = |column: &str, value: Option<u64>| value.map_or_else(|| Ok("NULL".to_string()), |value| checked(column, value));
+    writeln!(
+        writer,
+        "INSERT INTO transactions VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {});",
+        checked("height", row.height)?,
+        row.position,
+        text(&row.txid),
+        text(row.kind),
+        row.sender.as_deref().map_or_else(|| "NULL".to_string(), text),
+        row.recipient.as_deref().map_or_else(|| "NULL".to_string(), text),
+        optional("amount", row.amount)?,
+        checked("fee", row.fee)?,
+        optional("nonce", row.nonce)?,
+        row.lock.as_deref().map_or_else(|| "NULL".to_string(), text),
+    )?;
+    Ok(())
+}
+
+// A SQL integer literal, or an error naming `what` if SQLite cannot hold the value.
+fn integer(what: &str, column: &str, value: u64) -> Result<String, Box<dyn Error>> {
+    match i64::try_from(value) {
+        Ok(value) => Ok(value.to_string()),
+        Err(_) => Err(format!("{} has {} {}, above the largest SQLite integer", what, column, value).into()),
+    }
+}
+
+// A SQL string literal.
+fn text(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
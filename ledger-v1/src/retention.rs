@@ -0,0 +1,229 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use tracing::info;
+
+use crate::batch::ReadTrees;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::store::{BlockStore, TreeId};
+
+// Retention classes and legal holds keep blocks from being pruned or garbage
+// collected. A block or transaction tagged with one of `Config::retention_classes` is
+// kept for the class's period, counted from the timestamp of the block holding it;
+// one under legal hold is kept until the hold is released, whatever its class.
+// Holding a transaction keeps the whole block it is in. `prune` and `gc` pass over
+// held blocks, and pruning takes them once nothing holds them any more.
+//
+// The tags are indexed in the holds tree, under "block:<hash>" or "tx:<txid>".
+
+// What a hold is on, written "block:<hash>" or "tx:<txid>".
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldTarget {
+    Block(String),
+    Transaction(String),
+}
+
+impl fmt::Display for HoldTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HoldTarget::Block(hash) => write!(f, "block:{}", hash),
+            HoldTarget::Transaction(txid) => write!(f, "tx:{}", txid),
+        }
+    }
+}
+
+impl FromStr for HoldTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("block", hash)) if !hash.is_empty() => Ok(HoldTarget::Block(hash.to_string())),
+            Some(("tx", txid)) if !txid.is_empty() => Ok(HoldTarget::Transaction(txid.to_string())),
+            _ => Err(format!("Unknown hold target '{}' (expected block:HASH or tx:TXID)", s)),
+        }
+    }
+}
+
+// The tags on one block or transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Hold {
+    pub target: HoldTarget,
+    pub retention_class: Option<String>,
+    pub legal_hold: Option<LegalHold>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LegalHold {
+    pub reason: String,
+    pub placed_ms: u64,
+}
+
+// The holds tree, read once for a whole prune or collection.
+pub(crate) struct HoldIndex {
+    holds: HashMap<HoldTarget, Hold>,
+    classes: BTreeMap<String, u64>,
+    now_ms: u64,
+}
+
+impl HoldIndex {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.holds.is_empty()
+    }
+
+    // Records whose tags were all removed; see `Blockchain::update_hold`.
+    pub(crate) fn untagged(&self) -> impl Iterator<Item = &HoldTarget> {
+        self.holds.values().filter(|hold| hold.retention_class.is_none() && hold.legal_hold.is_none()).map(|hold| &hold.target)
+    }
+
+    // A hold still in force on the block, or on one of its transactions.
+    pub(crate) fn holding(&self, hash: &str, timestamp: u64, txids: Vec<String>) -> Option<&Hold> {
+        let block = self.holds.get(&HoldTarget::Block(hash.to_string()));
+        block
+            .into_iter()
+            .chain(txids.into_iter().filter_map(|txid| self.holds.get(&HoldTarget::Transaction(txid))))
+            .find(|hold| self.in_force(hold, timestamp))
+    }
+
+    // A class missing from the config keeps what it tags rather than let it go.
+    fn in_force(&self, hold: &Hold, timestamp: u64) -> bool {
+        if hold.legal_hold.is_some() {
+            return true;
+        }
+        match hold.retention_class.as_ref().map(|class| self.classes.get(class)) {
+            None => false,
+            Some(None) | Some(Some(0)) => true,
+            Some(Some(period)) => timestamp.saturating_add(*period) > self.now_ms,
+        }
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Tags `target` with a class from `Config::retention_classes`, or clears its class
+    // with None.
+    pub fn set_retention_class(&self, target: &HoldTarget, class: Option<&str>) -> Result<Hold, Box<dyn Error>> {
+        if let Some(class) = class
+            && !self.config.retention_classes.contains_key(class)
+        {
+            return Err(format!("Unknown retention class '{}'; list it under retention_classes in the config", class).into());
+        }
+        self.update_hold(target, |hold| {
+            hold.retention_class = class.map(str::to_string);
+            Ok(())
+        })
+    }
+
+    pub fn place_legal_hold(&self, target: &HoldTarget, reason: &str) -> Result<Hold, Box<dyn Error>> {
+        let placed_ms = self.clock.now_ms();
+        self.update_hold(target, |hold| {
+            hold.legal_hold = Some(LegalHold { reason: reason.to_string(), placed_ms });
+            Ok(())
+        })
+    }
+
+    pub fn release_legal_hold(&self, target: &HoldTarget) -> Result<Hold, Box<dyn Error>> {
+        self.update_hold(target, |hold| match hold.legal_hold.take() {
+            Some(_) => Ok(()),
+            None => Err(format!("{} is not under legal hold", hold.target).into()),
+        })
+    }
+
+    // Applies `change` to the tags on `target`. A record left without tags stays until
+    // the next prune, which may have a block to take back that it once passed over.
+    fn update_hold(
+        &self,
+        target: &HoldTarget,
+        change: impl FnOnce(&mut Hold) -> Result<(), Box<dyn Error>>,
+    ) -> Result<Hold, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        if self.holding_block(target)?.is_none() {
+            return Err(match target {
+                HoldTarget::Block(hash) => format!("Unknown block {}", hash),
+                HoldTarget::Transaction(txid) => format!("Transaction {} is in no canonical block", txid),
+            }
+            .into());
+        }
+        let mut hold = self.hold(target)?.unwrap_or(Hold { target: target.clone(), retention_class: None, legal_hold: None });
+        change(&mut hold)?;
+        self.store().insert(TreeId::Holds, target.to_string().as_bytes(), rmp_serde::to_vec(&hold)?)?;
+        self.store().flush()?;
+        info!(target = %target, class = ?hold.retention_class, legal_hold = hold.legal_hold.is_some(), "hold updated");
+        Ok(hold)
+    }
+
+    // The tags on `target`, if it ever had any.
+    pub fn hold(&self, target: &HoldTarget) -> Result<Option<Hold>, Box<dyn Error>> {
+        self.store().get_with(TreeId::Holds, target.to_string().as_bytes(), |bytes| Ok(rmp_serde::from_slice(bytes)?))
+    }
+
+    // Everything whose tags keep it now: under legal hold, or within its retention
+    // period. Ordered by target.
+    pub fn holds(&self) -> Result<Vec<Hold>, Box<dyn Error>> {
+        let index = self.hold_index()?;
+        let mut holds = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::Holds, &[]) {
+            let (_, bytes) = entry?;
+            let hold: Hold = rmp_serde::from_slice(&bytes)?;
+            // A target whose block is gone is counted as held.
+            let timestamp = match self.holding_block(&hold.target)? {
+                Some(hash) => self.trees.load_header(&hash)?.map(|header| header.timestamp),
+                None => None,
+            };
+            if index.in_force(&hold, timestamp.unwrap_or(index.now_ms)) {
+                holds.push(hold);
+            }
+        }
+        Ok(holds)
+    }
+
+    pub(crate) fn hold_index(&self) -> Result<HoldIndex, Box<dyn Error>> {
+        let mut holds = HashMap::new();
+        for entry in self.store().scan_prefix(TreeId::Holds, &[]) {
+            let (_, bytes) = entry?;
+            let hold: Hold = rmp_serde::from_slice(&bytes)?;
+            holds.insert(hold.target.clone(), hold);
+        }
+        Ok(HoldIndex { holds, classes: self.config.retention_classes.clone(), now_ms: self.clock.now_ms() })
+    }
+
+    // The block `target` is in: the block itself if stored, or for a transaction the
+    // canonical block holding it.
+    fn holding_block(&self, target: &HoldTarget) -> Result<Option<String>, Box<dyn Error>> {
+        match target {
+            HoldTarget::Block(hash) => Ok(self.trees.block_meta(hash)?.map(|_| hash.clone())),
+            HoldTarget::Transaction(txid) => match self.store().scan_prefix(TreeId::Transactions, txid.as_bytes()).last() {
+                Some(found) => Ok(Some(String::from_utf8(found?.1)?)),
+                None => Ok(None),
+            },
+        }
+    }
+
+    // Canonical blocks below `below` that pruning passed over for a hold that has
+    // since been released or run out, and that nothing else holds.
+    pub(crate) fn lapsed_holds(&self, index: &HoldIndex, below: u64) -> Result<Vec<Block>, Box<dyn Error>> {
+        let mut lapsed = Vec::new();
+        for hold in index.holds.values() {
+            let Some(hash) = self.holding_block(&hold.target)? else { continue };
+            let Some(meta) = self.trees.block_meta(&hash)? else { continue };
+            if meta.height == 0 || meta.height >= below || self.trees.canonical_hash(meta.height)?.as_deref() != Some(hash.as_str()) {
+                continue;
+            }
+            if self.is_pruned(&hash)? || lapsed.iter().any(|block: &Block| block.hash == hash) {
+                continue;
+            }
+            // The hold's own target decides most cases without reading the body.
+            let timestamp = self.trees.load_header(&hash)?.map(|header| header.timestamp).unwrap_or(index.now_ms);
+            if index.in_force(hold, timestamp) {
+                continue;
+            }
+            let block = self.canonical_block(meta.height)?;
+            if index.holding(&block.hash, block.timestamp, block.txids()).is_none() {
+                lapsed.push(block);
+            }
+        }
+        Ok(lapsed)
+    }
+}
@@ -0,0 +1,498 @@
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::store::BlockStore;
+
+// Payload schemas: blocks added through a node with `Config::payload_schema` or
+// `Config::tag_schemas` must carry a JSON payload that satisfies them, so teams
+// writing to a shared ledger cannot slip in records the others cannot read. It is a
+// local rule, like the mempool limits: blocks from peers are accepted whatever their
+// payload, and `deep_validate` reports stored blocks that don't match when
+// `Config::deep_validate_schemas` is set.
+//
+// The schemas are JSON Schema, with the validation keywords of draft 2020-12 that
+// need nothing beyond the document itself: no "$ref", "if"/"then" or
+// "dependentRequired". A schema using those is refused when it is loaded rather than
+// checked more loosely than it says. "format" and the other annotations are ignored.
+
+// Keywords that only describe, and never reject anything.
+const ANNOTATIONS: [&str; 11] = [
+    "$schema", "$id", "$comment", "$anchor", "title", "description", "default", "examples", "format", "readOnly", "writeOnly",
+];
+
+pub struct Schema {
+    root: Node,
+}
+
+enum Node {
+    // `true` accepts anything, `false` nothing.
+    Bool(bool),
+    Rules(Vec<Rule>),
+}
+
+enum Rule {
+    Type(Vec<String>),
+    Enum(Vec<Value>),
+    Const(Value),
+    Properties(BTreeMap<String, Node>),
+    PatternProperties(Vec<(Regex, Node)>),
+    // Applies to the members no "properties" or "patternProperties" entry covers.
+    AdditionalProperties { named: HashSet<String>, patterns: Vec<Regex>, schema: Node },
+    Required(Vec<String>),
+    MinProperties(u64),
+    MaxProperties(u64),
+    Items(Node),
+    MinItems(u64),
+    MaxItems(u64),
+    UniqueItems,
+    MinLength(u64),
+    MaxLength(u64),
+    Pattern(Regex),
+    Minimum(f64),
+    Maximum(f64),
+    ExclusiveMinimum(f64),
+    ExclusiveMaximum(f64),
+    MultipleOf(f64),
+    AllOf(Vec<Node>),
+    AnyOf(Vec<Node>),
+    OneOf(Vec<Node>),
+    Not(Node),
+}
+
+impl Schema {
+    pub fn parse(schema: &Value) -> Result<Schema, Box<dyn Error>> {
+        Ok(Schema { root: Node::parse(schema, "")? })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Schema, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("Cannot read schema {}: {}", path.display(), e))?;
+        let schema: Value = serde_json::from_str(&contents).map_err(|e| format!("Schema {} is not JSON: {}", path.display(), e))?;
+        Schema::parse(&schema).map_err(|e| format!("Schema {}: {}", path.display(), e).into())
+    }
+
+    // The first place `instance` breaks the schema, as a JSON pointer and what is wrong.
+    pub fn validate(&self, instance: &Value) -> Result<(), Box<dyn Error>> {
+        self.root.validate(instance, "").map_err(|e| e.into())
+    }
+}
+
+impl Node {
+    fn parse(schema: &Value, at: &str) -> Result<Node, String> {
+        let object = match schema {
+            Value::Bool(accept) => return Ok(Node::Bool(*accept)),
+            Value::Object(object) => object,
+            _ => return Err(format!("{} must be an object or a boolean", describe(at))),
+        };
+        let mut rules = Vec::new();
+        for (keyword, value) in object {
+            let at = format!("{}/{}", at, keyword);
+            let rule = match keyword.as_str() {
+                keyword if ANNOTATIONS.contains(&keyword) => continue,
+                "type" => Rule::Type(parse_types(value, &at)?),
+                "enum" => Rule::Enum(value.as_array().cloned().ok_or_else(|| format!("{} must be an array", at))?),
+                "const" => Rule::Const(value.clone()),
+                "properties" => {
+                    let mut properties = BTreeMap::new();
+                    for (name, schema) in as_object(value, &at)? {
+                        properties.insert(name.clone(), Node::parse(schema, &format!("{}/{}", at, name))?);
+                    }
+                    Rule::Properties(properties)
+                }
+                "patternProperties" => {
+                    let mut patterns = Vec::new();
+                    for (pattern, schema) in as_object(value, &at)? {
+                        patterns.push((parse_regex(pattern, &at)?, Node::parse(schema, &format!("{}/{}", at, pattern))?));
+                    }
+                    Rule::PatternProperties(patterns)
+                }
+                "additionalProperties" => {
+                    let named = object.get("properties").and_then(Value::as_object).map(|p| p.keys().cloned().collect()).unwrap_or_default();
+                    let patterns = match object.get("patternProperties").and_then(Value::as_object) {
+                        Some(patterns) => patterns.keys().map(|pattern| parse_regex(pattern, &at)).collect::<Result<_, _>>()?,
+                        None => Vec::new(),
+                    };
+                    Rule::AdditionalProperties { named, patterns, schema: Node::parse(value, &at)? }
+                }
+                "required" => Rule::Required(parse_strings(value, &at)?),
+                "minProperties" => Rule::MinProperties(parse_count(value, &at)?),
+                "maxProperties" => Rule::MaxProperties(parse_count(value, &at)?),
+                "items" => Rule::Items(Node::parse(value, &at)?),
+                "minItems" => Rule::MinItems(parse_count(value, &at)?),
+                "maxItems" => Rule::MaxItems(parse_count(value, &at)?),
+                "uniqueItems" if value == &Value::Bool(true) => Rule::UniqueItems,
+                "uniqueItems" if value.is_boolean() => continue,
+                "minLength" => Rule::MinLength(parse_count(value, &at)?),
+                "maxLength" => Rule::MaxLength(parse_count(value, &at)?),
+                "pattern" => Rule::Pattern(parse_regex(value.as_str().ok_or_else(|| format!("{} must be a string", at))?, &at)?),
+                "minimum" => Rule::Minimum(parse_number(value, &at)?),
+                "maximum" => Rule::Maximum(parse_number(value, &at)?),
+                "exclusiveMinimum" => Rule::ExclusiveMinimum(parse_number(value, &at)?),
+                "exclusiveMaximum" => Rule::ExclusiveMaximum(parse_number(value, &at)?),
+                "multipleOf" => match parse_number(value, &at)? {
+                    divisor if divisor > 0.0 => Rule::MultipleOf(divisor),
+                    _ => return Err(format!("{} must be greater than 0", at)),
+                },
+                "allOf" => Rule::AllOf(parse_list(value, &at)?),
+                "anyOf" => Rule::AnyOf(parse_list(value, &at)?),
+                "oneOf" => Rule::OneOf(parse_list(value, &at)?),
+                "not" => Rule::Not(Node::parse(value, &at)?),
+                _ => return Err(format!("{} is not supported or not valid here", at)),
+            };
+            rules.push(rule);
+        }
+        Ok(Node::Rules(rules))
+    }
+
+    fn validate(&self, instance: &Value, at: &str) -> Result<(), String> {
+        let rules = match self {
+            Node::Bool(true) => return Ok(()),
+            Node::Bool(false) => return Err(format!("{}: nothing is allowed here", describe(at))),
+            Node::Rules(rules) => rules,
+        };
+        for rule in rules {
+            rule.validate(instance, at).map_err(|problem| match problem.starts_with('/') {
+                // Already placed by a nested schema.
+                true => problem,
+                false => format!("{}: {}", describe(at), problem),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn accepts(&self, instance: &Value, at: &str) -> bool {
+        self.validate(instance, at).is_ok()
+    }
+}
+
+impl Rule {
+    fn validate(&self, instance: &Value, at: &str) -> Result<(), String> {
+        match self {
+            Rule::Type(types) => {
+                if !types.iter().any(|kind| is_type(instance, kind)) {
+                    return Err(format!("expected {}, found {}", types.join(" or "), type_name(instance)));
+                }
+            }
+            Rule::Enum(values) => {
+                if !values.iter().any(|value| equal(value, instance)) {
+                    return Err(format!("{} is not one of the allowed values", instance));
+                }
+            }
+            Rule::Const(value) => {
+                if !equal(value, instance) {
+                    return Err(format!("expected {}", value));
+                }
+            }
+            Rule::Properties(properties) => {
+                if let Value::Object(members) = instance {
+                    for (name, schema) in properties {
+                        if let Some(member) = members.get(name) {
+                            schema.validate(member, &pointer(at, name))?;
+                        }
+                    }
+                }
+            }
+            Rule::PatternProperties(patterns) => {
+                if let Value::Object(members) = instance {
+                    for (name, member) in members {
+                        for (pattern, schema) in patterns {
+                            if pattern.is_match(name) {
+                                schema.validate(member, &pointer(at, name))?;
+                            }
+                        }
+                    }
+                }
+            }
+            Rule::AdditionalProperties { named, patterns, schema } => {
+                if let Value::Object(members) = instance {
+                    for (name, member) in members {
+                        if !named.contains(name) && !patterns.iter().any(|pattern| pattern.is_match(name)) {
+                            schema.validate(member, &pointer(at, name)).map_err(|problem| match schema {
+                                Node::Bool(false) => format!("{}: unexpected property {:?}", describe(at), name),
+                                _ => problem,
+                            })?;
+                        }
+                    }
+                }
+            }
+            Rule::Required(names) => {
+                if let Value::Object(members) = instance
+                    && let Some(missing) = names.iter().find(|name| !members.contains_key(*name))
+                {
+                    return Err(format!("missing required property {:?}", missing));
+                }
+            }
+            Rule::MinProperties(min) => {
+                if let Value::Object(members) = instance
+                    && (members.len() as u64) < *min
+                {
+                    return Err(format!("needs at least {} properties", min));
+                }
+            }
+            Rule::MaxProperties(max) => {
+                if let Value::Object(members) = instance
+                    && members.len() as u64 > *max
+                {
+                    return Err(format!("may have at most {} properties", max));
+                }
+            }
+            Rule::Items(schema) => {
+                if let Value::Array(items) = instance {
+                    for (index, item) in items.iter().enumerate() {
+                        schema.validate(item, &pointer(at, &index.to_string()))?;
+                    }
+                }
+            }
+            Rule::MinItems(min) => {
+                if let Value::Array(items) = instance
+                    && (items.len() as u64) < *min
+                {
+                    return Err(format!("needs at least {} items", min));
+                }
+            }
+            Rule::MaxItems(max) => {
+                if let Value::Array(items) = instance
+                    && items.len() as u64 > *max
+                {
+                    return Err(format!("may have at most {} items", max));
+                }
+            }
+            Rule::UniqueItems => {
+                if let Value::Array(items) = instance {
+                    for (index, item) in items.iter().enumerate() {
+                        if items[..index].iter().any(|earlier| equal(earlier, item)) {
+                            return Err(format!("item {} repeats an earlier one", index));
+                        }
+                    }
+                }
+            }
+            Rule::MinLength(min) => {
+                if let Value::String(text) = instance
+                    && (text.chars().count() as u64) < *min
+                {
+                    return Err(format!("must be at least {} characters long", min));
+                }
+            }
+            Rule::MaxLength(max) => {
+                if let Value::String(text) = instance
+                    && text.chars().count() as u64 > *max
+                {
+                    return Err(format!("must be at most {} characters long", max));
+                }
+            }
+            Rule::Pattern(pattern) => {
+                if let Value::String(text) = instance
+                    && !pattern.is_match(text)
+                {
+                    return Err(format!("{:?} does not match {:?}", text, pattern.as_str()));
+                }
+            }
+            Rule::Minimum(min) => {
+                if let Some(number) = instance.as_f64()
+                    && number < *min
+                {
+                    return Err(format!("{} is below the minimum of {}", instance, min));
+                }
+            }
+            Rule::Maximum(max) => {
+                if let Some(number) = instance.as_f64()
+                    && number > *max
+                {
+                    return Err(format!("{} is above the maximum of {}", instance, max));
+                }
+            }
+            Rule::ExclusiveMinimum(min) => {
+                if let Some(number) = instance.as_f64()
+                    && number <= *min
+                {
+                    return Err(format!("{} must be above {}", instance, min));
+                }
+            }
+            Rule::ExclusiveMaximum(max) => {
+                if let Some(number) = instance.as_f64()
+                    && number >= *max
+                {
+                    return Err(format!("{} must be below {}", instance, max));
+                }
+            }
+            Rule::MultipleOf(divisor) => {
+                if let Some(number) = instance.as_f64()
+                    && (number / divisor).fract() != 0.0
+                {
+                    return Err(format!("{} is not a multiple of {}", instance, divisor));
+                }
+            }
+            Rule::AllOf(schemas) => {
+                for schema in schemas {
+                    schema.validate(instance, at)?;
+                }
+            }
+            Rule::AnyOf(schemas) => {
+                if !schemas.iter().any(|schema| schema.accepts(instance, at)) {
+                    return Err("matches none of the \"anyOf\" schemas".to_string());
+                }
+            }
+            Rule::OneOf(schemas) => {
+                let matching = schemas.iter().filter(|schema| schema.accepts(instance, at)).count();
+                if matching != 1 {
+                    return Err(format!("matches {} of the \"oneOf\" schemas instead of exactly one", matching));
+                }
+            }
+            Rule::Not(schema) => {
+                if schema.accepts(instance, at) {
+                    return Err("matches the \"not\" schema".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The compiled `Config::payload_schema` and `Config::tag_schemas`.
+#[derive(Default)]
+pub(crate) struct PayloadSchemas {
+    all: Option<Schema>,
+    // By "key=value" metadata entry.
+    tagged: BTreeMap<String, Schema>,
+}
+
+impl PayloadSchemas {
+    pub(crate) fn from_config(config: &Config) -> Result<PayloadSchemas, Box<dyn Error>> {
+        let all = config.payload_schema.as_ref().map(Schema::load).transpose()?;
+        let mut tagged = BTreeMap::new();
+        for (tag, path) in &config.tag_schemas {
+            if !tag.contains('=') {
+                return Err(format!("Schema tag {:?} must be key=value", tag).into());
+            }
+            tagged.insert(tag.clone(), Schema::load(path)?);
+        }
+        Ok(PayloadSchemas { all, tagged })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.all.is_none() && self.tagged.is_empty()
+    }
+
+    // Checks a payload against every schema that applies to it.
+    pub(crate) fn check(&self, data: &[u8], metadata: &BTreeMap<String, String>) -> Result<(), Box<dyn Error>> {
+        let tagged = metadata.iter().filter_map(|(key, value)| {
+            let tag = format!("{}={}", key, value);
+            self.tagged.get(&tag).map(|schema| (Some(tag), schema))
+        });
+        let schemas: Vec<(Option<String>, &Schema)> = self.all.iter().map(|schema| (None, schema)).chain(tagged).collect();
+        if schemas.is_empty() {
+            return Ok(());
+        }
+        let payload: Value = serde_json::from_slice(data).map_err(|e| format!("The payload must be JSON to meet its schema: {}", e))?;
+        for (tag, schema) in schemas {
+            schema.validate(&payload).map_err(|e| match tag {
+                Some(tag) => format!("The payload does not meet the schema for {}: {}", tag, e),
+                None => format!("The payload does not meet the chain's schema: {}", e),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Whether a stored block breaks the payload schemas, for `deep_validate`. Genesis and
+    // pruned blocks have no payload to check.
+    pub(crate) fn check_stored_payload(&self, block: &Block, pruned: bool) -> Result<(), Box<dyn Error>> {
+        let schemas = &self.shared.schemas;
+        if !self.config.deep_validate_schemas || schemas.is_empty() || block.is_genesis() || pruned {
+            return Ok(());
+        }
+        schemas.check(&block.data, &block.metadata)
+    }
+}
+
+fn describe(at: &str) -> &str {
+    if at.is_empty() { "/" } else { at }
+}
+
+// `at` extended by one step, escaped as JSON pointers are.
+fn pointer(at: &str, step: &str) -> String {
+    format!("{}/{}", at, step.replace('~', "~0").replace('/', "~1"))
+}
+
+fn as_object<'a>(value: &'a Value, at: &str) -> Result<&'a Map<String, Value>, String> {
+    value.as_object().ok_or_else(|| format!("{} must be an object", at))
+}
+
+fn parse_types(value: &Value, at: &str) -> Result<Vec<String>, String> {
+    let types = match value {
+        Value::String(kind) => vec![kind.clone()],
+        _ => parse_strings(value, at)?,
+    };
+    const TYPES: [&str; 7] = ["null", "boolean", "object", "array", "number", "integer", "string"];
+    match types.iter().find(|kind| !TYPES.contains(&kind.as_str())) {
+        Some(kind) => Err(format!("{}: unknown type {:?}", at, kind)),
+        None => Ok(types),
+    }
+}
+
+fn parse_strings(value: &Value, at: &str) -> Result<Vec<String>, String> {
+    value
+        .as_array()
+        .and_then(|items| items.iter().map(|item| item.as_str().map(str::to_string)).collect())
+        .ok_or_else(|| format!("{} must be an array of strings", at))
+}
+
+fn parse_count(value: &Value, at: &str) -> Result<u64, String> {
+    value.as_u64().ok_or_else(|| format!("{} must be a non-negative integer", at))
+}
+
+fn parse_number(value: &Value, at: &str) -> Result<f64, String> {
+    value.as_f64().ok_or_else(|| format!("{} must be a number", at))
+}
+
+fn parse_list(value: &Value, at: &str) -> Result<Vec<Node>, String> {
+    let items = value.as_array().filter(|items| !items.is_empty()).ok_or_else(|| format!("{} must be a non-empty array", at))?;
+    items.iter().enumerate().map(|(index, item)| Node::parse(item, &format!("{}/{}", at, index))).collect()
+}
+
+fn parse_regex(pattern: &str, at: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("{}: invalid pattern {:?}: {}", at, pattern, e))
+}
+
+fn is_type(value: &Value, kind: &str) -> bool {
+    match kind {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|number| number.fract() == 0.0),
+        "string" => value.is_string(),
+        _ => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+    }
+}
+
+// JSON equality, under which 1 and 1.0 are the same number.
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x == y || x.as_f64() == y.as_f64(),
+        (Value::Array(x), Value::Array(y)) => x.len() == y.len() && x.iter().zip(y).all(|(x, y)| equal(x, y)),
+        (Value::Object(x), Value::Object(y)) => {
+            x.len() == y.len() && x.iter().all(|(key, value)| y.get(key).is_some_and(|other| equal(value, other)))
+        }
+        _ => a == b,
+    }
+}
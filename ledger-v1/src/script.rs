@@ -0,0 +1,223 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+use crate::signer::Signer;
+
+// Spend conditions. A `Transaction::Lock` moves funds from the sender into a lock
+// account (`lock_address` of the lock's txid) guarded by a `Condition`; only an
+// `Transaction::Unlock` whose witness satisfies the condition pays them out. With a
+// hash lock on one branch and a timelock on the other this gives escrow and atomic
+// swaps: the counterparty claims with the preimage, or the sender refunds later.
+
+// Nesting and size limits, so every condition is cheap to evaluate.
+pub const MAX_DEPTH: usize = 8;
+pub const MAX_KEYS: usize = 16;
+pub const MAX_BRANCHES: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    // `threshold` of the ed25519 public keys (hex) sign the unlock.
+    MultiSig { threshold: usize, keys: Vec<String> },
+    // The unlock's block is at `height` or above.
+    After { height: u64 },
+    // The witness reveals a preimage whose SHA-256 is `hash` (hex).
+    HashLock { hash: String },
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+// What an unlock presents against the condition. Signatures are over
+// `unlock_message`, in any order; preimages are hex.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Witness {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preimages: Vec<String>,
+}
+
+impl Witness {
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty() && self.preimages.is_empty()
+    }
+}
+
+// Where the unlock is being evaluated.
+pub struct Context<'a> {
+    pub height: u64,
+    // What signatures must cover, from `unlock_message`.
+    pub message: &'a [u8],
+}
+
+impl Condition {
+    // Refuses conditions that could never be met or are too large; checked when the
+    // funds are locked, so they cannot get stuck behind a malformed condition.
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        self.check_at(1)
+    }
+
+    fn check_at(&self, depth: usize) -> Result<(), Box<dyn Error>> {
+        if depth > MAX_DEPTH {
+            return Err(format!("Spend conditions nest at most {} deep", MAX_DEPTH).into());
+        }
+        match self {
+            Condition::MultiSig { threshold, keys } => {
+                if keys.is_empty() || keys.len() > MAX_KEYS {
+                    return Err(format!("A multi-sig condition needs 1 to {} keys", MAX_KEYS).into());
+                }
+                if *threshold == 0 || *threshold > keys.len() {
+                    return Err(format!("A multi-sig threshold of {} does not fit {} keys", threshold, keys.len()).into());
+                }
+                let mut seen = Vec::with_capacity(keys.len());
+                for key in keys {
+                    let parsed = verifying_key(key)?;
+                    // A small-order key has signatures anyone can forge.
+                    if parsed.is_weak() {
+                        return Err(format!("Key {} is a weak ed25519 public key", key).into());
+                    }
+                    let bytes = parsed.to_bytes();
+                    if seen.contains(&bytes) {
+                        return Err(format!("Key {} appears twice in a multi-sig condition", key).into());
+                    }
+                    seen.push(bytes);
+                }
+            }
+            Condition::After { .. } => {}
+            Condition::HashLock { hash } => {
+                if hex::decode(hash).map_or(true, |bytes| bytes.len() != 32) {
+                    return Err(format!("Hash lock {} is not a hex-encoded SHA-256 digest", hash).into());
+                }
+            }
+            Condition::All(branches) | Condition::Any(branches) => {
+                if branches.is_empty() || branches.len() > MAX_BRANCHES {
+                    return Err(format!("All and any conditions take 1 to {} branches", MAX_BRANCHES).into());
+                }
+                for branch in branches {
+                    branch.check_at(depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Whether `witness` meets the condition in `context`; the error says why not. A
+    // witness may carry no more signatures than the condition has keys, nor more
+    // preimages than it has hash locks, so it can't make the check expensive.
+    pub fn evaluate(&self, witness: &Witness, context: &Context) -> Result<(), Box<dyn Error>> {
+        let (keys, hash_locks) = self.counts();
+        if witness.signatures.len() > keys {
+            return Err(format!("The witness carries {} signatures for {} keys", witness.signatures.len(), keys).into());
+        }
+        if witness.preimages.len() > hash_locks {
+            return Err(format!("The witness carries {} preimages for {} hash locks", witness.preimages.len(), hash_locks).into());
+        }
+        self.evaluate_branch(witness, context)
+    }
+
+    // Keys and hash locks in the condition, whichever branch they are on.
+    fn counts(&self) -> (usize, usize) {
+        match self {
+            Condition::MultiSig { keys, .. } => (keys.len(), 0),
+            Condition::After { .. } => (0, 0),
+            Condition::HashLock { .. } => (0, 1),
+            Condition::All(branches) | Condition::Any(branches) => branches
+                .iter()
+                .map(Condition::counts)
+                .fold((0, 0), |(keys, hash_locks), (k, h)| (keys + k, hash_locks + h)),
+        }
+    }
+
+    fn evaluate_branch(&self, witness: &Witness, context: &Context) -> Result<(), Box<dyn Error>> {
+        match self {
+            Condition::MultiSig { threshold, keys } => {
+                let signatures: Vec<Signature> = witness
+                    .signatures
+                    .iter()
+                    .filter_map(|signature| hex::decode(signature).ok())
+                    .filter_map(|bytes| Signature::from_slice(&bytes).ok())
+                    .collect();
+                // Each signer counts once, however many times its key is listed.
+                let mut signers = Vec::new();
+                for key in keys {
+                    let key = verifying_key(key)?;
+                    if !signers.contains(&key.to_bytes())
+                        && signatures.iter().any(|signature| key.verify_strict(context.message, signature).is_ok())
+                    {
+                        signers.push(key.to_bytes());
+                    }
+                }
+                let signed = signers.len();
+                if signed < *threshold {
+                    return Err(format!("Only {} of the {} required keys signed", signed, threshold).into());
+                }
+                Ok(())
+            }
+            Condition::After { height } => {
+                if context.height < *height {
+                    return Err(format!("Locked until height {} (now {})", height, context.height).into());
+                }
+                Ok(())
+            }
+            Condition::HashLock { hash } => {
+                let revealed = witness.preimages.iter().filter_map(|preimage| hex::decode(preimage).ok()).any(|preimage| {
+                    hex::encode(Sha256::digest(&preimage)).eq_ignore_ascii_case(hash)
+                });
+                if !revealed {
+                    return Err(format!("No preimage of hash lock {}", hash).into());
+                }
+                Ok(())
+            }
+            Condition::All(branches) => branches.iter().try_for_each(|branch| branch.evaluate_branch(witness, context)),
+            Condition::Any(branches) => {
+                let mut reasons = Vec::new();
+                for branch in branches {
+                    match branch.evaluate_branch(witness, context) {
+                        Ok(()) => return Ok(()),
+                        Err(e) => reasons.push(e.to_string()),
+                    }
+                }
+                Err(format!("No alternative holds ({})", reasons.join("; ")).into())
+            }
+        }
+    }
+}
+
+// The account holding the funds of the lock with this txid.
+pub fn lock_address(lock: &str) -> String {
+    format!("lock:{}", lock)
+}
+
+pub fn is_lock_address(address: &str) -> bool {
+    address.starts_with("lock:")
+}
+
+// What multi-sig keys sign to release lock `lock` to `to` on the chain with
+// `network_id`, so the signature is no good on another chain. The witness is not
+// part of it, so signers can sign independently.
+pub fn unlock_message(network_id: &str, lock: &str, to: &str, fee: u64) -> Vec<u8> {
+    let mut message = b"ledger-v1 unlock\0".to_vec();
+    message.extend_from_slice(network_id.as_bytes());
+    message.push(0);
+    message.extend_from_slice(lock.as_bytes());
+    message.push(0);
+    message.extend_from_slice(to.as_bytes());
+    message.push(0);
+    message.extend_from_slice(&fee.to_be_bytes());
+    message
+}
+
+// A witness signature (hex) for paying `lock` out to `to` on `network_id`.
+pub fn sign_unlock(signer: &dyn Signer, network_id: &str, lock: &str, to: &str, fee: u64) -> Result<String, Box<dyn Error>> {
+    Ok(hex::encode(signer.sign(&unlock_message(network_id, lock, to, fee))?.to_bytes()))
+}
+
+pub(crate) fn verifying_key(key: &str) -> Result<VerifyingKey, Box<dyn Error>> {
+    let bytes: [u8; 32] = hex::decode(key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("{} is not a hex-encoded ed25519 public key", key))?;
+    Ok(VerifyingKey::from_bytes(&bytes).map_err(|_| format!("{} is not a valid ed25519 public key", key))?)
+}
@@ -0,0 +1,197 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::encryption::BlockCipher;
+use crate::store::{BlockStore, TreeId};
+use crate::transaction::Transaction;
+
+// With `Config::search_index` the "search" tree maps every token of a canonical block
+// to it: the words of its data, and the addresses and ids of its transactions. Keys
+// are the lowercase token, a zero byte and the block hash. With an encryption key the
+// token is replaced by its keyed HMAC (see `BlockCipher::index_token`), so the index
+// gives away which blocks share a token but not the token; changing the key rebuilds
+// the index.
+
+// Marks a built index. Its value tells which tokens the index holds: empty for plain
+// ones, or else the HMAC of the empty token under the key in use.
+const BUILT_KEY: &str = "SEARCH_BUILT";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SearchHit {
+    pub height: u64,
+    pub block: String,
+    // Set when the match is in a transaction rather than the block data.
+    pub transaction: Option<String>,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Canonical blocks matching `predicate`, oldest first.
+    pub fn find_blocks(&self, predicate: impl Fn(&Block) -> bool) -> Result<Vec<Block>, Box<dyn Error>> {
+        let mut found = Vec::new();
+        for height in 0..=self.height()? {
+            if let Some(block) = self.canonical_block_if_stored(height)?
+                && predicate(&block)
+            {
+                found.push(block);
+            }
+        }
+        Ok(found)
+    }
+
+    // Canonical blocks whose metadata sets `key` to `value`, oldest first.
+    pub fn find_by_tag(&self, key: &str, value: &str) -> Result<Vec<Block>, Box<dyn Error>> {
+        self.find_blocks(|block| block.metadata.get(key).is_some_and(|tag| tag == value))
+    }
+
+    // Canonical blocks carrying every one of `tags`.
+    pub fn find_by_tags(&self, tags: &BTreeMap<String, String>) -> Result<Vec<Block>, Box<dyn Error>> {
+        self.find_blocks(|block| tags.iter().all(|(key, value)| block.metadata.get(key) == Some(value)))
+    }
+
+    // Case-insensitive substring search over block data (when it is text),
+    // transaction ids and addresses. Always scans the canonical chain.
+    pub fn search(&self, text: &str) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+        let needle = text.to_lowercase();
+        let mut hits = Vec::new();
+        for height in 0..=self.height()? {
+            let Some(block) = self.canonical_block_if_stored(height)? else { continue };
+            hits.extend(block_hits(&block, height, |field| field.to_lowercase().contains(&needle)));
+        }
+        Ok(hits)
+    }
+
+    // Whole-token search: a word of the block data, an address or a transaction id,
+    // ignoring case. Uses the search index when it is enabled, otherwise scans.
+    pub fn lookup(&self, term: &str) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+        let term = term.to_lowercase();
+        let matches = |field: &str| field.to_lowercase() == term || words(field).any(|word| word == term);
+        if !self.config.search_index {
+            let mut hits = Vec::new();
+            for height in 0..=self.height()? {
+                let Some(block) = self.canonical_block_if_stored(height)? else { continue };
+                hits.extend(block_hits(&block, height, matches));
+            }
+            return Ok(hits);
+        }
+
+        let mut prefix = index_token(self.trees.cipher(), &term).into_bytes();
+        prefix.push(0);
+        let mut hits = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::Search, &prefix) {
+            let (key, _) = entry?;
+            let hash = String::from_utf8(key[prefix.len()..].to_vec())?;
+            let (Some(block), Some(meta)) = (self.load_block(&hash)?, self.block_meta(&hash)?) else { continue };
+            let block_matches = block_hits(&block, meta.height, matches);
+            if block_matches.is_empty() {
+                // The body was pruned; the index still knows the block matched.
+                hits.push(SearchHit { height: meta.height, block: hash, transaction: None });
+            }
+            hits.extend(block_matches);
+        }
+        hits.sort_by_key(|hit| hit.height);
+        Ok(hits)
+    }
+
+    // Keeps the index in line with `Config::search_index` across restarts: built on
+    // the first open with it enabled, dropped when it is turned off, since it would
+    // miss the blocks added in between, and built again when the encryption key
+    // changes.
+    pub(crate) fn sync_search_index(&self) -> Result<(), Box<dyn Error>> {
+        let built = self.trees.get(TreeId::Blocks, BUILT_KEY.as_bytes())?;
+        let fingerprint = self.trees.cipher().map(|cipher| cipher.index_token("")).unwrap_or_default();
+        match &built {
+            Some(value) if self.config.search_index && *value == fingerprint.as_bytes() => return Ok(()),
+            None if !self.config.search_index => return Ok(()),
+            _ => {}
+        }
+        let mut batch = self.batch();
+        batch.clear(TreeId::Search)?;
+        if self.config.search_index {
+            for height in 0..=self.height()? {
+                if let Some(block) = self.canonical_block_if_stored(height)? {
+                    batch.index_block(&block);
+                }
+            }
+            batch.insert(TreeId::Blocks, BUILT_KEY, fingerprint);
+        } else {
+            batch.remove(TreeId::Blocks, BUILT_KEY);
+        }
+        self.commit(batch)
+    }
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    // Called as a block joins or leaves the canonical chain.
+    pub(crate) fn index_block(&mut self, block: &Block) {
+        if self.config.search_index {
+            for token in tokens(block) {
+                let key = index_key(self.cipher(), &token, &block.hash);
+                self.insert(TreeId::Search, key, []);
+            }
+        }
+    }
+
+    pub(crate) fn unindex_block(&mut self, block: &Block) {
+        if self.config.search_index {
+            for token in tokens(block) {
+                let key = index_key(self.cipher(), &token, &block.hash);
+                self.remove(TreeId::Search, key);
+            }
+        }
+    }
+}
+
+// The block itself if its data matches, then each matching transaction.
+fn block_hits(block: &Block, height: u64, matches: impl Fn(&str) -> bool) -> Vec<SearchHit> {
+    let mut hits = Vec::new();
+    if block.data_str().is_some_and(&matches) {
+        hits.push(SearchHit { height, block: block.hash.clone(), transaction: None });
+    }
+    for transaction in &block.transactions {
+        let txid = transaction.hash();
+        if matches(&txid) || addresses(transaction).iter().any(|address| matches(address)) {
+            hits.push(SearchHit { height, block: block.hash.clone(), transaction: Some(txid) });
+        }
+    }
+    hits
+}
+
+fn tokens(block: &Block) -> BTreeSet<String> {
+    let mut tokens: BTreeSet<String> = block.data_str().map(|data| words(data).collect()).unwrap_or_default();
+    for transaction in &block.transactions {
+        tokens.insert(transaction.hash());
+        tokens.extend(addresses(transaction).iter().map(|address| address.to_lowercase()));
+    }
+    tokens
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).map(str::to_lowercase)
+}
+
+fn addresses(transaction: &Transaction) -> Vec<&str> {
+    match transaction {
+        Transaction::Transfer { from, to, .. } => vec![from, to],
+        Transaction::Coinbase { to, .. } => vec![to],
+        Transaction::Lock { from, .. } => vec![from],
+        Transaction::Unlock { lock, to, .. } => vec![lock, to],
+    }
+}
+
+pub(crate) fn index_token(cipher: Option<&BlockCipher>, token: &str) -> String {
+    match cipher {
+        Some(cipher) => cipher.index_token(token),
+        None => token.to_string(),
+    }
+}
+
+fn index_key(cipher: Option<&BlockCipher>, token: &str, hash: &str) -> Vec<u8> {
+    let mut key = index_token(cipher, token).into_bytes();
+    key.push(0);
+    key.extend_from_slice(hash.as_bytes());
+    key
+}
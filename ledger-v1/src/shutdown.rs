@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::blockchain::Blockchain;
+use crate::error::LedgerError;
+use crate::store::{BlockStore, TreeId, Writes};
+use crate::transaction::Transaction;
+use tracing::{debug, info};
+
+// Stopping a long-lived node without losing work. `request_shutdown`, or a signal
+// handler setting `shutdown_flag`, makes the chain refuse new blocks and
+// transactions with `LedgerError::ShuttingDown`, stops the miner and ends the
+// `serve_*` loops. `shutdown` then waits for the commit under way, writes the
+// mempool next to the blocks and flushes. The next open that can write puts the
+// saved transactions back in the pool.
+
+// The mempool saved by `shutdown`, as JSON, until the next open takes it back.
+const MEMPOOL_KEY: &str = "MEMPOOL";
+
+// How often the servers look at the flag while no connection arrives.
+pub(crate) const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+impl<S: BlockStore> Blockchain<S> {
+    pub fn request_shutdown(&self) {
+        self.shared.closing.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shared.closing.load(Ordering::SeqCst)
+    }
+
+    // The flag `request_shutdown` sets, for signal handlers, which may only store to
+    // an atomic (e.g. `signal_hook::flag::register`).
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        self.shared.closing.clone()
+    }
+
+    // Requests shutdown if it wasn't already, waits for the write under way to
+    // commit, saves the mempool and flushes. Returns how many transactions were
+    // saved. The handle only serves reads afterwards.
+    pub fn shutdown(&self) -> Result<usize, Box<dyn Error>> {
+        self.request_shutdown();
+        let _writer = self.write_lock();
+        if self.store().is_read_only() {
+            return Ok(0);
+        }
+        let transactions = self.mempool();
+        if !transactions.is_empty() {
+            self.store().insert(TreeId::Blocks, MEMPOOL_KEY.as_bytes(), serde_json::to_vec(&transactions)?)?;
+        }
+        self.flush()?;
+        info!(transactions = transactions.len(), "shut down");
+        Ok(transactions.len())
+    }
+
+    pub(crate) fn check_open(&self) -> Result<(), Box<dyn Error>> {
+        if self.is_shutting_down() {
+            return Err(Box::new(LedgerError::ShuttingDown));
+        }
+        Ok(())
+    }
+
+    // Resubmits the mempool the last `shutdown` saved. Transactions mined or made
+    // invalid since are dropped.
+    pub(crate) fn restore_mempool(&self) -> Result<(), Box<dyn Error>> {
+        let Some(bytes) = self.store().get(TreeId::Blocks, MEMPOOL_KEY.as_bytes())? else {
+            return Ok(());
+        };
+        let transactions: Vec<Transaction> = serde_json::from_slice(&bytes)?;
+        let mut restored = 0;
+        for transaction in transactions {
+            let txid = transaction.hash();
+            match self.submit_transaction(transaction) {
+                Ok(_) => restored += 1,
+                Err(e) => debug!(%txid, error = %e, "dropping a saved mempool transaction"),
+            }
+        }
+        self.store().apply(&Writes::from([((TreeId::Blocks, MEMPOOL_KEY.as_bytes().to_vec()), None)]))?;
+        info!(transactions = restored, "mempool restored");
+        Ok(())
+    }
+
+    // The next connection to `listener`, or None once the chain is shutting down.
+    // Leaves the listener non-blocking; the connection is blocking.
+    pub(crate) fn accept(&self, listener: &TcpListener) -> Result<Option<TcpStream>, Box<dyn Error>> {
+        listener.set_nonblocking(true)?;
+        while !self.is_shutting_down() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    return Ok(Some(stream));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(SHUTDOWN_POLL),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+}
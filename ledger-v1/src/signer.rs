@@ -0,0 +1,310 @@
+use ed25519_dalek::{Signature, Signer as _, SigningKey};
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::blockchain::Blockchain;
+use crate::config;
+use crate::store::BlockStore;
+
+// Everything the node signs with ed25519 (authority block signatures, approvals,
+// unlock witnesses and transactions) goes through a `Signer`, so the secret key
+// need not be in this process: an implementation can hand the message to an HSM,
+// the OS keychain or a signing service. `LocalSigner` holds the key in memory, read from a key file;
+// `RemoteSigner` (with the remote-signer feature) asks an HTTP service.
+pub trait Signer: Send + Sync + 'static {
+    // The ed25519 public key the signatures verify against, hex.
+    fn public_key(&self) -> String;
+
+    fn sign(&self, message: &[u8]) -> Result<Signature, Box<dyn Error>>;
+}
+
+// A key held in memory.
+pub struct LocalSigner {
+    key: SigningKey,
+}
+
+impl LocalSigner {
+    pub fn new(key: SigningKey) -> LocalSigner {
+        LocalSigner { key }
+    }
+
+    // 32 secret bytes, hex, as `authority::generate_key` makes them.
+    pub fn from_hex(secret: &str) -> Result<LocalSigner, Box<dyn Error>> {
+        let secret: [u8; 32] = hex::decode(secret.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("The signing key must be 32 bytes, hex-encoded")?;
+        Ok(LocalSigner::new(SigningKey::from_bytes(&secret)))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<LocalSigner, Box<dyn Error>> {
+        let path = path.as_ref();
+        let secret = std::fs::read_to_string(path).map_err(|e| format!("Cannot read key {}: {}", path.display(), e))?;
+        LocalSigner::from_hex(&secret).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+}
+
+impl Signer for LocalSigner {
+    fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().as_bytes())
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Signature, Box<dyn Error>> {
+        Ok(self.key.sign(message))
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // This handle, signing its blocks on proof-of-authority chains with `signer`
+    // instead of the configured key. Clones of the result share it; other handles
+    // keep theirs.
+    pub fn with_authority_signer(self, signer: impl Signer) -> Self {
+        Blockchain { authority_signer: Some(Arc::new(signer)), ..self }
+    }
+
+    // Likewise for approving proposed blocks.
+    pub fn with_approver_signer(self, signer: impl Signer) -> Self {
+        Blockchain { approver_signer: Some(Arc::new(signer)), ..self }
+    }
+
+    // Likewise for signing anchor attestations.
+    pub fn with_anchor_signer(self, signer: impl Signer) -> Self {
+        Blockchain { anchor_signer: Some(Arc::new(signer)), ..self }
+    }
+}
+
+// A signing service holding one of this node's keys, see `RemoteSigner`. Settings
+// rather than the signer itself, so configs naming one still load in builds
+// without the remote-signer feature, which then refuse to open the chain.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RemoteSignerConfig {
+    // http:// URL the messages are posted to.
+    pub url: String,
+    // The key the service signs with, hex; every signature it returns is checked
+    // against it.
+    pub public_key: String,
+    // Bearer token for the service, read from this file. Without it the
+    // LEDGER_SIGNER_TOKEN variable is used, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+}
+
+// Read when a remote signer config names no token file.
+pub const TOKEN_ENV: &str = "LEDGER_SIGNER_TOKEN";
+
+// The signer a role takes from the config: a remote one if configured, else the key
+// in `file` or the `env` variable. None if there is neither.
+pub(crate) fn from_config(
+    remote: Option<&RemoteSignerConfig>,
+    file: Option<&Path>,
+    env: &str,
+    what: &str,
+) -> Result<Option<Arc<dyn Signer>>, Box<dyn Error>> {
+    if let Some(remote) = remote {
+        return remote_from_config(remote, what);
+    }
+    let Some(secret) = config::read_key(file, env, what)? else {
+        return Ok(None);
+    };
+    let secret: [u8; 32] = secret.try_into().map_err(|_| format!("The {} key must be 32 bytes", what))?;
+    Ok(Some(Arc::new(LocalSigner::new(SigningKey::from_bytes(&secret)))))
+}
+
+#[cfg(feature = "remote-signer")]
+fn remote_from_config(remote: &RemoteSignerConfig, what: &str) -> Result<Option<Arc<dyn Signer>>, Box<dyn Error>> {
+    let token = match &remote.token_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path).map_err(|e| format!("Cannot read the {} signer token {}: {}", what, path.display(), e))?,
+        ),
+        None => std::env::var(TOKEN_ENV).ok(),
+    };
+    let mut signer = RemoteSigner::new(&remote.url, &remote.public_key)?;
+    if let Some(token) = token {
+        signer = signer.with_token(token.trim());
+    }
+    Ok(Some(Arc::new(signer)))
+}
+
+#[cfg(not(feature = "remote-signer"))]
+fn remote_from_config(_remote: &RemoteSignerConfig, what: &str) -> Result<Option<Arc<dyn Signer>>, Box<dyn Error>> {
+    Err(format!("The config names a remote {} signer, but this build has no remote-signer feature", what).into())
+}
+
+#[cfg(feature = "remote-signer")]
+pub use remote::RemoteSigner;
+
+#[cfg(feature = "remote-signer")]
+mod remote {
+    use ed25519_dalek::{Signature, VerifyingKey};
+    use serde::{Serialize, Deserialize};
+    use std::error::Error;
+    use std::io::{Read, Write};
+    use std::net::{TcpStream, ToSocketAddrs};
+    use std::time::Duration;
+
+    use super::Signer;
+    use crate::authority;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    // Far more than a signature takes, so a misbehaving service can't fill memory.
+    const MAX_RESPONSE: u64 = 64 * 1024;
+
+    #[derive(Serialize)]
+    struct SignRequest<'a> {
+        public_key: &'a str,
+        // Hex.
+        message: String,
+    }
+
+    #[derive(Deserialize)]
+    struct SignResponse {
+        // Hex.
+        signature: String,
+    }
+
+    // A key kept by a signing service. Each signature is one POST to the service's
+    // URL of {"public_key", "message"}, the message hex, answered with
+    // {"signature"} in hex; the token, if any, goes in an "Authorization: Bearer"
+    // header. Plain http only, like webhooks: put a TLS proxy in front for https.
+    pub struct RemoteSigner {
+        url: String,
+        host: String,
+        port: u16,
+        path: String,
+        public_key: String,
+        verifying_key: VerifyingKey,
+        token: Option<String>,
+    }
+
+    impl RemoteSigner {
+        pub fn new(url: &str, public_key: &str) -> Result<RemoteSigner, Box<dyn Error>> {
+            let rest = url
+                .strip_prefix("http://")
+                .ok_or_else(|| format!("Signer {} is not an http:// URL; put a TLS proxy in front for https", url))?;
+            let (authority, path) = match rest.find('/') {
+                Some(slash) => (&rest[..slash], &rest[slash..]),
+                None => (rest, "/"),
+            };
+            // An IPv6 literal is bracketed, and has colons of its own.
+            let (host, port) = match authority.strip_prefix('[') {
+                Some(bracketed) => match bracketed.split_once(']') {
+                    Some((host, "")) => (host, None),
+                    Some((host, port)) => (host, Some(port.strip_prefix(':').unwrap_or(port))),
+                    None => return Err(format!("Signer {} has an unclosed [ in its host", url).into()),
+                },
+                None => match authority.rsplit_once(':') {
+                    Some((host, port)) => (host, Some(port)),
+                    None => (authority, None),
+                },
+            };
+            let port = match port {
+                Some(port) => port.parse().map_err(|_| format!("Signer {} has an invalid port", url))?,
+                None => 80,
+            };
+            if host.is_empty() {
+                return Err(format!("Signer {} has no host", url).into());
+            }
+            Ok(RemoteSigner {
+                url: url.to_string(),
+                host: host.to_string(),
+                port,
+                path: path.to_string(),
+                public_key: public_key.to_string(),
+                verifying_key: authority::verifying_key(public_key).map_err(|e| format!("Signer {}: {}", url, e))?,
+                token: None,
+            })
+        }
+
+        pub fn with_token(self, token: impl Into<String>) -> RemoteSigner {
+            RemoteSigner { token: Some(token.into()), ..self }
+        }
+
+        // One request; the answer must be 2xx with a JSON body.
+        fn post(&self, body: &str) -> Result<String, Box<dyn Error>> {
+            let addr = (self.host.as_str(), self.port)
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| format!("Cannot resolve {}", self.host))?;
+            let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+
+            let host = match self.host.contains(':') {
+                true => format!("[{}]", self.host),
+                false => self.host.clone(),
+            };
+            let mut request = format!(
+                "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+                self.path,
+                host,
+                body.len()
+            );
+            if let Some(token) = &self.token {
+                request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+            }
+            request.push_str("Connection: close\r\n\r\n");
+            request.push_str(body);
+            stream.write_all(request.as_bytes())?;
+
+            // The connection closes after the answer, so it is read to the end.
+            let mut response = Vec::new();
+            stream.take(MAX_RESPONSE + 1).read_to_end(&mut response)?;
+            if response.len() as u64 > MAX_RESPONSE {
+                return Err(format!("The response is over {} bytes", MAX_RESPONSE).into());
+            }
+            let response = String::from_utf8(response)?;
+            let (head, body) = response.split_once("\r\n\r\n").ok_or("Incomplete HTTP response")?;
+            match head.split_whitespace().nth(1) {
+                Some(status) if status.starts_with('2') => {}
+                Some(status) => return Err(format!("Answered {}: {}", status, body.trim()).into()),
+                None => return Err("No HTTP response".into()),
+            }
+            let chunked = head
+                .lines()
+                .any(|line| line.to_ascii_lowercase().replace(' ', "") == "transfer-encoding:chunked");
+            match chunked {
+                true => dechunk(body),
+                false => Ok(body.to_string()),
+            }
+        }
+    }
+
+    impl Signer for RemoteSigner {
+        fn public_key(&self) -> String {
+            self.public_key.clone()
+        }
+
+        fn sign(&self, message: &[u8]) -> Result<Signature, Box<dyn Error>> {
+            let request = serde_json::to_string(&SignRequest { public_key: &self.public_key, message: hex::encode(message) })?;
+            let response = self.post(&request).map_err(|e| format!("Signer {}: {}", self.url, e))?;
+            let response: SignResponse =
+                serde_json::from_str(&response).map_err(|e| format!("Signer {} answered badly: {}", self.url, e))?;
+            let signature = hex::decode(&response.signature)
+                .ok()
+                .and_then(|bytes| Signature::from_slice(&bytes).ok())
+                .ok_or_else(|| format!("Signer {} returned no valid signature", self.url))?;
+            // A service signing with another key, or garbling the message, is caught
+            // here rather than by whoever checks the block.
+            if self.verifying_key.verify_strict(message, &signature).is_err() {
+                return Err(format!("Signer {} returned a signature that does not verify against {}", self.url, self.public_key).into());
+            }
+            Ok(signature)
+        }
+    }
+
+    fn dechunk(mut body: &str) -> Result<String, Box<dyn Error>> {
+        let mut decoded = String::new();
+        loop {
+            let (size, rest) = body.split_once("\r\n").ok_or("Truncated chunked body")?;
+            let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)?;
+            if size == 0 {
+                return Ok(decoded);
+            }
+            decoded.push_str(rest.get(..size).ok_or("Truncated chunked body")?);
+            body = rest[size..].strip_prefix("\r\n").ok_or("Malformed chunked body")?;
+        }
+    }
+}
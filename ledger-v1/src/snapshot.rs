@@ -0,0 +1,198 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::io::{Read, Write};
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::block::Block;
+use crate::blockchain::{BlockMeta, Blockchain};
+use crate::store::{BlockStore, TreeId};
+use tracing::instrument;
+
+// Trees holding ledger state derived from the canonical chain. A snapshot copies
+// them whole, so state models register their trees here.
+pub(crate) const STATE_TREES: &[TreeId] = &[TreeId::State, TreeId::StateNodes];
+
+// Snapshot files: this magic followed by the MessagePack-encoded snapshot.
+const SNAPSHOT_FILE_MAGIC: &[u8; 8] = b"LDGRSTAT";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SnapshotInfo {
+    pub height: u64,
+    pub tip: String,
+    // Milliseconds since the epoch.
+    pub created_at: u64,
+    // Number of state records captured.
+    pub entries: usize,
+}
+
+// A tree's name and all of its records.
+type TreeDump = (String, Vec<(Vec<u8>, Vec<u8>)>);
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    info: SnapshotInfo,
+    genesis: Block,
+    tip: Block,
+    total_work: u128,
+    trees: Vec<TreeDump>,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Captures the state at the current tip and keeps it in the "snapshots" tree.
+    pub fn create_snapshot(&self) -> Result<SnapshotInfo, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        self.take_snapshot()
+    }
+
+    fn take_snapshot(&self) -> Result<SnapshotInfo, Box<dyn Error>> {
+        let tip_meta = self.tip_meta()?;
+        let mut trees = Vec::new();
+        let mut entries = 0;
+        for id in STATE_TREES {
+            let mut records = Vec::new();
+            for entry in self.store().scan_prefix(*id, &[]) {
+                records.push(entry?);
+            }
+            entries += records.len();
+            trees.push((id.name().to_string(), records));
+        }
+
+        let snapshot = Snapshot {
+            info: SnapshotInfo {
+                height: tip_meta.height,
+                tip: self.current_hash(),
+                created_at: self.clock.now_ms(),
+                entries,
+            },
+            genesis: self.canonical_block(0)?,
+            tip: self.canonical_block(tip_meta.height)?,
+            total_work: tip_meta.total_work,
+            trees,
+        };
+        self.store().insert(TreeId::Snapshots, &tip_meta.height.to_be_bytes(), rmp_serde::to_vec(&snapshot)?)?;
+        self.store().flush()?;
+        Ok(snapshot.info)
+    }
+
+    pub fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>, Box<dyn Error>> {
+        let mut snapshots = Vec::new();
+        for entry in self.store().scan_prefix(TreeId::Snapshots, &[]) {
+            let (_, bytes) = entry?;
+            let snapshot: Snapshot = rmp_serde::from_slice(&bytes)?;
+            snapshots.push(snapshot.info);
+        }
+        Ok(snapshots)
+    }
+
+    // Resets the state to the snapshot taken at `height` and re-applies the canonical
+    // blocks after it, instead of replaying everything from genesis.
+    #[instrument(skip_all)]
+    pub fn restore_snapshot(&self, height: u64) -> Result<SnapshotInfo, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        let snapshot = self.load_snapshot(height)?;
+        if self.canonical_hash(height)?.as_deref() != Some(snapshot.info.tip.as_str()) {
+            return Err(format!("Snapshot at height {} is no longer on the canonical chain", height).into());
+        }
+
+        let mut batch = self.batch();
+        batch.write_state(&snapshot.trees)?;
+        for height in height + 1..=self.height()? {
+            let block = self.canonical_block(height)?;
+            batch.connect_block(&block, height)?;
+        }
+        self.commit(batch)?;
+        Ok(snapshot.info)
+    }
+
+    pub fn export_snapshot<W: Write>(&self, height: u64, mut writer: W) -> Result<(), Box<dyn Error>> {
+        let snapshot = self.load_snapshot(height)?;
+        writer.write_all(SNAPSHOT_FILE_MAGIC)?;
+        writer.write_all(&rmp_serde::to_vec(&snapshot)?)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Starts an empty database from a snapshot written by `export_snapshot`. The
+    // blocks before the snapshot tip are not downloaded or re-validated: the tip
+    // becomes the trusted base that `is_chain_valid` stops at.
+    #[instrument(skip_all)]
+    pub fn bootstrap_from_snapshot<R: Read>(&self, mut reader: R) -> Result<SnapshotInfo, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let payload = bytes.strip_prefix(SNAPSHOT_FILE_MAGIC).ok_or("Not a ledger state snapshot")?;
+        let snapshot: Snapshot = rmp_serde::from_slice(payload)?;
+
+        for block in [&snapshot.genesis, &snapshot.tip] {
+            if block.hash != block.calculate_hash() {
+                return Err(format!("Hash mismatch for snapshot block {}", block.hash).into());
+            }
+        }
+        if !snapshot.genesis.is_genesis() || snapshot.tip.hash != snapshot.info.tip {
+            return Err("Snapshot is inconsistent".into());
+        }
+        if !self.holds_only_genesis()? {
+            return Err("Can only bootstrap an empty database from a snapshot".into());
+        }
+
+        let mut batch = self.batch();
+        if self.canonical_hash(0)?.as_deref() != Some(snapshot.genesis.hash.as_str()) {
+            batch.replace_genesis(&snapshot.genesis)?;
+        }
+        if snapshot.info.height > 0 {
+            let meta = BlockMeta { height: snapshot.info.height, total_work: snapshot.total_work };
+            batch.store_block(&snapshot.tip, &meta)?;
+            batch.remove(TreeId::Tips, &snapshot.genesis.hash);
+            // State comes from the snapshot, so the tip is indexed but not connected.
+            batch.insert(TreeId::Heights, meta.height.to_be_bytes(), snapshot.tip.hash.as_bytes());
+        }
+        batch.write_state(&snapshot.trees)?;
+        batch.insert(TreeId::Blocks, "BASE", snapshot.tip.hash.as_bytes());
+        batch.set_tip(&snapshot.tip.hash);
+        self.commit(batch)?;
+        Ok(snapshot.info)
+    }
+
+    // The block a snapshot bootstrap started from; history before it is not stored.
+    pub fn trusted_base(&self) -> Result<Option<String>, Box<dyn Error>> {
+        match self.trees.get(TreeId::Blocks, b"BASE")? {
+            Some(bytes) => Ok(Some(String::from_utf8(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Called whenever the tip moves.
+    pub(crate) fn maybe_snapshot(&self) -> Result<(), Box<dyn Error>> {
+        let interval = self.config.snapshot_interval;
+        if interval > 0 && self.height()? % interval == 0 {
+            self.take_snapshot()?;
+        }
+        Ok(())
+    }
+
+    fn load_snapshot(&self, height: u64) -> Result<Snapshot, Box<dyn Error>> {
+        let bytes = self
+            .trees
+            .get(TreeId::Snapshots, &height.to_be_bytes())?
+            .ok_or_else(|| format!("No snapshot at height {}", height))?;
+        Ok(rmp_serde::from_slice(&bytes)?)
+    }
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    fn write_state(&mut self, trees: &[TreeDump]) -> Result<(), Box<dyn Error>> {
+        for id in STATE_TREES {
+            self.clear(*id)?;
+            if let Some((_, records)) = trees.iter().find(|(name, _)| name == id.name()) {
+                for (key, value) in records {
+                    self.insert(*id, key, value.as_slice());
+                }
+            }
+        }
+        // Snapshots taken before state trees leave it to be built from the accounts.
+        if !trees.iter().any(|(name, _)| name == TreeId::StateNodes.name()) {
+            self.rebuild_state_tree()?;
+        }
+        Ok(())
+    }
+}
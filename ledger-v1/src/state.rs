@@ -0,0 +1,435 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::block::Block;
+use crate::error::ConsensusError;
+use crate::script::Condition;
+use crate::blockchain::Blockchain;
+use crate::hashing::{HashAlgorithm, HEADER_V2};
+use crate::header::BlockHeader;
+use crate::state_tree::{self, AccountProof, StateTree};
+use crate::store::{BlockStore, TreeId};
+use crate::script::{self, Context};
+use crate::transaction::Transaction;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Account {
+    pub balance: u64,
+    // Number of transfers and locks sent from this account, which is the nonce its
+    // next one carries.
+    pub nonce: u64,
+    // Set on lock accounts: what an unlock must meet (see `script`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Condition>,
+    // Set on lock accounts once an unlock has paid them out.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub spent: bool,
+}
+
+// Accounts touched by one block: address -> (before, after). `None` before means the
+// account did not exist yet.
+pub(crate) type StateChanges = BTreeMap<String, (Option<Account>, Account)>;
+
+// Written before a block's changes are applied, so `disconnect_block` can put the old
+// values back during a reorg. The records of canonical blocks also let past states be
+// read back (see `state_at`).
+type UndoRecord = Vec<(String, Option<Account>)>;
+
+// Commitment to a whole state: the root of its state tree (see `state_tree`). Empty
+// when there are no accounts. Builds the tree from scratch; the chain keeps its own
+// up to date instead.
+pub fn state_root(algorithm: HashAlgorithm, state: &BTreeMap<String, Account>) -> Result<String, Box<dyn Error>> {
+    let mut tree = StateTree::new(algorithm, Box::new(|_| Ok(None)));
+    for (address, account) in state {
+        tree.set(address, Some(account))?;
+    }
+    tree.root()
+}
+
+// Light-client check of a proof from `Blockchain::account_proof`: true if `address`
+// held `account` in the state committed to by `root`.
+pub fn verify_account_proof(algorithm: HashAlgorithm, root: &str, address: &str, account: &Account, proof: &AccountProof) -> bool {
+    state_tree::verify_proof(algorithm, root, address, account, proof)
+}
+
+// Blocks made before state roots carry none, and their children may leave it out too.
+// Once a block has one, every block after it must, and it must match the state the
+// block leaves behind: `root` is computed from the state, `header` is what the block claims.
+pub(crate) fn check_state_root(header: &BlockHeader, parent: &BlockHeader, root: &str) -> Result<(), Box<dyn Error>> {
+    if header.state_root.is_empty() {
+        if !parent.state_root.is_empty() {
+            return Err(format!("Block {} carries no state root, but its parent does", header.hash).into());
+        }
+        return Ok(());
+    }
+    if header.version < HEADER_V2 {
+        return Err(format!("Block {} is version {}, which cannot carry a state root", header.hash, header.version).into());
+    }
+    if header.state_root != root {
+        return Err(format!("Block {} claims state root {}, but applying it gives {}", header.hash, header.state_root, root).into());
+    }
+    Ok(())
+}
+
+// A spend's nonce must be its sender's next, the number of transactions it has sent
+// so far. Spends without one pass unless the chain requires nonces; they still count.
+fn check_nonce(required: bool, transaction: &Transaction, from: &str, expected: u64) -> Result<(), Box<dyn Error>> {
+    match transaction.nonce() {
+        Some(nonce) if nonce != expected => Err(format!(
+            "Transaction {} from {} has nonce {}, but the next one is {}",
+            transaction.hash(), from, nonce, expected
+        ).into()),
+        None if required => Err(format!("Transaction {} carries no nonce, which this chain requires", transaction.hash()).into()),
+        _ => Ok(()),
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Balance and nonce of `address` at the canonical tip. Unknown addresses are empty.
+    pub fn get_account(&self, address: &str) -> Result<Account, Box<dyn Error>> {
+        Ok(self.trees.load_account(address)?.unwrap_or_default())
+    }
+
+    // `address` as it stood once the canonical block at `height` was applied: the
+    // oldest undo record above it that touches the address holds its value then.
+    pub fn get_account_at(&self, address: &str, height: u64) -> Result<Account, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        self.account_at(address, height)
+    }
+
+    // `get_account_at` for callers holding the write lock.
+    fn account_at(&self, address: &str, height: u64) -> Result<Account, Box<dyn Error>> {
+        let tip = self.check_past_height(height)?;
+        for height in height + 1..=tip {
+            if let Some((_, before)) = self.load_undo(height)?.into_iter().find(|(touched, _)| touched == address) {
+                return Ok(before.unwrap_or_default());
+            }
+        }
+        self.get_account(address)
+    }
+
+    pub fn get_balance_at(&self, address: &str, height: u64) -> Result<u64, Box<dyn Error>> {
+        Ok(self.get_account_at(address, height)?.balance)
+    }
+
+    // Every account as of the canonical block at `height`, by rolling the current state
+    // back through the undo records of the blocks above it. For audits and reports; the
+    // cost grows with the size of the state and the distance from the tip.
+    pub fn state_at(&self, height: u64) -> Result<BTreeMap<String, Account>, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        self.rolled_back_state(height)
+    }
+
+    // `state_at` for callers holding the write lock.
+    pub(crate) fn rolled_back_state(&self, height: u64) -> Result<BTreeMap<String, Account>, Box<dyn Error>> {
+        let tip = self.check_past_height(height)?;
+        let mut state = BTreeMap::new();
+        for entry in self.store().scan_prefix(TreeId::State, &[]) {
+            let (address, bytes) = entry?;
+            state.insert(String::from_utf8(address)?, serde_json::from_slice(&bytes)?);
+        }
+        for height in (height + 1..=tip).rev() {
+            for (address, before) in self.load_undo(height)? {
+                match before {
+                    Some(account) => state.insert(address, account),
+                    None => state.remove(&address),
+                };
+            }
+        }
+        Ok(state)
+    }
+
+    // See `state_root`.
+    pub fn state_root_at(&self, height: u64) -> Result<String, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        self.state_tree_at(height)?.root()
+    }
+
+    // Proof that `address` is part of the state after the canonical block at
+    // `height`, to check against that block's state root with `verify_account_proof`.
+    // None for an address without an account then.
+    pub fn account_proof(&self, address: &str, height: u64) -> Result<Option<(Account, AccountProof)>, Box<dyn Error>> {
+        let _writer = self.write_lock();
+        let Some(proof) = self.state_tree_at(height)?.proof(address)? else {
+            return Ok(None);
+        };
+        Ok(Some((self.account_at(address, height)?, proof)))
+    }
+
+    // The stored state tree rolled back, in memory, through the undo records of the
+    // blocks above `height`, so only the accounts they touched are rehashed.
+    fn state_tree_at(&self, height: u64) -> Result<StateTree<'_>, Box<dyn Error>> {
+        let tip = self.check_past_height(height)?;
+        let mut tree = StateTree::new(self.genesis_config().hash_algorithm, Box::new(|key| self.trees.get(TreeId::StateNodes, key)));
+        for height in (height + 1..=tip).rev() {
+            for (address, before) in self.load_undo(height)? {
+                tree.set(&address, before.as_ref())?;
+            }
+        }
+        Ok(tree)
+    }
+
+    // Returns the tip height. A snapshot bootstrap has no undo records below its base.
+    fn check_past_height(&self, height: u64) -> Result<u64, Box<dyn Error>> {
+        let tip = self.height()?;
+        if height > tip {
+            return Err(format!("Height {} is above the tip at {}", height, tip).into());
+        }
+        if let Some(base) = self.trusted_base()? {
+            let base_height = self.block_meta(&base)?.ok_or_else(|| format!("Unknown base block {}", base))?.height;
+            if height < base_height {
+                return Err(format!("No state before height {}, where the chain was bootstrapped from a snapshot", base_height).into());
+            }
+        }
+        Ok(tip)
+    }
+
+    fn load_undo(&self, height: u64) -> Result<UndoRecord, Box<dyn Error>> {
+        let hash = self.canonical_hash(height)?.ok_or_else(|| format!("No canonical block at height {}", height))?;
+        let bytes = self
+            .trees
+            .get(TreeId::Undo, hash.as_bytes())?
+            .ok_or_else(|| format!("No undo record for block {}", hash))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    // Chains indexed before the state model existed get their state built once, by
+    // replaying the canonical chain.
+    pub(crate) fn rebuild_state_if_needed(&self) -> Result<(), Box<dyn Error>> {
+        if self.trees.contains(TreeId::Blocks, b"STATE_BUILT")? {
+            return Ok(());
+        }
+        let mut batch = self.batch();
+        // A snapshot bootstrap brought its own state and has no history to replay.
+        if self.trusted_base()?.is_none() {
+            batch.clear(TreeId::State)?;
+            batch.clear(TreeId::Undo)?;
+            batch.clear(TreeId::StateNodes)?;
+            for height in 0..=self.height()? {
+                let block = self.canonical_block(height)?;
+                let changes = batch.state_changes(&block, height)?;
+                batch.apply_state_changes(&block, &changes)?;
+            }
+        }
+        batch.mark_state_built();
+        self.commit(batch)
+    }
+
+    // Databases from before the spent flag: a lock account left with nothing was
+    // paid out, or locked nothing, and either way can't be unlocked again.
+    pub(crate) fn mark_spent_locks(&self) -> Result<(), Box<dyn Error>> {
+        let mut batch = self.batch();
+        for record in self.trees.store.scan_prefix(TreeId::State, b"lock:") {
+            let (address, bytes) = record?;
+            let mut account: Account = serde_json::from_slice(&bytes)?;
+            if account.condition.is_some() && account.balance == 0 && !account.spent {
+                account.spent = true;
+                batch.insert(TreeId::State, address, serde_json::to_vec(&account)?);
+            }
+        }
+        self.commit(batch)
+    }
+
+    // Databases from before state trees get theirs built once from the accounts.
+    pub(crate) fn build_state_tree(&self) -> Result<(), Box<dyn Error>> {
+        let mut batch = self.batch();
+        batch.rebuild_state_tree()?;
+        self.commit(batch)
+    }
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    // Runs a block's transactions against the state without staging anything.
+    // The genesis block credits the configured allocations.
+    pub(crate) fn state_changes(&self, block: &Block, height: u64) -> Result<StateChanges, Box<dyn Error>> {
+        let mut changes = StateChanges::new();
+
+        if block.is_genesis() {
+            for (address, amount) in &self.genesis.allocations {
+                let account = self.account_in(&mut changes, address)?;
+                account.balance = account.balance.checked_add(*amount).ok_or_else(|| ConsensusError::wrap("Genesis allocation overflows"))?;
+            }
+        }
+
+        for transaction in &block.transactions {
+            self.apply_transaction(&mut changes, transaction, height)?;
+        }
+
+        Ok(changes)
+    }
+
+    // Applies one transaction, in a block at `height`, on top of `changes`. The sender
+    // pays the amount plus the fee; the fee reaches the miner through the block's
+    // coinbase. Lock accounts only move through locks and unlocks.
+    pub(crate) fn apply_transaction(
+        &self,
+        changes: &mut StateChanges,
+        transaction: &Transaction,
+        height: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let touches_lock = match transaction {
+            Transaction::Transfer { from, to, .. } => script::is_lock_address(from) || script::is_lock_address(to),
+            Transaction::Lock { from, .. } => script::is_lock_address(from),
+            Transaction::Unlock { to, .. } | Transaction::Coinbase { to, .. } => script::is_lock_address(to),
+        };
+        if touches_lock {
+            return Err(ConsensusError::wrap(format!("Transaction {} moves lock funds outside an unlock", transaction.hash())));
+        }
+        transaction.check_signature(&self.genesis).map_err(ConsensusError::wrap)?;
+        match transaction {
+            Transaction::Transfer { from, to, amount, fee, .. } => {
+                let sender = self.account_in(changes, from)?;
+                check_nonce(self.genesis.require_nonces, transaction, from, sender.nonce).map_err(ConsensusError::wrap)?;
+                let total = amount
+                    .checked_add(*fee)
+                    .ok_or_else(|| ConsensusError::wrap(format!("Transfer {} overflows its amount", transaction.hash())))?;
+                sender.balance = sender.balance.checked_sub(total).ok_or_else(|| {
+                    ConsensusError::wrap(format!(
+                        "Transfer {} overdraws {}: balance {}, amount {}, fee {}",
+                        transaction.hash(), from, sender.balance, amount, fee
+                    ))
+                })?;
+                sender.nonce += 1;
+
+                let recipient = self.account_in(changes, to)?;
+                recipient.balance = recipient
+                    .balance
+                    .checked_add(*amount)
+                    .ok_or_else(|| ConsensusError::wrap(format!("Transfer {} overflows the balance of {}", transaction.hash(), to)))?;
+            }
+            Transaction::Coinbase { to, amount, .. } => {
+                let miner = self.account_in(changes, to)?;
+                miner.balance = miner
+                    .balance
+                    .checked_add(*amount)
+                    .ok_or_else(|| ConsensusError::wrap(format!("Coinbase {} overflows the balance of {}", transaction.hash(), to)))?;
+            }
+            Transaction::Lock { from, amount, fee, condition, .. } => {
+                condition.check().map_err(ConsensusError::wrap)?;
+                let txid = transaction.hash();
+                if *amount == 0 {
+                    return Err(ConsensusError::wrap(format!("Lock {} locks nothing", txid)));
+                }
+                let address = script::lock_address(&txid);
+                if self.account_in(changes, &address)?.condition.is_some() {
+                    return Err(ConsensusError::wrap(format!("Lock {} already exists", txid)));
+                }
+                let sender = self.account_in(changes, from)?;
+                check_nonce(self.genesis.require_nonces, transaction, from, sender.nonce).map_err(ConsensusError::wrap)?;
+                let total = amount.checked_add(*fee).ok_or_else(|| ConsensusError::wrap(format!("Lock {} overflows its amount", txid)))?;
+                sender.balance = sender.balance.checked_sub(total).ok_or_else(|| {
+                    ConsensusError::wrap(format!("Lock {} overdraws {}: balance {}, amount {}, fee {}", txid, from, sender.balance, amount, fee))
+                })?;
+                sender.nonce += 1;
+                *self.account_in(changes, &address)? = Account { balance: *amount, condition: Some(condition.clone()), ..Account::default() };
+            }
+            Transaction::Unlock { lock, to, fee, witness } => {
+                let address = script::lock_address(lock);
+                let locked = self.account_in(changes, &address)?;
+                let condition = locked.condition.clone().ok_or_else(|| ConsensusError::wrap(format!("Unknown lock {}", lock)))?;
+                if locked.spent {
+                    return Err(ConsensusError::wrap(format!("Lock {} was already unlocked", lock)));
+                }
+                let message = script::unlock_message(&self.genesis.network_id()?, lock, to, *fee);
+                condition
+                    .evaluate(witness, &Context { height, message: &message })
+                    .map_err(|e| ConsensusError::wrap(format!("Unlock {} does not meet lock {}: {}", transaction.hash(), lock, e)))?;
+                let payout = locked
+                    .balance
+                    .checked_sub(*fee)
+                    .ok_or_else(|| ConsensusError::wrap(format!("Unlock {} pays a fee of {} from a lock of {}", transaction.hash(), fee, locked.balance)))?;
+                locked.balance = 0;
+                locked.spent = true;
+
+                let recipient = self.account_in(changes, to)?;
+                recipient.balance = recipient
+                    .balance
+                    .checked_add(payout)
+                    .ok_or_else(|| ConsensusError::wrap(format!("Unlock {} overflows the balance of {}", transaction.hash(), to)))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Root of the state once `changes` are applied on top of it, from the stored state
+    // tree with the touched accounts rehashed.
+    pub(crate) fn state_root_with(&self, changes: &StateChanges) -> Result<String, Box<dyn Error>> {
+        self.state_tree_with(changes.iter().map(|(address, (_, after))| (address.as_str(), Some(after))))?.root()
+    }
+
+    fn state_tree_with<'a>(
+        &self,
+        accounts: impl IntoIterator<Item = (&'a str, Option<&'a Account>)>,
+    ) -> Result<StateTree<'_>, Box<dyn Error>> {
+        let mut tree = StateTree::new(self.genesis.hash_algorithm, Box::new(|key| self.get(TreeId::StateNodes, key)));
+        for (address, account) in accounts {
+            tree.set(address, account)?;
+        }
+        Ok(tree)
+    }
+
+    // Stages the state tree nodes the accounts rewrite.
+    fn update_state_tree<'a>(&mut self, accounts: impl IntoIterator<Item = (&'a str, Option<&'a Account>)>) -> Result<(), Box<dyn Error>> {
+        let writes = self.state_tree_with(accounts)?.writes;
+        for (key, node) in writes {
+            match node {
+                Some(node) => self.insert(TreeId::StateNodes, key, node),
+                None => self.remove(TreeId::StateNodes, key),
+            }
+        }
+        Ok(())
+    }
+
+    // The state tree built again from the stored accounts.
+    pub(crate) fn rebuild_state_tree(&mut self) -> Result<(), Box<dyn Error>> {
+        self.clear(TreeId::StateNodes)?;
+        let mut state = BTreeMap::new();
+        for (address, bytes) in self.scan(TreeId::State)? {
+            state.insert(String::from_utf8(address)?, serde_json::from_slice::<Account>(&bytes)?);
+        }
+        self.update_state_tree(state.iter().map(|(address, account)| (address.as_str(), Some(account))))
+    }
+
+    pub(crate) fn apply_state_changes(&mut self, block: &Block, changes: &StateChanges) -> Result<(), Box<dyn Error>> {
+        let undo: UndoRecord = changes.iter().map(|(address, (before, _))| (address.clone(), before.clone())).collect();
+        self.insert(TreeId::Undo, &block.hash, serde_json::to_vec(&undo)?);
+
+        for (address, (_, after)) in changes {
+            self.insert(TreeId::State, address, serde_json::to_vec(after)?);
+        }
+        self.update_state_tree(changes.iter().map(|(address, (_, after))| (address.as_str(), Some(after))))
+    }
+
+    // Only needs the hash, so it also works for blocks whose record is damaged.
+    pub(crate) fn revert_state_changes(&mut self, hash: &str) -> Result<(), Box<dyn Error>> {
+        let bytes = self
+            .get(TreeId::Undo, hash.as_bytes())?
+            .ok_or_else(|| format!("No undo record for block {}", hash))?;
+        let undo: UndoRecord = serde_json::from_slice(&bytes)?;
+
+        for (address, before) in &undo {
+            match before {
+                Some(account) => self.insert(TreeId::State, address, serde_json::to_vec(account)?),
+                None => self.remove(TreeId::State, address),
+            }
+        }
+        self.update_state_tree(undo.iter().map(|(address, before)| (address.as_str(), before.as_ref())))?;
+        self.remove(TreeId::Undo, hash);
+        Ok(())
+    }
+
+    pub(crate) fn mark_state_built(&mut self) {
+        self.insert(TreeId::Blocks, "STATE_BUILT", []);
+    }
+
+    // The working copy of `address` inside `changes`, loaded from the state on first use.
+    fn account_in<'a>(&self, changes: &'a mut StateChanges, address: &str) -> Result<&'a mut Account, Box<dyn Error>> {
+        if !changes.contains_key(address) {
+            let before = self.load_account(address)?;
+            changes.insert(address.to_string(), (before.clone(), before.unwrap_or_default()));
+        }
+        Ok(&mut changes.get_mut(address).expect("inserted above").1)
+    }
+}
@@ -0,0 +1,231 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::hashing::{self, HashAlgorithm};
+use crate::state::Account;
+
+// Sparse merkle tree committing to the account state, which block state roots are
+// the root of. Its nodes are kept in the "state_nodes" tree and updated with the
+// state, so a block only rehashes the paths of the accounts it touches.
+//
+// Each account sits on the 256-bit path H(address), with H the chain's hash
+// algorithm. A subtree holding no accounts hashes to all zeros; one holding a single
+// account is that account's leaf, H(0x00 || path || account preimage), placed as
+// high as it can go without sharing a subtree; one holding more is
+// H(0x01 || left || right). The tree for a set of accounts is therefore always the
+// same, whatever order they were added and removed in.
+//
+// Stored nodes are keyed by their depth (2 bytes, big-endian) and the bits of the
+// path above them, and hold either 0x00, the path and the leaf hash, or 0x01 and
+// the branch hash.
+
+const LEAF: u8 = 0;
+const BRANCH: u8 = 1;
+const EMPTY: [u8; 32] = [0; 32];
+const PATH_BITS: usize = 256;
+
+// Proof from `Blockchain::account_proof` that an account is part of a state.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccountProof {
+    // Hashes of the subtrees beside the account's path, leaf to root; all zeros for
+    // an empty one. The path itself follows from the address.
+    pub siblings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Node {
+    Empty,
+    Leaf { path: [u8; 32], hash: [u8; 32] },
+    Branch([u8; 32]),
+}
+
+impl Node {
+    fn hash(&self) -> [u8; 32] {
+        match self {
+            Node::Empty => EMPTY,
+            Node::Leaf { hash, .. } | Node::Branch(hash) => *hash,
+        }
+    }
+
+    fn encode(&self) -> Option<Vec<u8>> {
+        match self {
+            Node::Empty => None,
+            Node::Leaf { path, hash } => Some([&[LEAF][..], path, hash].concat()),
+            Node::Branch(hash) => Some([&[BRANCH][..], hash].concat()),
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Node, Box<dyn Error>> {
+        match bytes {
+            [LEAF, rest @ ..] if rest.len() == 64 => {
+                Ok(Node::Leaf { path: rest[..32].try_into()?, hash: rest[32..].try_into()? })
+            }
+            [BRANCH, rest @ ..] if rest.len() == 32 => Ok(Node::Branch(rest.try_into()?)),
+            _ => Err(format!("Damaged state tree node of {} bytes", bytes.len()).into()),
+        }
+    }
+}
+
+// Reads a stored node record by key.
+pub(crate) type ReadNode<'a> = Box<dyn Fn(&[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> + 'a>;
+
+// The stored tree seen through `read`, with changes kept in `writes` until the
+// caller stages them, or drops them to only look at the result.
+pub(crate) struct StateTree<'a> {
+    algorithm: HashAlgorithm,
+    read: ReadNode<'a>,
+    // Node key -> its new record, None where the node was removed.
+    pub(crate) writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> StateTree<'a> {
+    pub(crate) fn new(algorithm: HashAlgorithm, read: ReadNode<'a>) -> Self {
+        StateTree { algorithm, read, writes: BTreeMap::new() }
+    }
+
+    // Puts `account` in the place of `address`, or removes it for None.
+    pub(crate) fn set(&mut self, address: &str, account: Option<&Account>) -> Result<(), Box<dyn Error>> {
+        let path = self.algorithm.digest(address.as_bytes());
+        let leaf = account.map(|account| account_leaf(self.algorithm, &path, address, account));
+        self.set_at(0, &path, leaf)?;
+        Ok(())
+    }
+
+    // Hex root, or an empty string when there are no accounts.
+    pub(crate) fn root(&self) -> Result<String, Box<dyn Error>> {
+        Ok(match self.node(0, &EMPTY)? {
+            Node::Empty => String::new(),
+            node => hex::encode(node.hash()),
+        })
+    }
+
+    // None for an address without an account.
+    pub(crate) fn proof(&self, address: &str) -> Result<Option<AccountProof>, Box<dyn Error>> {
+        let path = self.algorithm.digest(address.as_bytes());
+        let mut siblings = Vec::new();
+        for depth in 0..=PATH_BITS {
+            match self.node(depth, &path)? {
+                Node::Empty => return Ok(None),
+                Node::Leaf { path: found, .. } => {
+                    if found != path {
+                        return Ok(None);
+                    }
+                    siblings.reverse();
+                    return Ok(Some(AccountProof { siblings }));
+                }
+                Node::Branch(_) => siblings.push(hex::encode(self.node(depth + 1, &flip(&path, depth))?.hash())),
+            }
+        }
+        Err("The state tree runs deeper than its paths".into())
+    }
+
+    fn node(&self, depth: usize, path: &[u8; 32]) -> Result<Node, Box<dyn Error>> {
+        let key = node_key(depth, path);
+        let record = match self.writes.get(&key) {
+            Some(staged) => staged.clone(),
+            None => (self.read)(&key)?,
+        };
+        record.map_or(Ok(Node::Empty), |bytes| Node::decode(&bytes))
+    }
+
+    fn put(&mut self, depth: usize, path: &[u8; 32], node: Node) {
+        self.writes.insert(node_key(depth, path), node.encode());
+    }
+
+    // Sets the leaf on `path` in the subtree at `depth` and returns what the subtree
+    // has become.
+    fn set_at(&mut self, depth: usize, path: &[u8; 32], leaf: Option<[u8; 32]>) -> Result<Node, Box<dyn Error>> {
+        let updated = match self.node(depth, path)? {
+            Node::Empty => match leaf {
+                Some(hash) => Node::Leaf { path: *path, hash },
+                None => return Ok(Node::Empty),
+            },
+            Node::Leaf { path: found, .. } if found == *path => leaf.map_or(Node::Empty, |hash| Node::Leaf { path: *path, hash }),
+            other @ Node::Leaf { path: found, .. } => {
+                if leaf.is_none() {
+                    return Ok(other);
+                }
+                if depth == PATH_BITS {
+                    return Err("Two addresses share a state tree path".into());
+                }
+                // A second account joins the subtree: the one here moves down a level
+                // and this becomes a branch.
+                self.put(depth + 1, &found, other);
+                self.branch_at(depth, path, leaf)?
+            }
+            Node::Branch(_) => self.branch_at(depth, path, leaf)?,
+        };
+        self.put(depth, path, updated);
+        Ok(updated)
+    }
+
+    fn branch_at(&mut self, depth: usize, path: &[u8; 32], leaf: Option<[u8; 32]>) -> Result<Node, Box<dyn Error>> {
+        let child = self.set_at(depth + 1, path, leaf)?;
+        let sibling = self.node(depth + 1, &flip(path, depth))?;
+        Ok(match (child, sibling) {
+            (Node::Empty, Node::Empty) => Node::Empty,
+            // An account left alone in the subtree moves up into its place.
+            (only @ Node::Leaf { path: moved, .. }, Node::Empty) | (Node::Empty, only @ Node::Leaf { path: moved, .. }) => {
+                self.put(depth + 1, &moved, Node::Empty);
+                only
+            }
+            (child, sibling) => {
+                let (left, right) = if bit(path, depth) { (sibling, child) } else { (child, sibling) };
+                Node::Branch(branch(self.algorithm, &left.hash(), &right.hash()))
+            }
+        })
+    }
+}
+
+// Whether `proof` places `address` holding `account` under `root`.
+pub fn verify_proof(algorithm: HashAlgorithm, root: &str, address: &str, account: &Account, proof: &AccountProof) -> bool {
+    let depth = proof.siblings.len();
+    if depth > PATH_BITS {
+        return false;
+    }
+    let path = algorithm.digest(address.as_bytes());
+    let mut hash = account_leaf(algorithm, &path, address, account);
+    for (up, sibling) in proof.siblings.iter().enumerate() {
+        let Some(sibling) = hex::decode(sibling).ok().and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()) else {
+            return false;
+        };
+        hash = if bit(&path, depth - 1 - up) { branch(algorithm, &sibling, &hash) } else { branch(algorithm, &hash, &sibling) };
+    }
+    hex::encode(hash) == root
+}
+
+fn account_leaf(algorithm: HashAlgorithm, path: &[u8; 32], address: &str, account: &Account) -> [u8; 32] {
+    let mut preimage = vec![LEAF];
+    preimage.extend_from_slice(path);
+    preimage.extend_from_slice(&hashing::account_preimage(address, account));
+    algorithm.digest(&preimage)
+}
+
+fn branch(algorithm: HashAlgorithm, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut preimage = Vec::with_capacity(65);
+    preimage.push(BRANCH);
+    preimage.extend_from_slice(left);
+    preimage.extend_from_slice(right);
+    algorithm.digest(&preimage)
+}
+
+fn node_key(depth: usize, path: &[u8; 32]) -> Vec<u8> {
+    let mut key = (depth as u16).to_be_bytes().to_vec();
+    key.extend_from_slice(&path[..depth.div_ceil(8)]);
+    if !depth.is_multiple_of(8) {
+        *key.last_mut().expect("a partial byte") &= 0xff << (8 - depth % 8);
+    }
+    key
+}
+
+// Whether `path` goes right below `depth`.
+fn bit(path: &[u8; 32], depth: usize) -> bool {
+    path[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+fn flip(path: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut flipped = *path;
+    flipped[depth / 8] ^= 0x80 >> (depth % 8);
+    flipped
+}
@@ -0,0 +1,63 @@
+use chrono::DateTime;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::blockchain::Blockchain;
+use crate::store::BlockStore;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChainStats {
+    // Canonical blocks, genesis included.
+    pub blocks: u64,
+    // Transactions in canonical blocks, coinbases included. Pruned blocks no longer
+    // hold theirs, so they count as empty.
+    pub transactions: u64,
+    // Mean time between consecutive blocks after genesis, in milliseconds.
+    pub average_block_interval_ms: Option<u64>,
+    // Everything sled keeps on disk for this database.
+    pub size_on_disk: u64,
+    // UTC day (YYYY-MM-DD) -> blocks timestamped that day. The genesis block is left
+    // out, since its timestamp comes from the config.
+    pub blocks_per_day: BTreeMap<String, u64>,
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Walks the canonical chain once. Blocks below a snapshot bootstrap's trusted base
+    // are not stored and are skipped.
+    pub fn stats(&self) -> Result<ChainStats, Box<dyn Error>> {
+        let height = self.height()?;
+        let mut stats = ChainStats {
+            blocks: height + 1,
+            transactions: 0,
+            average_block_interval_ms: None,
+            size_on_disk: self.store().size_on_disk()?,
+            blocks_per_day: BTreeMap::new(),
+        };
+
+        let mut first: Option<(u64, u64)> = None;
+        let mut last: Option<(u64, u64)> = None;
+        for height in 0..=height {
+            let Some(hash) = self.canonical_hash(height)? else { continue };
+            let Some(block) = self.load_block(&hash)? else { continue };
+            stats.transactions += block.transactions.len() as u64;
+            if block.is_genesis() {
+                continue;
+            }
+            let day = DateTime::from_timestamp_millis(block.timestamp as i64)
+                .map(|time| time.format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|| "invalid".to_string());
+            *stats.blocks_per_day.entry(day).or_default() += 1;
+            first.get_or_insert((height, block.timestamp));
+            last = Some((height, block.timestamp));
+        }
+
+        if let (Some((first_height, first_time)), Some((last_height, last_time))) = (first, last)
+            && last_height > first_height
+        {
+            stats.average_block_interval_ms =
+                Some(last_time.saturating_sub(first_time) / (last_height - first_height));
+        }
+        Ok(stats)
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+#[cfg(feature = "sled")]
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "sled")]
+use sled::Transactional;
+
+#[cfg(feature = "sled")]
+use crate::error::LedgerError;
+#[cfg(feature = "sled")]
+use crate::lockfile::{self, LockFile};
+#[cfg(feature = "sled")]
+use crate::registry;
+
+// The trees a chain keeps its records in. `Blocks` holds the blocks under their hash,
+// next to named keys such as "LAST" (the tip pointer) and "GENESIS".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TreeId {
+    Blocks,
+    Meta,    // block hash -> BlockMeta
+    Heights, // height (big-endian) -> hash of the canonical block
+    Tips,    // hashes of blocks nobody builds on yet
+    State,   // address -> Account
+    Undo,    // block hash -> account values before the block was applied
+    Invalid, // blocks on a branch that failed to connect
+    Pruned,  // blocks whose body was dropped
+    Quarantine, // damaged block records set aside by `repair`
+    Search,  // token, 0, block hash -> nothing (see search.rs)
+    History, // address, 0, height, position -> HistoryEntry (see history.rs)
+    Snapshots, // height (big-endian) -> state snapshot (see snapshot.rs)
+    Checkpoints, // height (big-endian) -> signed Checkpoint (see checkpoint.rs)
+    Sync,    // progress and headers of an interrupted sync (see sync.rs)
+    Peers,   // address -> JSON PeerRecord (see peers.rs)
+    Transactions, // txid, height, position -> block hash (see txindex.rs)
+    Headers, // block hash -> BlockHeader, kept apart from the body (see header.rs)
+    Proposals, // block hash -> block record waiting for approval (see approval.rs)
+    Replays, // name -> JSON ReplayToken of the last block visited (see replay.rs)
+    Journal, // the operation under way, with its writes (see journal.rs)
+    Anchors, // height (big-endian), tip hash -> Anchor published for it (see anchor.rs)
+    Holds,   // "block:<hash>" or "tx:<txid>" -> retention class and legal hold (see retention.rs)
+    Blobs,   // content hash -> attachment referenced by blocks (see blobs.rs)
+    StateNodes, // depth, path prefix -> node of the state tree (see state_tree.rs)
+    Reports, // name -> JSON ReplayToken of the last block a named report covered (see report.rs)
+}
+
+impl TreeId {
+    pub const ALL: [TreeId; 25] = [
+        TreeId::Blocks,
+        TreeId::Meta,
+        TreeId::Heights,
+        TreeId::Tips,
+        TreeId::State,
+        TreeId::Undo,
+        TreeId::Invalid,
+        TreeId::Pruned,
+        TreeId::Quarantine,
+        TreeId::Search,
+        TreeId::History,
+        TreeId::Snapshots,
+        TreeId::Checkpoints,
+        TreeId::Sync,
+        TreeId::Peers,
+        TreeId::Transactions,
+        TreeId::Headers,
+        TreeId::Proposals,
+        TreeId::Replays,
+        TreeId::Journal,
+        TreeId::Anchors,
+        TreeId::Holds,
+        TreeId::Blobs,
+        TreeId::StateNodes,
+        TreeId::Reports,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TreeId::Blocks => "__sled__default",
+            TreeId::Meta => "block_meta",
+            TreeId::Heights => "heights",
+            TreeId::Tips => "tips",
+            TreeId::State => "state",
+            TreeId::Undo => "undo",
+            TreeId::Invalid => "invalid",
+            TreeId::Pruned => "pruned",
+            TreeId::Quarantine => "quarantine",
+            TreeId::Search => "search",
+            TreeId::History => "history",
+            TreeId::Snapshots => "snapshots",
+            TreeId::Checkpoints => "checkpoints",
+            TreeId::Sync => "sync",
+            TreeId::Peers => "peers",
+            TreeId::Transactions => "transactions",
+            TreeId::Headers => "headers",
+            TreeId::Proposals => "proposals",
+            TreeId::Replays => "replays",
+            TreeId::Journal => "journal",
+            TreeId::Anchors => "anchors",
+            TreeId::Holds => "holds",
+            TreeId::Blobs => "blobs",
+            TreeId::StateNodes => "state_nodes",
+            TreeId::Reports => "reports",
+        }
+    }
+}
+
+// Staged writes for `BlockStore::apply`; `None` removes the key.
+pub type Writes = BTreeMap<(TreeId, Vec<u8>), Option<Vec<u8>>>;
+
+// A key and its value.
+pub type Record = (Vec<u8>, Vec<u8>);
+
+pub type Entries<'a> = Box<dyn Iterator<Item = Result<Record, Box<dyn Error>>> + 'a>;
+
+// Where a chain keeps its records. A store only holds bytes in the trees above;
+// blocks, the tip pointer and the indexes are encoded by the chain on top of it,
+// so another backend (RocksDB, SQLite, ...) only has to provide these operations.
+pub trait BlockStore: Clone + Send + Sync + 'static {
+    fn get(&self, tree: TreeId, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+
+    // Hands the value to `read` where it lies, for reads that only decode it. A
+    // backend that can lend its buffers (sled does) saves the copy `get` makes.
+    fn get_with<T>(
+        &self,
+        tree: TreeId,
+        key: &[u8],
+        read: impl FnOnce(&[u8]) -> Result<T, Box<dyn Error>>,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        self.get(tree, key)?.map(|bytes| read(&bytes)).transpose()
+    }
+
+    // The records of `tree` whose key starts with `prefix`, in key order. An empty
+    // prefix lists the whole tree.
+    fn scan_prefix(&self, tree: TreeId, prefix: &[u8]) -> Entries<'_>;
+
+    // Applies all the writes or none of them, even across a crash.
+    fn apply(&self, writes: &Writes) -> Result<(), Box<dyn Error>>;
+
+    // Makes everything applied so far durable.
+    fn flush(&self) -> Result<(), Box<dyn Error>>;
+
+    fn size_on_disk(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(0)
+    }
+
+    fn insert(&self, tree: TreeId, key: &[u8], value: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        self.apply(&Writes::from([((tree, key.to_vec()), Some(value))]))
+    }
+
+    fn is_empty(&self, tree: TreeId) -> Result<bool, Box<dyn Error>> {
+        Ok(self.scan_prefix(tree, &[]).next().is_none())
+    }
+
+    // The record with the greatest key.
+    fn last(&self, tree: TreeId) -> Result<Option<Record>, Box<dyn Error>> {
+        self.scan_prefix(tree, &[]).last().transpose()
+    }
+
+    // A read-only store refuses every write; the chain on top skips the upkeep it
+    // would otherwise do when opened.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+// What `Blockchain` and the types built on it keep their records in when no store
+// is named: sled, or process memory in builds without it.
+#[cfg(feature = "sled")]
+pub type DefaultStore = SledStore;
+#[cfg(not(feature = "sled"))]
+pub type DefaultStore = MemoryStore;
+
+#[cfg(feature = "sled")]
+// The default backend: one sled tree per `TreeId`, with batches applied as a sled
+// transaction.
+#[derive(Clone)]
+pub struct SledStore {
+    db: sled::Db,
+    trees: Vec<sled::Tree>,
+    read_only: bool,
+    // The `LockFile` of a store opened from a path, kept until the last clone is gone.
+    // After the trees, so sled is closed first.
+    _lock: Option<Arc<LockFile>>,
+}
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    // Fails with `LedgerError::AlreadyInUse` while another process has the database
+    // open; see `lockfile`.
+    pub fn open(path: &str) -> Result<SledStore, Box<dyn Error>> {
+        let (db, lock) = lockfile::open_locked(path)?;
+        Ok(SledStore { _lock: Some(Arc::new(lock)), ..Self::from_db(db)? })
+    }
+
+    // Opens an existing database without writing to it. sled allows one process per
+    // database, so this is for databases no node has open, and fails with
+    // `LedgerError::AlreadyInUse` while one does; read its replica (see `replica`) or
+    // a backup of it instead. (sled itself may still write recovery data, and trees
+    // added since the database was created, when it opens.)
+    pub fn open_read_only(path: &str) -> Result<SledStore, Box<dyn Error>> {
+        Ok(SledStore { read_only: true, ..Self::from_db(Self::open_db_read_only(path)?)? })
+    }
+
+    // The named chain `chain` of the database at `path`, read-only.
+    pub fn open_chain_read_only(path: &str, chain: &str) -> Result<SledStore, Box<dyn Error>> {
+        Ok(SledStore { read_only: true, ..Self::for_chain(Self::open_db_read_only(path)?, chain)? })
+    }
+
+    fn open_db_read_only(path: &str) -> Result<sled::Db, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Err(format!("No database at {}", path).into());
+        }
+        lockfile::check_unlocked(Path::new(path))?;
+        match sled::open(path) {
+            Ok(db) => Ok(db),
+            Err(e) if lockfile::is_lock_error(&e) => Err(Box::new(LedgerError::AlreadyInUse { pid: None })),
+            Err(e) => Err(format!("Cannot open {}: {}", path, e).into()),
+        }
+    }
+
+    // The database's default chain, kept in the unprefixed trees.
+    pub fn from_db(db: sled::Db) -> Result<SledStore, Box<dyn Error>> {
+        let mut trees = Vec::with_capacity(TreeId::ALL.len());
+        for id in TreeId::ALL {
+            trees.push(match id {
+                TreeId::Blocks => (*db).clone(),
+                _ => db.open_tree(id.name())?,
+            });
+        }
+        Ok(SledStore { db, trees, read_only: false, _lock: None })
+    }
+
+    // One of several named chains sharing `db`, each with trees of its own named
+    // "chain/NAME/TREE". See `registry` for creating and listing them.
+    pub fn for_chain(db: sled::Db, chain: &str) -> Result<SledStore, Box<dyn Error>> {
+        registry::check_name(chain)?;
+        let mut trees = Vec::with_capacity(TreeId::ALL.len());
+        for id in TreeId::ALL {
+            trees.push(db.open_tree(chain_tree_name(chain, id))?);
+        }
+        Ok(SledStore { db, trees, read_only: false, _lock: None })
+    }
+
+    fn tree(&self, id: TreeId) -> &sled::Tree {
+        &self.trees[id as usize]
+    }
+}
+
+#[cfg(feature = "sled")]
+impl BlockStore for SledStore {
+    fn get(&self, tree: TreeId, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Ok(self.tree(tree).get(key)?.map(|bytes| bytes.to_vec()))
+    }
+
+    fn get_with<T>(
+        &self,
+        tree: TreeId,
+        key: &[u8],
+        read: impl FnOnce(&[u8]) -> Result<T, Box<dyn Error>>,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        self.tree(tree).get(key)?.map(|bytes| read(&bytes)).transpose()
+    }
+
+    fn scan_prefix(&self, tree: TreeId, prefix: &[u8]) -> Entries<'_> {
+        Box::new(self.tree(tree).scan_prefix(prefix).map(|entry| {
+            let (key, value) = entry?;
+            Ok((key.to_vec(), value.to_vec()))
+        }))
+    }
+
+    fn apply(&self, writes: &Writes) -> Result<(), Box<dyn Error>> {
+        if self.read_only {
+            return Err("The database is open read-only".into());
+        }
+        let trees: Vec<&sled::Tree> = TreeId::ALL.iter().map(|id| self.tree(*id)).collect();
+        trees
+            .as_slice()
+            .transaction(|views| {
+                for ((tree, key), value) in writes {
+                    let view = &views[*tree as usize];
+                    match value {
+                        Some(value) => view.insert(key.as_slice(), value.as_slice())?,
+                        None => view.remove(key.as_slice())?,
+                    };
+                }
+                Ok(())
+            })
+            .map_err(|e: sled::transaction::TransactionError| format!("Transaction failed: {}", e))?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn Error>> {
+        if !self.read_only {
+            self.db.flush()?;
+        }
+        Ok(())
+    }
+
+    fn size_on_disk(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.db.size_on_disk()?)
+    }
+
+    fn is_empty(&self, tree: TreeId) -> Result<bool, Box<dyn Error>> {
+        Ok(self.tree(tree).is_empty())
+    }
+
+    fn last(&self, tree: TreeId) -> Result<Option<Record>, Box<dyn Error>> {
+        Ok(self.tree(tree).last()?.map(|(key, value)| (key.to_vec(), value.to_vec())))
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+#[cfg(feature = "sled")]
+// The sled tree holding `id` for the named chain `chain`.
+pub(crate) fn chain_tree_name(chain: &str, id: TreeId) -> String {
+    let tree = if id == TreeId::Blocks { "blocks" } else { id.name() };
+    format!("chain/{}/{}", chain, tree)
+}
+
+// Keeps everything in process memory; nothing survives the last handle. For tests
+// and demos.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    trees: Arc<RwLock<BTreeMap<TreeId, Records>>>,
+}
+
+type Records = BTreeMap<Vec<u8>, Vec<u8>>;
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl BlockStore for MemoryStore {
+    fn get(&self, tree: TreeId, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Ok(self.trees.read().unwrap().get(&tree).and_then(|records| records.get(key).cloned()))
+    }
+
+    // Copies the matching records out, so the lock isn't held while the caller writes.
+    fn scan_prefix(&self, tree: TreeId, prefix: &[u8]) -> Entries<'_> {
+        let trees = self.trees.read().unwrap();
+        let entries: Vec<_> = trees
+            .get(&tree)
+            .into_iter()
+            .flat_map(|records| records.range(prefix.to_vec()..))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| Ok((key.clone(), value.clone())))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+
+    fn apply(&self, writes: &Writes) -> Result<(), Box<dyn Error>> {
+        let mut trees = self.trees.write().unwrap();
+        for ((tree, key), value) in writes {
+            let records = trees.entry(*tree).or_default();
+            match value {
+                Some(value) => records.insert(key.clone(), value.clone()),
+                None => records.remove(key),
+            };
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+use tonic::transport::{Channel, Endpoint};
+use tracing::{info, warn};
+
+use crate::block::Block;
+use crate::blockchain::{check_network_id, check_sequence, check_timestamp, Blockchain};
+use crate::genesis::GenesisConfig;
+use crate::grpc::proto::ledger_client::LedgerClient;
+use crate::grpc::proto::{GetPeersRequest, GetTipRequest, RangeRequest, Tip};
+use crate::grpc::{block_from_proto, header_from_proto};
+use crate::hashing::HEADER_V2;
+use crate::header::BlockHeader;
+use crate::peers::{normalize_address, Misbehavior};
+use crate::pow;
+use crate::store::{BlockStore, TreeId, Writes};
+
+// Initial block download from peers serving the gRPC API: pick the peer with the most
+// work, find where its chain leaves ours, fetch and check its headers, then fetch the
+// bodies in parallel batches and add them with `add_blocks`, a batch per commit.
+//
+// Progress lives in the "sync" tree, so an interrupted sync from the same peer
+// carries on where it stopped:
+//   "STATE"                -> JSON SyncState
+//   'h', height (big-endian) -> hash of the peer's block at that height
+//   'b', hash              -> its header (MessagePack)
+
+const STATE_KEY: &[u8] = b"STATE";
+const HEADER_BATCH: u32 = 2000;
+const BLOCK_BATCH: u32 = 250;
+// Block responses are capped at 16 MiB by the server, plus one block over.
+const MAX_MESSAGE_BYTES: usize = 64 << 20;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SyncState {
+    pub peer: String,
+    // Highest height where both chains hold the same block.
+    pub fork_height: u64,
+    pub target_height: u64,
+    pub target_hash: String,
+    // Headers are checked up to `headers_to`, blocks added up to `blocks_to`.
+    pub headers_to: u64,
+    pub blocks_to: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SyncReport {
+    // None when no peer could be reached.
+    pub peer: Option<String>,
+    pub headers: u64,
+    pub blocks: u64,
+    pub height: u64,
+}
+
+type Client = LedgerClient<Channel>;
+
+impl<S: BlockStore> Blockchain<S> {
+    // The sync in progress, if one was interrupted.
+    pub fn sync_state(&self) -> Result<Option<SyncState>, Box<dyn Error>> {
+        match self.store().get(TreeId::Sync, STATE_KEY)? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Catches up with the best of `peers` (e.g. "10.0.0.2:50051") and the peers in the
+    // peer store, running up to `parallel` block requests at once. Peers that cannot
+    // be reached or are banned are skipped.
+    pub async fn sync(&self, peers: &[String], parallel: usize) -> Result<SyncReport, Box<dyn Error>> {
+        let mut candidates: Vec<String> = Vec::new();
+        for address in peers.iter().map(|peer| normalize_address(peer)).chain(self.good_peers()?) {
+            if self.is_banned(&address)? {
+                warn!(peer = %address, "skipping banned peer");
+            } else if !candidates.contains(&address) {
+                candidates.push(address);
+            }
+        }
+        let Some((peer, mut client, tip)) = self.best_peer(&candidates).await? else {
+            return Ok(SyncReport { peer: None, headers: 0, blocks: 0, height: self.height()? });
+        };
+        let local_work = self.tip_meta()?.total_work;
+        if tip.total_work.parse::<u128>().unwrap_or(0) <= local_work {
+            info!(%peer, "already in sync");
+            return Ok(SyncReport { peer: Some(peer), headers: 0, blocks: 0, height: self.height()? });
+        }
+
+        let mut state = match self.sync_state()? {
+            Some(state) if state.peer == peer => {
+                info!(%peer, headers_to = state.headers_to, blocks_to = state.blocks_to, "resuming sync");
+                state
+            }
+            _ => {
+                self.clear_sync()?;
+                let fork_height = self.find_fork(&mut client, tip.height).await?;
+                SyncState {
+                    peer: peer.clone(),
+                    fork_height,
+                    target_height: fork_height,
+                    target_hash: String::new(),
+                    headers_to: fork_height,
+                    blocks_to: fork_height,
+                }
+            }
+        };
+        state.target_height = tip.height;
+        state.target_hash = tip.hash;
+
+        // A peer that sends something invalid starts the next sync from scratch.
+        let result = match self.download_headers(&mut client, &mut state).await {
+            Ok(headers) => self.download_blocks(&client, &mut state, parallel.max(1)).await.map(|blocks| (headers, blocks)),
+            Err(e) => Err(e),
+        };
+        let (headers, blocks) = result?;
+        self.clear_sync()?;
+        info!(%peer, headers, blocks, height = state.blocks_to, "sync finished");
+        Ok(SyncReport { peer: Some(peer), headers, blocks, height: self.height()? })
+    }
+
+    // Highest height at which the peer's canonical block is also ours, found by
+    // bisection: the chains agree up to the fork and differ above it.
+    async fn find_fork(&self, client: &mut Client, peer_height: u64) -> Result<u64, Box<dyn Error>> {
+        if peer_header(client, 0).await?.hash != self.canonical_hash(0)?.unwrap_or_default() {
+            return Err("The peer is on a chain with another genesis block".into());
+        }
+        let (mut low, mut high) = (0, self.height()?.min(peer_height));
+        while low < high {
+            let middle = low + (high - low).div_ceil(2);
+            if Some(peer_header(client, middle).await?.hash) == self.canonical_hash(middle)? {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        Ok(low)
+    }
+
+    // Fetches and checks headers up to the target, like `HeaderChain::add_header`
+    // does, recording each batch. Returns how many were fetched.
+    async fn download_headers(&self, client: &mut Client, state: &mut SyncState) -> Result<u64, Box<dyn Error>> {
+        let genesis = self.genesis_config();
+        let mut parent = self.sync_header(state.headers_to, state)?;
+        let mut fetched = 0;
+        while state.headers_to < state.target_height {
+            let count = (state.target_height - state.headers_to).min(HEADER_BATCH as u64) as u32;
+            let response = client.get_headers(RangeRequest { from_height: state.headers_to + 1, count }).await?.into_inner();
+            if response.headers.is_empty() {
+                self.penalize_peer(&state.peer, Misbehavior::Unresponsive)?;
+                return Err(format!("The peer has no headers past height {}", state.headers_to).into());
+            }
+
+            let mut batch: HashMap<String, BlockHeader> = HashMap::new();
+            let mut writes = Writes::new();
+            for header in response.headers {
+                let header = header_from_proto(header)?;
+                let height = state.headers_to + 1;
+                let checked = self.check_header(&genesis, &header, &parent, height, &batch);
+                if let Err(e) = checked {
+                    self.clear_sync()?;
+                    self.penalize_peer(&state.peer, Misbehavior::InvalidBlock)?;
+                    return Err(e);
+                }
+                writes.insert((TreeId::Sync, height_key(height)), Some(header.hash.clone().into_bytes()));
+                writes.insert((TreeId::Sync, hash_key(&header.hash)), Some(rmp_serde::to_vec(&header)?));
+                batch.insert(header.hash.clone(), header.clone());
+                parent = header;
+                state.headers_to = height;
+                fetched += 1;
+            }
+            writes.insert((TreeId::Sync, STATE_KEY.to_vec()), Some(serde_json::to_vec(state)?));
+            self.store().apply(&writes)?;
+            self.store().flush()?;
+            info!(headers_to = state.headers_to, target = state.target_height, "sync headers");
+        }
+        Ok(fetched)
+    }
+
+    fn check_header(
+        &self,
+        genesis: &GenesisConfig,
+        header: &BlockHeader,
+        parent: &BlockHeader,
+        height: u64,
+        batch: &HashMap<String, BlockHeader>,
+    ) -> Result<(), Box<dyn Error>> {
+        if header.prev_hash != parent.hash {
+            return Err(format!("Header {} at height {} does not build on {}", header.hash, height, parent.hash).into());
+        }
+        // Older hash versions only commit to the whole block, which is checked later.
+        if header.version >= HEADER_V2 && header.hash != header.calculate_hash() {
+            return Err(format!("Hash mismatch for header {}", header.hash).into());
+        }
+        check_timestamp(&header.hash, header.timestamp, parent.timestamp, self.clock.now_ms(), self.config.max_future_drift_ms)?;
+        check_sequence(header, parent)?;
+        check_network_id(header, parent, &genesis.network_id()?)?;
+        pow::check_work(genesis, header, parent, height, |hash| match batch.get(hash) {
+            Some(header) => Ok(Some(header.clone())),
+            None => self.lookup_header(hash),
+        })
+    }
+
+    // Fetches the bodies for the checked headers, `parallel` batches at a time, and
+    // adds them in height order. Returns how many were added.
+    async fn download_blocks(&self, client: &Client, state: &mut SyncState, parallel: usize) -> Result<u64, Box<dyn Error>> {
+        let mut next = state.blocks_to + 1;
+        // Ranges a response came back short of, to ask for again.
+        let mut retry: VecDeque<(u64, u64)> = VecDeque::new();
+        // Fetched batches waiting for the ones below them, by first height.
+        let mut ready: BTreeMap<u64, Vec<Block>> = BTreeMap::new();
+        let mut tasks = JoinSet::new();
+        let mut added = 0;
+        loop {
+            while tasks.len() < parallel && ready.len() < parallel * 4 {
+                let (from, to) = match retry.pop_front() {
+                    Some(range) => range,
+                    None if next <= state.headers_to => {
+                        let to = (next + BLOCK_BATCH as u64 - 1).min(state.headers_to);
+                        let range = (next, to);
+                        next = to + 1;
+                        range
+                    }
+                    None => break,
+                };
+                let mut client = client.clone();
+                tasks.spawn(async move {
+                    let request = RangeRequest { from_height: from, count: (to - from + 1) as u32 };
+                    ((from, to), client.get_blocks(request).await)
+                });
+            }
+            let Some(joined) = tasks.join_next().await else { break };
+            let ((from, to), response) = joined?;
+            let blocks = response?.into_inner().blocks;
+            if blocks.is_empty() {
+                self.penalize_peer(&state.peer, Misbehavior::Unresponsive)?;
+                return Err(format!("The peer has no blocks past height {}", from - 1).into());
+            }
+            if from + (blocks.len() as u64) <= to {
+                retry.push_back((from + blocks.len() as u64, to));
+            }
+            ready.insert(from, blocks.into_iter().map(block_from_proto).collect::<Result<_, _>>()?);
+
+            while let Some(blocks) = ready.remove(&(state.blocks_to + 1)) {
+                let count = blocks.len() as u64;
+                let outcome = match self.check_bodies(&blocks, state.blocks_to + 1) {
+                    Ok(()) => self.spawn(move |chain| chain.add_blocks(&blocks)).await.map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = outcome {
+                    self.clear_sync()?;
+                    self.penalize_peer(&state.peer, Misbehavior::InvalidBlock)?;
+                    return Err(e);
+                }
+                state.blocks_to += count;
+                added += count;
+                self.store().insert(TreeId::Sync, STATE_KEY, serde_json::to_vec(state)?)?;
+                info!(blocks_to = state.blocks_to, target = state.target_height, "sync blocks");
+            }
+        }
+        Ok(added)
+    }
+
+    // Each block must be the one whose header was checked for its height.
+    fn check_bodies(&self, blocks: &[Block], from_height: u64) -> Result<(), Box<dyn Error>> {
+        for (offset, block) in blocks.iter().enumerate() {
+            let height = from_height + offset as u64;
+            let expected = self.store().get(TreeId::Sync, &height_key(height))?.map(String::from_utf8).transpose()?;
+            if expected.as_deref() != Some(block.hash.as_str()) {
+                return Err(format!("The peer sent block {} for height {}, not the one its headers named", block.hash, height).into());
+            }
+        }
+        Ok(())
+    }
+
+    // The header at `height` of the chain being synced: a fetched one above the fork,
+    // our own at or below it.
+    fn sync_header(&self, height: u64, state: &SyncState) -> Result<BlockHeader, Box<dyn Error>> {
+        if height > state.fork_height
+            && let Some(hash) = self.store().get(TreeId::Sync, &height_key(height))?
+        {
+            return self.lookup_header(&String::from_utf8(hash)?)?.ok_or_else(|| "The sync headers are incomplete".into());
+        }
+        let hash = self.canonical_hash(height)?.ok_or_else(|| format!("No block at height {}", height))?;
+        self.header(&hash)?.ok_or_else(|| format!("Block {} is missing", hash).into())
+    }
+
+    fn lookup_header(&self, hash: &str) -> Result<Option<BlockHeader>, Box<dyn Error>> {
+        match self.store().get(TreeId::Sync, &hash_key(hash))? {
+            Some(bytes) => Ok(Some(rmp_serde::from_slice(&bytes)?)),
+            None => self.header(hash),
+        }
+    }
+
+    // The reachable peer claiming the most work, with a client connected to it. Every
+    // peer reached is asked for the peers it knows, and told ours if we advertise one.
+    async fn best_peer(&self, peers: &[String]) -> Result<Option<(String, Client, Tip)>, Box<dyn Error>> {
+        let advertise = self.config.advertise_address.clone().unwrap_or_default();
+        let network_id = self.network_id()?;
+        let mut best: Option<(String, Client, Tip)> = None;
+        for peer in peers {
+            let contact = async {
+                let channel = Endpoint::from_shared(format!("http://{}", peer))?
+                    .connect_timeout(CONNECT_TIMEOUT)
+                    .connect()
+                    .await?;
+                let mut client = LedgerClient::new(channel).max_decoding_message_size(MAX_MESSAGE_BYTES);
+                let tip = client.get_tip(GetTipRequest {}).await?.into_inner();
+                // Peers that predate chain IDs send none; their blocks are checked all the same.
+                if !tip.network_id.is_empty() && tip.network_id != network_id {
+                    return Ok(None);
+                }
+                let request = GetPeersRequest { advertise: advertise.clone(), network_id: network_id.clone() };
+                let shared = client.get_peers(request).await?.into_inner();
+                Ok::<_, Box<dyn Error>>(Some((client, tip, shared.addresses)))
+            };
+            match contact.await {
+                Ok(None) => {
+                    warn!(%peer, "peer is on another chain");
+                    self.penalize_peer(peer, Misbehavior::WrongChain)?;
+                }
+                Ok(Some((client, tip, shared))) => {
+                    self.peer_seen(peer)?;
+                    let learned = self.learn_peers(&shared)?;
+                    if learned > 0 {
+                        info!(%peer, learned, "learned peers");
+                    }
+                    let work = |tip: &Tip| tip.total_work.parse::<u128>().unwrap_or(0);
+                    if best.as_ref().is_none_or(|(_, _, best)| work(&tip) > work(best)) {
+                        best = Some((peer.clone(), client, tip));
+                    }
+                }
+                Err(e) => warn!(%peer, error = %e, "peer unreachable"),
+            }
+        }
+        Ok(best)
+    }
+
+    fn clear_sync(&self) -> Result<(), Box<dyn Error>> {
+        let mut writes = Writes::new();
+        for entry in self.store().scan_prefix(TreeId::Sync, &[]) {
+            let (key, _) = entry?;
+            writes.insert((TreeId::Sync, key), None);
+        }
+        if !writes.is_empty() {
+            self.store().apply(&writes)?;
+            self.store().flush()?;
+        }
+        Ok(())
+    }
+}
+
+async fn peer_header(client: &mut Client, height: u64) -> Result<BlockHeader, Box<dyn Error>> {
+    let response = client.get_headers(RangeRequest { from_height: height, count: 1 }).await?.into_inner();
+    let header = response.headers.into_iter().next().ok_or_else(|| format!("The peer has no header at height {}", height))?;
+    header_from_proto(header)
+}
+
+fn height_key(height: u64) -> Vec<u8> {
+    let mut key = vec![b'h'];
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+fn hash_key(hash: &str) -> Vec<u8> {
+    let mut key = vec![b'b'];
+    key.extend_from_slice(hash.as_bytes());
+    key
+}
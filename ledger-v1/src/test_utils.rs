@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::authority;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::clock::Clock;
+use crate::config::{Compression, Config};
+use crate::encoding;
+use crate::encryption::BlockCipher;
+use crate::genesis::GenesisConfig;
+use crate::header::BlockHeader;
+use crate::signer::LocalSigner;
+use crate::store::{BlockStore, MemoryStore};
+use crate::transaction::{self, Transaction};
+
+// Helpers for property tests and fuzzing, behind the `test_utils` feature. Everything
+// here is deterministic: the same seed gives the same chain, byte for byte, on any
+// machine and at any time, so a failing case can be replayed from its seed. The
+// crate's own property tests, in tests/properties.rs, are built on these.
+
+// A clock that only moves when told to. Clones share the time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    pub fn new(now_ms: u64) -> Self {
+        ManualClock(Arc::new(AtomicU64::new(now_ms)))
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.0.store(now_ms, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.0.fetch_add(ms, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// SplitMix64: tiny, seedable and good enough to vary test data. Not for keys.
+#[derive(Debug, Clone)]
+pub struct TestRng(u64);
+
+impl TestRng {
+    pub fn new(seed: u64) -> Self {
+        TestRng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A number in `0..bound`; `bound` must not be 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+}
+
+// Test account `index`: a key made from the index (see
+// `authority::generate_key_from_seed`), so never one to use on a real chain.
+pub fn test_signer(index: u64) -> LocalSigner {
+    LocalSigner::from_hex(&authority::generate_key_from_seed(index).0).expect("seeded keys are valid")
+}
+
+// The address test account `index` owns.
+pub fn test_address(index: u64) -> String {
+    transaction::key_address(&authority::generate_key_from_seed(index).1).expect("seeded keys are valid")
+}
+
+// A genesis giving `balance` to each of test accounts 0 to `accounts - 1`, with a
+// block reward and nonces required, for chains moving funds around. Test account 0
+// mines (see `miner_config`).
+pub fn funded_genesis(accounts: u64, balance: u64) -> GenesisConfig {
+    GenesisConfig {
+        allocations: (0..accounts).map(|index| (test_address(index), balance)).collect(),
+        block_reward: 50,
+        halving_interval: 8,
+        require_nonces: true,
+        ..GenesisConfig::default()
+    }
+}
+
+// A config paying block rewards and fees to test account 0.
+pub fn miner_config() -> Config {
+    Config { miner_address: test_address(0), ..Config::default() }
+}
+
+// An in-memory chain of `length` blocks on top of the default genesis. Payloads,
+// tags and the gaps between timestamps all come from `seed`.
+pub fn generate_chain(seed: u64, length: u64) -> Result<Blockchain<MemoryStore>, Box<dyn Error>> {
+    generate_chain_with_genesis(&GenesisConfig::default(), seed, length)
+}
+
+// Like `generate_chain` on `genesis`. Blocks also carry signed transfers between the
+// test accounts it funds, if any, mined by test account 0.
+pub fn generate_chain_with_genesis(
+    genesis: &GenesisConfig,
+    seed: u64,
+    length: u64,
+) -> Result<Blockchain<MemoryStore>, Box<dyn Error>> {
+    let chain = Blockchain::open_store(MemoryStore::new(), Some(genesis), miner_config())?;
+    let clock = ManualClock::new(genesis.timestamp);
+    let chain = chain.with_clock(clock.clone());
+    extend(&chain, &clock, &mut TestRng::new(seed), length)?;
+    Ok(chain)
+}
+
+// `length` blocks building on the canonical block at `parent_height` of `chain`, as
+// another node would send them: hand them to `receive_block` or `add_blocks` to
+// make a fork. The chain itself is left alone.
+pub fn generate_branch<S: BlockStore>(
+    chain: &Blockchain<S>,
+    parent_height: u64,
+    length: u64,
+    seed: u64,
+) -> Result<Vec<Block>, Box<dyn Error>> {
+    let genesis = chain.genesis_config();
+    let other = Blockchain::open_store(MemoryStore::new(), Some(&genesis), miner_config())?;
+    if parent_height > 0 {
+        other.add_blocks(&chain.get_blocks_range(1, parent_height)?)?;
+    }
+    if other.height()? != parent_height {
+        return Err(format!("The chain has no block at height {}", parent_height).into());
+    }
+    let parent = other.get_blocks_range(parent_height, parent_height)?.remove(0);
+    let clock = ManualClock::new(parent.timestamp);
+    let other = other.with_clock(clock.clone());
+    extend(&other, &clock, &mut TestRng::new(seed), length)?;
+    other.get_blocks_range(parent_height + 1, parent_height + length)
+}
+
+fn extend<S: BlockStore>(chain: &Blockchain<S>, clock: &ManualClock, rng: &mut TestRng, length: u64) -> Result<(), Box<dyn Error>> {
+    let genesis = chain.genesis_config();
+    let accounts = (0..).take_while(|index| genesis.allocations.contains_key(&test_address(*index))).count() as u64;
+    for _ in 0..length {
+        clock.advance(1 + rng.below(60_000));
+        if accounts > 0 && rng.below(2) == 0 {
+            let transfers = transfers(chain, rng, accounts)?;
+            let len = rng.below(64) as usize;
+            chain.add_block_with_transactions(rng.bytes(len), transfers)?;
+            continue;
+        }
+        let len = rng.below(256) as usize;
+        let data = rng.bytes(len);
+        let mut metadata = BTreeMap::new();
+        if rng.below(4) == 0 {
+            metadata.insert("tag".to_string(), format!("{:x}", rng.next_u64()));
+        }
+        chain.add_block_with_metadata(data, None, metadata)?;
+    }
+    Ok(())
+}
+
+// Up to four signed transfers between test accounts that the tip state can pay for.
+fn transfers<S: BlockStore>(chain: &Blockchain<S>, rng: &mut TestRng, accounts: u64) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let network_id = chain.network_id()?;
+    let mut senders = BTreeMap::new();
+    let mut transfers = Vec::new();
+    for _ in 0..rng.below(5) {
+        let (from, to) = (rng.below(accounts), rng.below(accounts));
+        let address = test_address(from);
+        let sender = match senders.entry(from) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(chain.get_account(&address)?),
+        };
+        if sender.balance == 0 {
+            continue;
+        }
+        let fee = rng.below(3).min(sender.balance);
+        let amount = rng.below(sender.balance - fee + 1);
+        let transfer = Transaction::transfer_with_fee(&address, &test_address(to), amount, fee).with_nonce(sender.nonce);
+        transfers.push(transfer.signed(&test_signer(from), &network_id)?);
+        sender.balance -= amount + fee;
+        sender.nonce += 1;
+    }
+    Ok(transfers)
+}
+
+// The key `fuzz_decode` opens encrypted records with, so a fuzzer can reach what is
+// inside them.
+pub const FUZZ_KEY: [u8; 32] = [7; 32];
+
+// `block` as stored with `FUZZ_KEY`: sealed, compressed first if `compress` is set.
+pub fn fuzz_record(block: &Block, compress: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    let compression = if compress { Compression::Lz4 } else { Compression::None };
+    encoding::seal_block(block, compression, Some(&BlockCipher::new(&FUZZ_KEY)))
+}
+
+// Runs every decoder that takes untrusted bytes, for use as a fuzz target. It must
+// never panic, whatever `bytes` holds; errors are the expected outcome.
+pub fn fuzz_decode(bytes: &[u8]) {
+    let cipher = BlockCipher::new(&FUZZ_KEY);
+    let _ = encoding::open_block(bytes, Some(&cipher));
+    let _ = encoding::view_block(bytes, Some(&cipher), |view| Ok((view.header(), view.attachments().len())));
+    let _ = encoding::view_block(bytes, None, |view| Ok(view.transactions.len()));
+    let _ = encoding::decode_block(bytes);
+    let _ = rmp_serde::from_slice::<Block>(bytes);
+    let _ = rmp_serde::from_slice::<BlockHeader>(bytes);
+    let _ = serde_json::from_slice::<Block>(bytes);
+    let _ = serde_json::from_slice::<BlockHeader>(bytes);
+    if let Ok(block) = encoding::decode_block(bytes) {
+        let _ = block.calculate_hash();
+        let _ = block.header();
+    }
+}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone as _, Utc};
+use std::error::Error;
+use std::str::FromStr;
+
+// Timestamps are stored as milliseconds since the Unix epoch. These render them as
+// RFC 3339 in a chosen zone, and read the times people type for `--since` and
+// `--until`: an RFC 3339 time, a date or date and time in the zone, "now", a span
+// back from now such as "90m", "2d" or "1w ago", or plain milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TimeZone {
+    #[default]
+    Utc,
+    // The zone of the machine, offset per time so daylight saving is followed.
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl FromStr for TimeZone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utc" | "UTC" | "Z" => Ok(TimeZone::Utc),
+            "local" => Ok(TimeZone::Local),
+            offset => offset
+                .parse::<FixedOffset>()
+                .map(TimeZone::Fixed)
+                .map_err(|_| format!("Unknown time zone '{}' (expected utc, local or an offset such as +02:00)", offset)),
+        }
+    }
+}
+
+impl TimeZone {
+    // e.g. "2024-01-01T12:00:00.000Z", or with the zone's offset. Millisecond counts
+    // chrono cannot represent are shown as they are.
+    pub fn format(self, timestamp: u64) -> String {
+        let Some(time) = i64::try_from(timestamp).ok().and_then(DateTime::from_timestamp_millis) else {
+            return timestamp.to_string();
+        };
+        match self {
+            TimeZone::Utc => time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            TimeZone::Local => time.with_timezone(&Local).to_rfc3339_opts(SecondsFormat::Millis, false),
+            TimeZone::Fixed(offset) => time.with_timezone(&offset).to_rfc3339_opts(SecondsFormat::Millis, false),
+        }
+    }
+
+    // Milliseconds since the epoch of `text`, see above. Dates and times without an
+    // offset are taken in this zone, a date alone at its midnight; spans count back
+    // from `now_ms`.
+    pub fn parse(self, text: &str, now_ms: u64) -> Result<u64, Box<dyn Error>> {
+        let text = text.trim();
+        if let Ok(ms) = text.parse::<u64>() {
+            return Ok(ms);
+        }
+        if text == "now" {
+            return Ok(now_ms);
+        }
+        if let Some(span) = parse_span(text.strip_suffix("ago").unwrap_or(text).trim_end()) {
+            return Ok(now_ms.saturating_sub(span));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+            return millis(time.timestamp_millis(), text);
+        }
+        let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+            .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+            .ok_or_else(|| {
+                format!("Cannot read time '{}' (expected e.g. 2024-01-01, 2024-01-01 12:00, an RFC 3339 time or 2d ago)", text)
+            })?;
+        let time = match self {
+            TimeZone::Utc => Some(Utc.from_utc_datetime(&naive).timestamp_millis()),
+            TimeZone::Local => Local.from_local_datetime(&naive).earliest().map(|time| time.timestamp_millis()),
+            TimeZone::Fixed(offset) => offset.from_local_datetime(&naive).earliest().map(|time| time.timestamp_millis()),
+        };
+        millis(time.ok_or_else(|| format!("Time '{}' does not exist in this time zone", text))?, text)
+    }
+}
+
+// "90s", "15m", "2h", "3d" or "1w" in milliseconds.
+fn parse_span(text: &str) -> Option<u64> {
+    let unit_at = text.find(|c: char| !c.is_ascii_digit())?;
+    let (count, unit) = text.split_at(unit_at);
+    let unit_ms = match unit.trim() {
+        "ms" => 1,
+        "s" => 1000,
+        "m" | "min" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        "w" => 7 * 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    count.parse::<u64>().ok()?.checked_mul(unit_ms)
+}
+
+fn millis(ms: i64, text: &str) -> Result<u64, Box<dyn Error>> {
+    u64::try_from(ms).map_err(|_| format!("Time '{}' is before 1970", text).into())
+}
@@ -0,0 +1,214 @@
+use ed25519_dalek::Signature;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+use crate::genesis::GenesisConfig;
+use crate::hashing;
+use crate::script::{self, Condition, Witness};
+use crate::signer::Signer;
+
+// Transactions carried in a block and applied, in order, to the account state.
+// Transfers and locks carry the sender's nonce: its count of transactions sent so
+// far, so each can be applied once and in order. Those made before nonces existed
+// carry none, which only chains with `GenesisConfig::require_nonces` refuse.
+//
+// A signed transfer or lock also carries the sender's ed25519 public key and its
+// signature over `signing_message`. The sender must be the address the key owns
+// (see `key_address`), so only the holder of the key can spend from it. Chains
+// with `GenesisConfig::require_signatures` refuse unsigned ones, and no chain takes
+// an unsigned one from a key's address.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Transaction {
+    // Moves `amount` from one account balance to another. The sender also pays `fee`
+    // to the miner of the block.
+    Transfer {
+        from: String,
+        to: String,
+        amount: u64,
+        #[serde(default)]
+        fee: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nonce: Option<u64>,
+        // The sender's public key and signature, hex.
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        public_key: String,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        signature: String,
+    },
+    // The block reward, minted to the miner. Always the first transaction of a block on
+    // chains with rewards. The height keeps coinbase ids unique.
+    Coinbase { to: String, amount: u64, height: u64 },
+    // Moves `amount` from the sender into a lock account guarded by `condition`, paying
+    // `fee` like a transfer. The lock is named by this transaction's id (see `script`).
+    Lock {
+        from: String,
+        amount: u64,
+        #[serde(default)]
+        fee: u64,
+        condition: Condition,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nonce: Option<u64>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        public_key: String,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        signature: String,
+    },
+    // Pays the whole balance of lock `lock`, less `fee`, to `to` once `witness` meets
+    // the lock's condition. The lock must come first, in this block or an earlier one.
+    Unlock {
+        lock: String,
+        to: String,
+        #[serde(default)]
+        fee: u64,
+        #[serde(default)]
+        witness: Witness,
+    },
+}
+
+impl Transaction {
+    pub fn transfer(from: &str, to: &str, amount: u64) -> Self {
+        Self::transfer_with_fee(from, to, amount, 0)
+    }
+
+    pub fn transfer_with_fee(from: &str, to: &str, amount: u64, fee: u64) -> Self {
+        Transaction::Transfer { from: from.to_string(), to: to.to_string(), amount, fee, nonce: None, public_key: String::new(), signature: String::new() }
+    }
+
+    pub fn lock(from: &str, amount: u64, fee: u64, condition: Condition) -> Self {
+        Transaction::Lock {
+            from: from.to_string(),
+            amount,
+            fee,
+            condition,
+            nonce: None,
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    pub fn unlock(lock: &str, to: &str, fee: u64, witness: Witness) -> Self {
+        Transaction::Unlock { lock: lock.to_string(), to: to.to_string(), fee, witness }
+    }
+
+    pub fn fee(&self) -> u64 {
+        match self {
+            Transaction::Transfer { fee, .. } | Transaction::Lock { fee, .. } | Transaction::Unlock { fee, .. } => *fee,
+            Transaction::Coinbase { .. } => 0,
+        }
+    }
+
+    // This transfer or lock with sender nonce `nonce`. Other kinds have no sender and
+    // are returned unchanged.
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        if let Transaction::Transfer { nonce: slot, .. } | Transaction::Lock { nonce: slot, .. } = &mut self {
+            *slot = Some(nonce);
+        }
+        self
+    }
+
+    pub fn nonce(&self) -> Option<u64> {
+        match self {
+            Transaction::Transfer { nonce, .. } | Transaction::Lock { nonce, .. } => *nonce,
+            Transaction::Coinbase { .. } | Transaction::Unlock { .. } => None,
+        }
+    }
+
+    // The account paying for the transaction and what it pays, amount and fee
+    // together. Unlocks are paid for by their lock.
+    pub fn spend(&self) -> Option<(&str, u128)> {
+        match self {
+            Transaction::Transfer { from, amount, fee, .. } | Transaction::Lock { from, amount, fee, .. } => {
+                Some((from, *amount as u128 + *fee as u128))
+            }
+            Transaction::Coinbase { .. } | Transaction::Unlock { .. } => None,
+        }
+    }
+
+    // Encoded size in bytes, which block assembly packs by.
+    pub fn size(&self) -> usize {
+        rmp_serde::to_vec(self).map_or(0, |bytes| bytes.len())
+    }
+
+    pub fn coinbase(to: &str, amount: u64, height: u64) -> Self {
+        Transaction::Coinbase { to: to.to_string(), amount, height }
+    }
+
+    // The transaction id. It covers the public key and signature too.
+    pub fn hash(&self) -> String {
+        hashing::transaction_hash(self)
+    }
+
+    // What the sender signs: a tag, the network ID of the chain (so a signature is
+    // only good on one chain) and the transaction preimage without the signature.
+    pub fn signing_message(&self, network_id: &str) -> Vec<u8> {
+        let mut message = b"ledger-v1 transaction\0".to_vec();
+        message.extend_from_slice(network_id.as_bytes());
+        message.push(0);
+        message.extend(hashing::signing_preimage(self));
+        message
+    }
+
+    // This transfer or lock signed by `signer` for the chain `network_id`. Give it
+    // its nonce first: the signature covers it. Other kinds have no sender and are
+    // returned unchanged.
+    pub fn signed(mut self, signer: &dyn Signer, network_id: &str) -> Result<Self, Box<dyn Error>> {
+        let key = signer.public_key();
+        let address = key_address(&key)?;
+        let (Transaction::Transfer { from, public_key, signature, .. } | Transaction::Lock { from, public_key, signature, .. }) =
+            &mut self
+        else {
+            return Ok(self);
+        };
+        if *from != address {
+            return Err(format!("Key {} owns address {}, not {}", key, address, from).into());
+        }
+        *public_key = key;
+        signature.clear();
+        let message = self.signing_message(network_id);
+        if let Transaction::Transfer { signature, .. } | Transaction::Lock { signature, .. } = &mut self {
+            *signature = hex::encode(signer.sign(&message)?.to_bytes());
+        }
+        Ok(self)
+    }
+
+    // Checks the sender's key and signature against the chain of `genesis`, if the
+    // transaction has a sender. Signed transactions must carry a nonce, or they could
+    // be replayed.
+    pub(crate) fn check_signature(&self, genesis: &GenesisConfig) -> Result<(), Box<dyn Error>> {
+        let (Transaction::Transfer { from, public_key, signature, .. } | Transaction::Lock { from, public_key, signature, .. }) = self
+        else {
+            return Ok(());
+        };
+        if public_key.is_empty() {
+            if genesis.require_signatures || !signature.is_empty() || from.starts_with(KEY_ADDRESS_PREFIX) {
+                return Err(format!("Transaction {} is not signed by its sender {}", self.hash(), from).into());
+            }
+            return Ok(());
+        }
+        let address = key_address(public_key)?;
+        if *from != address {
+            return Err(format!("Transaction {} is signed with key {}, which owns {}, not {}", self.hash(), public_key, address, from).into());
+        }
+        if self.nonce().is_none() {
+            return Err(format!("Signed transaction {} carries no nonce", self.hash()).into());
+        }
+        let signature = hex::decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| format!("Transaction {} carries no valid signature", self.hash()))?;
+        if script::verifying_key(public_key)?.verify_strict(&self.signing_message(&genesis.network_id()?), &signature).is_err() {
+            return Err(format!("Transaction {} has a bad signature from {}", self.hash(), from).into());
+        }
+        Ok(())
+    }
+}
+
+const KEY_ADDRESS_PREFIX: &str = "ed25519:";
+
+// The address an ed25519 public key (hex) owns: "ed25519:" and the first 20 bytes
+// of the SHA-256 of the key, hex.
+pub fn key_address(public_key: &str) -> Result<String, Box<dyn Error>> {
+    let key = script::verifying_key(public_key)?;
+    Ok(format!("{}{}", KEY_ADDRESS_PREFIX, hex::encode(&Sha256::digest(key.as_bytes())[..20])))
+}
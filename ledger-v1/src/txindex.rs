@@ -0,0 +1,147 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use crate::batch::{ChainBatch, ReadTrees};
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::store::{BlockStore, TreeId};
+use crate::transaction::Transaction;
+
+// The "transactions" tree finds the canonical block holding a transaction. Keys are
+// the txid, then the height and the position in the block (both big-endian); values
+// the block hash. Identical transfers share a txid, so one txid may have several keys.
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TransactionInfo {
+    pub transaction: Transaction,
+    // None while the transaction waits in the mempool.
+    pub block: Option<String>,
+    pub height: Option<u64>,
+    // Tip height minus the block's height: 0 while pending and in the tip block.
+    pub confirmations: u64,
+}
+
+impl TransactionInfo {
+    pub fn is_confirmed(&self) -> bool {
+        self.block.is_some()
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Where `txid` stands: in a canonical block, waiting in the mempool, or unknown.
+    // A txid included more than once reports its newest inclusion.
+    pub fn get_transaction(&self, txid: &str) -> Result<Option<TransactionInfo>, Box<dyn Error>> {
+        if let Some(found) = self.store().scan_prefix(TreeId::Transactions, txid.as_bytes()).last() {
+            let (key, hash) = found?;
+            let (height, position) = parse_key(txid, &key)?;
+            let hash = String::from_utf8(hash)?;
+            let block = self
+                .get_block(&hash)?
+                .ok_or_else(|| format!("Block {} holding transaction {} is missing", hash, txid))?;
+            let transaction = block
+                .transactions
+                .get(position)
+                .cloned()
+                .ok_or_else(|| format!("Block {} has no transaction at position {}", hash, position))?;
+            return Ok(Some(TransactionInfo {
+                transaction,
+                block: Some(hash),
+                height: Some(height),
+                confirmations: self.height()?.saturating_sub(height),
+            }));
+        }
+
+        let pending = self.mempool().into_iter().find(|transaction| transaction.hash() == txid);
+        Ok(pending.map(|transaction| TransactionInfo { transaction, block: None, height: None, confirmations: 0 }))
+    }
+
+    // Blocks until `txid` has at least `confirmations`, rechecking as blocks arrive,
+    // and returns its status then. Fails if the transaction is unknown to begin with,
+    // or once `timeout` passes.
+    pub fn wait_for_confirmations(
+        &self,
+        txid: &str,
+        confirmations: u64,
+        timeout: Duration,
+    ) -> Result<TransactionInfo, Box<dyn Error>> {
+        // Subscribed before the first look, so no block slips past unseen.
+        let events = self.subscribe();
+        let deadline = Instant::now() + timeout;
+        if self.get_transaction(txid)?.is_none() {
+            return Err(format!("Unknown transaction {}", txid).into());
+        }
+        loop {
+            // A reorg may briefly leave the transaction in neither the chain nor the
+            // mempool, so only the first look requires it to exist.
+            if let Some(info) = self.get_transaction(txid)?
+                && info.is_confirmed()
+                && info.confirmations >= confirmations
+            {
+                return Ok(info);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match events.recv_timeout(remaining) {
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(format!("Transaction {} did not reach {} confirmations in time", txid, confirmations).into());
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err("The chain stopped sending events".into()),
+            }
+        }
+    }
+
+    // Chains written before the index existed get it built once.
+    pub(crate) fn build_transaction_index_if_needed(&self) -> Result<(), Box<dyn Error>> {
+        if self.trees.contains(TreeId::Blocks, b"TXINDEX_BUILT")? {
+            return Ok(());
+        }
+        let mut batch = self.batch();
+        batch.clear(TreeId::Transactions)?;
+        for height in 0..=self.height()? {
+            let Some(hash) = self.canonical_hash(height)? else { continue };
+            if let Some(block) = self.load_block(&hash)? {
+                batch.index_transactions(&block, height);
+            }
+        }
+        batch.mark_transaction_index_built();
+        self.commit(batch)
+    }
+}
+
+impl<S: BlockStore> ChainBatch<S> {
+    // Called as a block joins or leaves the canonical chain.
+    pub(crate) fn index_transactions(&mut self, block: &Block, height: u64) {
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            self.insert(TreeId::Transactions, index_key(&transaction.hash(), height, position), block.hash.as_bytes());
+        }
+    }
+
+    pub(crate) fn unindex_transactions(&mut self, block: &Block, height: u64) {
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            self.remove(TreeId::Transactions, index_key(&transaction.hash(), height, position));
+        }
+    }
+
+    pub(crate) fn mark_transaction_index_built(&mut self) {
+        self.insert(TreeId::Blocks, "TXINDEX_BUILT", []);
+    }
+}
+
+fn index_key(txid: &str, height: u64, position: usize) -> Vec<u8> {
+    let mut key = txid.as_bytes().to_vec();
+    key.extend_from_slice(&height.to_be_bytes());
+    key.extend_from_slice(&(position as u32).to_be_bytes());
+    key
+}
+
+fn parse_key(txid: &str, key: &[u8]) -> Result<(u64, usize), Box<dyn Error>> {
+    let rest = &key[txid.len()..];
+    if rest.len() != 12 {
+        return Err(format!("Malformed transaction index key for {}", txid).into());
+    }
+    let height = u64::from_be_bytes(rest[..8].try_into()?);
+    let position = u32::from_be_bytes(rest[8..].try_into()?);
+    Ok((height, position as usize))
+}
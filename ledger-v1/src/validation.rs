@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::batch::ReadTrees;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::store::{BlockStore, TreeId};
+
+// Where a running `validate_with_progress` has got to. The walk goes from the tip down
+// to genesis (or the snapshot base), so `height` counts down while `checked` counts up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValidationProgress {
+    pub checked: u64,
+    pub total: u64,
+    // Height of the block just checked.
+    pub height: u64,
+}
+
+impl ValidationProgress {
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        (self.checked as f64 * 100.0 / self.total as f64).min(100.0)
+    }
+}
+
+// Stops a validation from another thread. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Why `validate_candidate` refused a block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    // The parent isn't stored, so the block can't be checked yet; fetch it first.
+    UnknownParent { block: String, parent: String },
+    // The block breaks a consensus rule, or its transactions don't apply to the state.
+    Invalid { block: String, reason: String },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::UnknownParent { block, parent } => write!(f, "Unknown parent {} for block {}", parent, block),
+            ValidationError::Invalid { reason, .. } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Runs every check `receive_block` would on `block`, against the current tip and
+    // state, and throws the staged writes away. A block the chain already holds passes.
+    // If the block would win a reorg, the whole branch is connected in the dry run.
+    pub fn validate_candidate(&self, block: &Block) -> Result<(), ValidationError> {
+        let invalid = |e: Box<dyn Error>| ValidationError::Invalid { block: block.hash.clone(), reason: e.to_string() };
+        let mut batch = self.batch();
+        if !batch.contains(TreeId::Blocks, block.hash.as_bytes()).map_err(invalid)?
+            && batch.block_meta(&block.prev_hash).map_err(invalid)?.is_none()
+        {
+            return Err(ValidationError::UnknownParent { block: block.hash.clone(), parent: block.prev_hash.clone() });
+        }
+        batch.receive_block(block).map(|_| ()).map_err(invalid)
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+
+use crate::blockchain::check_consensus;
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::export::{self, ExportFormat};
+use crate::genesis::GenesisConfig;
+use crate::hashing;
+use crate::header::BlockHeader;
+use crate::merkle::{self, MerkleProof};
+
+// Checks chain exports and merkle proofs on their own, with no store or node behind
+// them, so a client handed one (a browser, say, through `wasm`) can check it before
+// trusting it. Nothing here needs sled, threads or a file system.
+//
+// An export is held to the rules a node holds the blocks it receives to, under the
+// genesis config its first block carries: hashes and links, timestamps, sequence
+// numbers, the chain ID, proof of work or signatures, the coinbase and duplicate
+// transactions. What depends on the account state (balances, nonces, locks and the
+// state roots) needs the blocks applied, which importing the export into
+// `Blockchain::in_memory` does. The export proves nothing about which chain it is:
+// compare its genesis hash or chain ID with the one expected.
+
+// What a checked export holds.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VerifiedExport {
+    pub genesis: String,
+    pub network_id: String,
+    pub tip: String,
+    pub height: u64,
+    pub transactions: usize,
+    // Of every block, genesis first, to check proofs against.
+    pub headers: Vec<BlockHeader>,
+}
+
+impl VerifiedExport {
+    pub fn header(&self, hash: &str) -> Option<&BlockHeader> {
+        self.headers.iter().find(|header| header.hash == hash)
+    }
+
+    // Checks that `proof` places its transaction in block `block_hash` of the export.
+    // Unlike `verify_proof` this works for blocks of any version, since the export's
+    // blocks were hashed whole.
+    pub fn verify_proof(&self, block_hash: &str, proof: &MerkleProof) -> Result<(), Box<dyn Error>> {
+        let header = self.header(block_hash).ok_or_else(|| format!("Block {} is not in the export", block_hash))?;
+        check_proof(header, proof)
+    }
+}
+
+pub fn verify_export<R: Read>(reader: R, format: ExportFormat) -> Result<VerifiedExport, Box<dyn Error>> {
+    let blocks = export::read_blocks(reader, format)?;
+    let first = blocks.first().ok_or("The export contains no blocks")?;
+    if !first.is_genesis() {
+        return Err(format!("The export does not start with a genesis block (first block {})", first.hash).into());
+    }
+    // As when a chain adopts an imported genesis: chains from before genesis configs
+    // used the defaults.
+    let genesis = serde_json::from_slice::<GenesisConfig>(&first.data).unwrap_or_else(|_| GenesisConfig::legacy());
+    let expected = genesis.genesis_block()?.hash;
+    if first.hash != expected {
+        return Err(format!("Genesis block {} does not match the config it carries, which makes {}", first.hash, expected).into());
+    }
+
+    // Blocks from the future are refused as a node would refuse them now.
+    let now = SystemClock.now_ms();
+    let drift = Config::default().max_future_drift_ms;
+    let mut headers: Vec<BlockHeader> = Vec::with_capacity(blocks.len());
+    let mut positions = HashMap::new();
+    let mut transactions = 0;
+    for (height, block) in blocks.iter().enumerate() {
+        if block.hash != block.calculate_hash() {
+            return Err(format!("Block {} at height {} has an invalid hash", block.hash, height).into());
+        }
+        if let Some(parent) = headers.last() {
+            if block.prev_hash != parent.hash {
+                return Err(format!("Broken link! Block {} does not follow {}", block.hash, parent.hash).into());
+            }
+            let load_header = |hash: &str| Ok(positions.get(hash).map(|position: &usize| headers[*position].clone()));
+            check_consensus(&genesis, block, parent, height as u64, now, drift, load_header)
+                .map_err(|e| format!("At height {}: {}", height, e))?;
+        }
+        transactions += block.transactions.len();
+        positions.insert(block.hash.clone(), headers.len());
+        headers.push(block.header());
+    }
+
+    let tip = blocks.last().map(|block| block.hash.clone()).unwrap_or_default();
+    Ok(VerifiedExport {
+        genesis: first.hash.clone(),
+        network_id: genesis.network_id()?,
+        tip,
+        height: blocks.len() as u64 - 1,
+        transactions,
+        headers,
+    })
+}
+
+// Checks that `proof` places its transaction in the block `header` describes, for
+// clients that only hold headers. The header must be version 2 or later, whose hash
+// commits to the merkle root; whether the hash is one of the chain's is up to the
+// caller.
+pub fn verify_proof(header: &BlockHeader, proof: &MerkleProof) -> Result<(), Box<dyn Error>> {
+    if header.version < hashing::HEADER_V2 {
+        return Err(format!("Block {} is version {}, whose hash commits to no merkle root", header.hash, header.version).into());
+    }
+    if header.hash != header.calculate_hash() {
+        return Err(format!("Header {} does not match its hash", header.hash).into());
+    }
+    check_proof(header, proof)
+}
+
+fn check_proof(header: &BlockHeader, proof: &MerkleProof) -> Result<(), Box<dyn Error>> {
+    if !merkle::verify_proof(header.hash_algorithm, &header.merkle_root, proof) {
+        return Err(format!("The proof does not place transaction {} in block {}", proof.txid, header.hash).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::error::Error;
+
+use wasm_bindgen::prelude::*;
+
+use crate::export::ExportFormat;
+use crate::header::BlockHeader;
+use crate::merkle::MerkleProof;
+use crate::verify::{self, VerifiedExport};
+
+// JavaScript bindings for `verify` (the wasm feature, on wasm32), for checking in a
+// browser what a node served:
+//
+//     const chain = new Export(new Uint8Array(await response.arrayBuffer()), "binary");
+//     if (chain.genesis !== expectedGenesis) throw new Error("another chain");
+//     chain.verifyProof(blockHash, JSON.stringify(proof));
+//
+// Headers and proofs cross as JSON, failures as thrown `Error`s.
+
+// A checked export; the constructor throws if it doesn't hold up.
+#[wasm_bindgen]
+pub struct Export {
+    verified: VerifiedExport,
+}
+
+#[wasm_bindgen]
+impl Export {
+    // `format` is "json", "csv" or "binary", as for `ledger import`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8], format: &str) -> Result<Export, JsError> {
+        let format: ExportFormat = format.parse().map_err(|e: String| JsError::new(&e))?;
+        let verified = verify::verify_export(bytes, format).map_err(js_error)?;
+        Ok(Export { verified })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn genesis(&self) -> String {
+        self.verified.genesis.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = networkId)]
+    pub fn network_id(&self) -> String {
+        self.verified.network_id.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn tip(&self) -> String {
+        self.verified.tip.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u64 {
+        self.verified.height
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn transactions(&self) -> usize {
+        self.verified.transactions
+    }
+
+    // The header of block `hash` as JSON, or undefined if the export has no such block.
+    pub fn header(&self, hash: &str) -> Result<Option<String>, JsError> {
+        Ok(self.verified.header(hash).map(serde_json::to_string).transpose()?)
+    }
+
+    // Throws unless the `MerkleProof` in `proof` places its transaction in block
+    // `block_hash` of the export.
+    #[wasm_bindgen(js_name = verifyProof)]
+    pub fn verify_proof(&self, block_hash: &str, proof: &str) -> Result<(), JsError> {
+        let proof: MerkleProof = serde_json::from_str(proof)?;
+        self.verified.verify_proof(block_hash, &proof).map_err(js_error)
+    }
+}
+
+// Throws unless the `MerkleProof` in `proof` places its transaction in the block the
+// `BlockHeader` in `header` describes; see `verify::verify_proof`.
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof(header: &str, proof: &str) -> Result<(), JsError> {
+    let header: BlockHeader = serde_json::from_str(header)?;
+    let proof: MerkleProof = serde_json::from_str(proof)?;
+    verify::verify_proof(&header, &proof).map_err(js_error)
+}
+
+fn js_error(e: Box<dyn Error>) -> JsError {
+    JsError::new(&e.to_string())
+}
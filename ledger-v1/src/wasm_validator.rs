@@ -0,0 +1,132 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use wasmi::core::TrapCode;
+use wasmi::{Engine, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use crate::block::Block;
+use crate::hooks::Validator;
+use crate::transaction::Transaction;
+
+// Validation hooks written as WebAssembly modules, run by the wasmi interpreter in a
+// sandbox: a module gets no imports, so it can only compute on what it is handed,
+// every call starts from a fresh instance and is limited to a number of fuel units
+// (roughly one per instruction), and its memory may not grow past `MAX_MEMORY`.
+// Running out of fuel, trapping or breaking the module's interface rejects the
+// block or transaction, the same as the module refusing it.
+//
+// A module exports its `memory`, `alloc(len: i32) -> i32`, which returns where the
+// host may write `len` bytes, and either or both of:
+//
+//     check_block(ptr: i32, len: i32, height: i64) -> i32
+//     check_transaction(ptr: i32, len: i32) -> i32
+//
+// which are handed the block or transaction as JSON and return 0 to accept it, or
+// any other code to reject it. What a module leaves out, it accepts.
+
+// Linear memory a module may use, in bytes.
+const MAX_MEMORY: usize = 16 << 20;
+
+pub struct WasmValidator {
+    path: PathBuf,
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+}
+
+// An instance ready for a check, with the input at `ptr`.
+struct Call {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    ptr: i32,
+    len: i32,
+}
+
+impl WasmValidator {
+    // Compiles the module at `path`, failing if it is not valid WebAssembly, imports
+    // anything or does not export the interface above.
+    pub fn load(path: impl AsRef<Path>, fuel: u64) -> Result<WasmValidator, Box<dyn Error>> {
+        let path = path.as_ref();
+        let wasm = std::fs::read(path).map_err(|e| format!("Cannot read validator module {}: {}", path.display(), e))?;
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &wasm).map_err(|e| format!("Validator module {} is invalid: {}", path.display(), e))?;
+        if let Some(import) = module.imports().next() {
+            return Err(format!(
+                "Validator module {} imports {}::{}, but validator modules get no imports",
+                path.display(), import.module(), import.name()
+            ).into());
+        }
+        let mut exports: Vec<&str> = module.exports().map(|export| export.name()).collect();
+        exports.sort_unstable();
+        for required in ["alloc", "memory"] {
+            if exports.binary_search(&required).is_err() {
+                return Err(format!("Validator module {} does not export {}", path.display(), required).into());
+            }
+        }
+        if !exports.iter().any(|name| *name == "check_block" || *name == "check_transaction") {
+            return Err(format!("Validator module {} exports neither check_block nor check_transaction", path.display()).into());
+        }
+        Ok(WasmValidator { path: path.to_path_buf(), engine, module, fuel })
+    }
+
+    // A fresh instance, given `fuel`, with `json` copied into its memory. None if the
+    // module does not export `check`.
+    fn prepare(&self, check: &str, json: &[u8]) -> Result<Option<Call>, Box<dyn Error>> {
+        if self.module.get_export(check).is_none() {
+            return Ok(None);
+        }
+        let mut store = Store::new(&self.engine, StoreLimitsBuilder::new().memory_size(MAX_MEMORY).instances(1).build());
+        store.limiter(|limits| limits);
+        store.set_fuel(self.fuel)?;
+        let instance = Linker::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| self.failure(&store, e))?;
+        let len = i32::try_from(json.len()).map_err(|_| format!("{} bytes are too many for a validator module", json.len()))?;
+        let alloc: TypedFunc<i32, i32> = instance.get_typed_func(&store, "alloc")?;
+        let ptr = alloc.call(&mut store, len).map_err(|e| self.failure(&store, e))?;
+        let memory = instance.get_memory(&store, "memory").ok_or("The module exports no memory")?;
+        memory
+            .write(&mut store, ptr as u32 as usize, json)
+            .map_err(|_| format!("alloc returned {}, which does not fit {} bytes in memory", ptr, len))?;
+        Ok(Some(Call { store, instance, ptr, len }))
+    }
+
+    fn failure(&self, store: &Store<StoreLimits>, e: wasmi::Error) -> Box<dyn Error> {
+        match e.as_trap_code() {
+            Some(TrapCode::OutOfFuel) => format!("ran out of fuel ({} units)", self.fuel).into(),
+            _ => {
+                let used = self.fuel - store.get_fuel().unwrap_or(0);
+                format!("trapped after {} units of fuel: {}", used, e).into()
+            }
+        }
+    }
+
+    fn verdict(&self, code: i32) -> Result<(), Box<dyn Error>> {
+        match code {
+            0 => Ok(()),
+            code => Err(format!("refused by {} (code {})", self.path.display(), code).into()),
+        }
+    }
+}
+
+impl Validator for WasmValidator {
+    fn check_block(&self, block: &Block, height: u64) -> Result<(), Box<dyn Error>> {
+        let Some(Call { mut store, instance, ptr, len }) = self.prepare("check_block", &serde_json::to_vec(block)?)? else {
+            return Ok(());
+        };
+        let check: TypedFunc<(i32, i32, i64), i32> = instance.get_typed_func(&store, "check_block")?;
+        let code = check.call(&mut store, (ptr, len, height as i64)).map_err(|e| self.failure(&store, e))?;
+        self.verdict(code)
+    }
+
+    fn check_transaction(&self, transaction: &Transaction) -> Result<(), Box<dyn Error>> {
+        let Some(Call { mut store, instance, ptr, len }) = self.prepare("check_transaction", &serde_json::to_vec(transaction)?)? else {
+            return Ok(());
+        };
+        let check: TypedFunc<(i32, i32), i32> = instance.get_typed_func(&store, "check_transaction")?;
+        let code = check.call(&mut store, (ptr, len)).map_err(|e| self.failure(&store, e))?;
+        self.verdict(code)
+    }
+}
@@ -0,0 +1,202 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::blockchain::Blockchain;
+use crate::config;
+use crate::events::ChainEvent;
+use crate::store::BlockStore;
+use tracing::{debug, warn};
+
+// Webhooks: every URL in `Config::webhooks` gets a JSON POST for each block added,
+// reorg and rejected block, in the order they happened. With a webhook key the body
+// is signed: the `X-Ledger-Signature` header holds "sha256=" and the hex HMAC-SHA256
+// of the body, so receivers can tell the node sent it. A failed delivery is retried
+// `Config::webhook_retries` times, waiting twice as long each time, before it is
+// dropped; later notifications wait their turn meanwhile.
+const KEY_ENV: &str = "LEDGER_WEBHOOK_KEY";
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(10);
+// How often the worker looks up from the queue to see whether it should stop.
+const POLL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Notification {
+    BlockAdded { hash: String, height: u64 },
+    ChainReorged { disconnected: Vec<String>, connected: Vec<String> },
+    BlockRejected { hash: String, error: String },
+}
+
+impl Notification {
+    fn from_event(event: ChainEvent) -> Option<Notification> {
+        match event {
+            ChainEvent::BlockAdded { hash, height } => Some(Notification::BlockAdded { hash, height }),
+            ChainEvent::ChainReorged { disconnected, connected } => Some(Notification::ChainReorged { disconnected, connected }),
+            ChainEvent::BlockRejected { hash, error } => Some(Notification::BlockRejected { hash, error }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Body {
+    // See `GenesisConfig::network_id`, for receivers following several chains.
+    network_id: String,
+    #[serde(flatten)]
+    notification: Notification,
+}
+
+// An http:// URL, taken apart.
+struct Endpoint {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Endpoint, Box<dyn Error>> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Webhook {} is not an http:// URL; put a TLS proxy in front for https", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("Webhook {} has an invalid port", url))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Webhook {} has no host", url).into());
+        }
+        Ok(Endpoint { url: url.to_string(), host: host.to_string(), port, path: path.to_string() })
+    }
+
+    // One attempt; anything but a 2xx answer is a failure.
+    fn post(&self, event: &str, body: &str, signature: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("Cannot resolve {}", self.host))?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nX-Ledger-Event: {}\r\n",
+            self.path,
+            self.host,
+            body.len(),
+            event
+        );
+        if let Some(signature) = signature {
+            request.push_str(&format!("X-Ledger-Signature: sha256={}\r\n", signature));
+        }
+        request.push_str("Connection: close\r\n\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes())?;
+
+        let mut status_line = String::new();
+        BufReader::new(&stream).read_line(&mut status_line)?;
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            Some(status) => Err(format!("Answered {}", status).into()),
+            None => Err("No HTTP response".into()),
+        }
+    }
+}
+
+// The thread delivering a chain's webhooks, from `Blockchain::start_webhooks`.
+// Dropping it delivers what is still queued, retries included, and then stops the
+// thread, so a short-lived process that keeps it until it exits announces
+// everything it did.
+pub struct WebhookWorker {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WebhookWorker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Starts delivering notifications to `Config::webhooks`. None if there are none.
+    pub fn start_webhooks(&self) -> Result<Option<WebhookWorker>, Box<dyn Error>> {
+        if self.config.webhooks.is_empty() {
+            return Ok(None);
+        }
+        let endpoints = self.config.webhooks.iter().map(|url| Endpoint::parse(url)).collect::<Result<Vec<_>, _>>()?;
+        let key = config::read_key(self.config.webhook_key_file.as_deref(), KEY_ENV, "webhook")?;
+        let network_id = self.network_id()?;
+        let retries = self.config.webhook_retries;
+        let events = self.subscribe();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = stop.clone();
+        let thread = thread::spawn(move || deliver_all(events, &stopping, &endpoints, key.as_deref(), &network_id, retries));
+        Ok(Some(WebhookWorker { stop, thread: Some(thread) }))
+    }
+}
+
+fn deliver_all(events: Receiver<ChainEvent>, stop: &AtomicBool, endpoints: &[Endpoint], key: Option<&[u8]>, network_id: &str, retries: u32) {
+    loop {
+        let event = match events.recv_timeout(POLL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) if stop.load(Ordering::Relaxed) => return,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let Some(notification) = Notification::from_event(event) else { continue };
+        let body = Body { network_id: network_id.to_string(), notification };
+        let (name, json) = match serde_json::to_value(&body) {
+            Ok(value) => (value["event"].as_str().unwrap_or_default().to_string(), value.to_string()),
+            Err(e) => {
+                warn!(error = %e, "cannot encode webhook notification");
+                continue;
+            }
+        };
+        let signature = key.map(|key| sign(key, &json));
+        for endpoint in endpoints {
+            deliver(endpoint, &name, &json, signature.as_deref(), retries);
+        }
+    }
+}
+
+fn deliver(endpoint: &Endpoint, event: &str, body: &str, signature: Option<&str>, retries: u32) {
+    let mut wait = FIRST_RETRY;
+    for attempt in 0..=retries {
+        match endpoint.post(event, body, signature) {
+            Ok(()) => {
+                debug!(url = %endpoint.url, event, "webhook delivered");
+                return;
+            }
+            Err(e) if attempt < retries => {
+                debug!(url = %endpoint.url, event, error = %e, retry_in_ms = wait.as_millis() as u64, "webhook failed");
+                thread::sleep(wait);
+                wait = (wait * 2).min(MAX_RETRY);
+            }
+            Err(e) => warn!(url = %endpoint.url, event, error = %e, "webhook failed, giving up"),
+        }
+    }
+}
+
+fn sign(key: &[u8], body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
@@ -0,0 +1,159 @@
+use serde::Serialize;
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+use crate::auth::{self, AuthError, Role};
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::events::ChainEvent;
+use crate::ratelimit::Slot;
+use crate::store::BlockStore;
+use crate::transaction::Transaction;
+use tracing::{debug, warn};
+
+// How long a client's socket is read before pending events are pushed, so this
+// bounds the delay of a notification.
+const POLL: Duration = Duration::from_millis(100);
+
+// How long a client has to complete the handshake, and each send may block. One
+// that is only to be told the server is full gets less.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Handshakes in progress at once, each on a thread of its own. Connections past it
+// are closed straight away, so clients that never finish one can't pile up threads.
+pub const MAX_HANDSHAKES: usize = 64;
+
+// One JSON text message per event, e.g.
+// {"type":"block","height":7,"block":{...}} or
+// {"type":"transaction","txid":"...","transaction":{...}}
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Notification<'a> {
+    // Also sent for each block a reorg connects; a repeated height replaces the block
+    // sent before.
+    Block { height: u64, block: &'a Block },
+    Transaction { txid: &'a str, transaction: &'a Transaction },
+}
+
+impl<S: BlockStore> Blockchain<S> {
+    // Pushes new canonical blocks and mempool transactions to every WebSocket client
+    // connected to `addr`, until the listener fails or the chain shuts down, when
+    // clients are sent a close frame. What clients send is ignored,
+    // but each connection and each message counts against the client's rate limit,
+    // and a client over it is disconnected. With API keys configured, the handshake
+    // needs one with the read_only role in an "Authorization: Bearer <key>" header.
+    // Past `Config::max_websocket_clients` subscribers, handshakes are answered 503,
+    // and past `MAX_HANDSHAKES` handshakes in progress, connections are closed.
+    pub fn serve_websocket(&self, addr: impl ToSocketAddrs) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(addr)?;
+        let clients = Arc::new(AtomicUsize::new(0));
+        let handshakes = Arc::new(AtomicUsize::new(0));
+        while let Some(stream) = self.accept(&listener)? {
+            if let Ok(peer) = stream.peer_addr()
+                && !self.allow_request(peer.ip())
+            {
+                continue;
+            }
+            let Some(handshake) = Slot::claim(&handshakes, MAX_HANDSHAKES) else {
+                debug!("too many websocket handshakes in progress, connection closed");
+                continue;
+            };
+            let slot = Slot::claim(&clients, self.config.max_websocket_clients);
+            let chain = self.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                match chain.notify_client(stream, handshake, slot) {
+                    Ok(()) => debug!(%peer, "websocket client left"),
+                    Err(e) => warn!(%peer, error = %e, "websocket client dropped"),
+                }
+            });
+        }
+        Ok(())
+    }
+
+    // `slot` is None when the server is full, and the client is only told so.
+    // `handshake` is given back once the handshake is over.
+    fn notify_client(&self, stream: TcpStream, handshake: Slot, slot: Option<Slot>) -> Result<(), Box<dyn Error>> {
+        // A slow handshake must not be cut off by the short read timeout below.
+        let timeout = if slot.is_some() { HANDSHAKE_TIMEOUT } else { REFUSAL_TIMEOUT };
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let peer = stream.peer_addr()?.ip();
+        // tungstenite's callback signature, not ours.
+        #[allow(clippy::result_large_err)]
+        let check_key = |request: &Request, response: Response| {
+            let key = request.headers().get("authorization").and_then(|value| value.to_str().ok()).and_then(auth::bearer);
+            match self.authorize(key, Role::ReadOnly) {
+                Ok(()) if slot.is_none() => {
+                    let mut refusal = ErrorResponse::new(Some("Too many subscribers, try again later".to_string()));
+                    *refusal.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    Err(refusal)
+                }
+                Ok(()) => Ok(response),
+                Err(e) => {
+                    let status = match e {
+                        AuthError::Unauthenticated => StatusCode::UNAUTHORIZED,
+                        AuthError::Forbidden { .. } => StatusCode::FORBIDDEN,
+                    };
+                    let mut refusal = ErrorResponse::new(Some(e.to_string()));
+                    *refusal.status_mut() = status;
+                    Err(refusal)
+                }
+            }
+        };
+        // The handshake error holds the callback, which borrows `self`.
+        let accepted = tungstenite::accept_hdr(stream, check_key).map_err(|e| e.to_string());
+        drop(handshake);
+        let mut socket = accepted?;
+        socket.get_ref().set_read_timeout(Some(POLL))?;
+        let events = self.subscribe();
+        while !self.is_shutting_down() {
+            // Reading answers pings and close frames.
+            match socket.read() {
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) if !self.allow_request(peer) => return Err("Rate limit exceeded".into()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            loop {
+                match events.try_recv() {
+                    Ok(event) => self.push(&mut socket, event)?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+        }
+        // The node is going away; a client that doesn't hear of it reconnects anyway.
+        let _ = socket.close(None);
+        let _ = socket.flush();
+        Ok(())
+    }
+
+    fn push(&self, socket: &mut WebSocket<TcpStream>, event: ChainEvent) -> Result<(), Box<dyn Error>> {
+        let text = match event {
+            ChainEvent::BlockAdded { hash, height } => {
+                let block = self.get_block(&hash)?.ok_or_else(|| format!("Block {} is missing", hash))?;
+                serde_json::to_string(&Notification::Block { height, block: &block })?
+            }
+            ChainEvent::TransactionSubmitted { txid, transaction } => {
+                serde_json::to_string(&Notification::Transaction { txid: &txid, transaction: &transaction })?
+            }
+            _ => return Ok(()),
+        };
+        socket.send(Message::text(text))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+// On a chain with approvers a block is proposed, and only joins the chain once
+// `threshold` different approvers have signed it.
+
+use std::collections::BTreeMap;
+
+use ledger_v1::approval::sign_approval;
+use ledger_v1::signer::Signer;
+use ledger_v1::test_utils::test_signer;
+use ledger_v1::{ApprovalOutcome, ApprovalPolicy, Block, Blockchain, Config, GenesisConfig, MemoryStore};
+
+fn genesis() -> GenesisConfig {
+    let approvers = (1..=3).map(|index| test_signer(index).public_key()).collect();
+    GenesisConfig { approval: Some(ApprovalPolicy { threshold: 2, approvers }), ..GenesisConfig::default() }
+}
+
+fn node() -> Blockchain<MemoryStore> {
+    Blockchain::open_store(MemoryStore::new(), Some(&genesis()), Config::default()).unwrap()
+}
+
+#[test]
+fn a_proposal_connects_at_its_threshold() {
+    let chain = node();
+    assert!(chain.add_block("direct").is_err());
+    let id = chain.propose_block("proposed", None, BTreeMap::new()).unwrap();
+    assert_eq!(chain.height().unwrap(), 0);
+
+    let pending = ApprovalOutcome::Pending { approvals: 1, needed: 2 };
+    assert_eq!(chain.add_approval(&id, sign_approval(&test_signer(1), &id).unwrap()).unwrap(), pending);
+    // The same approver again counts once.
+    assert_eq!(chain.add_approval(&id, sign_approval(&test_signer(1), &id).unwrap()).unwrap(), pending);
+    assert!(chain.add_approval(&id, sign_approval(&test_signer(7), &id).unwrap()).is_err());
+    let mut forged = sign_approval(&test_signer(1), &id).unwrap();
+    forged.approver = test_signer(2).public_key();
+    assert!(chain.add_approval(&id, forged).is_err());
+    assert_eq!(chain.height().unwrap(), 0);
+
+    let committed = chain.add_approval(&id, sign_approval(&test_signer(2), &id).unwrap()).unwrap();
+    assert_eq!(committed, ApprovalOutcome::Committed { height: 1 });
+    assert_eq!(chain.current_hash(), id);
+    assert!(chain.proposals().unwrap().is_empty());
+
+    // Another node takes the block with its approvals, and not without enough of them.
+    let block = chain.get_block(&id).unwrap().unwrap();
+    let observer = node();
+    assert!(observer.receive_block(Block { approvals: block.approvals[..1].to_vec(), ..block.clone() }).is_err());
+    observer.receive_block(block).unwrap();
+    assert_eq!(observer.current_hash(), id);
+}
+
+#[test]
+fn a_proposal_on_an_old_tip_cannot_connect() {
+    let chain = node();
+    let first = chain.propose_block("first", None, BTreeMap::new()).unwrap();
+    let second = chain.propose_block("second", None, BTreeMap::new()).unwrap();
+    for id in [&first, &second] {
+        chain.add_approval(id, sign_approval(&test_signer(1), id).unwrap()).unwrap();
+    }
+    chain.add_approval(&first, sign_approval(&test_signer(3), &first).unwrap()).unwrap();
+
+    let stale = chain.add_approval(&second, sign_approval(&test_signer(3), &second).unwrap()).unwrap_err();
+    assert!(stale.to_string().contains("the tip has moved"), "{}", stale);
+    assert!(chain.discard_proposal(&second).unwrap());
+    assert_eq!(chain.current_hash(), first);
+}
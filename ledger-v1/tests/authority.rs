@@ -0,0 +1,68 @@
+// On a proof-of-authority chain each height belongs to one authority, in turn: a node
+// only signs its own heights, and a block signed by anyone else is refused.
+
+use std::path::PathBuf;
+
+use ed25519_dalek::{Signer, SigningKey};
+use ledger_v1::authority;
+use ledger_v1::{Block, Blockchain, Config, Consensus, GenesisConfig, MemoryStore};
+
+struct Authority {
+    secret: String,
+    public: String,
+    key_file: PathBuf,
+}
+
+fn authority(seed: u64) -> Authority {
+    let (secret, public) = authority::generate_key_from_seed(seed);
+    let key_file = std::env::temp_dir().join(format!("ledger-v1-authority-{}-{}", std::process::id(), seed));
+    std::fs::write(&key_file, &secret).unwrap();
+    Authority { secret, public, key_file }
+}
+
+fn node(genesis: &GenesisConfig, key: Option<&Authority>) -> Blockchain<MemoryStore> {
+    let config = Config { authority_key_file: key.map(|key| key.key_file.clone()), ..Config::default() };
+    Blockchain::open_store(MemoryStore::new(), Some(genesis), config).unwrap()
+}
+
+// `block` signed by `key` instead.
+fn signed_by(mut block: Block, key: &Authority) -> Block {
+    let secret: [u8; 32] = hex::decode(&key.secret).unwrap().try_into().unwrap();
+    block.signature = hex::encode(SigningKey::from_bytes(&secret).sign(block.hash.as_bytes()).to_bytes());
+    block
+}
+
+#[test]
+fn authorities_take_turns() {
+    let (a, b) = (authority(1), authority(2));
+    let genesis = GenesisConfig {
+        consensus: Consensus::ProofOfAuthority { authorities: vec![a.public.clone(), b.public.clone()] },
+        ..GenesisConfig::default()
+    };
+    let (node_a, node_b, observer) = (node(&genesis, Some(&a)), node(&genesis, Some(&b)), node(&genesis, None));
+    assert_eq!(authority::scheduled(&[a.public.clone(), b.public.clone()], 1), Some(a.public.as_str()));
+
+    let refused = node_b.add_block("not my turn").unwrap_err();
+    assert!(refused.to_string().contains("is for authority"), "{}", refused);
+    assert!(observer.add_block("no key").is_err());
+
+    node_a.add_block("one").unwrap();
+    let one = node_a.get_blocks_range(1, 1).unwrap().remove(0);
+    node_b.receive_block(one.clone()).unwrap();
+    node_b.add_block("two").unwrap();
+    let two = node_b.get_blocks_range(2, 2).unwrap().remove(0);
+
+    // Height 1 is a's; the same block signed by b, or by nobody, is no good.
+    for forged in [signed_by(one.clone(), &b), Block { signature: String::new(), ..one.clone() }] {
+        assert!(observer.receive_block(forged).is_err());
+    }
+    observer.receive_block(one).unwrap();
+    assert!(observer.receive_block(signed_by(two.clone(), &a)).is_err());
+    observer.receive_block(two.clone()).unwrap();
+    assert_eq!(observer.current_hash(), two.hash);
+    assert!(observer.is_chain_valid().unwrap());
+
+    for key in [a, b] {
+        let _ = std::fs::remove_file(key.key_file);
+    }
+}
@@ -0,0 +1,43 @@
+// `add_blocks` commits a batch as a whole: one bad block keeps every other block of
+// it out, and a batch sent again is only acknowledged.
+
+use ledger_v1::test_utils;
+use ledger_v1::{BlockStatus, BlockStore, Blockchain, MemoryStore};
+
+fn check_batches<S: BlockStore>(store: S) {
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let source = test_utils::generate_chain_with_genesis(&genesis, 11, 5).unwrap();
+    let blocks = source.get_blocks_range(1, 5).unwrap();
+    let chain = Blockchain::open_store(store, Some(&genesis), test_utils::miner_config()).unwrap();
+
+    let mut damaged = blocks.clone();
+    damaged[3].data.push(b'!');
+    assert!(chain.add_blocks(&damaged).is_err());
+    assert_eq!(chain.height().unwrap(), 0);
+    for block in &blocks[..3] {
+        assert!(chain.get_block(&block.hash).unwrap().is_none());
+    }
+
+    let statuses = chain.add_blocks(&blocks).unwrap();
+    assert!(statuses.iter().all(|status| matches!(status, BlockStatus::Extended)));
+    assert_eq!(chain.current_hash(), source.current_hash());
+    assert_eq!(chain.get_account(&test_utils::test_address(1)).unwrap(), source.get_account(&test_utils::test_address(1)).unwrap());
+
+    let statuses = chain.add_blocks(&blocks).unwrap();
+    assert!(statuses.iter().all(|status| matches!(status, BlockStatus::AlreadyKnown)));
+    assert_eq!(chain.height().unwrap(), 5);
+}
+
+#[test]
+fn a_bad_block_keeps_the_whole_batch_out() {
+    check_batches(MemoryStore::new());
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn a_bad_block_keeps_the_whole_batch_out_of_sled() {
+    let dir = std::env::temp_dir().join(format!("ledger-v1-batch-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    check_batches(ledger_v1::SledStore::open(dir.to_str().unwrap()).unwrap());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,27 @@
+// Adding a block that is stored already never overwrites it: `receive_block` fails
+// with `LedgerError::DuplicateBlock`, and `add_block_if_absent` and `add_blocks`
+// only report it as known.
+
+use ledger_v1::test_utils;
+use ledger_v1::{Block, BlockStatus, Blockchain, LedgerError, MemoryStore};
+
+#[test]
+fn a_stored_block_is_never_overwritten() {
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let source = test_utils::generate_chain_with_genesis(&genesis, 8, 2).unwrap();
+    let blocks = source.get_blocks_range(1, 2).unwrap();
+    let chain = Blockchain::open_store(MemoryStore::new(), Some(&genesis), test_utils::miner_config()).unwrap();
+    chain.receive_block(blocks[0].clone()).unwrap();
+
+    // The signature is not part of the hash, so this is the same block to the store.
+    let resent = Block { signature: "ff".repeat(64), ..blocks[0].clone() };
+    let refused = chain.receive_block(resent.clone()).unwrap_err();
+    assert_eq!(refused.downcast_ref::<LedgerError>(), Some(&LedgerError::DuplicateBlock { hash: blocks[0].hash.clone() }));
+    assert!(matches!(chain.add_block_if_absent(resent).unwrap(), BlockStatus::AlreadyKnown));
+    assert_eq!(chain.get_block(&blocks[0].hash).unwrap().unwrap().signature, blocks[0].signature);
+
+    let statuses = chain.add_blocks(&[blocks[0].clone(), blocks[1].clone(), blocks[1].clone()]).unwrap();
+    assert!(matches!(statuses[..], [BlockStatus::AlreadyKnown, BlockStatus::Extended, BlockStatus::AlreadyKnown]));
+    assert_eq!(chain.current_hash(), blocks[1].hash);
+    assert_eq!(chain.get_account(&test_utils::test_address(1)).unwrap(), source.get_account(&test_utils::test_address(1)).unwrap());
+}
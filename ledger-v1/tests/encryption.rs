@@ -0,0 +1,66 @@
+// An encrypted chain keeps block contents out of every tree, headers and indexes
+// included.
+
+use ledger_v1::test_utils::{self, miner_config};
+use ledger_v1::{Blockchain, BlockStore, Config, MemoryStore, Transaction, TreeId};
+
+fn encrypted(config: Config, name: &str) -> Config {
+    let path = std::env::temp_dir().join(format!("ledger-v1-encryption-{}-{}.key", std::process::id(), name));
+    std::fs::write(&path, "33".repeat(32)).unwrap();
+    Config { encryption_key_file: Some(path), ..config }
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn header_metadata_never_reaches_the_disk_in_the_clear() {
+    fn holds(dir: &std::path::Path, needle: &[u8]) -> bool {
+        std::fs::read_dir(dir).unwrap().any(|entry| {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                return holds(&path, needle);
+            }
+            std::fs::read(&path).unwrap().windows(needle.len()).any(|window| window == needle)
+        })
+    }
+
+    let dir = std::env::temp_dir().join(format!("ledger-v1-encryption-{}", std::process::id()));
+    let chain = Blockchain::open_with_config(dir.to_str().unwrap(), None, encrypted(Config::default(), "sled")).unwrap();
+    let metadata = std::collections::BTreeMap::from([("case".to_string(), "quarterly-restructuring".to_string())]);
+    chain.add_block_with_metadata("body", Some("application/x-board-minutes"), metadata).unwrap();
+    let tip = chain.current_hash();
+    assert_eq!(chain.header(&tip).unwrap().unwrap().metadata["case"], "quarterly-restructuring");
+    drop(chain);
+
+    assert!(!holds(&dir, b"quarterly-restructuring"));
+    assert!(!holds(&dir, b"x-board-minutes"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn history_is_keyed_by_index_token() {
+    let store = MemoryStore::new();
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let history_keys = || -> Vec<Vec<u8>> { store.scan_prefix(TreeId::History, &[]).map(|entry| entry.unwrap().0).collect() };
+    let needle = b"confidential-payee";
+    let names_payee = |key: &Vec<u8>| key.windows(needle.len()).any(|window| window == needle);
+
+    // Written in the clear, then rebuilt once a key is configured.
+    let chain = Blockchain::open_store(store.clone(), Some(&genesis), miner_config()).unwrap();
+    let pay = |chain: &Blockchain<MemoryStore>, nonce: u64| {
+        let transfer = Transaction::transfer(&test_utils::test_address(1), "confidential-payee", 5)
+            .with_nonce(nonce)
+            .signed(&test_utils::test_signer(1), &chain.network_id().unwrap())
+            .unwrap();
+        chain.add_block_with_transactions("pay", vec![transfer]).unwrap();
+    };
+    pay(&chain, 0);
+    assert!(history_keys().iter().any(names_payee));
+    drop(chain);
+
+    let chain = Blockchain::open_store(store.clone(), Some(&genesis), encrypted(miner_config(), "history")).unwrap();
+    pay(&chain, 1);
+    let keys = history_keys();
+    assert!(!keys.is_empty());
+    assert!(!keys.iter().any(names_payee));
+    assert_eq!(chain.get_history("confidential-payee").unwrap().len(), 2);
+}
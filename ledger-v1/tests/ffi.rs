@@ -0,0 +1,46 @@
+#![cfg(all(feature = "ffi", feature = "sled"))]
+
+// The C ABI hands back the block it added, and tells a database in use from other
+// failures.
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use ledger_v1::ffi::*;
+
+#[test]
+fn add_block_returns_the_new_blocks_hash() {
+    let dir = std::env::temp_dir().join(format!("ledger-v1-ffi-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = CString::new(dir.to_str().unwrap()).unwrap();
+    unsafe {
+        let mut chain = ptr::null_mut();
+        assert_eq!(ledger_open(path.as_ptr(), ptr::null(), ptr::null(), &mut chain), LEDGER_OK);
+        let mut hash = ptr::null_mut();
+        assert_eq!(ledger_add_block(chain, b"hello".as_ptr(), 5, &mut hash), LEDGER_OK);
+        let mut json = ptr::null_mut();
+        assert_eq!(ledger_get_block(chain, hash, &mut json), LEDGER_OK);
+        let block: ledger_v1::Block = serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+        assert_eq!(block.data, b"hello");
+        assert_eq!(block.hash, CStr::from_ptr(hash).to_str().unwrap());
+        ledger_string_free(json);
+        ledger_string_free(hash);
+
+        let mut second = ptr::null_mut();
+        assert_eq!(ledger_open(path.as_ptr(), ptr::null(), ptr::null(), &mut second), LEDGER_ERR_IN_USE);
+        assert_eq!(ledger_close(chain), LEDGER_OK);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn other_failures_are_not_reported_as_in_use() {
+    let dir = std::env::temp_dir().join(format!("ledger-v1-ffi-failed-{}", std::process::id()));
+    let path = CString::new(dir.to_str().unwrap()).unwrap();
+    let config = CString::new(dir.join("missing.toml").to_str().unwrap()).unwrap();
+    unsafe {
+        let mut chain = ptr::null_mut();
+        assert_eq!(ledger_open(path.as_ptr(), config.as_ptr(), ptr::null(), &mut chain), LEDGER_ERR_FAILED);
+        assert!(chain.is_null());
+    }
+}
@@ -0,0 +1,28 @@
+// Blocks `finality_depth` below the tip are final: a branch forking under them is
+// refused however much work it has, and one forking at or above them may still win.
+
+use ledger_v1::test_utils;
+use ledger_v1::{BlockStatus, Blockchain, Config, MemoryStore};
+
+#[test]
+fn branches_below_the_finalized_height_are_refused() {
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let source = test_utils::generate_chain_with_genesis(&genesis, 9, 5).unwrap();
+    let config = Config { finality_depth: 2, ..test_utils::miner_config() };
+    let chain = Blockchain::open_store(MemoryStore::new(), Some(&genesis), config).unwrap();
+    assert_eq!(chain.finalized_tip().unwrap(), None);
+    chain.add_blocks(&source.get_blocks_range(1, 5).unwrap()).unwrap();
+    let final_block = source.get_blocks_range(3, 3).unwrap().remove(0);
+    assert_eq!(chain.finalized_tip().unwrap(), Some((3, final_block.hash)));
+
+    let below = test_utils::generate_branch(&chain, 2, 5, 1).unwrap();
+    let refused = chain.add_blocks(&below).unwrap_err();
+    assert!(refused.to_string().contains("finalized height 3"), "{}", refused);
+    assert_eq!(chain.current_hash(), source.current_hash());
+
+    let above = test_utils::generate_branch(&chain, 3, 3, 2).unwrap();
+    let statuses = chain.add_blocks(&above).unwrap();
+    assert!(matches!(statuses.last(), Some(BlockStatus::Reorged { .. })));
+    assert_eq!(chain.current_hash(), above[2].hash);
+    assert_eq!(chain.finalized_tip().unwrap().map(|(height, _)| height), Some(4));
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "grpc")]
+
+// Block streams past the server's limit are refused with RESOURCE_EXHAUSTED, and the
+// next one is served once a stream ends.
+
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use ledger_v1::grpc::proto::ledger_client::LedgerClient;
+use ledger_v1::grpc::proto::StreamBlocksRequest;
+use ledger_v1::{Blockchain, Config, MemoryStore};
+use tonic::Code;
+
+#[test]
+fn block_streams_past_the_limit_are_refused() {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    let addr: SocketAddr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let chain = Blockchain::open_store(MemoryStore::new(), None, Config { max_grpc_streams: 1, ..Config::default() }).unwrap();
+    let server = chain.clone();
+    runtime.spawn(async move { server.serve_grpc(addr).await.unwrap() });
+
+    runtime.block_on(async {
+        let mut client = loop {
+            match LedgerClient::connect(format!("http://{}", addr)).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        };
+        let mut first = client.stream_blocks(StreamBlocksRequest { from_height: 0 }).await.unwrap().into_inner();
+        assert!(first.message().await.unwrap().is_some());
+        let refused = client.stream_blocks(StreamBlocksRequest { from_height: 0 }).await.unwrap_err();
+        assert_eq!(refused.code(), Code::ResourceExhausted);
+
+        // The stream's thread notices the client left at its next idle check.
+        drop(first);
+        let mut admitted = false;
+        for _ in 0..50 {
+            if client.stream_blocks(StreamBlocksRequest { from_height: 0 }).await.is_ok() {
+                admitted = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(admitted);
+    });
+    chain.request_shutdown();
+}
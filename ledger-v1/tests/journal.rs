@@ -0,0 +1,101 @@
+// A reorg interrupted halfway through its writes leaves its journal entry behind, and
+// the next open finishes it or undoes it, as `journal_recovery` says, so the chain
+// comes back whole either way.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use ledger_v1::store::{Entries, Writes};
+use ledger_v1::test_utils::{self, ManualClock};
+use ledger_v1::{BlockStore, Blockchain, Config, GenesisConfig, JournalRecovery, MemoryStore, TreeId};
+
+// A MemoryStore that, once `crash` is set, applies half of the next batch and dies,
+// as a process killed mid-write would. Journal writes go through.
+#[derive(Clone, Default)]
+struct CrashingStore {
+    inner: MemoryStore,
+    crash: Arc<AtomicBool>,
+}
+
+impl BlockStore for CrashingStore {
+    fn get(&self, tree: TreeId, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        self.inner.get(tree, key)
+    }
+
+    fn scan_prefix(&self, tree: TreeId, prefix: &[u8]) -> Entries<'_> {
+        self.inner.scan_prefix(tree, prefix)
+    }
+
+    fn apply(&self, writes: &Writes) -> Result<(), Box<dyn std::error::Error>> {
+        if self.crash.load(Ordering::SeqCst) && !writes.keys().any(|(tree, _)| *tree == TreeId::Journal) {
+            let half: Writes = writes.iter().take(writes.len() / 2).map(|(key, value)| (key.clone(), value.clone())).collect();
+            self.inner.apply(&half)?;
+            panic!("killed while writing");
+        }
+        self.inner.apply(writes)
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.inner.flush()
+    }
+}
+
+struct Crashed {
+    store: MemoryStore,
+    genesis: GenesisConfig,
+    old_tip: String,
+    new_tip: String,
+}
+
+// A chain of two blocks killed while reorganizing onto a branch of two forking
+// after the first.
+fn crash_during_reorg() -> Crashed {
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let store = CrashingStore::default();
+    let clock = ManualClock::new(genesis.timestamp);
+    let chain = Blockchain::open_store(store.clone(), Some(&genesis), test_utils::miner_config()).unwrap().with_clock(clock.clone());
+    for data in ["one", "two"] {
+        clock.advance(1_000);
+        chain.add_block(data).unwrap();
+    }
+    let branch = test_utils::generate_branch(&chain, 1, 2, 5).unwrap();
+    clock.set(branch[1].timestamp);
+    chain.receive_block(branch[0].clone()).unwrap();
+    let old_tip = chain.current_hash();
+
+    store.crash.store(true, Ordering::SeqCst);
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| chain.receive_block(branch[1].clone()))).is_err());
+    assert!(store.inner.get(TreeId::Journal, b"PENDING").unwrap().is_some());
+    Crashed { store: store.inner, genesis, old_tip, new_tip: branch[1].hash.clone() }
+}
+
+// Opens the crashed store, and checks its state against a chain built from its
+// canonical blocks alone.
+fn reopen(crashed: &Crashed, journal_recovery: JournalRecovery) -> Blockchain<MemoryStore> {
+    let config = Config { journal_recovery, ..test_utils::miner_config() };
+    let chain = Blockchain::open_store(crashed.store.clone(), Some(&crashed.genesis), config).unwrap();
+    assert_eq!(chain.pending_operation().unwrap(), None);
+    assert!(chain.is_chain_valid().unwrap());
+
+    let replayed = Blockchain::open_store(MemoryStore::new(), Some(&crashed.genesis), test_utils::miner_config()).unwrap();
+    replayed.add_blocks(&chain.get_blocks_range(1, chain.height().unwrap()).unwrap()).unwrap();
+    assert_eq!(replayed.current_hash(), chain.current_hash());
+    for index in 0..2 {
+        let address = test_utils::test_address(index);
+        assert_eq!(chain.get_account(&address).unwrap(), replayed.get_account(&address).unwrap());
+    }
+    chain
+}
+
+#[test]
+fn an_interrupted_reorg_is_replayed() {
+    let crashed = crash_during_reorg();
+    assert_eq!(reopen(&crashed, JournalRecovery::Replay).current_hash(), crashed.new_tip);
+}
+
+#[test]
+fn an_interrupted_reorg_is_rolled_back() {
+    let crashed = crash_during_reorg();
+    assert_eq!(reopen(&crashed, JournalRecovery::Rollback).current_hash(), crashed.old_tip);
+}
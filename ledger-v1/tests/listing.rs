@@ -0,0 +1,24 @@
+// Listing the canonical chain by height and by time.
+
+use ledger_v1::test_utils::ManualClock;
+use ledger_v1::{Blockchain, Config, MemoryStore};
+
+#[test]
+fn a_time_range_is_paged_by_height() {
+    let chain = Blockchain::open_store(MemoryStore::new(), None, Config::default()).unwrap();
+    let clock = ManualClock::new(chain.get_blocks_range(0, 0).unwrap()[0].timestamp);
+    let chain = chain.with_clock(clock.clone());
+    for height in 1..=6u64 {
+        clock.advance(1_000);
+        chain.add_block(height.to_string()).unwrap();
+    }
+    let at = |height: u64| chain.get_blocks_range(height, height).unwrap()[0].timestamp;
+    let heights = |offset, limit| -> Vec<u64> {
+        chain.list_blocks_between(Some(at(2)), Some(at(6)), offset, limit).unwrap().iter().map(|block| block.height).collect()
+    };
+
+    assert_eq!(heights(0, None), [2, 3, 4, 5]);
+    assert_eq!(heights(3, None), [3, 4, 5]);
+    assert_eq!(heights(3, Some(1)), [3]);
+    assert_eq!(heights(9, None), Vec::<u64>::new());
+}
@@ -0,0 +1,50 @@
+// A database in use is refused to readers as well as writers, rather than read
+// from a copy that may be torn.
+#![cfg(feature = "sled")]
+
+use ledger_v1::{lockfile, LedgerError, SledStore};
+
+#[test]
+fn read_only_opens_are_refused_while_the_database_is_in_use() {
+    let dir = std::env::temp_dir().join(format!("ledger-v1-lock-{}", std::process::id()));
+    let path = dir.to_str().unwrap();
+    let writer = SledStore::open(path).unwrap();
+    let refused = SledStore::open_read_only(path).err().unwrap();
+    assert!(matches!(refused.downcast_ref::<LedgerError>(), Some(LedgerError::AlreadyInUse { pid: Some(pid) }) if *pid == std::process::id()));
+    drop(writer);
+    assert!(SledStore::open_read_only(path).is_ok());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn write_owner(dir: &std::path::Path, pid: u32) {
+    std::fs::create_dir_all(dir).unwrap();
+    let owner = format!(r#"{{"pid": {}, "host": "", "opened_at": 0}}"#, pid);
+    std::fs::write(dir.join("ledger.lock"), owner).unwrap();
+}
+
+#[test]
+fn a_lock_file_nobody_holds_is_taken_over_whatever_pid_it_names() {
+    // Our own pid, as after a restart that reused it, and one that is surely alive.
+    for pid in [std::process::id(), 1] {
+        let dir = std::env::temp_dir().join(format!("ledger-v1-lock-stale-{}-{}", std::process::id(), pid));
+        write_owner(&dir, pid);
+        let store = SledStore::open(dir.to_str().unwrap()).unwrap();
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn force_unlock_clears_any_pid_but_not_a_holder() {
+    let dir = std::env::temp_dir().join(format!("ledger-v1-lock-force-{}", std::process::id()));
+    let path = dir.to_str().unwrap();
+    let writer = SledStore::open(path).unwrap();
+    let refused = lockfile::force_unlock(path).unwrap_err();
+    assert!(matches!(refused.downcast_ref::<LedgerError>(), Some(LedgerError::AlreadyInUse { .. })));
+    drop(writer);
+
+    write_owner(&dir, 1);
+    assert_eq!(lockfile::force_unlock(path).unwrap(), Some(1));
+    assert_eq!(lockfile::force_unlock(path).unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,77 @@
+// A full mempool must make room for better-paying transactions without leaving a
+// sender's later nonces stranded.
+
+use ledger_v1::test_utils::{self, miner_config};
+use ledger_v1::{Blockchain, Config, MempoolLimitError, MemoryStore, Transaction};
+
+fn pool(limit: usize) -> Blockchain<MemoryStore> {
+    let config = Config { max_mempool_transactions: limit, min_fee: 0, ..miner_config() };
+    Blockchain::open_store(MemoryStore::new(), Some(&test_utils::funded_genesis(3, 1_000)), config).unwrap()
+}
+
+fn transfer(chain: &Blockchain<MemoryStore>, sender: u64, nonce: u64, fee: u64) -> Transaction {
+    Transaction::transfer_with_fee(&test_utils::test_address(sender), "anyone", 1, fee)
+        .with_nonce(nonce)
+        .signed(&test_utils::test_signer(sender), &chain.network_id().unwrap())
+        .unwrap()
+}
+
+#[test]
+fn eviction_takes_the_end_of_a_nonce_chain() {
+    let chain = pool(3);
+    // The cheapest transaction is the first of its sender's chain, so the one after
+    // it must go instead.
+    chain.submit_transaction(transfer(&chain, 1, 0, 1)).unwrap();
+    chain.submit_transaction(transfer(&chain, 1, 1, 5)).unwrap();
+    chain.submit_transaction(transfer(&chain, 2, 0, 4)).unwrap();
+    chain.submit_transaction(transfer(&chain, 2, 1, 9)).unwrap();
+
+    let pending = chain.mempool();
+    assert_eq!(pending.len(), 3);
+    assert!(pending.contains(&transfer(&chain, 1, 0, 1)));
+    assert!(!pending.contains(&transfer(&chain, 1, 1, 5)));
+    chain.mine_block("block").unwrap();
+    assert!(chain.mempool().is_empty());
+}
+
+#[test]
+fn a_transaction_does_not_evict_what_it_builds_on() {
+    let chain = pool(2);
+    chain.submit_transaction(transfer(&chain, 1, 0, 1)).unwrap();
+    chain.submit_transaction(transfer(&chain, 2, 0, 9)).unwrap();
+    chain.submit_transaction(transfer(&chain, 1, 1, 5)).unwrap_err();
+    assert_eq!(chain.mempool().len(), 2);
+}
+
+#[test]
+fn a_full_pool_replaces_its_lowest_fee_rate_transaction() {
+    let chain = pool(2);
+    chain.submit_transaction(transfer(&chain, 1, 0, 1)).unwrap();
+    chain.submit_transaction(transfer(&chain, 2, 0, 9)).unwrap();
+
+    chain.submit_transaction(transfer(&chain, 0, 0, 5)).unwrap();
+    let pending = chain.mempool();
+    assert_eq!(pending.len(), 2);
+    assert!(!pending.contains(&transfer(&chain, 1, 0, 1)));
+
+    // Nothing pays less than what it would replace.
+    let refused = chain.submit_transaction(transfer(&chain, 1, 0, 2)).unwrap_err();
+    assert!(matches!(refused.downcast_ref::<MempoolLimitError>(), Some(MempoolLimitError::PoolFull { limit: 2 })), "{}", refused);
+    assert_eq!(chain.mempool(), pending);
+}
+
+#[test]
+fn a_full_pool_evicts_around_what_a_transaction_builds_on() {
+    let chain = pool(2);
+    chain.submit_transaction(transfer(&chain, 1, 0, 1)).unwrap();
+    chain.submit_transaction(transfer(&chain, 2, 0, 9)).unwrap();
+
+    // Its parent has the lowest fee rate, so the next lowest goes instead.
+    chain.submit_transaction(transfer(&chain, 1, 1, 20)).unwrap();
+    let pending = chain.mempool();
+    assert!(pending.contains(&transfer(&chain, 1, 0, 1)));
+    assert!(pending.contains(&transfer(&chain, 1, 1, 20)));
+    assert!(!pending.contains(&transfer(&chain, 2, 0, 9)));
+    chain.mine_block("block").unwrap();
+    assert_eq!(chain.get_account(&test_utils::test_address(1)).unwrap().nonce, 2);
+}
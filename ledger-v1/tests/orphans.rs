@@ -0,0 +1,48 @@
+// Blocks arriving before their parent wait in the orphan pool and connect once it
+// does. The pool holds `max_orphans`, dropping the oldest, and forgets blocks after
+// `orphan_expiry_ms`.
+
+use ledger_v1::test_utils::{self, ManualClock};
+use ledger_v1::{Block, BlockStatus, Blockchain, Config, MemoryStore};
+
+fn setup(config: Config) -> (Blockchain<MemoryStore>, ManualClock, Vec<Block>) {
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let source = test_utils::generate_chain_with_genesis(&genesis, 4, 4).unwrap();
+    let blocks = source.get_blocks_range(1, 4).unwrap();
+    let clock = ManualClock::new(blocks[3].timestamp);
+    let chain = Blockchain::open_store(MemoryStore::new(), Some(&genesis), config).unwrap().with_clock(clock.clone());
+    (chain, clock, blocks)
+}
+
+#[test]
+fn orphans_connect_once_their_parent_arrives() {
+    let (chain, _, blocks) = setup(test_utils::miner_config());
+    for block in blocks[1..].iter().rev() {
+        assert!(matches!(chain.receive_block(block.clone()).unwrap(), BlockStatus::Orphaned));
+    }
+    assert_eq!(chain.height().unwrap(), 0);
+    assert_eq!(chain.metrics().unwrap().orphans, 3);
+
+    chain.receive_block(blocks[0].clone()).unwrap();
+    assert_eq!(chain.current_hash(), blocks[3].hash);
+    assert_eq!(chain.metrics().unwrap().orphans, 0);
+}
+
+#[test]
+fn the_pool_drops_the_oldest_and_expired_orphans() {
+    let config = Config { max_orphans: 2, orphan_expiry_ms: 1_000, ..test_utils::miner_config() };
+    let (chain, clock, blocks) = setup(config);
+    // The block at height 4 is the oldest when the third orphan comes in.
+    for block in blocks[1..].iter().rev() {
+        chain.receive_block(block.clone()).unwrap();
+        clock.advance(1);
+    }
+    chain.receive_block(blocks[0].clone()).unwrap();
+    assert_eq!(chain.current_hash(), blocks[2].hash);
+
+    let (chain, clock, blocks) = setup(Config { orphan_expiry_ms: 1_000, ..test_utils::miner_config() });
+    chain.receive_block(blocks[1].clone()).unwrap();
+    clock.advance(1_000);
+    chain.receive_block(blocks[0].clone()).unwrap();
+    assert_eq!(chain.current_hash(), blocks[0].hash);
+}
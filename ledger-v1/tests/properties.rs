@@ -0,0 +1,202 @@
+// Properties every chain the `test_utils` generators make must have, checked over
+// many seeds, and decoders that must survive any bytes. A failing case prints the
+// seed it started from, which replays it exactly.
+
+use std::collections::BTreeMap;
+
+use ledger_v1::state::{self, Account};
+use ledger_v1::test_utils::{self, TestRng};
+use ledger_v1::{Block, BlockStatus, Blockchain, MemoryStore, Transaction};
+use proptest::prelude::*;
+
+const ACCOUNTS: u64 = 4;
+
+fn ledger(seed: u64, length: u64) -> Blockchain<MemoryStore> {
+    test_utils::generate_chain_with_genesis(&test_utils::funded_genesis(ACCOUNTS, 1_000), seed, length).unwrap()
+}
+
+// The state after every block, worked out from the blocks alone: balances move as
+// each transaction says, and each sender's nonce counts what it has sent.
+fn replay(chain: &Blockchain<MemoryStore>) -> Vec<BTreeMap<String, Account>> {
+    let genesis = chain.genesis_config();
+    let mut accounts: BTreeMap<String, Account> = genesis
+        .allocations
+        .iter()
+        .map(|(address, balance)| (address.clone(), Account { balance: *balance, ..Account::default() }))
+        .collect();
+    let mut states = vec![accounts.clone()];
+    for block in chain.get_blocks_range(1, chain.height().unwrap()).unwrap() {
+        for transaction in &block.transactions {
+            match transaction {
+                Transaction::Transfer { from, to, amount, fee, .. } => {
+                    let sender = accounts.entry(from.clone()).or_default();
+                    sender.balance -= amount + fee;
+                    sender.nonce += 1;
+                    accounts.entry(to.clone()).or_default().balance += amount;
+                }
+                Transaction::Coinbase { to, amount, .. } => accounts.entry(to.clone()).or_default().balance += amount,
+                other => panic!("generated an unexpected {:?}", other),
+            }
+        }
+        states.push(accounts.clone());
+    }
+    states
+}
+
+// `block` with its first transaction, the coinbase, minting `extra` more, and hashed
+// again so only the coinbase rule can refuse it.
+fn inflate_coinbase(mut block: Block, extra: u64) -> Block {
+    if let Some(Transaction::Coinbase { amount, .. }) = block.transactions.first_mut() {
+        *amount += extra;
+    }
+    block.hash = block.calculate_hash();
+    block
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn generated_chains_validate(seed in any::<u64>(), length in 0u64..12) {
+        let chain = test_utils::generate_chain(seed, length).unwrap();
+        prop_assert_eq!(chain.height().unwrap(), length);
+        prop_assert!(chain.is_chain_valid().unwrap());
+        prop_assert!(chain.deep_validate().unwrap().is_consistent());
+
+        let ledger = ledger(seed, length);
+        prop_assert!(ledger.is_chain_valid().unwrap());
+        prop_assert!(ledger.deep_validate().unwrap().is_consistent());
+    }
+
+    #[test]
+    fn generation_is_deterministic(seed in any::<u64>(), length in 1u64..8) {
+        prop_assert_eq!(ledger(seed, length).current_hash(), ledger(seed, length).current_hash());
+    }
+
+    #[test]
+    fn state_roots_match_a_recomputation(seed in any::<u64>(), length in 1u64..12) {
+        let chain = ledger(seed, length);
+        let algorithm = chain.genesis_config().hash_algorithm;
+        let states = replay(&chain);
+        for (height, expected) in states.iter().enumerate() {
+            let height = height as u64;
+            prop_assert_eq!(&chain.state_at(height).unwrap(), expected, "state at height {}", height);
+            let block = chain.get_blocks_range(height, height).unwrap().remove(0);
+            let root = state::state_root(algorithm, expected).unwrap();
+            prop_assert_eq!(&chain.state_root_at(height).unwrap(), &root, "stored root at height {}", height);
+            if height > 0 {
+                prop_assert_eq!(&block.state_root, &root, "root at height {}", height);
+            }
+        }
+    }
+
+    #[test]
+    fn reorgs_roll_the_state_back(seed in any::<u64>(), length in 1u64..10, fork in 0u64..10, extra in 1u64..4) {
+        let fork = fork % length;
+        let chain = ledger(seed, length);
+        let at_fork = chain.state_at(fork).unwrap();
+        let branch = test_utils::generate_branch(&chain, fork, length - fork + extra, seed ^ 1).unwrap();
+        chain.add_blocks(&branch).unwrap();
+        prop_assert_eq!(chain.current_hash(), branch.last().unwrap().hash.clone());
+        prop_assert_eq!(chain.state_at(fork).unwrap(), at_fork);
+
+        // The same as a node that only ever saw the branch.
+        let other = Blockchain::open_store(MemoryStore::new(), Some(&chain.genesis_config()), test_utils::miner_config()).unwrap();
+        other.add_blocks(&chain.get_blocks_range(1, chain.height().unwrap()).unwrap()).unwrap();
+        let tip = chain.height().unwrap();
+        prop_assert_eq!(chain.state_at(tip).unwrap(), other.state_at(tip).unwrap());
+        prop_assert_eq!(chain.state_at(tip).unwrap(), replay(&chain).pop().unwrap());
+        prop_assert_eq!(chain.state_root_at(tip).unwrap(), other.state_root_at(tip).unwrap());
+        prop_assert!(chain.deep_validate().unwrap().is_consistent());
+    }
+
+    #[test]
+    fn coinbases_mint_the_reward_and_fees(seed in any::<u64>(), length in 1u64..12) {
+        let chain = ledger(seed, length);
+        let genesis = chain.genesis_config();
+        for (height, block) in (1..).zip(chain.get_blocks_range(1, length).unwrap()) {
+            let fees: u64 = block.transactions.iter().map(Transaction::fee).sum();
+            let coinbases: Vec<_> = block.transactions.iter().filter(|t| matches!(t, Transaction::Coinbase { .. })).collect();
+            prop_assert_eq!(coinbases.len(), 1);
+            prop_assert_eq!(
+                &block.transactions[0],
+                &Transaction::coinbase(&test_utils::test_address(0), genesis.reward_at(height) + fees, height)
+            );
+        }
+    }
+
+    #[test]
+    fn nonces_count_up_from_zero(seed in any::<u64>(), length in 1u64..12) {
+        let chain = ledger(seed, length);
+        let mut next = BTreeMap::new();
+        for block in chain.get_blocks_range(1, length).unwrap() {
+            for transaction in &block.transactions {
+                if let Some((sender, _)) = transaction.spend() {
+                    let expected = next.entry(sender.to_string()).or_insert(0);
+                    prop_assert_eq!(transaction.nonce(), Some(*expected));
+                    *expected += 1;
+                }
+            }
+        }
+        for (sender, nonce) in next {
+            prop_assert_eq!(chain.get_account(&sender).unwrap().nonce, nonce);
+        }
+    }
+
+    #[test]
+    fn spent_nonces_and_inflated_coinbases_are_refused(seed in any::<u64>(), length in 2u64..12, extra in 1u64..1_000) {
+        let chain = ledger(seed, length);
+        let mined: Vec<Transaction> = chain
+            .get_blocks_range(1, length)
+            .unwrap()
+            .into_iter()
+            .flat_map(|block| block.transactions)
+            .filter(|transaction| transaction.spend().is_some())
+            .collect();
+        for transaction in &mined {
+            prop_assert!(chain.submit_transaction(transaction.clone()).is_err(), "replayed {}", transaction.hash());
+        }
+
+        let sender = test_utils::test_address(1);
+        let nonce = chain.get_account(&sender).unwrap().nonce;
+        let network_id = chain.network_id().unwrap();
+        let skipped = Transaction::transfer(&sender, "anyone", 1).with_nonce(nonce + 1).signed(&test_utils::test_signer(1), &network_id).unwrap();
+        prop_assert!(chain.submit_transaction(skipped).is_err());
+        prop_assert!(chain.submit_transaction(Transaction::transfer(&sender, "anyone", 1).with_nonce(nonce)).is_err());
+
+        let branch = test_utils::generate_branch(&chain, length - 1, 2, seed ^ 1).unwrap();
+        let inflated = inflate_coinbase(branch[0].clone(), extra);
+        let refused = chain.receive_block(inflated).unwrap_err().to_string();
+        prop_assert!(refused.contains("oinbase"), "{}", refused);
+        prop_assert!(matches!(chain.receive_block(branch[0].clone()).unwrap(), BlockStatus::SideChain));
+    }
+
+    #[test]
+    fn decoders_survive_any_bytes(bytes in proptest::collection::vec(any::<u8>(), 0..512)) {
+        test_utils::fuzz_decode(&bytes);
+    }
+
+    #[test]
+    fn decoders_survive_damaged_records(seed in any::<u64>(), flips in proptest::collection::vec((any::<usize>(), any::<u8>()), 0..8), cut in any::<usize>()) {
+        let chain = ledger(seed, 2);
+        let block = chain.get_blocks_range(2, 2).unwrap().remove(0);
+        let mut rng = TestRng::new(seed);
+        for mut record in [
+            ledger_v1::encoding::encode_block(&block).unwrap(),
+            test_utils::fuzz_record(&block, false).unwrap(),
+            test_utils::fuzz_record(&block, true).unwrap(),
+            serde_json::to_vec(&block).unwrap(),
+        ] {
+            test_utils::fuzz_decode(&record);
+            for (at, value) in &flips {
+                let at = at % record.len();
+                record[at] ^= value | 1;
+                test_utils::fuzz_decode(&record);
+            }
+            record.truncate(cut % (record.len() + 1));
+            test_utils::fuzz_decode(&record);
+            record.extend(rng.bytes(16));
+            test_utils::fuzz_decode(&record);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// The rate limiter tracks a bounded number of client addresses, forgetting the one
+// heard from least recently to make room.
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use ledger_v1::ratelimit::MAX_TRACKED;
+use ledger_v1::test_utils::ManualClock;
+use ledger_v1::{Blockchain, Config, MemoryStore};
+
+fn ip(index: usize) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + index as u32))
+}
+
+#[test]
+fn tracked_addresses_stay_bounded() {
+    let config = Config { rate_limit_per_minute: 1, ..Config::default() };
+    let clock = ManualClock::new(1_000_000);
+    let chain = Blockchain::open_store(MemoryStore::new(), None, config).unwrap().with_clock(clock.clone());
+    assert!(chain.allow_request(ip(0)));
+    assert!(!chain.allow_request(ip(0)));
+    assert!(chain.allow_request(ip(1)));
+
+    for index in 2..MAX_TRACKED + 100 {
+        clock.advance(1);
+        assert!(chain.allow_request(ip(index)));
+        // The first client stays heard from, so it is never the one forgotten.
+        if index % 1000 == 0 {
+            assert!(!chain.allow_request(ip(0)));
+        }
+    }
+    assert_eq!(chain.metrics().unwrap().rate_limited_addresses, MAX_TRACKED as u64);
+
+    // The second was forgotten along the way and starts over; the first was kept.
+    assert!(!chain.allow_request(ip(0)));
+    assert!(chain.allow_request(ip(1)));
+    assert_eq!(chain.metrics().unwrap().rate_limited_addresses, MAX_TRACKED as u64);
+}
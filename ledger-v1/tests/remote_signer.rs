@@ -0,0 +1,38 @@
+#![cfg(feature = "remote-signer")]
+
+// A remote signer's URL may name an IPv6 host, and a service answering with more
+// than a signature is cut off.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use ledger_v1::signer::{RemoteSigner, Signer};
+use ledger_v1::test_utils;
+
+#[test]
+fn ipv6_hosts_are_bracketed() {
+    let key = test_utils::test_signer(1).public_key();
+    assert!(RemoteSigner::new("http://[::1]:8080/sign", &key).is_ok());
+    assert!(RemoteSigner::new("http://[::1]/sign", &key).is_ok());
+    for url in ["http://[::1/sign", "http://[::1]x/sign", "http://[]:8080/sign"] {
+        assert!(RemoteSigner::new(url, &key).is_err(), "{}", url);
+    }
+}
+
+#[test]
+fn oversized_responses_are_refused() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read(&mut [0; 4096]);
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n");
+        let padding = vec![b' '; 1024 * 1024];
+        let _ = stream.write_all(&padding);
+    });
+    let key = test_utils::test_signer(1).public_key();
+    let signer = RemoteSigner::new(&format!("http://127.0.0.1:{}/sign", port), &key).unwrap();
+    let error = signer.sign(b"message").unwrap_err();
+    assert!(error.to_string().contains("over"), "{}", error);
+}
@@ -0,0 +1,125 @@
+// A reorg puts the transactions of the blocks it disconnects back in the mempool. One
+// that fails on a consensus rule marks the rest of the branch invalid for good; one
+// that fails for any other reason leaves the branch to be sent again.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use ledger_v1::store::{Entries, Writes};
+use ledger_v1::test_utils::{self, ManualClock};
+use ledger_v1::{Block, BlockStatus, BlockStore, Blockchain, ConsensusError, MemoryStore, Transaction, TreeId};
+
+// A MemoryStore whose account reads fail while `failing` is set, as a disk would.
+#[derive(Clone, Default)]
+struct FlakyStore {
+    inner: MemoryStore,
+    failing: Arc<AtomicBool>,
+}
+
+impl BlockStore for FlakyStore {
+    fn get(&self, tree: TreeId, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        if tree == TreeId::State && self.failing.load(Ordering::SeqCst) {
+            return Err("Input/output error".into());
+        }
+        self.inner.get(tree, key)
+    }
+
+    fn scan_prefix(&self, tree: TreeId, prefix: &[u8]) -> Entries<'_> {
+        self.inner.scan_prefix(tree, prefix)
+    }
+
+    fn apply(&self, writes: &Writes) -> Result<(), Box<dyn std::error::Error>> {
+        self.inner.apply(writes)
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.inner.flush()
+    }
+}
+
+// A chain of two blocks, and a branch of two forking after the first, which wins
+// once both are received.
+fn fork(store: FlakyStore) -> (Blockchain<FlakyStore>, Vec<Block>) {
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let clock = ManualClock::new(genesis.timestamp);
+    let chain = Blockchain::open_store(store, Some(&genesis), test_utils::miner_config()).unwrap().with_clock(clock.clone());
+    for data in ["one", "two"] {
+        clock.advance(1_000);
+        chain.add_block(data).unwrap();
+    }
+    let branch = test_utils::generate_branch(&chain, 1, 2, 7).unwrap();
+    clock.set(branch[1].timestamp);
+    assert!(matches!(chain.receive_block(branch[0].clone()).unwrap(), BlockStatus::SideChain));
+    (chain, branch)
+}
+
+#[test]
+fn a_reorg_failing_on_the_store_can_be_retried() {
+    let store = FlakyStore::default();
+    let (chain, branch) = fork(store.clone());
+    let tip = chain.current_hash();
+
+    store.failing.store(true, Ordering::SeqCst);
+    let error = chain.receive_block(branch[1].clone()).unwrap_err();
+    assert!(error.to_string().contains("Input/output error") && error.downcast_ref::<ConsensusError>().is_none(), "{}", error);
+    store.failing.store(false, Ordering::SeqCst);
+    assert_eq!(chain.current_hash(), tip);
+    for block in &branch {
+        assert!(!chain.is_invalid(&block.hash).unwrap());
+    }
+
+    assert!(matches!(chain.receive_block(branch[1].clone()).unwrap(), BlockStatus::Reorged { .. }));
+    assert_eq!(chain.current_hash(), branch[1].hash);
+}
+
+#[test]
+fn a_reorg_failing_on_a_rule_marks_the_branch_invalid() {
+    let (chain, branch) = fork(FlakyStore::default());
+    let tip = chain.current_hash();
+
+    // A wrong state root only shows once the block is applied.
+    let mut lying = branch[1].clone();
+    lying.state_root = "00".repeat(32);
+    lying.hash = lying.calculate_hash();
+    let error = chain.receive_block(lying.clone()).unwrap_err();
+    assert!(error.downcast_ref::<ConsensusError>().is_some(), "{}", error);
+    assert_eq!(chain.current_hash(), tip);
+    assert!(chain.is_invalid(&lying.hash).unwrap());
+    // Its parent connected fine and stays a candidate.
+    assert!(!chain.is_invalid(&branch[0].hash).unwrap());
+    assert!(matches!(chain.receive_block(branch[1].clone()).unwrap(), BlockStatus::Reorged { .. }));
+}
+
+#[test]
+fn a_reorg_returns_disconnected_transactions_to_the_mempool() {
+    // Test account 5 is funded but left alone by the branch generator, which only uses
+    // the accounts from 0 up.
+    let mut genesis = test_utils::funded_genesis(1, 1_000);
+    genesis.allocations.insert(test_utils::test_address(5), 1_000);
+    let clock = ManualClock::new(genesis.timestamp);
+    let chain = Blockchain::open_store(MemoryStore::new(), Some(&genesis), test_utils::miner_config()).unwrap().with_clock(clock.clone());
+    clock.advance(1_000);
+    chain.add_block("one").unwrap();
+    let transfer = Transaction::transfer(&test_utils::test_address(5), "payee", 10)
+        .with_nonce(0)
+        .signed(&test_utils::test_signer(5), &chain.network_id().unwrap())
+        .unwrap();
+    chain.submit_transaction(transfer.clone()).unwrap();
+    clock.advance(1_000);
+    chain.mine_block("two").unwrap();
+    assert!(chain.mempool().is_empty());
+    assert_eq!(chain.get_account("payee").unwrap().balance, 10);
+
+    let branch = test_utils::generate_branch(&chain, 1, 2, 3).unwrap();
+    clock.set(branch[1].timestamp);
+    chain.add_blocks(&branch).unwrap();
+    assert_eq!(chain.current_hash(), branch[1].hash);
+    assert_eq!(chain.get_account("payee").unwrap().balance, 0);
+    assert_eq!(chain.mempool(), [transfer]);
+
+    // Mined again on the new tip, it leaves the pool.
+    clock.advance(1_000);
+    chain.mine_block("three").unwrap();
+    assert!(chain.mempool().is_empty());
+    assert_eq!(chain.get_account("payee").unwrap().balance, 10);
+}
@@ -0,0 +1,44 @@
+// `repair` truncates the chain to the longest intact prefix, sets aside everything
+// above it and rolls the state back with it.
+
+use ledger_v1::test_utils;
+use ledger_v1::{BlockStore, Blockchain, MemoryStore, TreeId};
+
+#[test]
+fn repair_truncates_to_the_last_intact_block() {
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let source = test_utils::generate_chain_with_genesis(&genesis, 5, 5).unwrap();
+    let blocks = source.get_blocks_range(1, 5).unwrap();
+    let open = |store: &MemoryStore| Blockchain::open_store(store.clone(), Some(&genesis), test_utils::miner_config()).unwrap();
+    let store = MemoryStore::new();
+    open(&store).add_blocks(&blocks).unwrap();
+
+    let damaged = &blocks[2].hash;
+    store.insert(TreeId::Blocks, damaged.as_bytes(), b"not a block".to_vec()).unwrap();
+    let chain = open(&store);
+
+    let planned = chain.repair(true).unwrap();
+    assert_eq!(planned.last_valid, blocks[1].hash);
+    assert_eq!(planned.height, 2);
+    assert_eq!(planned.quarantined, [blocks[4].hash.clone(), blocks[3].hash.clone(), damaged.clone()]);
+    assert_eq!(chain.current_hash(), blocks[4].hash);
+
+    assert_eq!(chain.repair(false).unwrap(), planned);
+    assert_eq!(chain.current_hash(), blocks[1].hash);
+    assert_eq!(chain.height().unwrap(), 2);
+    assert_eq!(store.get(TreeId::Quarantine, damaged.as_bytes()).unwrap().unwrap(), b"not a block");
+    assert!(store.get(TreeId::Blocks, damaged.as_bytes()).unwrap().is_none());
+    // The state is the one the first two blocks leave.
+    let truncated = test_utils::generate_chain_with_genesis(&genesis, 5, 2).unwrap();
+    assert_eq!(truncated.current_hash(), blocks[1].hash);
+    for index in 0..2 {
+        let address = test_utils::test_address(index);
+        assert_eq!(chain.get_account(&address).unwrap(), truncated.get_account(&address).unwrap());
+    }
+
+    // The intact blocks above the damage can come back.
+    chain.add_blocks(&blocks[2..]).unwrap();
+    assert_eq!(chain.current_hash(), blocks[4].hash);
+    assert!(chain.is_chain_valid().unwrap());
+    assert!(chain.repair(false).unwrap().quarantined.is_empty());
+}
@@ -0,0 +1,37 @@
+// A running node's database can't be opened by anyone else, so readers go through
+// the replica it keeps.
+#![cfg(feature = "sled")]
+
+use ledger_v1::{Blockchain, Config, LedgerError};
+
+#[test]
+fn the_replica_follows_a_live_node() {
+    let dir = std::env::temp_dir().join(format!("ledger-v1-replica-{}", std::process::id()));
+    let replica = dir.join("replica");
+    let db = dir.join("db");
+    let config = Config { read_replica: Some(replica.clone()), read_replica_interval: 2, ..Config::default() };
+    let node = Blockchain::open_with_config(db.to_str().unwrap(), None, config.clone()).unwrap();
+    for data in ["one", "two", "three"] {
+        node.add_block(data).unwrap();
+    }
+
+    let refused = Blockchain::open_read_only(db.to_str().unwrap()).err().unwrap();
+    assert!(matches!(refused.downcast_ref::<LedgerError>(), Some(LedgerError::AlreadyInUse { .. })));
+    let reader = Blockchain::open_replica(&replica, Config::default()).unwrap();
+    assert_eq!(reader.height().unwrap(), 2);
+    assert!(reader.add_block("four").is_err());
+    drop(reader);
+
+    for data in ["four", "five", "six", "seven"] {
+        node.add_block(data).unwrap();
+    }
+    let reader = Blockchain::open_replica(&replica, Config::default()).unwrap();
+    assert_eq!(reader.height().unwrap(), 6);
+    assert!(reader.is_chain_valid().unwrap());
+    // The generation being read and the one before it.
+    let generations = std::fs::read_dir(&replica).unwrap().filter(|entry| entry.as_ref().unwrap().path().is_dir()).count();
+    assert_eq!(generations, 2);
+    drop(reader);
+    drop(node);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
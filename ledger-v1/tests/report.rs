@@ -0,0 +1,44 @@
+// SQL reports refuse values SQLite cannot hold, and named reports keep their own
+// place apart from named replays.
+
+use ledger_v1::test_utils::{self, miner_config};
+use ledger_v1::{Blockchain, ExportFormat, MemoryStore, Transaction};
+
+fn chain(balance: u64) -> Blockchain<MemoryStore> {
+    Blockchain::open_store(MemoryStore::new(), Some(&test_utils::funded_genesis(2, balance)), miner_config()).unwrap()
+}
+
+fn send(chain: &Blockchain<MemoryStore>, amount: u64) {
+    let transfer = Transaction::transfer(&test_utils::test_address(1), "payee", amount)
+        .with_nonce(chain.next_nonce(&test_utils::test_address(1)).unwrap())
+        .signed(&test_utils::test_signer(1), &chain.network_id().unwrap())
+        .unwrap();
+    chain.add_block_with_transactions("", vec![transfer]).unwrap();
+}
+
+#[test]
+fn amounts_above_i64_fail_sql_reports() {
+    let chain = chain(1 << 63);
+    send(&chain, 1 << 63);
+    let refused = chain.export_report(Vec::new(), ExportFormat::Sql, None).unwrap_err();
+    assert!(refused.to_string().contains("amount 9223372036854775808"), "{}", refused);
+
+    let mut csv = Vec::new();
+    chain.export_report(&mut csv, ExportFormat::TxCsv, None).unwrap();
+    assert!(String::from_utf8(csv).unwrap().contains("9223372036854775808"));
+}
+
+#[test]
+fn reports_and_replays_keep_separate_tokens() {
+    let chain = chain(1_000);
+    send(&chain, 5);
+    assert_eq!(chain.export_report(Vec::new(), ExportFormat::Sql, Some("books")).unwrap(), Some((0, 1)));
+    assert!(chain.replay_token("books").unwrap().is_none());
+
+    chain.resume_replay("books", 0, |_| Ok(())).unwrap();
+    send(&chain, 5);
+    assert_eq!(chain.export_report(Vec::new(), ExportFormat::Sql, Some("books")).unwrap(), Some((2, 2)));
+    assert_eq!(chain.replay_token("books").unwrap().unwrap().height, 0);
+    assert_eq!(chain.report_token("books").unwrap().unwrap().height, 2);
+    assert!("sqlite".parse::<ExportFormat>().is_err());
+}
@@ -0,0 +1,88 @@
+// Multi-sig conditions count signers, not keys, and unlock signatures are bound to
+// one chain. Witnesses and locks that could be abused are refused.
+
+use ledger_v1::script::{self, Condition, Context, Witness};
+use ledger_v1::signer::Signer;
+use ledger_v1::test_utils::{self, miner_config};
+use ledger_v1::{Blockchain, MemoryStore, Transaction};
+use sha2::Digest;
+
+fn key(index: u64) -> String {
+    test_utils::test_signer(index).public_key()
+}
+
+#[test]
+fn repeated_keys_are_refused_and_count_once() {
+    let twice = Condition::MultiSig { threshold: 2, keys: vec![key(1), key(1).to_uppercase()] };
+    assert!(twice.check().unwrap_err().to_string().contains("twice"));
+
+    let signature = script::sign_unlock(&test_utils::test_signer(1), "net", "lock", "to", 0).unwrap();
+    let witness = Witness { signatures: vec![signature.clone(), signature], preimages: vec![] };
+    let message = script::unlock_message("net", "lock", "to", 0);
+    let refused = twice.evaluate(&witness, &Context { height: 1, message: &message }).unwrap_err();
+    assert!(refused.to_string().contains("Only 1 of the 2"), "{}", refused);
+}
+
+#[test]
+fn unlock_signatures_only_work_on_their_network() {
+    let chain = Blockchain::open_store(MemoryStore::new(), Some(&test_utils::funded_genesis(2, 1_000)), miner_config()).unwrap();
+    let network_id = chain.network_id().unwrap();
+    let condition = Condition::MultiSig { threshold: 1, keys: vec![key(1)] };
+    let lock = Transaction::lock(&test_utils::test_address(0), 100, 0, condition)
+        .with_nonce(0)
+        .signed(&test_utils::test_signer(0), &network_id)
+        .unwrap();
+    let txid = lock.hash();
+    chain.add_block_with_transactions("lock", vec![lock]).unwrap();
+
+    let unlock = |network_id: &str| {
+        let signature = script::sign_unlock(&test_utils::test_signer(1), network_id, &txid, "payee", 0).unwrap();
+        Transaction::unlock(&txid, "payee", 0, Witness { signatures: vec![signature], preimages: vec![] })
+    };
+    assert!(chain.add_block_with_transactions("unlock", vec![unlock("other")]).is_err());
+    chain.add_block_with_transactions("unlock", vec![unlock(&network_id)]).unwrap();
+    assert_eq!(chain.get_account("payee").unwrap().balance, 100);
+}
+
+#[test]
+fn weak_keys_and_oversized_witnesses_are_refused() {
+    // The identity point, which every signature "verifies" against.
+    let identity = format!("01{}", "00".repeat(31));
+    let weak = Condition::MultiSig { threshold: 1, keys: vec![identity] };
+    assert!(weak.check().unwrap_err().to_string().contains("weak"));
+
+    let message = script::unlock_message("net", "lock", "to", 0);
+    let context = Context { height: 1, message: &message };
+    let signature = script::sign_unlock(&test_utils::test_signer(1), "net", "lock", "to", 0).unwrap();
+    let multisig = Condition::MultiSig { threshold: 1, keys: vec![key(1)] };
+    let padded = Witness { signatures: vec!["00".repeat(64), signature], preimages: vec![] };
+    assert!(multisig.evaluate(&padded, &context).unwrap_err().to_string().contains("2 signatures for 1 keys"));
+
+    let hash_lock = Condition::HashLock { hash: "00".repeat(32) };
+    let guesses = Witness { signatures: vec![], preimages: vec!["01".to_string(), "02".to_string()] };
+    assert!(hash_lock.evaluate(&guesses, &context).unwrap_err().to_string().contains("2 preimages for 1 hash locks"));
+}
+
+#[test]
+fn empty_locks_are_refused_and_spent_ones_stay_spent() {
+    let chain = Blockchain::open_store(MemoryStore::new(), Some(&test_utils::funded_genesis(1, 1_000)), miner_config()).unwrap();
+    let network_id = chain.network_id().unwrap();
+    let preimage = b"secret";
+    let condition = Condition::HashLock { hash: hex::encode(sha2::Sha256::digest(preimage)) };
+    let lock = |amount, nonce| {
+        Transaction::lock(&test_utils::test_address(0), amount, 0, condition.clone())
+            .with_nonce(nonce)
+            .signed(&test_utils::test_signer(0), &network_id)
+            .unwrap()
+    };
+    assert!(chain.add_block_with_transactions("empty", vec![lock(0, 0)]).unwrap_err().to_string().contains("locks nothing"));
+
+    let locked = lock(100, 0);
+    let txid = locked.hash();
+    chain.add_block_with_transactions("lock", vec![locked]).unwrap();
+    let unlock = || Transaction::unlock(&txid, "payee", 0, Witness { signatures: vec![], preimages: vec![hex::encode(preimage)] });
+    chain.add_block_with_transactions("unlock", vec![unlock()]).unwrap();
+    assert!(chain.get_account(&script::lock_address(&txid)).unwrap().spent);
+    let again = chain.add_block_with_transactions("again", vec![unlock()]).unwrap_err();
+    assert!(again.to_string().contains("already unlocked"), "{}", again);
+}
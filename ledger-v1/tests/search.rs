@@ -0,0 +1,42 @@
+// The search index of an encrypted chain must not give its contents away.
+
+use ledger_v1::{Blockchain, BlockStore, Config, MemoryStore, TreeId};
+
+fn config(key: Option<&str>) -> Config {
+    let encryption_key_file = key.map(|key| {
+        let path = std::env::temp_dir().join(format!("ledger-v1-search-{}-{}.key", std::process::id(), &key[..8]));
+        std::fs::write(&path, key).unwrap();
+        path
+    });
+    Config { search_index: true, encryption_key_file, ..Config::default() }
+}
+
+fn index_keys(store: &MemoryStore) -> Vec<String> {
+    store.scan_prefix(TreeId::Search, &[]).map(|entry| String::from_utf8_lossy(&entry.unwrap().0).into_owned()).collect()
+}
+
+#[test]
+fn encrypted_chains_index_keyed_tokens() {
+    let store = MemoryStore::new();
+    let chain = Blockchain::open_store(store.clone(), None, config(Some(&"11".repeat(32)))).unwrap();
+    chain.add_block("confidential merger memo").unwrap();
+    let keys = index_keys(&store);
+    assert!(!keys.is_empty());
+    assert!(keys.iter().all(|key| !key.contains("confidential") && !key.contains("merger")), "{:?}", keys);
+    assert_eq!(chain.lookup("Merger").unwrap().len(), 1);
+    assert!(chain.lookup("memorandum").unwrap().is_empty());
+}
+
+#[test]
+fn adding_a_key_rebuilds_the_index() {
+    let store = MemoryStore::new();
+    let chain = Blockchain::open_store(store.clone(), None, config(None)).unwrap();
+    chain.add_block("public notice").unwrap();
+    assert!(index_keys(&store).iter().any(|key| key.starts_with("notice\0")));
+    assert_eq!(chain.lookup("notice").unwrap().len(), 1);
+    drop(chain);
+
+    let chain = Blockchain::open_store(store.clone(), None, config(Some(&"22".repeat(32)))).unwrap();
+    assert!(index_keys(&store).iter().all(|key| !key.contains("notice")));
+    assert_eq!(chain.lookup("notice").unwrap().len(), 1);
+}
@@ -0,0 +1,48 @@
+// Only configs that turn signatures off take unsigned transfers, and never from a
+// key's address.
+
+use ledger_v1::test_utils::{self, miner_config};
+use ledger_v1::{Blockchain, GenesisConfig, MemoryStore, Transaction};
+
+fn chain(genesis: &GenesisConfig) -> Blockchain<MemoryStore> {
+    Blockchain::open_store(MemoryStore::new(), Some(genesis), miner_config()).unwrap()
+}
+
+fn unsigned(from: &str) -> Transaction {
+    Transaction::transfer(from, "payee", 10).with_nonce(0)
+}
+
+#[test]
+fn a_config_without_the_setting_refuses_unsigned_transfers() {
+    let address = test_utils::test_address(1);
+    let json = format!(r#"{{"chain_id": "old", "allocations": {{"alice": 100, "{}": 100}}}}"#, address);
+    let genesis: GenesisConfig = serde_json::from_str(&json).unwrap();
+    assert!(genesis.require_signatures);
+
+    let chain = chain(&genesis);
+    assert!(chain.add_block_with_transactions("unsigned", vec![unsigned("alice")]).is_err());
+    assert!(chain.add_block_with_transactions("unsigned", vec![unsigned(&address)]).is_err());
+    assert_eq!(chain.get_account("payee").unwrap().balance, 0);
+}
+
+#[test]
+fn turning_signatures_off_still_protects_key_addresses() {
+    let address = test_utils::test_address(1);
+    let mut genesis = GenesisConfig { require_signatures: false, ..GenesisConfig::default() };
+    genesis.allocations.insert("alice".to_string(), 100);
+    genesis.allocations.insert(address.clone(), 100);
+
+    let chain = chain(&genesis);
+    assert!(chain.add_block_with_transactions("unsigned", vec![unsigned(&address)]).is_err());
+    chain.add_block_with_transactions("unsigned", vec![unsigned("alice")]).unwrap();
+    assert_eq!(chain.get_account("payee").unwrap().balance, 10);
+}
+
+#[test]
+fn the_default_config_keeps_its_genesis_data() {
+    let data = serde_json::to_string(&GenesisConfig::default()).unwrap();
+    assert!(!data.contains("require_signatures"), "{}", data);
+    let off = serde_json::to_string(&GenesisConfig::legacy()).unwrap();
+    let read: GenesisConfig = serde_json::from_str(&off).unwrap();
+    assert!(!read.require_signatures);
+}
@@ -0,0 +1,21 @@
+// Account proofs from the stored state tree, at the tip and below it.
+
+use ledger_v1::state::{self, Account};
+use ledger_v1::test_utils;
+
+#[test]
+fn account_proofs_check_against_block_state_roots() {
+    let chain = test_utils::generate_chain_with_genesis(&test_utils::funded_genesis(4, 1_000), 7, 6).unwrap();
+    let algorithm = chain.genesis_config().hash_algorithm;
+    for block in chain.get_blocks_range(1, 6).unwrap() {
+        let height = block.sequence;
+        for (address, account) in chain.state_at(height).unwrap() {
+            let (proven, proof) = chain.account_proof(&address, height).unwrap().unwrap();
+            assert_eq!(proven, account);
+            assert!(state::verify_account_proof(algorithm, &block.state_root, &address, &account, &proof));
+            let forged = Account { balance: account.balance + 1, ..account.clone() };
+            assert!(!state::verify_account_proof(algorithm, &block.state_root, &address, &forged, &proof));
+        }
+        assert!(chain.account_proof("nobody", height).unwrap().is_none());
+    }
+}
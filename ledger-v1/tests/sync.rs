@@ -0,0 +1,48 @@
+#![cfg(feature = "grpc")]
+
+// A node syncing from a peer with more work catches up with it, from wherever its own
+// chain left the peer's, and a node in sync has nothing to fetch.
+
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use ledger_v1::test_utils;
+use ledger_v1::{Blockchain, MemoryStore};
+
+#[test]
+fn a_node_catches_up_with_a_peer_past_its_fork() {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    let addr: SocketAddr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let genesis = test_utils::funded_genesis(2, 1_000);
+    let peer = test_utils::generate_chain_with_genesis(&genesis, 21, 12).unwrap();
+    let server = peer.clone();
+    runtime.spawn(async move { server.serve_grpc(addr).await.unwrap() });
+
+    // Shares the first three blocks, then goes its own way.
+    let chain = Blockchain::open_store(MemoryStore::new(), Some(&genesis), test_utils::miner_config()).unwrap();
+    chain.add_blocks(&peer.get_blocks_range(1, 3).unwrap()).unwrap();
+    chain.add_block("ours").unwrap();
+    let ours = chain.current_hash();
+
+    let peers = [addr.to_string()];
+    let report = runtime.block_on(async {
+        for _ in 0..50 {
+            match chain.sync(&peers, 4).await {
+                Ok(report) if report.peer.is_some() => return report,
+                _ => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+        panic!("the peer never answered");
+    });
+    assert_eq!(report.blocks, 9);
+    assert_eq!(report.height, 12);
+    assert_eq!(chain.current_hash(), peer.current_hash());
+    // Our own block is kept, off the canonical chain.
+    assert!(chain.get_block(&ours).unwrap().is_some());
+    assert!(chain.sync_state().unwrap().is_none());
+    assert!(chain.is_chain_valid().unwrap());
+
+    let again = runtime.block_on(chain.sync(&peers, 4)).unwrap();
+    assert_eq!((again.headers, again.blocks), (0, 0));
+    peer.request_shutdown();
+}
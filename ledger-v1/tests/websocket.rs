@@ -0,0 +1,100 @@
+#![cfg(feature = "websocket")]
+
+// The WebSocket server refuses subscribers past its limit with 503, and lets the
+// next one in once a place is free; connections past its handshake limit are closed.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use ledger_v1::{Blockchain, Config, MemoryStore};
+use tungstenite::http::StatusCode;
+
+// Starts a server on a free port and returns the port.
+fn serve(config: Config) -> u16 {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let chain = Blockchain::open_store(MemoryStore::new(), None, config).unwrap();
+    thread::spawn(move || {
+        let _ = chain.serve_websocket(("127.0.0.1", port));
+    });
+    port
+}
+
+#[test]
+fn subscribers_past_the_limit_get_503() {
+    let port = serve(Config { max_websocket_clients: 1, ..Config::default() });
+    let url = format!("ws://127.0.0.1:{}", port);
+    // tungstenite's error type, not ours.
+    #[allow(clippy::result_large_err)]
+    let connect = || {
+        for _ in 0..50 {
+            match tungstenite::connect(&url) {
+                Err(tungstenite::Error::Io(_) | tungstenite::Error::Url(_)) => thread::sleep(Duration::from_millis(20)),
+                result => return result,
+            }
+        }
+        panic!("the server never listened");
+    };
+
+    let (mut first, _) = connect().unwrap();
+    match connect() {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE),
+        other => panic!("expected a 503, got {:?}", other.map(|(_, response)| response.status())),
+    }
+
+    first.close(None).unwrap();
+    while first.read().is_ok() {}
+    let mut admitted = false;
+    for _ in 0..50 {
+        if connect().is_ok() {
+            admitted = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(admitted);
+}
+
+#[test]
+fn slow_handshakes_are_not_cut_off() {
+    let port = serve(Config::default());
+    let mut stream = (0..50)
+        .find_map(|_| TcpStream::connect(("127.0.0.1", port)).map_err(|_| thread::sleep(Duration::from_millis(20))).ok())
+        .expect("the server never listened");
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+    thread::sleep(Duration::from_millis(500));
+    stream
+        .write_all(b"Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n")
+        .unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"HTTP/1.1 101");
+}
+
+#[test]
+fn handshakes_in_progress_are_capped() {
+    let port = serve(Config::default());
+    let connect = || {
+        (0..50)
+            .find_map(|_| TcpStream::connect(("127.0.0.1", port)).map_err(|_| thread::sleep(Duration::from_millis(20))).ok())
+            .expect("the server never listened")
+    };
+    // Connections that never send a handshake hold every place.
+    let idle: Vec<TcpStream> = (0..ledger_v1::websocket::MAX_HANDSHAKES).map(|_| connect()).collect();
+    thread::sleep(Duration::from_millis(200));
+
+    let mut closed = connect();
+    closed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(closed.read(&mut [0; 1]).unwrap(), 0);
+
+    drop(idle);
+    thread::sleep(Duration::from_millis(200));
+    let mut admitted = connect();
+    admitted
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n")
+        .unwrap();
+    let mut response = [0; 12];
+    admitted.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"HTTP/1.1 101");
+}